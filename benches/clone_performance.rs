@@ -19,7 +19,7 @@ fn main() {
     println!("==============================================");
 
     // Create a sketch with substantial data
-    let mut sketch = KllDoubleSketch::new().expect("Failed to create sketch");
+    let mut sketch = KllDoubleSketch::new();
 
     // Add a significant amount of data to make the performance difference noticeable
     println!("Preparing sketch with 100,000 data points...");
@@ -151,7 +151,7 @@ fn test_serialization_size_vs_data_count() {
     let data_counts = [1_000, 10_000, 50_000, 100_000, 500_000, 1_000_000];
 
     for &count in &data_counts {
-        let mut sketch = KllDoubleSketch::new().expect("Failed to create sketch");
+        let mut sketch = KllDoubleSketch::new();
 
         // Add data points
         for i in 0..count {
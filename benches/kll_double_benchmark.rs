@@ -18,7 +18,7 @@ fn bench_sketch_creation(c: &mut Criterion) {
 
     group.bench_function("new_default", |b| {
         b.iter(|| {
-            let sketch = KllDoubleSketch::new().unwrap();
+            let sketch = KllDoubleSketch::new();
             black_box(sketch);
         });
     });
@@ -35,7 +35,7 @@ fn bench_update_operations(c: &mut Criterion) {
 
     // Test update performance with different data patterns
     group.bench_function("update_random", |b| {
-        let mut sketch = KllDoubleSketch::new().unwrap();
+        let mut sketch = KllDoubleSketch::new();
         b.iter(|| {
             let value: f64 = rng.random_range(0.0..1000000.0);
             sketch.update(black_box(value));
@@ -43,7 +43,7 @@ fn bench_update_operations(c: &mut Criterion) {
     });
 
     group.bench_function("update_sequential", |b| {
-        let mut sketch = KllDoubleSketch::new().unwrap();
+        let mut sketch = KllDoubleSketch::new();
         let mut counter = 0.0;
         b.iter(|| {
             sketch.update(black_box(counter));
@@ -70,7 +70,7 @@ fn bench_bulk_updates(c: &mut Criterion) {
 
     group.bench_function("random_data_100k", |b| {
         b.iter(|| {
-            let mut sketch = KllDoubleSketch::new().unwrap();
+            let mut sketch = KllDoubleSketch::new();
             for &value in &data {
                 sketch.update(black_box(value));
             }
@@ -86,7 +86,7 @@ fn bench_quantile_queries(c: &mut Criterion) {
     let mut group = c.benchmark_group("quantile_queries");
 
     // Setup sketch with maximum amount of data
-    let mut sketch = KllDoubleSketch::new().unwrap();
+    let mut sketch = KllDoubleSketch::new();
     let mut rng = StdRng::seed_from_u64(42);
 
     for _ in 0..100_000 {
@@ -126,7 +126,7 @@ fn bench_rank_queries(c: &mut Criterion) {
     let mut group = c.benchmark_group("rank_queries");
 
     // Setup sketch with data
-    let mut sketch = KllDoubleSketch::new().unwrap();
+    let mut sketch = KllDoubleSketch::new();
     let mut rng = StdRng::seed_from_u64(42);
 
     for _ in 0..100_000 {
@@ -148,7 +148,7 @@ fn bench_serialization(c: &mut Criterion) {
     let mut group = c.benchmark_group("serialization");
 
     // Create sketch with maximum amount of data
-    let mut sketch = KllDoubleSketch::new().unwrap();
+    let mut sketch = KllDoubleSketch::new();
     let mut rng = StdRng::seed_from_u64(42);
 
     for _ in 0..100_000 {
@@ -188,12 +188,12 @@ fn bench_merge_operations(c: &mut Criterion) {
                 // Setup: create two sketches with data
                 let mut rng = StdRng::seed_from_u64(42);
 
-                let mut sketch1 = KllDoubleSketch::new().unwrap();
+                let mut sketch1 = KllDoubleSketch::new();
                 for _ in 0..size {
                     sketch1.update(rng.random_range(0.0..1000000.0));
                 }
 
-                let mut sketch2 = KllDoubleSketch::new().unwrap();
+                let mut sketch2 = KllDoubleSketch::new();
                 for _ in 0..size {
                     sketch2.update(rng.random_range(0.0..1000000.0));
                 }
@@ -263,7 +263,7 @@ fn bench_clone_operations(c: &mut Criterion) {
     let mut group = c.benchmark_group("clone_operations");
 
     // Create sketch with maximum amount of data
-    let mut sketch = KllDoubleSketch::new().unwrap();
+    let mut sketch = KllDoubleSketch::new();
     let mut rng = StdRng::seed_from_u64(42);
 
     for _ in 0..50_000 {
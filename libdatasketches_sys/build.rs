@@ -1,15 +1,78 @@
 extern crate bindgen;
 extern crate cc;
-extern crate cmake;
 
 use cc::Build;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::{env, str};
+use std::process::Command;
+use std::{env, fs, str};
 
-// Generate the bindings to datasketches C-API.
-fn bindgen_datasketches(file_path: &Path) {
+// A sketch family: the cargo feature enabling it, the datasketches-cpp
+// directory holding its headers and the wrapper sources compiled for it.
+struct Family {
+    feature: &'static str,
+    include_dir: &'static str,
+    sources: &'static [&'static str],
+}
+
+// Each family is only compiled, and only declared in the bindings, when its
+// feature is enabled. New families (hll, theta, req) get an entry here, a
+// feature in Cargo.toml and a `KLL_RS_FAMILY_*` section in wrapper.h.
+const FAMILIES: &[Family] = &[Family {
+    feature: "kll",
+    include_dir: "kll",
+    sources: &["wrapper.cpp"],
+}];
+
+fn enabled_families() -> Vec<&'static Family> {
+    FAMILIES
+        .iter()
+        .filter(|family| {
+            let var = format!("CARGO_FEATURE_{}", family.feature.to_uppercase());
+            env::var_os(var).is_some()
+        })
+        .collect()
+}
+
+// Preprocessor define that enables a family's section of wrapper.h.
+fn family_define(family: &Family) -> String {
+    format!("KLL_RS_FAMILY_{}", family.feature.to_uppercase())
+}
+
+// Targets that never run bindgen unless asked to with `UPDATE_BIND=1`, which
+// writes their bindings under `bindings/`. Cross toolchains for the 32-bit
+// targets rarely ship libclang, and macOS and Windows developers often lack
+// it. None of these files is committed yet: lib.rs declares the functions by
+// hand and does not include `BINDING_PATH`, so the targets build without
+// them.
+const PREGENERATED_TARGETS: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "aarch64-unknown-linux-gnu",
+    "i686-unknown-linux-gnu",
+    "armv7-unknown-linux-gnueabihf",
+    "aarch64-apple-darwin",
+    "x86_64-apple-darwin",
+    "x86_64-pc-windows-msvc",
+    "x86_64-pc-windows-gnu",
+    "i686-pc-windows-msvc",
+];
+
+fn pregenerated_path(target: &str) -> PathBuf {
+    PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("bindings")
+        .join(format!("{}-bindings.rs", target))
+}
+
+// Generate the bindings to datasketches C-API as seen by `target`.
+fn bindgen_datasketches(file_path: &Path, target: &str) {
+    let defines = enabled_families()
+        .into_iter()
+        .map(|family| format!("-D{}", family_define(family)));
     let bindings = bindgen::Builder::default()
         .header("wrapper.h")
+        .clang_arg(format!("--target={}", target))
+        .clang_args(defines)
         .ctypes_prefix("libc")
         .generate()
         .expect("unable to generate datasketches bindings");
@@ -24,54 +87,290 @@ fn config_binding_path() {
     let file_path: PathBuf;
 
     let target = env::var("TARGET").unwrap_or_else(|_| "".to_owned());
-    match target.as_str() {
-        "x86_64-unknown-linux-gnu" | "aarch64-unknown-linux-gnu" => {
-            file_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-                .join("bindings")
-                .join(format!("{}-bindings.rs", target));
-            if env::var("UPDATE_BIND")
-                .map(|s| s.as_str() == "1")
-                .unwrap_or(false)
-            {
-                bindgen_datasketches(&file_path);
+    if PREGENERATED_TARGETS.contains(&target.as_str()) {
+        file_path = pregenerated_path(&target);
+        // A regeneration run rewrites the bindings of every listed target,
+        // so one machine with libclang keeps them all in step.
+        if env::var("UPDATE_BIND")
+            .map(|s| s.as_str() == "1")
+            .unwrap_or(false)
+        {
+            fs::create_dir_all(file_path.parent().unwrap())
+                .expect("unable to create the bindings directory");
+            for target in PREGENERATED_TARGETS {
+                bindgen_datasketches(&pregenerated_path(target), target);
             }
         }
-        _ => {
-            file_path =
-                PathBuf::from(env::var("OUT_DIR").unwrap()).join("datasketches-bindings.rs");
-            bindgen_datasketches(&file_path);
-        }
-    };
+    } else {
+        file_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("datasketches-bindings.rs");
+        bindgen_datasketches(&file_path, &target);
+    }
     println!(
         "cargo:rustc-env=BINDING_PATH={}",
         file_path.to_str().unwrap()
     );
 }
 
+// The datasketches-cpp sources: the vendored submodule, or the checkout named
+// by DATASKETCHES_CPP_DIR for builds that vendor it elsewhere.
+fn datasketches_dir() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=DATASKETCHES_CPP_DIR");
+    let dir = env::var_os("DATASKETCHES_CPP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::current_dir().unwrap().join("datasketches-cpp"));
+    if !dir.join("common").join("include").is_dir() {
+        panic!(
+            "datasketches-cpp sources not found in {}; run `git submodule update --init` \
+             or point DATASKETCHES_CPP_DIR at a checkout",
+            dir.display()
+        );
+    }
+    dir
+}
+
+// Record the vendored datasketches-cpp version and commit for runtime introspection.
+fn config_version_info() {
+    let cpp_dir = datasketches_dir();
+    let cmake_lists = cpp_dir.join("CMakeLists.txt");
+    println!("cargo:rerun-if-changed={}", cmake_lists.display());
+
+    let version = fs::read_to_string(&cmake_lists)
+        .ok()
+        .and_then(|contents| parse_project_version(&contents))
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    // Only ask git when the submodule has its own checkout, otherwise we
+    // would report the commit of the enclosing repository.
+    let commit = if cpp_dir.join(".git").exists() {
+        Command::new("git")
+            .arg("-C")
+            .arg(&cpp_dir)
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|s| s.trim().to_owned())
+    } else {
+        None
+    }
+    .unwrap_or_else(|| "unknown".to_owned());
+
+    println!("cargo:rustc-env=DATASKETCHES_CPP_VERSION={}", version);
+    println!("cargo:rustc-env=DATASKETCHES_CPP_COMMIT={}", commit);
+}
+
+// Extract `x.y.z` from `project(DataSketches VERSION x.y.z ...)`.
+fn parse_project_version(contents: &str) -> Option<String> {
+    let start = contents.find("project(")?;
+    let project = &contents[start..];
+    let project = &project[..project.find(')')?];
+    let mut tokens = project.split_whitespace();
+    tokens.find(|t| *t == "VERSION")?;
+    tokens.next().map(|v| v.to_owned())
+}
+
 fn main() {
     println!("cargo:rerun-if-env-changed=UPDATE_BIND");
+    println!("cargo:rerun-if-changed=wrapper.h");
 
-    let mut build = build_datasketches();
+    let families = enabled_families();
+    // Without any family there is nothing to compile or link against.
+    if !families.is_empty() {
+        config_compiler_cache();
+        let mut build = build_datasketches(&families);
 
-    build.cpp(true).file("wrapper.cpp");
-    if env::var("CARGO_CFG_TARGET_OS").unwrap() != "windows" {
-        build.flag("-std=c++14");
+        build.cpp(true);
+        if env::var("CARGO_CFG_TARGET_OS").unwrap() != "windows" {
+            build.flag("-std=c++14");
+        }
+        if env::var_os("CARGO_FEATURE_MINIMAL").is_some() {
+            config_minimal(&mut build);
+        }
+        build.warnings(false);
+        if env::var_os("CARGO_FEATURE_DYNAMIC").is_some() {
+            build_shared(&mut build);
+        } else {
+            let stdlib_linked = link_cpp(&mut build);
+            compile_if_changed(&mut build, &families, stdlib_linked);
+        }
     }
-    link_cpp(&mut build);
-    build.warnings(false).compile("libdatasketches.a");
 
     config_binding_path();
+    config_version_info();
 }
 
-fn link_cpp(build: &mut Build) {
+// Trim the native archive for size. The wrapper only instantiates the
+// float and double sketches the bindings use, and the library is header-only,
+// so what is left to cut is per-function code and metadata. Sections are
+// split per function so the linker's --gc-sections, which rustc passes by
+// default, drops whatever the Rust side never calls.
+fn config_minimal(build: &mut Build) {
+    let tool = build.get_compiler();
+    if tool.is_like_msvc() {
+        build.flag("/O1").flag("/Gy").flag("/Gw").flag("/GR-");
+    } else {
+        build
+            .flag("-Os")
+            .flag("-ffunction-sections")
+            .flag("-fdata-sections")
+            .flag("-fno-rtti")
+            .flag("-fvisibility=hidden")
+            .flag("-fvisibility-inlines-hidden");
+    }
+}
+
+// cc already honors a wrapper in CXX ("ccache c++") and an sccache
+// RUSTC_WRAPPER. When neither is set, wrap the compiler in sccache or ccache
+// if one is installed; KLL_RS_NO_COMPILER_CACHE=1 opts out.
+fn config_compiler_cache() {
+    println!("cargo:rerun-if-env-changed=KLL_RS_NO_COMPILER_CACHE");
+    let target = env::var("TARGET").unwrap();
+    let configured = [
+        "CXX".to_owned(),
+        format!("CXX_{}", target),
+        format!("CXX_{}", target.replace('-', "_")),
+        "TARGET_CXX".to_owned(),
+        "RUSTC_WRAPPER".to_owned(),
+        "KLL_RS_NO_COMPILER_CACHE".to_owned(),
+    ];
+    if configured.iter().any(|var| env::var_os(var).is_some()) {
+        return;
+    }
+
+    let compiler = Build::new().cpp(true).get_compiler();
+    // ccache does not understand MSVC command lines.
+    let caches: &[&str] = if compiler.is_like_msvc() {
+        &["sccache"]
+    } else {
+        &["sccache", "ccache"]
+    };
+    let Some(cache) = caches.iter().find_map(|name| find_in_path(name)) else {
+        return;
+    };
+    let (cache, compiler) = (
+        cache.display().to_string(),
+        compiler.path().display().to_string(),
+    );
+    // cc splits CXX on whitespace, so paths with spaces cannot be wrapped.
+    if cache.contains(char::is_whitespace) || compiler.contains(char::is_whitespace) {
+        return;
+    }
+    env::set_var("CXX", format!("{} {}", cache, compiler));
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let file = format!("{}{}", name, env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+}
+
+// Compile the archive unless the one in OUT_DIR was built from the same
+// sources, headers and compiler command line. Cargo reruns this script for
+// changes that do not touch the native code, such as UPDATE_BIND, and the
+// C++ compile dominates a clean build.
+//
+// A skipped compile has to emit the link lines cc would have, which is only
+// known when link_cpp took care of the C++ standard library.
+fn compile_if_changed(build: &mut Build, families: &[&Family], stdlib_linked: bool) {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let archive = out_dir.join("libdatasketches.a");
+    let stamp = out_dir.join("libdatasketches.fingerprint");
+    let fingerprint = fingerprint(build, families);
+
+    let unchanged = stdlib_linked
+        && archive.is_file()
+        && fs::read_to_string(&stamp).is_ok_and(|previous| previous == fingerprint);
+    if unchanged {
+        println!("cargo:rustc-link-search=native={}", out_dir.display());
+        println!("cargo:rustc-link-lib=static=datasketches");
+        return;
+    }
+    build.compile("libdatasketches.a");
+    fs::write(&stamp, fingerprint).expect("unable to write the build fingerprint");
+}
+
+// Link the wrapper into a shared library in OUT_DIR for the `dynamic`
+// feature, which loads it at runtime, and record its path as the default.
+// The C++ standard library is linked into it rather than into the crate.
+fn build_shared(build: &mut Build) {
+    let tool = build.get_compiler();
+    if tool.is_like_msvc() {
+        // wrapper.h does not mark its functions dllexport.
+        panic!("the dynamic feature needs a GNU or Clang toolchain");
+    }
+    let (prefix, suffix) = match env::var("CARGO_CFG_TARGET_OS").unwrap().as_str() {
+        "macos" | "ios" => ("lib", "dylib"),
+        "windows" => ("", "dll"),
+        _ => ("lib", "so"),
+    };
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let library = out_dir.join(format!("{}datasketches.{}", prefix, suffix));
+
+    let objects = build.compile_intermediates();
+    let mut command = tool.to_command();
+    command
+        .arg("-shared")
+        .arg("-o")
+        .arg(&library)
+        .args(&objects);
+    if cfg!(feature = "static") && tool.is_like_gnu() {
+        command.arg("-static-libstdc++");
+    }
+    let status = command.status().expect("unable to run the linker");
+    if !status.success() {
+        panic!("linking {} failed: {}", library.display(), status);
+    }
+    println!("cargo:rustc-env=DATASKETCHES_DYLIB={}", library.display());
+}
+
+// Hash of everything the archive is built from.
+fn fingerprint(build: &Build, families: &[&Family]) -> String {
+    let mut hasher = DefaultHasher::new();
+    let tool = build.get_compiler();
+    tool.path().hash(&mut hasher);
+    tool.args().hash(&mut hasher);
+
+    let cpp_dir = datasketches_dir();
+    let mut inputs = vec![PathBuf::from("wrapper.h"), cpp_dir.join("common")];
+    for family in families {
+        inputs.push(cpp_dir.join(family.include_dir));
+        inputs.extend(family.sources.iter().map(PathBuf::from));
+    }
+    for input in inputs {
+        hash_tree(&input, &mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+fn hash_tree(path: &Path, hasher: &mut DefaultHasher) {
+    if path.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        for entry in entries {
+            hash_tree(&entry, hasher);
+        }
+    } else if let Ok(contents) = fs::read(path) {
+        path.hash(hasher);
+        contents.hash(hasher);
+    }
+}
+
+// Link the C++ standard library ourselves, statically when the `static`
+// feature is on. Returns false if it was left to cc.
+fn link_cpp(build: &mut Build) -> bool {
     let tool = build.get_compiler();
     let stdlib = if tool.is_like_gnu() {
         "libstdc++.a"
     } else if tool.is_like_clang() {
         "libc++.a"
     } else {
-        // Don't link to c++ statically on windows.
-        return;
+        // Don't link to c++ statically on windows; MSVC needs no flag.
+        return tool.is_like_msvc();
     };
 
     let output = tool
@@ -82,15 +381,15 @@ fn link_cpp(build: &mut Build) {
         .unwrap();
     if !output.status.success() || output.stdout.is_empty() {
         // fallback to dynamically
-        return;
+        return false;
     }
 
     let path = match str::from_utf8(&output.stdout) {
         Ok(path) => PathBuf::from(path),
-        Err(_) => return,
+        Err(_) => return false,
     };
     if !path.is_absolute() {
-        return;
+        return false;
     }
 
     // remove lib prefix and .a postfix.
@@ -106,23 +405,30 @@ fn link_cpp(build: &mut Build) {
         path.parent().unwrap().display()
     );
     build.cpp_link_stdlib(None);
+    true
 }
 
-fn build_datasketches() -> Build {
-    let cur_dir = env::current_dir().unwrap();
+fn build_datasketches(families: &[&Family]) -> Build {
+    let cpp_dir = datasketches_dir();
     let mut build = Build::new();
 
     // Include datasketches headers
-    build.include(
-        cur_dir
-            .join("datasketches-cpp")
-            .join("common")
-            .join("include"),
-    );
-    build.include(cur_dir.join("datasketches-cpp").join("kll").join("include"));
+    let common = cpp_dir.join("common").join("include");
+    println!("cargo:rerun-if-changed={}", common.display());
+    build.include(common);
+    for family in families {
+        let include = cpp_dir.join(family.include_dir).join("include");
+        println!("cargo:rerun-if-changed={}", include.display());
+        build.include(include);
+        build.define(&family_define(family), None);
+        for source in family.sources {
+            println!("cargo:rerun-if-changed={}", source);
+            build.file(source);
+        }
+    }
 
     // Add the main directory to include path as well (for relative includes)
-    build.include(cur_dir.join("datasketches-cpp"));
+    build.include(&cpp_dir);
 
     // DataSketches is header-only for the most part, but we need our wrapper
     // No need to compile kll.cpp as it's header-only implementation
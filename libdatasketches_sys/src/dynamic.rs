@@ -0,0 +1,76 @@
+//! Runtime loading of the wrapper, with the `dynamic` feature.
+//!
+//! The build compiles the wrapper into a shared library instead of linking it
+//! in. The first native call opens it from the path given to
+//! [`load_library`], or else from `KLL_RS_NATIVE_LIBRARY`, or else from where
+//! the build put it. Each function's symbol is resolved on its first call.
+
+use libloading::Library;
+use std::ffi::OsStr;
+use std::sync::OnceLock;
+
+pub use libloading::Error as LoadError;
+
+/// Environment variable naming the shared library to load.
+pub const LIBRARY_PATH_VAR: &str = "KLL_RS_NATIVE_LIBRARY";
+
+/// Path of the shared library built with this crate.
+pub const DEFAULT_LIBRARY_PATH: &str = match option_env!("DATASKETCHES_DYLIB") {
+    Some(path) => path,
+    None => "",
+};
+
+/// Symbol every build of the wrapper exports, checked when a library is
+/// loaded so a wrong path fails there rather than at the first call.
+const PROBE: &str = "kll_set_random_seed_checked";
+
+static LIBRARY: OnceLock<Library> = OnceLock::new();
+
+/// Opens the wrapper library at `path` for every later native call.
+///
+/// Only the first library loaded is used: once one is, whether by this
+/// function or by a native call, this returns `Ok(())` without opening
+/// `path`. Fails if the library cannot be opened or does not export the
+/// wrapper.
+pub fn load_library<P: AsRef<OsStr>>(path: P) -> Result<(), LoadError> {
+    if LIBRARY.get().is_some() {
+        return Ok(());
+    }
+    let library = open(path.as_ref())?;
+    // A concurrent load may have won; its library is used and ours dropped.
+    let _ = LIBRARY.set(library);
+    Ok(())
+}
+
+fn open(path: &OsStr) -> Result<Library, LoadError> {
+    // SAFETY: the wrapper's initializers only set up C++ statics.
+    let library = unsafe { Library::new(path)? };
+    // SAFETY: only the address is read; the symbol is not called.
+    unsafe { library.get::<*const ()>(PROBE.as_bytes())? };
+    Ok(library)
+}
+
+#[cfg(feature = "kll")]
+fn library() -> &'static Library {
+    LIBRARY.get_or_init(|| {
+        let path =
+            std::env::var_os(LIBRARY_PATH_VAR).unwrap_or_else(|| DEFAULT_LIBRARY_PATH.into());
+        open(&path).unwrap_or_else(|err| {
+            panic!(
+                "cannot load the datasketches wrapper from {:?} ({}); call load_library or set {}",
+                path, err, LIBRARY_PATH_VAR
+            )
+        })
+    })
+}
+
+/// Resolves `name` in the loaded library as a function pointer of type `T`.
+#[cfg(feature = "kll")]
+pub(crate) fn symbol<T: Copy>(name: &str) -> T {
+    // SAFETY: callers pass the C signature declared for `name` in wrapper.h.
+    unsafe {
+        *library()
+            .get::<T>(name.as_bytes())
+            .unwrap_or_else(|err| panic!("datasketches wrapper lacks {}: {}", name, err))
+    }
+}
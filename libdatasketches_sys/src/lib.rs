@@ -1,4 +1,8 @@
 //! Low-level FFI bindings for Apache DataSketches C++ library
+//!
+//! Each sketch family sits behind a cargo feature of the same name, which
+//! controls both the C++ sources compiled and the functions declared here.
+//! Only `kll` exists so far and is enabled by default.
 
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
@@ -7,92 +11,407 @@
 extern crate libc;
 
 pub use libc::size_t;
+use std::os::raw::c_char;
+#[cfg(feature = "kll")]
 use std::os::raw::c_void;
 
+#[cfg(feature = "dynamic")]
+mod dynamic;
+#[cfg(feature = "dynamic")]
+pub use dynamic::{load_library, LoadError, DEFAULT_LIBRARY_PATH, LIBRARY_PATH_VAR};
+
 // Include the generated bindings (if available)
 // include!(env!("BINDING_PATH"));
 
+/// Version of the vendored datasketches-cpp library, or `"unknown"`.
+pub const DATASKETCHES_CPP_VERSION: &str = env!("DATASKETCHES_CPP_VERSION");
+
+/// Commit of the vendored datasketches-cpp checkout, or `"unknown"`.
+pub const DATASKETCHES_CPP_COMMIT: &str = env!("DATASKETCHES_CPP_COMMIT");
+
+/// Major version of the datasketches-cpp API the wrapper was compiled against.
+pub const DATASKETCHES_CPP_API_MAJOR: u8 = 5;
+
+// The declarations below pass lengths as `size_t` and read them back as
+// `usize`, which only round-trips when the two have the same width. This holds
+// on every 32- and 64-bit target Rust supports; fail the build if it ever
+// does not.
+const _: () = assert!(std::mem::size_of::<size_t>() == std::mem::size_of::<usize>());
+
+/// Length of the message buffer of a [`SketchStatus`].
+pub const SKETCH_STATUS_MESSAGE_LEN: usize = 256;
+
+pub const SKETCH_STATUS_OK: i32 = 0;
+/// A sketch handle was null.
+pub const SKETCH_STATUS_NULL_POINTER: i32 = 1;
+/// An argument was rejected, including malformed serialized bytes.
+pub const SKETCH_STATUS_INVALID_ARGUMENT: i32 = 2;
+/// The operation is undefined in the sketch's state, e.g. min of an empty sketch.
+pub const SKETCH_STATUS_INVALID_STATE: i32 = 3;
+pub const SKETCH_STATUS_OUT_OF_MEMORY: i32 = 4;
+pub const SKETCH_STATUS_UNKNOWN: i32 = 5;
+
+/// Outcome of a `*_checked` call: one of the `SKETCH_STATUS_*` codes and, on
+/// failure, a NUL-terminated message.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SketchStatus {
+    pub code: i32,
+    pub message: [c_char; SKETCH_STATUS_MESSAGE_LEN],
+}
+
+impl SketchStatus {
+    /// Returns a status reporting success, to pass to a checked call.
+    pub const fn new() -> Self {
+        SketchStatus {
+            code: SKETCH_STATUS_OK,
+            message: [0; SKETCH_STATUS_MESSAGE_LEN],
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.code == SKETCH_STATUS_OK
+    }
+
+    /// Returns the message, replacing invalid UTF-8.
+    pub fn message(&self) -> String {
+        let bytes: Vec<u8> = self
+            .message
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Default for SketchStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for SketchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SketchStatus")
+            .field("code", &self.code)
+            .field("message", &self.message())
+            .finish()
+    }
+}
+
 // FFI-safe opaque types
+#[cfg(feature = "kll")]
 #[repr(C)]
 pub struct KllFloatSketch(c_void);
 
+#[cfg(feature = "kll")]
 #[repr(C)]
 pub struct KllDoubleSketch(c_void);
 
-// Re-export the generated functions with proper types
-unsafe extern "C" {
-    // KLL Float Sketch functions
-    pub fn kll_float_sketch_new() -> *mut c_void;
-    pub fn kll_float_sketch_new_with_k(k: u16) -> *mut c_void;
-    pub fn kll_float_sketch_copy(sketch: *mut c_void) -> *mut c_void;
-    pub fn kll_float_sketch_delete(sketch: *mut c_void);
-
-    pub fn kll_float_sketch_update(sketch: *mut c_void, value: f32);
-    pub fn kll_float_sketch_merge(sketch: *mut c_void, other: *mut c_void);
-
-    pub fn kll_float_sketch_is_empty(sketch: *mut c_void) -> bool;
-    pub fn kll_float_sketch_get_k(sketch: *mut c_void) -> u16;
-    pub fn kll_float_sketch_get_n(sketch: *mut c_void) -> u64;
-    pub fn kll_float_sketch_get_num_retained(sketch: *mut c_void) -> u32;
-    pub fn kll_float_sketch_is_estimation_mode(sketch: *mut c_void) -> bool;
-
-    pub fn kll_float_sketch_get_min_value(sketch: *mut c_void) -> f32;
-    pub fn kll_float_sketch_get_max_value(sketch: *mut c_void) -> f32;
-    pub fn kll_float_sketch_get_quantile(sketch: *mut c_void, fraction: f64) -> f32;
-    pub fn kll_float_sketch_get_rank(sketch: *mut c_void, value: f32) -> f64;
-
-    pub fn kll_float_sketch_serialize(sketch: *mut c_void, size: *mut size_t) -> *mut u8;
-    pub fn kll_float_sketch_deserialize(data: *const u8, size: size_t) -> *mut c_void;
-
-    pub fn kll_float_sketch_get_quantiles(
-        sketch: *mut c_void,
-        fractions: *const f64,
-        num_fractions: size_t,
-        results: *mut f32,
-    );
-    pub fn kll_float_sketch_get_quantiles_evenly_spaced(
-        sketch: *mut c_void,
-        num: u32,
-        results: *mut f32,
-    );
-
-    // KLL Double Sketch functions
-    pub fn kll_double_sketch_new() -> *mut c_void;
-    pub fn kll_double_sketch_new_with_k(k: u16) -> *mut c_void;
-    pub fn kll_double_sketch_copy(sketch: *mut c_void) -> *mut c_void;
-    pub fn kll_double_sketch_delete(sketch: *mut c_void);
-
-    pub fn kll_double_sketch_update(sketch: *mut c_void, value: f64);
-    pub fn kll_double_sketch_merge(sketch: *mut c_void, other: *mut c_void);
-
-    pub fn kll_double_sketch_is_empty(sketch: *mut c_void) -> bool;
-    pub fn kll_double_sketch_get_k(sketch: *mut c_void) -> u16;
-    pub fn kll_double_sketch_get_n(sketch: *mut c_void) -> u64;
-    pub fn kll_double_sketch_get_num_retained(sketch: *mut c_void) -> u32;
-    pub fn kll_double_sketch_is_estimation_mode(sketch: *mut c_void) -> bool;
-
-    pub fn kll_double_sketch_get_min_value(sketch: *mut c_void) -> f64;
-    pub fn kll_double_sketch_get_max_value(sketch: *mut c_void) -> f64;
-    pub fn kll_double_sketch_get_quantile(sketch: *mut c_void, fraction: f64) -> f64;
-    pub fn kll_double_sketch_get_rank(sketch: *mut c_void, value: f64) -> f64;
-
-    pub fn kll_double_sketch_serialize(sketch: *mut c_void, size: *mut size_t) -> *mut u8;
-    pub fn kll_double_sketch_deserialize(data: *const u8, size: size_t) -> *mut c_void;
-
-    pub fn kll_double_sketch_get_quantiles(
-        sketch: *mut c_void,
-        fractions: *const f64,
-        num_fractions: size_t,
-        results: *mut f64,
-    );
-    pub fn kll_double_sketch_get_quantiles_evenly_spaced(
-        sketch: *mut c_void,
-        num: u32,
-        results: *mut f64,
-    );
+/// Called after an update compacts a level, with the user data passed at
+/// registration, the compacted level and the number of items taken from it.
+#[cfg(feature = "kll")]
+pub type kll_compaction_callback_t =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, level: u8, items_compacted: u32)>;
+
+/// Declares native functions: linked `extern "C"` declarations, or with the
+/// `dynamic` feature, wrappers of the same signature that call through
+/// symbols resolved from the library [`load_library`] opened.
+#[cfg(all(feature = "kll", not(feature = "dynamic")))]
+macro_rules! native_functions {
+    ($(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        unsafe extern "C" {
+            $(pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+    };
+}
+
+#[cfg(all(feature = "kll", feature = "dynamic"))]
+macro_rules! native_functions {
+    ($(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {$(
+        /// # Safety
+        ///
+        /// Same contract as the C function of the same name.
+        pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+            type Symbol = unsafe extern "C" fn($($ty),*) $(-> $ret)?;
+            static SYMBOL: std::sync::OnceLock<Symbol> = std::sync::OnceLock::new();
+            let symbol = *SYMBOL.get_or_init(|| dynamic::symbol(stringify!($name)));
+            symbol($($arg),*)
+        }
+    )*};
 }
 
-#[cfg(test)]
+/// Declares the native functions of one sketch item type.
+///
+/// `$t` is the infix of the C names, e.g. `float` for
+/// `kll_float_sketch_update`, and `$item` the matching Rust type.
+#[cfg(feature = "kll")]
+macro_rules! declare_kll_sketch {
+    ($t:ident, $item:ident) => {
+        paste::paste! {
+            native_functions! {
+                pub fn [<kll_ $t _sketch_new>]() -> *mut c_void;
+                pub fn [<kll_ $t _sketch_new_with_k>](k: u16) -> *mut c_void;
+                pub fn [<kll_ $t _sketch_copy>](sketch: *mut c_void) -> *mut c_void;
+                pub fn [<kll_ $t _sketch_delete>](sketch: *mut c_void);
+
+                pub fn [<kll_ $t _sketch_update>](sketch: *mut c_void, value: $item);
+                pub fn [<kll_ $t _sketch_merge>](sketch: *mut c_void, other: *mut c_void);
+
+                pub fn [<kll_ $t _sketch_is_empty>](sketch: *mut c_void) -> bool;
+                pub fn [<kll_ $t _sketch_get_k>](sketch: *mut c_void) -> u16;
+                pub fn [<kll_ $t _sketch_get_n>](sketch: *mut c_void) -> u64;
+                pub fn [<kll_ $t _sketch_get_num_retained>](sketch: *mut c_void) -> u32;
+                pub fn [<kll_ $t _sketch_is_estimation_mode>](sketch: *mut c_void) -> bool;
+
+                pub fn [<kll_ $t _sketch_get_min_value>](sketch: *mut c_void) -> $item;
+                pub fn [<kll_ $t _sketch_get_max_value>](sketch: *mut c_void) -> $item;
+                pub fn [<kll_ $t _sketch_get_quantile>](sketch: *mut c_void, fraction: f64) -> $item;
+                pub fn [<kll_ $t _sketch_get_rank>](sketch: *mut c_void, value: $item) -> f64;
+
+                pub fn [<kll_ $t _sketch_serialize>](sketch: *mut c_void, size: *mut size_t) -> *mut u8;
+                pub fn [<kll_ $t _sketch_deserialize>](data: *const u8, size: size_t) -> *mut c_void;
+
+                pub fn [<kll_ $t _sketch_get_quantiles>](
+                    sketch: *mut c_void,
+                    fractions: *const f64,
+                    num_fractions: size_t,
+                    results: *mut $item,
+                );
+                pub fn [<kll_ $t _sketch_get_quantiles_evenly_spaced>](
+                    sketch: *mut c_void,
+                    num: u32,
+                    results: *mut $item,
+                );
+
+                pub fn [<kll_ $t _sketch_get_retained_items>](
+                    sketch: *mut c_void,
+                    items: *mut $item,
+                    weights: *mut u64,
+                    capacity: u32,
+                ) -> u32;
+
+                pub fn [<kll_ $t _sketch_get_normalized_rank_error>](sketch: *mut c_void, pmf: bool) -> f64;
+
+                pub fn [<kll_ $t _sketch_set_compaction_callback>](
+                    sketch: *mut c_void,
+                    callback: kll_compaction_callback_t,
+                    user_data: *mut c_void,
+                );
+
+                pub fn [<kll_ $t _sketch_update_tracked>](sketch: *mut c_void, value: $item) -> bool;
+                pub fn [<kll_ $t _sketch_merge_tracked>](sketch: *mut c_void, other: *mut c_void) -> u32;
+                pub fn [<kll_ $t _sketch_get_items_discarded>](sketch: *mut c_void) -> u64;
+                pub fn [<kll_ $t _sketch_get_level_capacities>](
+                    sketch: *mut c_void,
+                    capacities: *mut u32,
+                    capacity: u8,
+                ) -> u8;
+
+                pub fn [<kll_ $t _sketch_get_pmf>](
+                    sketch: *mut c_void,
+                    split_points: *const $item,
+                    num_split_points: u32,
+                    results: *mut f64,
+                );
+                pub fn [<kll_ $t _sketch_get_cdf>](
+                    sketch: *mut c_void,
+                    split_points: *const $item,
+                    num_split_points: u32,
+                    results: *mut f64,
+                );
+                pub fn [<kll_ $t _sketch_sorted_view_copy>](
+                    sketch: *mut c_void,
+                    items: *mut $item,
+                    cumulative_weights: *mut u64,
+                    capacity: u32,
+                ) -> u32;
+                pub fn [<kll_ $t _sketch_max_serialized_size>](k: u16, n: u64) -> size_t;
+
+                // The same functions, reporting failures in `status`.
+                pub fn [<kll_ $t _sketch_new_checked>](status: *mut SketchStatus) -> *mut c_void;
+                pub fn [<kll_ $t _sketch_new_with_k_checked>](
+                    k: u16,
+                    status: *mut SketchStatus,
+                ) -> *mut c_void;
+                pub fn [<kll_ $t _sketch_copy_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> *mut c_void;
+                pub fn [<kll_ $t _sketch_delete_checked>](sketch: *mut c_void, status: *mut SketchStatus);
+                pub fn [<kll_ $t _sketch_update_checked>](
+                    sketch: *mut c_void,
+                    value: $item,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_merge_checked>](
+                    sketch: *mut c_void,
+                    other: *mut c_void,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_is_empty_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> bool;
+                pub fn [<kll_ $t _sketch_get_k_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> u16;
+                pub fn [<kll_ $t _sketch_get_n_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> u64;
+                pub fn [<kll_ $t _sketch_get_num_retained_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> u32;
+                pub fn [<kll_ $t _sketch_is_estimation_mode_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> bool;
+                pub fn [<kll_ $t _sketch_get_min_value_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> $item;
+                pub fn [<kll_ $t _sketch_get_max_value_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> $item;
+                pub fn [<kll_ $t _sketch_get_quantile_checked>](
+                    sketch: *mut c_void,
+                    fraction: f64,
+                    status: *mut SketchStatus,
+                ) -> $item;
+                pub fn [<kll_ $t _sketch_get_rank_checked>](
+                    sketch: *mut c_void,
+                    value: $item,
+                    status: *mut SketchStatus,
+                ) -> f64;
+                pub fn [<kll_ $t _sketch_get_quantile_criteria_checked>](
+                    sketch: *mut c_void,
+                    fraction: f64,
+                    inclusive: bool,
+                    status: *mut SketchStatus,
+                ) -> $item;
+                pub fn [<kll_ $t _sketch_get_rank_criteria_checked>](
+                    sketch: *mut c_void,
+                    value: $item,
+                    inclusive: bool,
+                    status: *mut SketchStatus,
+                ) -> f64;
+                pub fn [<kll_ $t _sketch_serialize_checked>](
+                    sketch: *mut c_void,
+                    size: *mut size_t,
+                    status: *mut SketchStatus,
+                ) -> *mut u8;
+                pub fn [<kll_ $t _sketch_deserialize_checked>](
+                    data: *const u8,
+                    size: size_t,
+                    status: *mut SketchStatus,
+                ) -> *mut c_void;
+                pub fn [<kll_ $t _sketch_get_quantiles_checked>](
+                    sketch: *mut c_void,
+                    fractions: *const f64,
+                    num_fractions: size_t,
+                    results: *mut $item,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_get_quantiles_criteria_checked>](
+                    sketch: *mut c_void,
+                    fractions: *const f64,
+                    num_fractions: size_t,
+                    inclusive: bool,
+                    results: *mut $item,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_get_quantiles_evenly_spaced_checked>](
+                    sketch: *mut c_void,
+                    num: u32,
+                    results: *mut $item,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_get_retained_items_checked>](
+                    sketch: *mut c_void,
+                    items: *mut $item,
+                    weights: *mut u64,
+                    capacity: u32,
+                    status: *mut SketchStatus,
+                ) -> u32;
+                pub fn [<kll_ $t _sketch_get_normalized_rank_error_checked>](
+                    sketch: *mut c_void,
+                    pmf: bool,
+                    status: *mut SketchStatus,
+                ) -> f64;
+                pub fn [<kll_ $t _sketch_set_compaction_callback_checked>](
+                    sketch: *mut c_void,
+                    callback: kll_compaction_callback_t,
+                    user_data: *mut c_void,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_update_tracked_checked>](
+                    sketch: *mut c_void,
+                    value: $item,
+                    status: *mut SketchStatus,
+                ) -> bool;
+                pub fn [<kll_ $t _sketch_merge_tracked_checked>](
+                    sketch: *mut c_void,
+                    other: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> u32;
+                pub fn [<kll_ $t _sketch_get_items_discarded_checked>](
+                    sketch: *mut c_void,
+                    status: *mut SketchStatus,
+                ) -> u64;
+                pub fn [<kll_ $t _sketch_get_level_capacities_checked>](
+                    sketch: *mut c_void,
+                    capacities: *mut u32,
+                    capacity: u8,
+                    status: *mut SketchStatus,
+                ) -> u8;
+                pub fn [<kll_ $t _sketch_get_pmf_checked>](
+                    sketch: *mut c_void,
+                    split_points: *const $item,
+                    num_split_points: u32,
+                    results: *mut f64,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_get_cdf_checked>](
+                    sketch: *mut c_void,
+                    split_points: *const $item,
+                    num_split_points: u32,
+                    results: *mut f64,
+                    status: *mut SketchStatus,
+                );
+                pub fn [<kll_ $t _sketch_sorted_view_copy_checked>](
+                    sketch: *mut c_void,
+                    items: *mut $item,
+                    cumulative_weights: *mut u64,
+                    capacity: u32,
+                    status: *mut SketchStatus,
+                ) -> u32;
+                pub fn [<kll_ $t _sketch_max_serialized_size_checked>](
+                    k: u16,
+                    n: u64,
+                    status: *mut SketchStatus,
+                ) -> size_t;
+            }
+        }
+    };
+}
+
+#[cfg(feature = "kll")]
+native_functions! {
+    pub fn kll_set_random_seed(seed: u64);
+    pub fn kll_set_random_seed_checked(seed: u64, status: *mut SketchStatus);
+}
+
+#[cfg(feature = "kll")]
+declare_kll_sketch!(float, f32);
+#[cfg(feature = "kll")]
+declare_kll_sketch!(double, f64);
+
+#[cfg(all(test, feature = "kll"))]
 mod tests {
     use super::*;
 
@@ -115,4 +434,94 @@ mod tests {
             kll_double_sketch_delete(sketch);
         }
     }
+    #[test]
+    fn test_checked_functions_report_failures() {
+        unsafe {
+            let mut status = SketchStatus::new();
+            assert_eq!(
+                kll_double_sketch_get_n_checked(std::ptr::null_mut(), &mut status),
+                0
+            );
+            assert_eq!(status.code, SKETCH_STATUS_NULL_POINTER);
+
+            let garbage = [1u8, 2, 3];
+            let sketch = kll_double_sketch_deserialize_checked(garbage.as_ptr(), 3, &mut status);
+            assert!(sketch.is_null());
+            assert_eq!(status.code, SKETCH_STATUS_INVALID_ARGUMENT);
+            assert!(!status.message().is_empty());
+
+            let sketch = kll_float_sketch_new_with_k_checked(4, &mut status);
+            assert!(sketch.is_null());
+            assert_eq!(status.code, SKETCH_STATUS_INVALID_ARGUMENT);
+
+            let sketch = kll_float_sketch_new_with_k_checked(200, &mut status);
+            assert!(status.is_ok(), "{:?}", status);
+            kll_float_sketch_update_checked(sketch, 1.0, &mut status);
+            assert_eq!(kll_float_sketch_get_n_checked(sketch, &mut status), 1);
+            assert!(status.is_ok());
+            kll_float_sketch_delete_checked(sketch, &mut status);
+            assert!(status.is_ok());
+        }
+    }
+    #[test]
+    fn test_distribution_functions() {
+        unsafe {
+            let sketch = kll_double_sketch_new_with_k(200);
+            for i in 1..=10 {
+                kll_double_sketch_update(sketch, i as f64);
+            }
+            let split_points = [3.0, 7.0];
+            let mut pmf = [0.0; 3];
+            let mut cdf = [0.0; 3];
+            kll_double_sketch_get_pmf(sketch, split_points.as_ptr(), 2, pmf.as_mut_ptr());
+            kll_double_sketch_get_cdf(sketch, split_points.as_ptr(), 2, cdf.as_mut_ptr());
+            for (actual, expected) in pmf.iter().zip([0.3, 0.4, 0.3]) {
+                assert!((actual - expected).abs() < 1e-12);
+            }
+            assert_eq!(cdf, [0.3, 0.7, 1.0]);
+
+            let mut items = [0.0; 10];
+            let mut weights = [0u64; 10];
+            let count = kll_double_sketch_sorted_view_copy(
+                sketch,
+                items.as_mut_ptr(),
+                weights.as_mut_ptr(),
+                10,
+            );
+            assert_eq!(count, 10);
+            assert_eq!(items[0], 1.0);
+            assert_eq!(weights[9], 10);
+
+            let mut status = SketchStatus::new();
+            let unsorted = [7.0, 3.0];
+            kll_double_sketch_get_cdf_checked(
+                sketch,
+                unsorted.as_ptr(),
+                2,
+                cdf.as_mut_ptr(),
+                &mut status,
+            );
+            assert_eq!(status.code, SKETCH_STATUS_INVALID_ARGUMENT);
+            kll_double_sketch_delete(sketch);
+        }
+    }
+    #[test]
+    fn test_max_serialized_size() {
+        unsafe {
+            let sketch = kll_float_sketch_new_with_k(64);
+            for i in 0..10_000 {
+                kll_float_sketch_update(sketch, i as f32);
+            }
+            let mut size = 0;
+            let bytes = kll_float_sketch_serialize(sketch, &mut size);
+            assert!(size <= kll_float_sketch_max_serialized_size(64, 10_000));
+            assert!(
+                kll_float_sketch_max_serialized_size(64, 10_000)
+                    < kll_double_sketch_max_serialized_size(64, 10_000)
+            );
+            assert_eq!(kll_float_sketch_max_serialized_size(4, 10_000), 0);
+            libc::free(bytes as *mut c_void);
+            kll_float_sketch_delete(sketch);
+        }
+    }
 }
@@ -3,15 +3,676 @@
  */
 
 #include "wrapper.h"
-#include "datasketches-cpp/kll/include/kll_sketch.hpp"
+#include "kll_sketch.hpp"
+#include <algorithm>
+#include <atomic>
 #include <memory>
 #include <cstring>
+#include <mutex>
+#include <new>
+#include <stdexcept>
+#include <string>
+#include <unordered_map>
+#include <vector>
 
 using datasketches::kll_sketch;
 
-// KLL Float Sketch implementation
+// The datasketches-cpp calls whose names or defaults have changed between
+// major versions. The C functions below only go through these, so moving to
+// another major means adapting this namespace alone. Only the vendored 5.x
+// API is supported.
+namespace compat {
+
+template<typename T>
+T min_item(const kll_sketch<T>& sketch) {
+    return sketch.get_min_item();
+}
+
+template<typename T>
+T max_item(const kll_sketch<T>& sketch) {
+    return sketch.get_max_item();
+}
+
+// Quantiles and ranks use inclusive search criteria unless asked otherwise:
+// a rank counts the items <= a value, and a quantile is the smallest item
+// whose inclusive rank reaches the fraction.
+template<typename T>
+T quantile(const kll_sketch<T>& sketch, double rank, bool inclusive = true) {
+    return sketch.get_quantile(rank, inclusive);
+}
+
+template<typename T>
+double rank(const kll_sketch<T>& sketch, const T& item, bool inclusive = true) {
+    return sketch.get_rank(item, inclusive);
+}
+
+// CDF and PMF at the given split points, with inclusive ranks like rank().
+template<typename T>
+std::vector<double> cdf(const kll_sketch<T>& sketch, const T* split_points, uint32_t size) {
+    auto result = sketch.get_CDF(split_points, size, true);
+    return std::vector<double>(result.begin(), result.end());
+}
+
+template<typename T>
+std::vector<double> pmf(const kll_sketch<T>& sketch, const T* split_points, uint32_t size) {
+    auto result = sketch.get_PMF(split_points, size, true);
+    return std::vector<double>(result.begin(), result.end());
+}
+
+inline void override_seed(uint64_t seed) {
+    datasketches::random_utils::override_seed(seed);
+}
+
+// The generator compactions draw from. Each compaction draws one bit to pick
+// which half of the level survives, so the draws made count compactions.
+using compaction_generator_t = decltype(datasketches::random_utils::random_bit);
+
+inline compaction_generator_t& compaction_generator() {
+    return datasketches::random_utils::random_bit;
+}
+
+} // namespace compat
+
+// Compaction observers, keyed by sketch handle. kll_sketch has no hook for
+// compactions, so an observer keeps the number of retained items per level as
+// of the last compaction, and a compaction reports the levels that grew since.
+// Updates only look the observer up once they have compacted, which a drop in
+// retained items shows; unobserved sketches only pay for an atomic load then.
+namespace observers {
+
+struct observer {
+    kll_compaction_callback_t callback;
+    void* user_data;
+    // Retained items per level after the last compaction or merge. Updates
+    // in between only add to level 0.
+    std::vector<uint32_t> levels;
+};
+
+std::mutex mutex;
+std::unordered_map<const void*, observer> registry;
+std::atomic<size_t> registered{0};
+
+// Items at level L carry weight 2^L.
+template<typename T>
+std::vector<uint32_t> level_sizes(const kll_sketch<T>& sketch) {
+    std::vector<uint32_t> sizes;
+    for (auto pair : sketch) {
+        size_t level = 0;
+        for (uint64_t weight = pair.second; weight > 1; weight >>= 1) {
+            ++level;
+        }
+        if (level >= sizes.size()) {
+            sizes.resize(level + 1, 0);
+        }
+        ++sizes[level];
+    }
+    return sizes;
+}
+
+template<typename T>
+void set(const kll_sketch<T>* sketch, kll_compaction_callback_t callback, void* user_data) {
+    std::vector<uint32_t> levels;
+    if (callback) {
+        levels = level_sizes(*sketch);
+    }
+    std::lock_guard<std::mutex> lock(mutex);
+    if (callback) {
+        registry[sketch] = observer{callback, user_data, std::move(levels)};
+    } else {
+        registry.erase(sketch);
+    }
+    registered.store(registry.size(), std::memory_order_relaxed);
+}
+
+void remove(const void* sketch) {
+    if (registered.load(std::memory_order_relaxed) != 0) {
+        std::lock_guard<std::mutex> lock(mutex);
+        registry.erase(sketch);
+        registered.store(registry.size(), std::memory_order_relaxed);
+    }
+}
+
+// Stores the current level sizes of an observed sketch in its observer and
+// copies the observer, with the level sizes stored before, into `before`.
+// Returns false if the sketch is not observed.
+template<typename T>
+bool swap_levels(const kll_sketch<T>& sketch, observer& before) {
+    if (registered.load(std::memory_order_relaxed) == 0) {
+        return false;
+    }
+    auto levels = level_sizes(sketch);
+    std::lock_guard<std::mutex> lock(mutex);
+    auto it = registry.find(&sketch);
+    if (it == registry.end()) {
+        return false;
+    }
+    std::swap(it->second.levels, levels);
+    before = observer{it->second.callback, it->second.user_data, std::move(levels)};
+    return true;
+}
+
+// Reports a compaction of an observed sketch. A compaction of level L shows
+// up as growth of level L + 1 by half the items compacted. The callback runs
+// without the lock held, so it may replace or remove observers.
+template<typename T>
+void compacted(const kll_sketch<T>& sketch) {
+    observer before;
+    if (!swap_levels(sketch, before)) {
+        return;
+    }
+    const auto after = level_sizes(sketch);
+    for (size_t level = 1; level < after.size(); ++level) {
+        const uint32_t previous = level < before.levels.size() ? before.levels[level] : 0;
+        if (after[level] > previous) {
+            before.callback(before.user_data, static_cast<uint8_t>(level - 1),
+                            2 * (after[level] - previous));
+        }
+    }
+}
+
+// Re-reads the level sizes of an observed sketch after a merge, which
+// changes them without reporting compactions.
+template<typename T>
+void refresh(const kll_sketch<T>& sketch) {
+    observer before;
+    swap_levels(sketch, before);
+}
+
+} // namespace observers
+
+// Compaction bookkeeping exposed through the *_update_tracked and
+// statistics functions.
+namespace stats {
+
+// Minimum level width; datasketches-cpp always uses m = 8.
+constexpr uint8_t MIN_LEVEL_WIDTH = 8;
+
+// Updates the sketch and reports whether the update compacted a level. Each
+// update compacts at most one level, which shows up as a drop in retained
+// items and as growth of the level above the compacted one. A compaction
+// discards half of at least MIN_LEVEL_WIDTH items, so it always outweighs the
+// item added; an ignored update, such as NaN, leaves the count unchanged.
+template<typename T>
+bool update(kll_sketch<T>* sketch, T value) {
+    const uint32_t retained = sketch->get_num_retained();
+    sketch->update(value);
+    if (sketch->get_num_retained() >= retained) {
+        return false;
+    }
+    observers::compacted(*sketch);
+    return true;
+}
+
+// Merges `other` into the sketch, keeping its observer's level sizes current,
+// and returns the number of compactions the merge ran. A merge can compact
+// several levels, or compact and still retain more items than before, so the
+// compactions are counted from the random bits they drew.
+template<typename T>
+uint32_t merge(kll_sketch<T>* sketch, const kll_sketch<T>& other) {
+    compat::compaction_generator_t generator = compat::compaction_generator();
+    sketch->merge(other);
+    observers::refresh(*sketch);
+    uint32_t compactions = 0;
+    while (generator != compat::compaction_generator()) {
+        generator();
+        ++compactions;
+    }
+    return compactions;
+}
+
+// Every compaction keeps half of the items it takes and promotes them with
+// double weight, so the discarded items are exactly n minus the retained ones.
+template<typename T>
+uint64_t items_discarded(const kll_sketch<T>& sketch) {
+    return sketch.get_n() - sketch.get_num_retained();
+}
+
+template<typename T>
+uint8_t level_capacities(const kll_sketch<T>& sketch, uint32_t* capacities, uint8_t capacity) {
+    const uint8_t num_levels =
+        static_cast<uint8_t>(std::max<size_t>(observers::level_sizes(sketch).size(), 1));
+    for (uint8_t level = 0; level < num_levels && level < capacity; ++level) {
+        capacities[level] = datasketches::kll_helper::level_capacity(
+            sketch.get_k(), num_levels, level, MIN_LEVEL_WIDTH);
+    }
+    return num_levels;
+}
+
+// Upper bound on the serialized size after n updates, for any k accepted by
+// the constructor.
+template<typename T>
+size_t max_serialized_size(uint16_t k, uint64_t n) {
+    if (k < kll_sketch<T>::MIN_K) {
+        throw std::invalid_argument("K must be >= 8 and <= 65535");
+    }
+    return kll_sketch<T>::get_max_serialized_size_bytes(k, n);
+}
+
+} // namespace stats
+
+// Distribution queries exposed through the *_get_pmf, *_get_cdf and
+// *_sorted_view_copy functions.
+namespace distribution {
+
+// Copies up to `capacity` entries of the sorted view, ascending by item, with
+// inclusive cumulative weights, and returns the number of retained items.
+// Built from the retained items so it does not depend on the sorted view API,
+// which changed between datasketches-cpp versions.
+template<typename T>
+uint32_t sorted_view_copy(const kll_sketch<T>& sketch, T* items, uint64_t* cumulative_weights,
+                          uint32_t capacity) {
+    std::vector<std::pair<T, uint64_t>> entries;
+    entries.reserve(sketch.get_num_retained());
+    for (auto pair : sketch) {
+        entries.emplace_back(pair.first, pair.second);
+    }
+    std::stable_sort(entries.begin(), entries.end(),
+                     [](const std::pair<T, uint64_t>& a, const std::pair<T, uint64_t>& b) {
+                         return a.first < b.first;
+                     });
+    uint64_t cumulative = 0;
+    for (size_t i = 0; i < entries.size() && i < capacity; ++i) {
+        cumulative += entries[i].second;
+        items[i] = entries[i].first;
+        if (cumulative_weights) {
+            cumulative_weights[i] = cumulative;
+        }
+    }
+    return static_cast<uint32_t>(entries.size());
+}
+
+// Writes num_split_points + 1 values to results.
+inline void write(const std::vector<double>& values, double* results) {
+    std::copy(values.begin(), values.end(), results);
+}
+
+} // namespace distribution
+
+// Error reporting for the *_checked functions. Bodies run inside guard(),
+// which maps the exception they throw to a status code and message, and
+// returns the fallback the unchecked function would have returned.
+namespace checked {
+
+struct null_pointer : std::exception {
+    const char* what() const noexcept override {
+        return "sketch handle is null";
+    }
+};
+
+void set(sketch_status_t* status, int32_t code, const char* message) {
+    if (!status) {
+        return;
+    }
+    status->code = code;
+    std::strncpy(status->message, message, SKETCH_STATUS_MESSAGE_LEN - 1);
+    status->message[SKETCH_STATUS_MESSAGE_LEN - 1] = '\0';
+}
+
+template<typename R, typename F>
+R guard(sketch_status_t* status, R fallback, F body) {
+    try {
+        R result = body();
+        set(status, SKETCH_STATUS_OK, "");
+        return result;
+    } catch (const null_pointer& e) {
+        set(status, SKETCH_STATUS_NULL_POINTER, e.what());
+    } catch (const std::invalid_argument& e) {
+        set(status, SKETCH_STATUS_INVALID_ARGUMENT, e.what());
+    } catch (const std::out_of_range& e) {
+        set(status, SKETCH_STATUS_INVALID_ARGUMENT, e.what());
+    } catch (const std::bad_alloc& e) {
+        set(status, SKETCH_STATUS_OUT_OF_MEMORY, e.what());
+    } catch (const std::runtime_error& e) {
+        set(status, SKETCH_STATUS_INVALID_STATE, e.what());
+    } catch (const std::exception& e) {
+        set(status, SKETCH_STATUS_UNKNOWN, e.what());
+    } catch (...) {
+        set(status, SKETCH_STATUS_UNKNOWN, "unknown exception");
+    }
+    return fallback;
+}
+
+template<typename F>
+void guard_void(sketch_status_t* status, F body) {
+    guard(status, 0, [&] {
+        body();
+        return 0;
+    });
+}
+
+template<typename T>
+kll_sketch<T>* handle(void* sketch) {
+    if (!sketch) {
+        throw null_pointer();
+    }
+    return static_cast<kll_sketch<T>*>(sketch);
+}
+
+// Output and input buffers other than the sketch handle.
+template<typename P>
+P* buffer(P* pointer, const char* name) {
+    if (!pointer) {
+        throw std::invalid_argument(std::string(name) + " is null");
+    }
+    return pointer;
+}
+
+} // namespace checked
+
+// Defines the *_checked functions for one item type. Each does what its
+// unchecked counterpart does, except that a null handle or buffer is
+// reported instead of ignored.
+#define KLL_RS_CHECKED_FUNCTIONS(T, t)                                                           \
+kll_##t##_sketch_t kll_##t##_sketch_new_checked(sketch_status_t* status) {                       \
+    return checked::guard<void*>(status, nullptr, [&] {                                          \
+        return static_cast<void*>(new kll_sketch<T>());                                          \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+kll_##t##_sketch_t kll_##t##_sketch_new_with_k_checked(uint16_t k, sketch_status_t* status) {    \
+    return checked::guard<void*>(status, nullptr, [&] {                                          \
+        return static_cast<void*>(new kll_sketch<T>(k));                                         \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+kll_##t##_sketch_t kll_##t##_sketch_copy_checked(kll_##t##_sketch_t sketch,                      \
+                                                 sketch_status_t* status) {                      \
+    return checked::guard<void*>(status, nullptr, [&] {                                          \
+        return static_cast<void*>(new kll_sketch<T>(*checked::handle<T>(sketch)));               \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+/* Deleting a null handle is a no-op, as for `delete`. */                                        \
+void kll_##t##_sketch_delete_checked(kll_##t##_sketch_t sketch, sketch_status_t* status) {       \
+    checked::guard_void(status, [&] {                                                            \
+        if (sketch) {                                                                            \
+            observers::remove(sketch);                                                           \
+            delete static_cast<kll_sketch<T>*>(sketch);                                          \
+        }                                                                                        \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_update_checked(kll_##t##_sketch_t sketch, T value,                         \
+                                     sketch_status_t* status) {                                  \
+    checked::guard_void(status, [&] { stats::update(checked::handle<T>(sketch), value); });      \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_merge_checked(kll_##t##_sketch_t sketch, kll_##t##_sketch_t other,         \
+                                    sketch_status_t* status) {                                   \
+    checked::guard_void(status, [&] {                                                            \
+        stats::merge(checked::handle<T>(sketch), *checked::handle<T>(other));                    \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+bool kll_##t##_sketch_is_empty_checked(kll_##t##_sketch_t sketch, sketch_status_t* status) {     \
+    return checked::guard(status, true, [&] { return checked::handle<T>(sketch)->is_empty(); }); \
+}                                                                                                \
+                                                                                                 \
+uint16_t kll_##t##_sketch_get_k_checked(kll_##t##_sketch_t sketch, sketch_status_t* status) {    \
+    return checked::guard<uint16_t>(status, 0, [&] {                                             \
+        return checked::handle<T>(sketch)->get_k();                                              \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint64_t kll_##t##_sketch_get_n_checked(kll_##t##_sketch_t sketch, sketch_status_t* status) {    \
+    return checked::guard<uint64_t>(status, 0, [&] {                                             \
+        return checked::handle<T>(sketch)->get_n();                                              \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint32_t kll_##t##_sketch_get_num_retained_checked(kll_##t##_sketch_t sketch,                    \
+                                                   sketch_status_t* status) {                    \
+    return checked::guard<uint32_t>(status, 0, [&] {                                             \
+        return checked::handle<T>(sketch)->get_num_retained();                                   \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+bool kll_##t##_sketch_is_estimation_mode_checked(kll_##t##_sketch_t sketch,                      \
+                                                 sketch_status_t* status) {                      \
+    return checked::guard(status, false, [&] {                                                   \
+        return checked::handle<T>(sketch)->is_estimation_mode();                                 \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+T kll_##t##_sketch_get_min_value_checked(kll_##t##_sketch_t sketch, sketch_status_t* status) {   \
+    return checked::guard<T>(status, 0, [&] {                                                    \
+        return compat::min_item(*checked::handle<T>(sketch));                                    \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+T kll_##t##_sketch_get_max_value_checked(kll_##t##_sketch_t sketch, sketch_status_t* status) {   \
+    return checked::guard<T>(status, 0, [&] {                                                    \
+        return compat::max_item(*checked::handle<T>(sketch));                                    \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+T kll_##t##_sketch_get_quantile_checked(kll_##t##_sketch_t sketch, double fraction,              \
+                                        sketch_status_t* status) {                               \
+    return checked::guard<T>(status, 0, [&] {                                                    \
+        return compat::quantile(*checked::handle<T>(sketch), fraction);                          \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+double kll_##t##_sketch_get_rank_checked(kll_##t##_sketch_t sketch, T value,                     \
+                                         sketch_status_t* status) {                              \
+    return checked::guard(status, 0.0, [&] {                                                     \
+        return compat::rank(*checked::handle<T>(sketch), value);                                 \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+T kll_##t##_sketch_get_quantile_criteria_checked(kll_##t##_sketch_t sketch, double fraction,     \
+                                                 bool inclusive, sketch_status_t* status) {      \
+    return checked::guard<T>(status, 0, [&] {                                                    \
+        return compat::quantile(*checked::handle<T>(sketch), fraction, inclusive);               \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+double kll_##t##_sketch_get_rank_criteria_checked(kll_##t##_sketch_t sketch, T value,            \
+                                                  bool inclusive, sketch_status_t* status) {     \
+    return checked::guard(status, 0.0, [&] {                                                     \
+        return compat::rank(*checked::handle<T>(sketch), value, inclusive);                      \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint8_t* kll_##t##_sketch_serialize_checked(kll_##t##_sketch_t sketch, size_t* size,             \
+                                            sketch_status_t* status) {                           \
+    return checked::guard<uint8_t*>(status, nullptr, [&] {                                       \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        checked::buffer(size, "size");                                                           \
+        auto bytes = s.serialize();                                                              \
+        uint8_t* result = new uint8_t[bytes.size()];                                             \
+        std::memcpy(result, bytes.data(), bytes.size());                                         \
+        *size = bytes.size();                                                                    \
+        return result;                                                                           \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+kll_##t##_sketch_t kll_##t##_sketch_deserialize_checked(const uint8_t* data, size_t size,        \
+                                                        sketch_status_t* status) {               \
+    return checked::guard<void*>(status, nullptr, [&] {                                          \
+        auto sketch = kll_sketch<T>::deserialize(checked::buffer(data, "data"), size);           \
+        return static_cast<void*>(new kll_sketch<T>(std::move(sketch)));                         \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_get_quantiles_checked(kll_##t##_sketch_t sketch,                           \
+                                            const double* fractions, size_t num_fractions,       \
+                                            T* results, sketch_status_t* status) {               \
+    checked::guard_void(status, [&] {                                                            \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        if (num_fractions == 0) {                                                                \
+            return;                                                                              \
+        }                                                                                        \
+        checked::buffer(fractions, "fractions");                                                 \
+        checked::buffer(results, "results");                                                     \
+        for (size_t i = 0; i < num_fractions; ++i) {                                             \
+            results[i] = compat::quantile(s, fractions[i]);                                      \
+        }                                                                                        \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_get_quantiles_criteria_checked(kll_##t##_sketch_t sketch,                  \
+                                                     const double* fractions,                    \
+                                                     size_t num_fractions, bool inclusive,       \
+                                                     T* results, sketch_status_t* status) {      \
+    checked::guard_void(status, [&] {                                                            \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        if (num_fractions == 0) {                                                                \
+            return;                                                                              \
+        }                                                                                        \
+        checked::buffer(fractions, "fractions");                                                 \
+        checked::buffer(results, "results");                                                     \
+        for (size_t i = 0; i < num_fractions; ++i) {                                             \
+            results[i] = compat::quantile(s, fractions[i], inclusive);                           \
+        }                                                                                        \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_get_quantiles_evenly_spaced_checked(kll_##t##_sketch_t sketch,             \
+                                                          uint32_t num, T* results,              \
+                                                          sketch_status_t* status) {             \
+    checked::guard_void(status, [&] {                                                            \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        if (num == 0) {                                                                          \
+            return;                                                                              \
+        }                                                                                        \
+        checked::buffer(results, "results");                                                     \
+        for (uint32_t i = 0; i < num; ++i) {                                                     \
+            double fraction = num == 1 ? 0.0 : static_cast<double>(i) / (num - 1);               \
+            results[i] = compat::quantile(s, fraction);                                          \
+        }                                                                                        \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint32_t kll_##t##_sketch_get_retained_items_checked(kll_##t##_sketch_t sketch, T* items,        \
+                                                     uint64_t* weights, uint32_t capacity,       \
+                                                     sketch_status_t* status) {                  \
+    return checked::guard<uint32_t>(status, 0, [&] {                                             \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        checked::buffer(items, "items");                                                         \
+        uint32_t count = 0;                                                                      \
+        for (auto pair : s) {                                                                    \
+            if (count == capacity) {                                                             \
+                break;                                                                           \
+            }                                                                                    \
+            items[count] = pair.first;                                                           \
+            if (weights) {                                                                       \
+                weights[count] = pair.second;                                                    \
+            }                                                                                    \
+            ++count;                                                                             \
+        }                                                                                        \
+        return count;                                                                            \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+double kll_##t##_sketch_get_normalized_rank_error_checked(kll_##t##_sketch_t sketch,             \
+                                                          bool pmf,                              \
+                                                          sketch_status_t* status) {             \
+    return checked::guard(status, 0.0, [&] {                                                     \
+        return checked::handle<T>(sketch)->get_normalized_rank_error(pmf);                       \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_set_compaction_callback_checked(kll_##t##_sketch_t sketch,                 \
+                                                      kll_compaction_callback_t callback,        \
+                                                      void* user_data,                           \
+                                                      sketch_status_t* status) {                 \
+    checked::guard_void(status, [&] {                                                            \
+        observers::set(checked::handle<T>(sketch), callback, user_data);                         \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+bool kll_##t##_sketch_update_tracked_checked(kll_##t##_sketch_t sketch, T value,                 \
+                                             sketch_status_t* status) {                          \
+    return checked::guard(status, false, [&] {                                                   \
+        return stats::update(checked::handle<T>(sketch), value);                                 \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint32_t kll_##t##_sketch_merge_tracked_checked(kll_##t##_sketch_t sketch,                       \
+                                                kll_##t##_sketch_t other,                        \
+                                                sketch_status_t* status) {                       \
+    return checked::guard<uint32_t>(status, 0, [&] {                                             \
+        return stats::merge(checked::handle<T>(sketch), *checked::handle<T>(other));             \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint64_t kll_##t##_sketch_get_items_discarded_checked(kll_##t##_sketch_t sketch,                 \
+                                                      sketch_status_t* status) {                 \
+    return checked::guard<uint64_t>(status, 0, [&] {                                             \
+        return stats::items_discarded(*checked::handle<T>(sketch));                              \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint8_t kll_##t##_sketch_get_level_capacities_checked(kll_##t##_sketch_t sketch,                 \
+                                                      uint32_t* capacities, uint8_t capacity,    \
+                                                      sketch_status_t* status) {                 \
+    return checked::guard<uint8_t>(status, 0, [&] {                                              \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        return stats::level_capacities(s, checked::buffer(capacities, "capacities"),             \
+                                       capacity);                                                \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_get_pmf_checked(kll_##t##_sketch_t sketch, const T* split_points,          \
+                                      uint32_t num_split_points, double* results,                \
+                                      sketch_status_t* status) {                                 \
+    checked::guard_void(status, [&] {                                                            \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        checked::buffer(results, "results");                                                     \
+        if (num_split_points > 0) {                                                              \
+            checked::buffer(split_points, "split_points");                                       \
+        }                                                                                        \
+        distribution::write(compat::pmf(s, split_points, num_split_points), results);            \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+void kll_##t##_sketch_get_cdf_checked(kll_##t##_sketch_t sketch, const T* split_points,          \
+                                      uint32_t num_split_points, double* results,                \
+                                      sketch_status_t* status) {                                 \
+    checked::guard_void(status, [&] {                                                            \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        checked::buffer(results, "results");                                                     \
+        if (num_split_points > 0) {                                                              \
+            checked::buffer(split_points, "split_points");                                       \
+        }                                                                                        \
+        distribution::write(compat::cdf(s, split_points, num_split_points), results);            \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+uint32_t kll_##t##_sketch_sorted_view_copy_checked(kll_##t##_sketch_t sketch, T* items,          \
+                                                   uint64_t* cumulative_weights,                 \
+                                                   uint32_t capacity, sketch_status_t* status) { \
+    return checked::guard<uint32_t>(status, 0, [&] {                                             \
+        const kll_sketch<T>& s = *checked::handle<T>(sketch);                                    \
+        return distribution::sorted_view_copy(s, checked::buffer(items, "items"),                \
+                                              cumulative_weights, capacity);                     \
+    });                                                                                          \
+}                                                                                                \
+                                                                                                 \
+size_t kll_##t##_sketch_max_serialized_size_checked(uint16_t k, uint64_t n,                      \
+                                                   sketch_status_t* status) {                    \
+    return checked::guard<size_t>(status, 0, [&] {                                               \
+        return stats::max_serialized_size<T>(k, n);                                       \
+    });                                                                                          \
+}
+
+// No exception may unwind out of these functions into Rust. Each one either
+// catches internally or is a function-try-block returning the same fallback
+// as for a null handle.
 extern "C" {
 
+void kll_set_random_seed(uint64_t seed) try {
+    compat::override_seed(seed);
+} catch (...) {
+}
+
+void kll_set_random_seed_checked(uint64_t seed, sketch_status_t* status) {
+    checked::guard_void(status, [&] { compat::override_seed(seed); });
+}
+
+// KLL Float Sketch implementation
+
 kll_float_sketch_t kll_float_sketch_new(void) {
     try {
         return static_cast<void*>(new kll_sketch<float>());
@@ -42,87 +703,108 @@ kll_float_sketch_t kll_float_sketch_copy(kll_float_sketch_t sketch) {
     }
 }
 
-void kll_float_sketch_delete(kll_float_sketch_t sketch) {
+void kll_float_sketch_delete(kll_float_sketch_t sketch) try {
     if (sketch) {
+        observers::remove(sketch);
         delete static_cast<kll_sketch<float>*>(sketch);
     }
+} catch (...) {
 }
 
-void kll_float_sketch_update(kll_float_sketch_t sketch, float value) {
+void kll_float_sketch_update(kll_float_sketch_t sketch, float value) try {
     if (sketch) {
-        static_cast<kll_sketch<float>*>(sketch)->update(value);
+        stats::update(static_cast<kll_sketch<float>*>(sketch), value);
     }
+} catch (...) {
 }
 
-void kll_float_sketch_merge(kll_float_sketch_t sketch, kll_float_sketch_t other) {
+void kll_float_sketch_merge(kll_float_sketch_t sketch, kll_float_sketch_t other) try {
     if (sketch && other) {
-        static_cast<kll_sketch<float>*>(sketch)->merge(
-            *static_cast<const kll_sketch<float>*>(other)
-        );
+        stats::merge(static_cast<kll_sketch<float>*>(sketch),
+                     *static_cast<const kll_sketch<float>*>(other));
     }
+} catch (...) {
 }
 
-bool kll_float_sketch_is_empty(kll_float_sketch_t sketch) {
+bool kll_float_sketch_is_empty(kll_float_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<float>*>(sketch)->is_empty();
     }
     return true;
+} catch (...) {
+    return true;
 }
 
-uint16_t kll_float_sketch_get_k(kll_float_sketch_t sketch) {
+uint16_t kll_float_sketch_get_k(kll_float_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<float>*>(sketch)->get_k();
     }
     return 0;
+} catch (...) {
+    return 0;
 }
 
-uint64_t kll_float_sketch_get_n(kll_float_sketch_t sketch) {
+uint64_t kll_float_sketch_get_n(kll_float_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<float>*>(sketch)->get_n();
     }
     return 0;
+} catch (...) {
+    return 0;
 }
 
-uint32_t kll_float_sketch_get_num_retained(kll_float_sketch_t sketch) {
+uint32_t kll_float_sketch_get_num_retained(kll_float_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<float>*>(sketch)->get_num_retained();
     }
     return 0;
+} catch (...) {
+    return 0;
 }
 
-bool kll_float_sketch_is_estimation_mode(kll_float_sketch_t sketch) {
+bool kll_float_sketch_is_estimation_mode(kll_float_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<float>*>(sketch)->is_estimation_mode();
     }
     return false;
+} catch (...) {
+    return false;
 }
 
-float kll_float_sketch_get_min_value(kll_float_sketch_t sketch) {
+float kll_float_sketch_get_min_value(kll_float_sketch_t sketch) try {
     if (sketch) {
-        return static_cast<const kll_sketch<float>*>(sketch)->get_min_item();
+        return compat::min_item(*static_cast<const kll_sketch<float>*>(sketch));
     }
     return 0.0f;
+} catch (...) {
+    return 0.0f;
 }
 
-float kll_float_sketch_get_max_value(kll_float_sketch_t sketch) {
+float kll_float_sketch_get_max_value(kll_float_sketch_t sketch) try {
     if (sketch) {
-        return static_cast<const kll_sketch<float>*>(sketch)->get_max_item();
+        return compat::max_item(*static_cast<const kll_sketch<float>*>(sketch));
     }
     return 0.0f;
+} catch (...) {
+    return 0.0f;
 }
 
-float kll_float_sketch_get_quantile(kll_float_sketch_t sketch, double fraction) {
+float kll_float_sketch_get_quantile(kll_float_sketch_t sketch, double fraction) try {
     if (sketch) {
-        return static_cast<const kll_sketch<float>*>(sketch)->get_quantile(fraction);
+        return compat::quantile(*static_cast<const kll_sketch<float>*>(sketch), fraction);
     }
     return 0.0f;
+} catch (...) {
+    return 0.0f;
 }
 
-double kll_float_sketch_get_rank(kll_float_sketch_t sketch, float value) {
+double kll_float_sketch_get_rank(kll_float_sketch_t sketch, float value) try {
     if (sketch) {
-        return static_cast<const kll_sketch<float>*>(sketch)->get_rank(value);
+        return compat::rank(*static_cast<const kll_sketch<float>*>(sketch), value);
     }
     return 0.0;
+} catch (...) {
+    return 0.0;
 }
 
 uint8_t* kll_float_sketch_serialize(kll_float_sketch_t sketch, size_t* size) {
@@ -163,7 +845,7 @@ void kll_float_sketch_get_quantiles(kll_float_sketch_t sketch,
     
     try {
         for (size_t i = 0; i < num_fractions; ++i) {
-            results[i] = static_cast<const kll_sketch<float>*>(sketch)->get_quantile(fractions[i]);
+            results[i] = compat::quantile(*static_cast<const kll_sketch<float>*>(sketch), fractions[i]);
         }
     } catch (...) {
         // Handle error appropriately
@@ -179,13 +861,129 @@ void kll_float_sketch_get_quantiles_evenly_spaced(kll_float_sketch_t sketch,
     try {
         for (uint32_t i = 0; i < num; ++i) {
             double fraction = static_cast<double>(i) / (num - 1);
-            results[i] = static_cast<const kll_sketch<float>*>(sketch)->get_quantile(fraction);
+            results[i] = compat::quantile(*static_cast<const kll_sketch<float>*>(sketch), fraction);
         }
     } catch (...) {
         // Handle error appropriately
     }
 }
 
+uint32_t kll_float_sketch_get_retained_items(kll_float_sketch_t sketch, float* items,
+                                             uint64_t* weights, uint32_t capacity) try {
+    if (!sketch || !items) {
+        return 0;
+    }
+
+    uint32_t count = 0;
+    for (auto pair : *static_cast<const kll_sketch<float>*>(sketch)) {
+        if (count == capacity) {
+            break;
+        }
+        items[count] = pair.first;
+        if (weights) {
+            weights[count] = pair.second;
+        }
+        ++count;
+    }
+    return count;
+} catch (...) {
+    return 0;
+}
+
+double kll_float_sketch_get_normalized_rank_error(kll_float_sketch_t sketch, bool pmf) try {
+    if (sketch) {
+        return static_cast<const kll_sketch<float>*>(sketch)->get_normalized_rank_error(pmf);
+    }
+    return 0.0;
+} catch (...) {
+    return 0.0;
+}
+
+void kll_float_sketch_set_compaction_callback(kll_float_sketch_t sketch,
+                                        kll_compaction_callback_t callback,
+                                        void* user_data) try {
+    if (sketch) {
+        observers::set(static_cast<const kll_sketch<float>*>(sketch), callback, user_data);
+    }
+} catch (...) {
+}
+
+bool kll_float_sketch_update_tracked(kll_float_sketch_t sketch, float value) try {
+    if (sketch) {
+        return stats::update(static_cast<kll_sketch<float>*>(sketch), value);
+    }
+    return false;
+} catch (...) {
+    return false;
+}
+
+uint32_t kll_float_sketch_merge_tracked(kll_float_sketch_t sketch, kll_float_sketch_t other) try {
+    if (sketch && other) {
+        return stats::merge(static_cast<kll_sketch<float>*>(sketch),
+                            *static_cast<const kll_sketch<float>*>(other));
+    }
+    return 0;
+} catch (...) {
+    return 0;
+}
+
+uint64_t kll_float_sketch_get_items_discarded(kll_float_sketch_t sketch) try {
+    if (sketch) {
+        return stats::items_discarded(*static_cast<const kll_sketch<float>*>(sketch));
+    }
+    return 0;
+} catch (...) {
+    return 0;
+}
+
+uint8_t kll_float_sketch_get_level_capacities(kll_float_sketch_t sketch, uint32_t* capacities,
+                                        uint8_t capacity) try {
+    if (!sketch || !capacities) {
+        return 0;
+    }
+    return stats::level_capacities(*static_cast<const kll_sketch<float>*>(sketch), capacities,
+                                   capacity);
+} catch (...) {
+    return 0;
+}
+
+void kll_float_sketch_get_pmf(kll_float_sketch_t sketch, const float* split_points,
+                           uint32_t num_split_points, double* results) try {
+    if (sketch && results && (split_points || num_split_points == 0)) {
+        distribution::write(
+            compat::pmf(*static_cast<const kll_sketch<float>*>(sketch), split_points, num_split_points),
+            results);
+    }
+} catch (...) {
+}
+
+void kll_float_sketch_get_cdf(kll_float_sketch_t sketch, const float* split_points,
+                           uint32_t num_split_points, double* results) try {
+    if (sketch && results && (split_points || num_split_points == 0)) {
+        distribution::write(
+            compat::cdf(*static_cast<const kll_sketch<float>*>(sketch), split_points, num_split_points),
+            results);
+    }
+} catch (...) {
+}
+
+uint32_t kll_float_sketch_sorted_view_copy(kll_float_sketch_t sketch, float* items,
+                                        uint64_t* cumulative_weights, uint32_t capacity) try {
+    if (!sketch || !items) {
+        return 0;
+    }
+    return distribution::sorted_view_copy(*static_cast<const kll_sketch<float>*>(sketch), items,
+                                          cumulative_weights, capacity);
+} catch (...) {
+    return 0;
+}
+
+size_t kll_float_sketch_max_serialized_size(uint16_t k, uint64_t n) try {
+    return stats::max_serialized_size<float>(k, n);
+} catch (...) {
+    return 0;
+}
+
 // KLL Double Sketch implementation (similar to float sketch)
 kll_double_sketch_t kll_double_sketch_new(void) {
     try {
@@ -217,87 +1015,108 @@ kll_double_sketch_t kll_double_sketch_copy(kll_double_sketch_t sketch) {
     }
 }
 
-void kll_double_sketch_delete(kll_double_sketch_t sketch) {
+void kll_double_sketch_delete(kll_double_sketch_t sketch) try {
     if (sketch) {
+        observers::remove(sketch);
         delete static_cast<kll_sketch<double>*>(sketch);
     }
+} catch (...) {
 }
 
-void kll_double_sketch_update(kll_double_sketch_t sketch, double value) {
+void kll_double_sketch_update(kll_double_sketch_t sketch, double value) try {
     if (sketch) {
-        static_cast<kll_sketch<double>*>(sketch)->update(value);
+        stats::update(static_cast<kll_sketch<double>*>(sketch), value);
     }
+} catch (...) {
 }
 
-void kll_double_sketch_merge(kll_double_sketch_t sketch, kll_double_sketch_t other) {
+void kll_double_sketch_merge(kll_double_sketch_t sketch, kll_double_sketch_t other) try {
     if (sketch && other) {
-        static_cast<kll_sketch<double>*>(sketch)->merge(
-            *static_cast<const kll_sketch<double>*>(other)
-        );
+        stats::merge(static_cast<kll_sketch<double>*>(sketch),
+                     *static_cast<const kll_sketch<double>*>(other));
     }
+} catch (...) {
 }
 
-bool kll_double_sketch_is_empty(kll_double_sketch_t sketch) {
+bool kll_double_sketch_is_empty(kll_double_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<double>*>(sketch)->is_empty();
     }
     return true;
+} catch (...) {
+    return true;
 }
 
-uint16_t kll_double_sketch_get_k(kll_double_sketch_t sketch) {
+uint16_t kll_double_sketch_get_k(kll_double_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<double>*>(sketch)->get_k();
     }
     return 0;
+} catch (...) {
+    return 0;
 }
 
-uint64_t kll_double_sketch_get_n(kll_double_sketch_t sketch) {
+uint64_t kll_double_sketch_get_n(kll_double_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<double>*>(sketch)->get_n();
     }
     return 0;
+} catch (...) {
+    return 0;
 }
 
-uint32_t kll_double_sketch_get_num_retained(kll_double_sketch_t sketch) {
+uint32_t kll_double_sketch_get_num_retained(kll_double_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<double>*>(sketch)->get_num_retained();
     }
     return 0;
+} catch (...) {
+    return 0;
 }
 
-bool kll_double_sketch_is_estimation_mode(kll_double_sketch_t sketch) {
+bool kll_double_sketch_is_estimation_mode(kll_double_sketch_t sketch) try {
     if (sketch) {
         return static_cast<const kll_sketch<double>*>(sketch)->is_estimation_mode();
     }
     return false;
+} catch (...) {
+    return false;
 }
 
-double kll_double_sketch_get_min_value(kll_double_sketch_t sketch) {
+double kll_double_sketch_get_min_value(kll_double_sketch_t sketch) try {
     if (sketch) {
-        return static_cast<const kll_sketch<double>*>(sketch)->get_min_item();
+        return compat::min_item(*static_cast<const kll_sketch<double>*>(sketch));
     }
     return 0.0;
+} catch (...) {
+    return 0.0;
 }
 
-double kll_double_sketch_get_max_value(kll_double_sketch_t sketch) {
+double kll_double_sketch_get_max_value(kll_double_sketch_t sketch) try {
     if (sketch) {
-        return static_cast<const kll_sketch<double>*>(sketch)->get_max_item();
+        return compat::max_item(*static_cast<const kll_sketch<double>*>(sketch));
     }
     return 0.0;
+} catch (...) {
+    return 0.0;
 }
 
-double kll_double_sketch_get_quantile(kll_double_sketch_t sketch, double fraction) {
+double kll_double_sketch_get_quantile(kll_double_sketch_t sketch, double fraction) try {
     if (sketch) {
-        return static_cast<const kll_sketch<double>*>(sketch)->get_quantile(fraction);
+        return compat::quantile(*static_cast<const kll_sketch<double>*>(sketch), fraction);
     }
     return 0.0;
+} catch (...) {
+    return 0.0;
 }
 
-double kll_double_sketch_get_rank(kll_double_sketch_t sketch, double value) {
+double kll_double_sketch_get_rank(kll_double_sketch_t sketch, double value) try {
     if (sketch) {
-        return static_cast<const kll_sketch<double>*>(sketch)->get_rank(value);
+        return compat::rank(*static_cast<const kll_sketch<double>*>(sketch), value);
     }
     return 0.0;
+} catch (...) {
+    return 0.0;
 }
 
 uint8_t* kll_double_sketch_serialize(kll_double_sketch_t sketch, size_t* size) {
@@ -338,7 +1157,7 @@ void kll_double_sketch_get_quantiles(kll_double_sketch_t sketch,
     
     try {
         for (size_t i = 0; i < num_fractions; ++i) {
-            results[i] = static_cast<const kll_sketch<double>*>(sketch)->get_quantile(fractions[i]);
+            results[i] = compat::quantile(*static_cast<const kll_sketch<double>*>(sketch), fractions[i]);
         }
     } catch (...) {
         // Handle error appropriately
@@ -354,11 +1173,130 @@ void kll_double_sketch_get_quantiles_evenly_spaced(kll_double_sketch_t sketch,
     try {
         for (uint32_t i = 0; i < num; ++i) {
             double fraction = static_cast<double>(i) / (num - 1);
-            results[i] = static_cast<const kll_sketch<double>*>(sketch)->get_quantile(fraction);
+            results[i] = compat::quantile(*static_cast<const kll_sketch<double>*>(sketch), fraction);
         }
     } catch (...) {
         // Handle error appropriately
     }
 }
 
+uint32_t kll_double_sketch_get_retained_items(kll_double_sketch_t sketch, double* items,
+                                              uint64_t* weights, uint32_t capacity) try {
+    if (!sketch || !items) {
+        return 0;
+    }
+
+    uint32_t count = 0;
+    for (auto pair : *static_cast<const kll_sketch<double>*>(sketch)) {
+        if (count == capacity) {
+            break;
+        }
+        items[count] = pair.first;
+        if (weights) {
+            weights[count] = pair.second;
+        }
+        ++count;
+    }
+    return count;
+} catch (...) {
+    return 0;
+}
+
+double kll_double_sketch_get_normalized_rank_error(kll_double_sketch_t sketch, bool pmf) try {
+    if (sketch) {
+        return static_cast<const kll_sketch<double>*>(sketch)->get_normalized_rank_error(pmf);
+    }
+    return 0.0;
+} catch (...) {
+    return 0.0;
+}
+
+void kll_double_sketch_set_compaction_callback(kll_double_sketch_t sketch,
+                                         kll_compaction_callback_t callback,
+                                         void* user_data) try {
+    if (sketch) {
+        observers::set(static_cast<const kll_sketch<double>*>(sketch), callback, user_data);
+    }
+} catch (...) {
+}
+
+bool kll_double_sketch_update_tracked(kll_double_sketch_t sketch, double value) try {
+    if (sketch) {
+        return stats::update(static_cast<kll_sketch<double>*>(sketch), value);
+    }
+    return false;
+} catch (...) {
+    return false;
+}
+
+uint32_t kll_double_sketch_merge_tracked(kll_double_sketch_t sketch, kll_double_sketch_t other) try {
+    if (sketch && other) {
+        return stats::merge(static_cast<kll_sketch<double>*>(sketch),
+                            *static_cast<const kll_sketch<double>*>(other));
+    }
+    return 0;
+} catch (...) {
+    return 0;
+}
+
+uint64_t kll_double_sketch_get_items_discarded(kll_double_sketch_t sketch) try {
+    if (sketch) {
+        return stats::items_discarded(*static_cast<const kll_sketch<double>*>(sketch));
+    }
+    return 0;
+} catch (...) {
+    return 0;
+}
+
+uint8_t kll_double_sketch_get_level_capacities(kll_double_sketch_t sketch, uint32_t* capacities,
+                                         uint8_t capacity) try {
+    if (!sketch || !capacities) {
+        return 0;
+    }
+    return stats::level_capacities(*static_cast<const kll_sketch<double>*>(sketch), capacities,
+                                   capacity);
+} catch (...) {
+    return 0;
+}
+
+void kll_double_sketch_get_pmf(kll_double_sketch_t sketch, const double* split_points,
+                           uint32_t num_split_points, double* results) try {
+    if (sketch && results && (split_points || num_split_points == 0)) {
+        distribution::write(
+            compat::pmf(*static_cast<const kll_sketch<double>*>(sketch), split_points, num_split_points),
+            results);
+    }
+} catch (...) {
+}
+
+void kll_double_sketch_get_cdf(kll_double_sketch_t sketch, const double* split_points,
+                           uint32_t num_split_points, double* results) try {
+    if (sketch && results && (split_points || num_split_points == 0)) {
+        distribution::write(
+            compat::cdf(*static_cast<const kll_sketch<double>*>(sketch), split_points, num_split_points),
+            results);
+    }
+} catch (...) {
+}
+
+uint32_t kll_double_sketch_sorted_view_copy(kll_double_sketch_t sketch, double* items,
+                                        uint64_t* cumulative_weights, uint32_t capacity) try {
+    if (!sketch || !items) {
+        return 0;
+    }
+    return distribution::sorted_view_copy(*static_cast<const kll_sketch<double>*>(sketch), items,
+                                          cumulative_weights, capacity);
+} catch (...) {
+    return 0;
+}
+
+size_t kll_double_sketch_max_serialized_size(uint16_t k, uint64_t n) try {
+    return stats::max_serialized_size<double>(k, n);
+} catch (...) {
+    return 0;
+}
+
+KLL_RS_CHECKED_FUNCTIONS(float, float)
+KLL_RS_CHECKED_FUNCTIONS(double, double)
+
 } // extern "C"
@@ -14,10 +14,45 @@
 extern "C" {
 #endif
 
+// Outcome of a *_checked call. On failure `message` holds a NUL-terminated
+// description, truncated to fit.
+#define SKETCH_STATUS_MESSAGE_LEN 256
+
+typedef enum {
+    SKETCH_STATUS_OK = 0,
+    // A sketch handle was null.
+    SKETCH_STATUS_NULL_POINTER = 1,
+    // An argument was rejected, including malformed serialized bytes.
+    SKETCH_STATUS_INVALID_ARGUMENT = 2,
+    // The operation is undefined in the sketch's state, e.g. min of an empty sketch.
+    SKETCH_STATUS_INVALID_STATE = 3,
+    SKETCH_STATUS_OUT_OF_MEMORY = 4,
+    SKETCH_STATUS_UNKNOWN = 5
+} sketch_status_code_t;
+
+typedef struct {
+    int32_t code;
+    char message[SKETCH_STATUS_MESSAGE_LEN];
+} sketch_status_t;
+
+// Each sketch family is declared only when the build enables it through its
+// KLL_RS_FAMILY_* define.
+#ifdef KLL_RS_FAMILY_KLL
+
 // Opaque handles for C++ objects
 typedef void* kll_float_sketch_t;
 typedef void* kll_double_sketch_t;
 
+// Called after an update compacts a level. items_compacted is the number of
+// items taken from that level; half of them are promoted to the next level.
+typedef void (*kll_compaction_callback_t)(void* user_data, uint8_t level,
+                                          uint32_t items_compacted);
+
+// Reseeds the random generator used for compactions on the calling thread.
+// Sketches built afterwards on that thread from the same input are identical.
+void kll_set_random_seed(uint64_t seed);
+void kll_set_random_seed_checked(uint64_t seed, sketch_status_t* status);
+
 // KLL Float Sketch functions
 kll_float_sketch_t kll_float_sketch_new(void);
 kll_float_sketch_t kll_float_sketch_new_with_k(uint16_t k);
@@ -49,6 +84,114 @@ void kll_float_sketch_get_quantiles(kll_float_sketch_t sketch,
 void kll_float_sketch_get_quantiles_evenly_spaced(kll_float_sketch_t sketch, 
                                                   uint32_t num, float* results);
 
+// Retained items and their weights
+uint32_t kll_float_sketch_get_retained_items(kll_float_sketch_t sketch, float* items,
+                                             uint64_t* weights, uint32_t capacity);
+
+// Accuracy
+double kll_float_sketch_get_normalized_rank_error(kll_float_sketch_t sketch, bool pmf);
+
+// Registers a compaction observer for updates on this sketch. Passing a null
+// callback removes it. Observers are not carried over by copy.
+void kll_float_sketch_set_compaction_callback(kll_float_sketch_t sketch,
+                                        kll_compaction_callback_t callback,
+                                        void* user_data);
+
+// Same as update, returning true if the update compacted a level.
+bool kll_float_sketch_update_tracked(kll_float_sketch_t sketch, float value);
+// Same as merge, returning the number of compactions the merge ran.
+uint32_t kll_float_sketch_merge_tracked(kll_float_sketch_t sketch, kll_float_sketch_t other);
+// Number of items dropped by compactions over the sketch's lifetime.
+uint64_t kll_float_sketch_get_items_discarded(kll_float_sketch_t sketch);
+// Writes up to `capacity` level capacities, lowest level first, and returns
+// the number of levels.
+uint8_t kll_float_sketch_get_level_capacities(kll_float_sketch_t sketch, uint32_t* capacities,
+                                        uint8_t capacity);
+
+// PMF and CDF at `num_split_points` unique, increasing split points, with
+// inclusive ranks. Both write num_split_points + 1 values to `results`; the
+// last CDF value is always 1.
+void kll_float_sketch_get_pmf(kll_float_sketch_t sketch, const float* split_points,
+                           uint32_t num_split_points, double* results);
+void kll_float_sketch_get_cdf(kll_float_sketch_t sketch, const float* split_points,
+                           uint32_t num_split_points, double* results);
+// Writes up to `capacity` retained items in ascending order, with the
+// inclusive cumulative weight at each (if `cumulative_weights` is non-null),
+// and returns the number of retained items.
+uint32_t kll_float_sketch_sorted_view_copy(kll_float_sketch_t sketch, float* items,
+                                        uint64_t* cumulative_weights, uint32_t capacity);
+// Upper bound on the serialized size of a sketch with parameter k after n
+// updates. Returns 0 if k is out of range.
+size_t kll_float_sketch_max_serialized_size(uint16_t k, uint64_t n);
+
+// KLL Float Sketch checked variants: the same operations, reporting failures in
+// `status` instead of returning a fallback value.
+kll_float_sketch_t kll_float_sketch_new_checked(sketch_status_t* status);
+kll_float_sketch_t kll_float_sketch_new_with_k_checked(uint16_t k, sketch_status_t* status);
+kll_float_sketch_t kll_float_sketch_copy_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+void kll_float_sketch_delete_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+void kll_float_sketch_update_checked(kll_float_sketch_t sketch, float value,
+                                     sketch_status_t* status);
+void kll_float_sketch_merge_checked(kll_float_sketch_t sketch, kll_float_sketch_t other,
+                                    sketch_status_t* status);
+bool kll_float_sketch_is_empty_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+uint16_t kll_float_sketch_get_k_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+uint64_t kll_float_sketch_get_n_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+uint32_t kll_float_sketch_get_num_retained_checked(kll_float_sketch_t sketch,
+                                                   sketch_status_t* status);
+bool kll_float_sketch_is_estimation_mode_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+float kll_float_sketch_get_min_value_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+float kll_float_sketch_get_max_value_checked(kll_float_sketch_t sketch, sketch_status_t* status);
+float kll_float_sketch_get_quantile_checked(kll_float_sketch_t sketch, double fraction,
+                                            sketch_status_t* status);
+double kll_float_sketch_get_rank_checked(kll_float_sketch_t sketch, float value,
+                                         sketch_status_t* status);
+// Like the two above, with exclusive search criteria if `inclusive` is false.
+float kll_float_sketch_get_quantile_criteria_checked(kll_float_sketch_t sketch, double fraction,
+                                                     bool inclusive, sketch_status_t* status);
+double kll_float_sketch_get_rank_criteria_checked(kll_float_sketch_t sketch, float value,
+                                                  bool inclusive, sketch_status_t* status);
+uint8_t* kll_float_sketch_serialize_checked(kll_float_sketch_t sketch, size_t* size,
+                                            sketch_status_t* status);
+kll_float_sketch_t kll_float_sketch_deserialize_checked(const uint8_t* data, size_t size,
+                                                        sketch_status_t* status);
+void kll_float_sketch_get_quantiles_checked(kll_float_sketch_t sketch, const double* fractions,
+                                            size_t num_fractions, float* results,
+                                            sketch_status_t* status);
+void kll_float_sketch_get_quantiles_criteria_checked(kll_float_sketch_t sketch,
+                                                     const double* fractions, size_t num_fractions,
+                                                     bool inclusive, float* results,
+                                                     sketch_status_t* status);
+void kll_float_sketch_get_quantiles_evenly_spaced_checked(kll_float_sketch_t sketch, uint32_t num,
+                                                          float* results, sketch_status_t* status);
+uint32_t kll_float_sketch_get_retained_items_checked(kll_float_sketch_t sketch, float* items,
+                                                     uint64_t* weights, uint32_t capacity,
+                                                     sketch_status_t* status);
+double kll_float_sketch_get_normalized_rank_error_checked(kll_float_sketch_t sketch, bool pmf,
+                                                          sketch_status_t* status);
+void kll_float_sketch_set_compaction_callback_checked(kll_float_sketch_t sketch,
+                                                      kll_compaction_callback_t callback,
+                                                      void* user_data, sketch_status_t* status);
+bool kll_float_sketch_update_tracked_checked(kll_float_sketch_t sketch, float value,
+                                             sketch_status_t* status);
+uint32_t kll_float_sketch_merge_tracked_checked(kll_float_sketch_t sketch,
+                                                kll_float_sketch_t other, sketch_status_t* status);
+uint64_t kll_float_sketch_get_items_discarded_checked(kll_float_sketch_t sketch,
+                                                      sketch_status_t* status);
+uint8_t kll_float_sketch_get_level_capacities_checked(kll_float_sketch_t sketch,
+                                                      uint32_t* capacities, uint8_t capacity,
+                                                      sketch_status_t* status);
+void kll_float_sketch_get_pmf_checked(kll_float_sketch_t sketch, const float* split_points,
+                                      uint32_t num_split_points, double* results,
+                                      sketch_status_t* status);
+void kll_float_sketch_get_cdf_checked(kll_float_sketch_t sketch, const float* split_points,
+                                      uint32_t num_split_points, double* results,
+                                      sketch_status_t* status);
+uint32_t kll_float_sketch_sorted_view_copy_checked(kll_float_sketch_t sketch, float* items,
+                                                   uint64_t* cumulative_weights, uint32_t capacity,
+                                                   sketch_status_t* status);
+size_t kll_float_sketch_max_serialized_size_checked(uint16_t k, uint64_t n, sketch_status_t* status);
+
 // KLL Double Sketch functions  
 kll_double_sketch_t kll_double_sketch_new(void);
 kll_double_sketch_t kll_double_sketch_new_with_k(uint16_t k);
@@ -80,6 +223,118 @@ void kll_double_sketch_get_quantiles(kll_double_sketch_t sketch,
 void kll_double_sketch_get_quantiles_evenly_spaced(kll_double_sketch_t sketch, 
                                                    uint32_t num, double* results);
 
+// Retained items and their weights
+uint32_t kll_double_sketch_get_retained_items(kll_double_sketch_t sketch, double* items,
+                                              uint64_t* weights, uint32_t capacity);
+
+// Accuracy
+double kll_double_sketch_get_normalized_rank_error(kll_double_sketch_t sketch, bool pmf);
+
+// Registers a compaction observer for updates on this sketch. Passing a null
+// callback removes it. Observers are not carried over by copy.
+void kll_double_sketch_set_compaction_callback(kll_double_sketch_t sketch,
+                                         kll_compaction_callback_t callback,
+                                         void* user_data);
+
+// Same as update, returning true if the update compacted a level.
+bool kll_double_sketch_update_tracked(kll_double_sketch_t sketch, double value);
+// Same as merge, returning the number of compactions the merge ran.
+uint32_t kll_double_sketch_merge_tracked(kll_double_sketch_t sketch, kll_double_sketch_t other);
+// Number of items dropped by compactions over the sketch's lifetime.
+uint64_t kll_double_sketch_get_items_discarded(kll_double_sketch_t sketch);
+// Writes up to `capacity` level capacities, lowest level first, and returns
+// the number of levels.
+uint8_t kll_double_sketch_get_level_capacities(kll_double_sketch_t sketch, uint32_t* capacities,
+                                         uint8_t capacity);
+
+// PMF and CDF at `num_split_points` unique, increasing split points, with
+// inclusive ranks. Both write num_split_points + 1 values to `results`; the
+// last CDF value is always 1.
+void kll_double_sketch_get_pmf(kll_double_sketch_t sketch, const double* split_points,
+                           uint32_t num_split_points, double* results);
+void kll_double_sketch_get_cdf(kll_double_sketch_t sketch, const double* split_points,
+                           uint32_t num_split_points, double* results);
+// Writes up to `capacity` retained items in ascending order, with the
+// inclusive cumulative weight at each (if `cumulative_weights` is non-null),
+// and returns the number of retained items.
+uint32_t kll_double_sketch_sorted_view_copy(kll_double_sketch_t sketch, double* items,
+                                        uint64_t* cumulative_weights, uint32_t capacity);
+// Upper bound on the serialized size of a sketch with parameter k after n
+// updates. Returns 0 if k is out of range.
+size_t kll_double_sketch_max_serialized_size(uint16_t k, uint64_t n);
+
+// KLL Double Sketch checked variants: the same operations, reporting failures in
+// `status` instead of returning a fallback value.
+kll_double_sketch_t kll_double_sketch_new_checked(sketch_status_t* status);
+kll_double_sketch_t kll_double_sketch_new_with_k_checked(uint16_t k, sketch_status_t* status);
+kll_double_sketch_t kll_double_sketch_copy_checked(kll_double_sketch_t sketch,
+                                                   sketch_status_t* status);
+void kll_double_sketch_delete_checked(kll_double_sketch_t sketch, sketch_status_t* status);
+void kll_double_sketch_update_checked(kll_double_sketch_t sketch, double value,
+                                      sketch_status_t* status);
+void kll_double_sketch_merge_checked(kll_double_sketch_t sketch, kll_double_sketch_t other,
+                                     sketch_status_t* status);
+bool kll_double_sketch_is_empty_checked(kll_double_sketch_t sketch, sketch_status_t* status);
+uint16_t kll_double_sketch_get_k_checked(kll_double_sketch_t sketch, sketch_status_t* status);
+uint64_t kll_double_sketch_get_n_checked(kll_double_sketch_t sketch, sketch_status_t* status);
+uint32_t kll_double_sketch_get_num_retained_checked(kll_double_sketch_t sketch,
+                                                    sketch_status_t* status);
+bool kll_double_sketch_is_estimation_mode_checked(kll_double_sketch_t sketch,
+                                                  sketch_status_t* status);
+double kll_double_sketch_get_min_value_checked(kll_double_sketch_t sketch, sketch_status_t* status);
+double kll_double_sketch_get_max_value_checked(kll_double_sketch_t sketch, sketch_status_t* status);
+double kll_double_sketch_get_quantile_checked(kll_double_sketch_t sketch, double fraction,
+                                              sketch_status_t* status);
+double kll_double_sketch_get_rank_checked(kll_double_sketch_t sketch, double value,
+                                          sketch_status_t* status);
+// Like the two above, with exclusive search criteria if `inclusive` is false.
+double kll_double_sketch_get_quantile_criteria_checked(kll_double_sketch_t sketch, double fraction,
+                                                       bool inclusive, sketch_status_t* status);
+double kll_double_sketch_get_rank_criteria_checked(kll_double_sketch_t sketch, double value,
+                                                   bool inclusive, sketch_status_t* status);
+uint8_t* kll_double_sketch_serialize_checked(kll_double_sketch_t sketch, size_t* size,
+                                             sketch_status_t* status);
+kll_double_sketch_t kll_double_sketch_deserialize_checked(const uint8_t* data, size_t size,
+                                                          sketch_status_t* status);
+void kll_double_sketch_get_quantiles_checked(kll_double_sketch_t sketch, const double* fractions,
+                                             size_t num_fractions, double* results,
+                                             sketch_status_t* status);
+void kll_double_sketch_get_quantiles_criteria_checked(kll_double_sketch_t sketch,
+                                                      const double* fractions, size_t num_fractions,
+                                                      bool inclusive, double* results,
+                                                      sketch_status_t* status);
+void kll_double_sketch_get_quantiles_evenly_spaced_checked(kll_double_sketch_t sketch, uint32_t num,
+                                                           double* results, sketch_status_t* status);
+uint32_t kll_double_sketch_get_retained_items_checked(kll_double_sketch_t sketch, double* items,
+                                                      uint64_t* weights, uint32_t capacity,
+                                                      sketch_status_t* status);
+double kll_double_sketch_get_normalized_rank_error_checked(kll_double_sketch_t sketch, bool pmf,
+                                                           sketch_status_t* status);
+void kll_double_sketch_set_compaction_callback_checked(kll_double_sketch_t sketch,
+                                                       kll_compaction_callback_t callback,
+                                                       void* user_data, sketch_status_t* status);
+bool kll_double_sketch_update_tracked_checked(kll_double_sketch_t sketch, double value,
+                                              sketch_status_t* status);
+uint32_t kll_double_sketch_merge_tracked_checked(kll_double_sketch_t sketch,
+                                                 kll_double_sketch_t other, sketch_status_t* status);
+uint64_t kll_double_sketch_get_items_discarded_checked(kll_double_sketch_t sketch,
+                                                       sketch_status_t* status);
+uint8_t kll_double_sketch_get_level_capacities_checked(kll_double_sketch_t sketch,
+                                                       uint32_t* capacities, uint8_t capacity,
+                                                       sketch_status_t* status);
+void kll_double_sketch_get_pmf_checked(kll_double_sketch_t sketch, const double* split_points,
+                                       uint32_t num_split_points, double* results,
+                                       sketch_status_t* status);
+void kll_double_sketch_get_cdf_checked(kll_double_sketch_t sketch, const double* split_points,
+                                       uint32_t num_split_points, double* results,
+                                       sketch_status_t* status);
+uint32_t kll_double_sketch_sorted_view_copy_checked(kll_double_sketch_t sketch, double* items,
+                                                    uint64_t* cumulative_weights, uint32_t capacity,
+                                                    sketch_status_t* status);
+size_t kll_double_sketch_max_serialized_size_checked(uint16_t k, uint64_t n, sketch_status_t* status);
+
+#endif // KLL_RS_FAMILY_KLL
+
 #ifdef __cplusplus
 }
 #endif
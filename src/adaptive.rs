@@ -0,0 +1,229 @@
+//! A sketch whose k grows with the number of values it has seen.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::traits::SketchLike;
+use crate::KllDoubleSketch;
+
+/// The initial k of [`AdaptiveSketch::new`].
+const DEFAULT_INITIAL_K: u16 = 32;
+
+/// The growth steps of [`AdaptiveSketch::new`]: from 2,000 values on the
+/// sketch uses k = 64, and so on up to the default k from 200,000.
+const DEFAULT_STEPS: [(u64, u16); 3] = [(2_000, 64), (20_000, 128), (200_000, 200)];
+
+/// A sketch that starts small and moves to a larger k as values arrive.
+///
+/// A growth step `(n, k)` takes effect once the sketch has seen `n` values:
+/// the current sketch is merged into a new one with the larger k, and later
+/// values are compacted at that k. Rarely updated keys therefore stay at the
+/// footprint of the initial k while busy keys reach the accuracy of the last
+/// step.
+///
+/// Values compacted before a step keep the rank error of the k they were
+/// compacted at, as an absolute number of ranks, which shrinks relative to
+/// `n` as later values arrive. The underlying sketch keeps reporting the
+/// error bound of the smallest k it was built from, as every merge across
+/// k does.
+///
+/// ```
+/// use kll_rs::AdaptiveSketch;
+///
+/// let mut sketch = AdaptiveSketch::new();
+/// assert_eq!(sketch.get_k(), 32);
+/// for i in 0..50_000 {
+///     sketch.update(i as f64);
+/// }
+/// assert_eq!(sketch.get_k(), 128);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AdaptiveSketch {
+    sketch: KllDoubleSketch,
+    /// Growth steps not taken yet, by ascending threshold.
+    steps: Vec<(u64, KParam)>,
+    growths: u32,
+}
+
+impl AdaptiveSketch {
+    /// Creates a sketch starting at k = 32 and growing to the default k of
+    /// 200 by 200,000 values.
+    pub fn new() -> Self {
+        let k = |k: u16| KParam::new(k).expect("default schedule k is valid");
+        let steps: Vec<_> = DEFAULT_STEPS
+            .iter()
+            .map(|&(n, step)| (n, k(step)))
+            .collect();
+        Self::with_schedule(k(DEFAULT_INITIAL_K), &steps).expect("default schedule is valid")
+    }
+
+    /// Creates a sketch starting at k = `initial` and taking each `(n, k)`
+    /// growth step once it has seen `n` values.
+    ///
+    /// Fails unless both the thresholds and the k values of the steps
+    /// increase, starting above `initial`.
+    pub fn with_schedule(initial: KParam, steps: &[(u64, KParam)]) -> Result<Self> {
+        let mut previous = (0, initial);
+        for &(n, k) in steps {
+            if n <= previous.0 || k <= previous.1 {
+                return Err(DataSketchesError::InvalidParameter(format!(
+                    "growth step to k={} at n={} does not follow k={} at n={}",
+                    k, n, previous.1, previous.0
+                )));
+            }
+            previous = (n, k);
+        }
+        Ok(AdaptiveSketch {
+            sketch: KllDoubleSketch::with_k(initial),
+            steps: steps.to_vec(),
+            growths: 0,
+        })
+    }
+
+    /// Updates the sketch with a new value, growing k if a step is due.
+    pub fn update(&mut self, value: f64) {
+        self.sketch.update(value);
+        self.grow();
+    }
+
+    /// Merges another adaptive sketch into this one, growing k if the
+    /// combined count reaches a step.
+    pub fn merge(&mut self, other: &AdaptiveSketch) -> Result<()> {
+        self.sketch.merge_mixed_k(&other.sketch)?;
+        self.grow();
+        Ok(())
+    }
+
+    /// Returns the current k.
+    pub fn get_k(&self) -> u16 {
+        self.sketch.get_k()
+    }
+
+    /// Returns how many growth steps have been taken.
+    pub fn growths(&self) -> u32 {
+        self.growths
+    }
+
+    /// Returns the number of values processed.
+    pub fn get_n(&self) -> u64 {
+        self.sketch.get_n()
+    }
+
+    /// Returns true if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+
+    /// Returns the approximate quantile, or NaN if the sketch is empty.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        self.sketch.get_quantile(fraction)
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        self.sketch.get_quantiles(fractions)
+    }
+
+    /// Returns the approximate rank of a value.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.sketch.get_rank(value)
+    }
+
+    /// Returns the sketch at its current k.
+    pub fn sketch(&self) -> &KllDoubleSketch {
+        &self.sketch
+    }
+
+    /// Returns the sketch at its current k, ending growth.
+    pub fn into_sketch(self) -> KllDoubleSketch {
+        self.sketch
+    }
+
+    /// Takes every step whose threshold has been reached, in one rebuild.
+    fn grow(&mut self) {
+        if self.steps.is_empty() {
+            return;
+        }
+        let n = self.sketch.get_n();
+        let due = self.steps.iter().take_while(|&&(at, _)| n >= at).count();
+        if due == 0 {
+            return;
+        }
+        let (_, k) = self.steps[due - 1];
+        self.steps.drain(..due);
+        let mut grown = KllDoubleSketch::with_k(k);
+        grown
+            .merge_mixed_k(&self.sketch)
+            .expect("merging into an empty sketch succeeds");
+        self.sketch = grown;
+        self.growths += 1;
+    }
+}
+
+impl Default for AdaptiveSketch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SketchLike for AdaptiveSketch {
+    fn update(&mut self, value: f64) {
+        AdaptiveSketch::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        AdaptiveSketch::get_n(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        AdaptiveSketch::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        AdaptiveSketch::get_quantiles(self, fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        AdaptiveSketch::get_rank(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn k(k: u16) -> KParam {
+        KParam::new(k).unwrap()
+    }
+
+    #[test]
+    fn test_schedule_validation() {
+        assert!(AdaptiveSketch::with_schedule(k(64), &[(100, k(32))]).is_err());
+        assert!(AdaptiveSketch::with_schedule(k(32), &[(100, k(64)), (100, k(128))]).is_err());
+        assert!(AdaptiveSketch::with_schedule(k(32), &[(0, k(64))]).is_err());
+    }
+
+    #[test]
+    fn test_grows_at_thresholds() {
+        let mut sketch =
+            AdaptiveSketch::with_schedule(k(16), &[(100, k(32)), (1000, k(200))]).unwrap();
+        for i in 0..99 {
+            sketch.update(i as f64);
+        }
+        assert_eq!((sketch.get_k(), sketch.growths()), (16, 0));
+        sketch.update(99.0);
+        assert_eq!((sketch.get_k(), sketch.growths()), (32, 1));
+
+        let mut other = AdaptiveSketch::with_schedule(k(16), &[]).unwrap();
+        for i in 100..5000 {
+            other.update(i as f64);
+        }
+        sketch.merge(&other).unwrap();
+        assert_eq!((sketch.get_k(), sketch.growths()), (200, 2));
+        assert_eq!(sketch.get_n(), 5000);
+        let median = sketch.get_quantile(0.5);
+        assert!((median - 2500.0).abs() < 5000.0 * 0.2, "{}", median);
+
+        let cold = AdaptiveSketch::new();
+        assert_eq!(cold.get_k(), DEFAULT_INITIAL_K);
+    }
+}
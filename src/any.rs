@@ -0,0 +1,292 @@
+//! A sketch of either item type behind one value.
+
+use crate::error::{DataSketchesError, Result};
+use crate::format::infer_item_size;
+use crate::{KllDoubleSketch, KllFloatSketch};
+use serde::{Deserialize, Serialize};
+
+/// Item type of a sketch, as recorded in tagged bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum SketchKind {
+    /// `KllFloatSketch`.
+    Float = 1,
+    /// `KllDoubleSketch`.
+    Double = 2,
+}
+
+impl SketchKind {
+    pub(crate) fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            1 => Ok(SketchKind::Float),
+            2 => Ok(SketchKind::Double),
+            other => Err(DataSketchesError::DeserializationError(format!(
+                "unknown sketch type tag {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Either sketch type, for storage that holds both.
+///
+/// Values are passed and returned as `f64`; float sketches convert on the
+/// way in and out. Serde output is tagged with the item type, and
+/// [`to_tagged_bytes`](AnySketch::to_tagged_bytes) prefixes the DataSketches
+/// bytes with a one-byte [`SketchKind`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "sketch", rename_all = "snake_case")]
+pub enum AnySketch {
+    /// A sketch of `f32` values.
+    Float(KllFloatSketch),
+    /// A sketch of `f64` values.
+    Double(KllDoubleSketch),
+}
+
+impl AnySketch {
+    /// Returns the item type of the wrapped sketch.
+    pub fn kind(&self) -> SketchKind {
+        match self {
+            AnySketch::Float(_) => SketchKind::Float,
+            AnySketch::Double(_) => SketchKind::Double,
+        }
+    }
+
+    /// Updates the sketch with a new value.
+    pub fn update(&mut self, value: f64) {
+        match self {
+            AnySketch::Float(sketch) => sketch.update(value as f32),
+            AnySketch::Double(sketch) => sketch.update(value),
+        }
+    }
+
+    /// Merges another sketch of the same item type into this one.
+    ///
+    /// Fails with [`DataSketchesError::IncompatibleMerge`] if the item types
+    /// differ, and otherwise as the typed sketch's `merge` does.
+    pub fn merge(&mut self, other: &AnySketch) -> Result<()> {
+        match (self, other) {
+            (AnySketch::Float(sketch), AnySketch::Float(other)) => sketch.merge(other),
+            (AnySketch::Double(sketch), AnySketch::Double(other)) => sketch.merge(other),
+            (sketch, other) => Err(DataSketchesError::IncompatibleMerge(format!(
+                "cannot merge a {:?} sketch into a {:?} sketch: the item types differ; \
+                 rebuild one side with the other's item type first",
+                other.kind(),
+                sketch.kind()
+            ))),
+        }
+    }
+
+    /// Returns true if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            AnySketch::Float(sketch) => sketch.is_empty(),
+            AnySketch::Double(sketch) => sketch.is_empty(),
+        }
+    }
+
+    /// Returns the k parameter.
+    pub fn get_k(&self) -> u16 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_k(),
+            AnySketch::Double(sketch) => sketch.get_k(),
+        }
+    }
+
+    /// Returns the number of values processed.
+    pub fn get_n(&self) -> u64 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_n(),
+            AnySketch::Double(sketch) => sketch.get_n(),
+        }
+    }
+
+    /// Returns the number of retained items.
+    pub fn get_num_retained(&self) -> u32 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_num_retained(),
+            AnySketch::Double(sketch) => sketch.get_num_retained(),
+        }
+    }
+
+    /// Returns true if the sketch is in estimation mode.
+    pub fn is_estimation_mode(&self) -> bool {
+        match self {
+            AnySketch::Float(sketch) => sketch.is_estimation_mode(),
+            AnySketch::Double(sketch) => sketch.is_estimation_mode(),
+        }
+    }
+
+    /// Returns the minimum value, or NaN if empty.
+    pub fn get_min_value(&self) -> f64 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_min_value() as f64,
+            AnySketch::Double(sketch) => sketch.get_min_value(),
+        }
+    }
+
+    /// Returns the maximum value, or NaN if empty.
+    pub fn get_max_value(&self) -> f64 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_max_value() as f64,
+            AnySketch::Double(sketch) => sketch.get_max_value(),
+        }
+    }
+
+    /// Returns the approximate quantile for a given fraction.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_quantile(fraction) as f64,
+            AnySketch::Double(sketch) => sketch.get_quantile(fraction),
+        }
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        match self {
+            AnySketch::Float(sketch) => sketch
+                .get_quantiles(fractions)
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+            AnySketch::Double(sketch) => sketch.get_quantiles(fractions),
+        }
+    }
+
+    /// Returns the fraction of values less than or equal to `value`.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        match self {
+            AnySketch::Float(sketch) => sketch.get_rank(value as f32),
+            AnySketch::Double(sketch) => sketch.get_rank(value),
+        }
+    }
+
+    /// Serializes the wrapped sketch in the DataSketches format, without a tag.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        match self {
+            AnySketch::Float(sketch) => sketch.serialize(),
+            AnySketch::Double(sketch) => sketch.serialize(),
+        }
+    }
+
+    /// Serializes the sketch prefixed with its one-byte [`SketchKind`].
+    pub fn to_tagged_bytes(&self) -> Result<Vec<u8>> {
+        let payload = self.serialize()?;
+        let mut bytes = Vec::with_capacity(payload.len() + 1);
+        bytes.push(self.kind() as u8);
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    /// Deserializes bytes written by [`to_tagged_bytes`](AnySketch::to_tagged_bytes).
+    pub fn from_tagged_bytes(bytes: &[u8]) -> Result<Self> {
+        let (&tag, payload) = bytes.split_first().ok_or_else(|| {
+            DataSketchesError::DeserializationError("missing sketch type tag".to_string())
+        })?;
+        match SketchKind::from_tag(tag)? {
+            SketchKind::Float => KllFloatSketch::deserialize(payload).map(AnySketch::Float),
+            SketchKind::Double => KllDoubleSketch::deserialize(payload).map(AnySketch::Double),
+        }
+    }
+}
+
+/// Deserializes a KLL sketch of either item type.
+///
+/// The DataSketches preamble only identifies the sketch family, so the item
+/// type is inferred from the payload length. Empty sketches carry no items
+/// and come back as [`AnySketch::Double`].
+pub fn deserialize_any(bytes: &[u8]) -> Result<AnySketch> {
+    match infer_item_size(bytes)? {
+        Some(4) => KllFloatSketch::deserialize(bytes).map(AnySketch::Float),
+        _ => KllDoubleSketch::deserialize(bytes).map(AnySketch::Double),
+    }
+}
+
+impl From<KllFloatSketch> for AnySketch {
+    fn from(sketch: KllFloatSketch) -> Self {
+        AnySketch::Float(sketch)
+    }
+}
+
+impl From<KllDoubleSketch> for AnySketch {
+    fn from(sketch: KllDoubleSketch) -> Self {
+        AnySketch::Double(sketch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tagged_bytes_round_trip() {
+        let mut float = AnySketch::from(KllFloatSketch::new());
+        let mut double = AnySketch::from(KllDoubleSketch::new());
+        for i in 1..=100 {
+            float.update(i as f64);
+            double.update(i as f64);
+        }
+
+        for sketch in [&float, &double] {
+            let bytes = sketch.to_tagged_bytes().unwrap();
+            assert_eq!(bytes[0], sketch.kind() as u8);
+            let restored = AnySketch::from_tagged_bytes(&bytes).unwrap();
+            assert_eq!(restored.kind(), sketch.kind());
+            assert_eq!(restored.get_n(), 100);
+            assert_eq!(restored.get_quantile(0.5), 50.0);
+        }
+
+        assert!(matches!(
+            float.merge(&double),
+            Err(DataSketchesError::IncompatibleMerge(_))
+        ));
+        let float_bytes = float.serialize().unwrap();
+        assert!(KllDoubleSketch::deserialize(&float_bytes).is_err());
+        assert_eq!(
+            deserialize_any(&float_bytes).unwrap().kind(),
+            SketchKind::Float
+        );
+        assert!(AnySketch::from_tagged_bytes(&[9, 0]).is_err());
+        assert!(AnySketch::from_tagged_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn test_serde_is_tagged() {
+        let mut sketch = AnySketch::from(KllDoubleSketch::new());
+        sketch.update(1.0);
+        let json = serde_json::to_string(&sketch).unwrap();
+        assert!(json.contains("\"type\":\"double\""));
+        let restored: AnySketch = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.kind(), SketchKind::Double);
+        assert_eq!(restored.get_n(), 1);
+    }
+
+    #[test]
+    fn test_deserialize_any_detects_item_type() {
+        for n in [0u32, 1, 5, 1_000, 100_000] {
+            let mut float = KllFloatSketch::new_with_k(64).unwrap();
+            let mut double = KllDoubleSketch::new_with_k(64).unwrap();
+            for i in 0..n {
+                float.update(i as f32);
+                double.update(i as f64);
+            }
+
+            let restored = deserialize_any(&float.serialize().unwrap()).unwrap();
+            let expected = if n == 0 {
+                SketchKind::Double
+            } else {
+                SketchKind::Float
+            };
+            assert_eq!(restored.kind(), expected);
+            assert_eq!(restored.get_n(), n as u64);
+
+            let restored = deserialize_any(&double.serialize().unwrap()).unwrap();
+            assert_eq!(restored.kind(), SketchKind::Double);
+            assert_eq!(restored.get_n(), n as u64);
+        }
+
+        let mut bytes = KllDoubleSketch::new().serialize().unwrap();
+        bytes[2] = 3;
+        assert!(deserialize_any(&bytes).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Text rendering of a sketch's distribution for terminals and logs.
+
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Renders a vertical bar histogram from a rank function.
+///
+/// The `[min, max]` range is split into `width` equal bins, each drawn as a
+/// column of `#` up to `height` rows tall and scaled to the fullest bin. A
+/// last line labels the range.
+pub(crate) fn histogram(
+    rank: impl Fn(f64) -> f64,
+    min: f64,
+    max: f64,
+    width: usize,
+    height: usize,
+) -> String {
+    if width == 0 || height == 0 || min.is_nan() {
+        return String::new();
+    }
+    let step = (max - min) / width as f64;
+    let mut previous = 0.0;
+    let mass: Vec<f64> = (1..=width)
+        .map(|i| {
+            let edge = if i == width {
+                max
+            } else {
+                min + step * i as f64
+            };
+            let cumulative = rank(edge);
+            let bin = cumulative - previous;
+            previous = cumulative;
+            bin
+        })
+        .collect();
+    let fullest = mass.iter().cloned().fold(0.0, f64::max);
+
+    let mut lines = Vec::with_capacity(height + 1);
+    for row in (0..height).rev() {
+        let line: String = mass
+            .iter()
+            .map(|&bin| {
+                let filled = (bin / fullest * height as f64).round() as usize;
+                if filled > row {
+                    '#'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+        lines.push(line.trim_end().to_string());
+    }
+    let low = format!("{}", min);
+    let high = format!("{}", max);
+    let gap = width.saturating_sub(low.len() + high.len()).max(1);
+    lines.push(format!("{}{}{}", low, " ".repeat(gap), high));
+    lines.join("\n")
+}
+
+/// Renders the CDF at `width` evenly spaced points as a one-line sparkline.
+pub(crate) fn sparkline(rank: impl Fn(f64) -> f64, min: f64, max: f64, width: usize) -> String {
+    if width == 0 || min.is_nan() {
+        return String::new();
+    }
+    let step = if width == 1 {
+        0.0
+    } else {
+        (max - min) / (width - 1) as f64
+    };
+    (0..width)
+        .map(|i| {
+            let value = if i == width - 1 {
+                max
+            } else {
+                min + step * i as f64
+            };
+            let level = (rank(value) * (SPARK_LEVELS.len() - 1) as f64).round() as usize;
+            SPARK_LEVELS[level.min(SPARK_LEVELS.len() - 1)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::KllDoubleSketch;
+
+    #[test]
+    fn test_ascii_rendering() {
+        let mut sketch = KllDoubleSketch::new();
+        assert_eq!(sketch.render_ascii_histogram(10, 3), "");
+        assert_eq!(sketch.cdf_sparkline(8), "");
+
+        for i in 0..100 {
+            sketch.update(if i < 75 { 1.0 } else { 4.0 });
+        }
+        assert_eq!(sketch.render_ascii_histogram(4, 4), "#\n#\n#\n#  #\n1  4");
+        assert_eq!(sketch.cdf_sparkline(4), "▆▆▆█");
+    }
+}
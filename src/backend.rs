@@ -0,0 +1,279 @@
+//! Quantile estimators chosen at runtime.
+//!
+//! A [`QuantileBackend`] puts every estimator the crate binds behind one API,
+//! so a pipeline can pick one per metric from configuration. Only KLL is
+//! bound today; other estimators join [`BackendKind`] as their bindings land.
+
+use crate::any::AnySketch;
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::traits::SketchLike;
+use crate::{KllDoubleSketch, KllFloatSketch};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Leading bytes of [`QuantileBackend::to_tagged_bytes`].
+const MAGIC: &[u8; 2] = b"QB";
+
+/// An estimator a [`QuantileBackend`] can run, as named in configuration.
+///
+/// Parses from and serializes to the lowercase names `kll` and `kll_float`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum BackendKind {
+    /// A KLL sketch of `f64` values.
+    Kll = 1,
+    /// A KLL sketch of `f32` values, at half the memory.
+    KllFloat = 2,
+}
+
+impl BackendKind {
+    /// Every kind, by tag.
+    pub const ALL: [BackendKind; 2] = [BackendKind::Kll, BackendKind::KllFloat];
+
+    /// Returns the configuration name of the kind.
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Kll => "kll",
+            BackendKind::KllFloat => "kll_float",
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        BackendKind::ALL
+            .into_iter()
+            .find(|kind| *kind as u8 == tag)
+            .ok_or_else(|| {
+                DataSketchesError::DeserializationError(format!("unknown backend tag {}", tag))
+            })
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = DataSketchesError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        BackendKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| {
+                DataSketchesError::InvalidParameter(format!(
+                    "unknown quantile backend {:?}; expected one of kll, kll_float",
+                    name
+                ))
+            })
+    }
+}
+
+/// A quantile estimator whose kind is picked at runtime.
+///
+/// Values are passed and returned as `f64` whatever the backend.
+/// [`to_tagged_bytes`](QuantileBackend::to_tagged_bytes) records the backend
+/// ahead of its own bytes, so stored state comes back as the estimator that
+/// wrote it even after the configuration changes.
+///
+/// ```
+/// use kll_rs::{BackendKind, QuantileBackend};
+///
+/// let kind: BackendKind = "kll_float".parse().unwrap();
+/// let mut backend = QuantileBackend::new(kind);
+/// for i in 1..=100 {
+///     backend.update(i as f64);
+/// }
+/// let bytes = backend.to_tagged_bytes().unwrap();
+/// let restored = QuantileBackend::from_tagged_bytes(&bytes).unwrap();
+/// assert_eq!(restored.kind(), BackendKind::KllFloat);
+/// assert_eq!(restored.get_quantile(0.5), 50.0);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuantileBackend {
+    sketch: AnySketch,
+}
+
+impl QuantileBackend {
+    /// Creates an empty estimator of `kind` with its default accuracy.
+    pub fn new(kind: BackendKind) -> Self {
+        let sketch = match kind {
+            BackendKind::Kll => AnySketch::Double(KllDoubleSketch::new()),
+            BackendKind::KllFloat => AnySketch::Float(KllFloatSketch::new()),
+        };
+        QuantileBackend { sketch }
+    }
+
+    /// Creates an empty KLL estimator of `kind` with a specific k.
+    pub fn with_k(kind: BackendKind, k: KParam) -> Self {
+        let sketch = match kind {
+            BackendKind::Kll => AnySketch::Double(KllDoubleSketch::with_k(k)),
+            BackendKind::KllFloat => AnySketch::Float(KllFloatSketch::with_k(k)),
+        };
+        QuantileBackend { sketch }
+    }
+
+    /// Returns the kind of estimator.
+    pub fn kind(&self) -> BackendKind {
+        match self.sketch {
+            AnySketch::Double(_) => BackendKind::Kll,
+            AnySketch::Float(_) => BackendKind::KllFloat,
+        }
+    }
+
+    /// Updates the estimator with a new value.
+    pub fn update(&mut self, value: f64) {
+        self.sketch.update(value);
+    }
+
+    /// Merges another estimator of the same kind into this one.
+    ///
+    /// Fails with [`DataSketchesError::IncompatibleMerge`] if the kinds
+    /// differ.
+    pub fn merge(&mut self, other: &QuantileBackend) -> Result<()> {
+        if self.kind() != other.kind() {
+            return Err(DataSketchesError::IncompatibleMerge(format!(
+                "cannot merge a {} backend into a {} backend",
+                other.kind(),
+                self.kind()
+            )));
+        }
+        self.sketch.merge(&other.sketch)
+    }
+
+    /// Returns the number of values processed.
+    pub fn get_n(&self) -> u64 {
+        self.sketch.get_n()
+    }
+
+    /// Returns true if the estimator is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+
+    /// Returns the approximate quantile, or NaN if the estimator is empty.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        self.sketch.get_quantile(fraction)
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        self.sketch.get_quantiles(fractions)
+    }
+
+    /// Returns the approximate rank of a value.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.sketch.get_rank(value)
+    }
+
+    /// Returns the KLL sketch behind the estimator.
+    pub fn as_kll(&self) -> &AnySketch {
+        &self.sketch
+    }
+
+    /// Serializes the estimator as `"QB"`, a one-byte [`BackendKind`] tag and
+    /// the backend's own bytes.
+    pub fn to_tagged_bytes(&self) -> Result<Vec<u8>> {
+        let payload = self.sketch.serialize()?;
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 1 + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.kind() as u8);
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    /// Deserializes bytes written by
+    /// [`to_tagged_bytes`](QuantileBackend::to_tagged_bytes), as whichever
+    /// backend wrote them.
+    pub fn from_tagged_bytes(bytes: &[u8]) -> Result<Self> {
+        let payload = bytes.strip_prefix(MAGIC.as_slice()).ok_or_else(|| {
+            DataSketchesError::DeserializationError("not a tagged quantile backend".to_string())
+        })?;
+        let (&tag, payload) = payload.split_first().ok_or_else(|| {
+            DataSketchesError::DeserializationError("missing backend tag".to_string())
+        })?;
+        let sketch = match BackendKind::from_tag(tag)? {
+            BackendKind::Kll => KllDoubleSketch::deserialize(payload).map(AnySketch::Double)?,
+            BackendKind::KllFloat => KllFloatSketch::deserialize(payload).map(AnySketch::Float)?,
+        };
+        Ok(QuantileBackend { sketch })
+    }
+}
+
+impl SketchLike for QuantileBackend {
+    fn update(&mut self, value: f64) {
+        QuantileBackend::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        QuantileBackend::get_n(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        QuantileBackend::is_empty(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        QuantileBackend::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        QuantileBackend::get_quantiles(self, fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        QuantileBackend::get_rank(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_names() {
+        for kind in BackendKind::ALL {
+            assert_eq!(kind.to_string().parse::<BackendKind>().unwrap(), kind);
+            assert_eq!(BackendKind::from_tag(kind as u8).unwrap(), kind);
+        }
+        assert!("tdigest".parse::<BackendKind>().is_err());
+        let kind: BackendKind = serde_json::from_str("\"kll_float\"").unwrap();
+        assert_eq!(kind, BackendKind::KllFloat);
+    }
+
+    #[test]
+    fn test_tagged_round_trip_and_merge() {
+        let k = KParam::new(64).unwrap();
+        let mut double = QuantileBackend::with_k(BackendKind::Kll, k);
+        let mut float = QuantileBackend::new(BackendKind::KllFloat);
+        for i in 1..=1000 {
+            double.update(i as f64);
+            float.update(i as f64);
+        }
+
+        for backend in [&double, &float] {
+            let bytes = backend.to_tagged_bytes().unwrap();
+            assert_eq!(&bytes[..3], &[b'Q', b'B', backend.kind() as u8]);
+            let restored = QuantileBackend::from_tagged_bytes(&bytes).unwrap();
+            assert_eq!(restored.kind(), backend.kind());
+            assert_eq!(restored.get_n(), 1000);
+        }
+        assert_eq!(double.as_kll().get_k(), 64);
+
+        assert!(matches!(
+            double.merge(&float),
+            Err(DataSketchesError::IncompatibleMerge(_))
+        ));
+        double.merge(&double.clone()).unwrap();
+        assert_eq!(double.get_n(), 2000);
+
+        assert!(QuantileBackend::from_tagged_bytes(b"QB").is_err());
+        assert!(QuantileBackend::from_tagged_bytes(b"QB\x09").is_err());
+        assert!(QuantileBackend::from_tagged_bytes(&[1, 2, 3]).is_err());
+    }
+}
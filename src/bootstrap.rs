@@ -0,0 +1,113 @@
+//! Bootstrap confidence intervals for quantiles, for the `bootstrap` feature.
+//!
+//! Resampling `n` values from the sketch's distribution and taking the
+//! quantile of the resample picks the value at the `r`-th smallest of `n`
+//! uniform draws, which follows a Beta(r, n - r + 1) distribution. Each
+//! bootstrap iteration therefore draws one Beta variate and reads the sketch's
+//! inverse CDF there, at a cost independent of `n`.
+
+use crate::sketch_type::SketchItem;
+use crate::{KllDoubleSketch, KllFloatSketch};
+use rand::Rng;
+use rand_distr::{Beta, Distribution};
+
+/// Two-sided confidence level of [`bootstrap_quantile_ci`](KllDoubleSketch::bootstrap_quantile_ci).
+pub const BOOTSTRAP_CONFIDENCE: f64 = 0.95;
+
+/// Returns the fractions at which the bootstrap reads the inverse CDF, or
+/// `None` if there is nothing to resample.
+fn resampled_fractions<R: Rng + ?Sized>(
+    n: u64,
+    fraction: f64,
+    iterations: u32,
+    rng: &mut R,
+) -> Option<Vec<f64>> {
+    if n == 0 || iterations == 0 || !(0.0..=1.0).contains(&fraction) {
+        return None;
+    }
+    let rank = ((fraction * n as f64).ceil() as u64).clamp(1, n);
+    let order_statistic = Beta::new(rank as f64, (n - rank + 1) as f64).ok()?;
+    Some(
+        (0..iterations)
+            .map(|_| order_statistic.sample(rng))
+            .collect(),
+    )
+}
+
+/// Returns the central [`BOOTSTRAP_CONFIDENCE`] range of the bootstrap
+/// quantiles.
+fn percentile_interval(mut quantiles: Vec<f64>) -> (f64, f64) {
+    quantiles.sort_by(f64::total_cmp);
+    let tail = (1.0 - BOOTSTRAP_CONFIDENCE) / 2.0;
+    let last = quantiles.len() - 1;
+    let lower = (tail * last as f64).floor() as usize;
+    let upper = ((1.0 - tail) * last as f64).ceil() as usize;
+    (quantiles[lower], quantiles[upper.min(last)])
+}
+
+macro_rules! bootstrap_ci {
+    ($Sketch:ident) => {
+        impl $Sketch {
+            /// Returns a 95% bootstrap confidence interval for the quantile at
+            /// `fraction`, from `iterations` resamples drawn with `rng`.
+            ///
+            /// Resamples are drawn from the distribution the sketch retains, so the
+            /// interval reflects sampling variability in the data, not the sketch's
+            /// own rank error; see `get_normalized_rank_error` for that. Returns NaN
+            /// bounds if the sketch is empty, `iterations` is 0 or `fraction` is
+            /// outside `[0, 1]`.
+            pub fn bootstrap_quantile_ci<R: Rng + ?Sized>(
+                &self,
+                fraction: f64,
+                iterations: u32,
+                rng: &mut R,
+            ) -> (f64, f64) {
+                match resampled_fractions(self.get_n(), fraction, iterations, rng) {
+                    Some(fractions) => percentile_interval(
+                        self.get_quantiles(&fractions)
+                            .into_iter()
+                            .map(SketchItem::to_f64)
+                            .collect(),
+                    ),
+                    None => (f64::NAN, f64::NAN),
+                }
+            }
+        }
+    };
+}
+
+bootstrap_ci!(KllFloatSketch);
+bootstrap_ci!(KllDoubleSketch);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_interval_narrows_with_n() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let width = |n: u32, rng: &mut StdRng| {
+            let mut sketch = KllDoubleSketch::new();
+            for i in 0..n {
+                sketch.update(i as f64 / n as f64);
+            }
+            let (low, high) = sketch.bootstrap_quantile_ci(0.5, 500, rng);
+            assert!(low <= 0.5 && 0.5 <= high, "{} {}", low, high);
+            high - low
+        };
+        let small = width(100, &mut rng);
+        let large = width(100_000, &mut rng);
+        // The width of the median's interval shrinks like 1 / sqrt(n).
+        assert!(small > 0.1 && small < 0.3, "{}", small);
+        assert!(large < small / 10.0, "{} vs {}", large, small);
+
+        let mut float = KllFloatSketch::new();
+        assert!(float.bootstrap_quantile_ci(0.5, 100, &mut rng).0.is_nan());
+        float.update(3.0);
+        assert_eq!(float.bootstrap_quantile_ci(0.9, 100, &mut rng), (3.0, 3.0));
+        assert!(float.bootstrap_quantile_ci(1.5, 100, &mut rng).1.is_nan());
+        assert!(float.bootstrap_quantile_ci(0.5, 0, &mut rng).1.is_nan());
+    }
+}
@@ -0,0 +1,38 @@
+//! Histogram bucket boundaries suggested from a sketch.
+
+/// How [`suggest_buckets`](crate::KllDoubleSketch::suggest_buckets) spaces
+/// the boundaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BucketStrategy {
+    /// Boundaries at evenly spaced quantiles, so each bucket holds about the
+    /// same number of values.
+    EquiDepth,
+    /// Boundaries growing by a constant factor from the smallest positive
+    /// value to the largest, as for latencies or sizes spanning orders of
+    /// magnitude. Zero and negative values fall in the first bucket.
+    LogSpaced,
+}
+
+/// Returns `num_buckets` upper bounds growing by a constant factor from just
+/// above `low` up to `high`, both positive.
+pub(crate) fn log_spaced(low: f64, high: f64, num_buckets: usize) -> Vec<f64> {
+    if low >= high {
+        return vec![high];
+    }
+    let ratio = (high / low).powf(1.0 / num_buckets as f64);
+    let mut bounds: Vec<f64> = (1..num_buckets)
+        .map(|i| low * ratio.powi(i as i32))
+        .collect();
+    // The last bound is the maximum exactly, not a rounded power.
+    bounds.push(high);
+    bounds
+}
+
+/// Sorts and removes repeated bounds, which equal quantiles produce where
+/// many values are the same.
+pub(crate) fn dedup_bounds(mut bounds: Vec<f64>) -> Vec<f64> {
+    bounds.retain(|bound| bound.is_finite());
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    bounds
+}
@@ -0,0 +1,191 @@
+//! Memoized quantile queries.
+
+use crate::error::Result;
+use crate::KllDoubleSketch;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+/// Number of answers kept per query kind before the cache is cleared.
+const MAX_CACHED_ENTRIES: usize = 1024;
+
+#[derive(Debug, Default)]
+struct Cache {
+    quantiles: HashMap<u64, f64>,
+    ranks: HashMap<u64, f64>,
+    pmfs: HashMap<Vec<u64>, Vec<f64>>,
+}
+
+impl Cache {
+    fn clear(&mut self) {
+        self.quantiles.clear();
+        self.ranks.clear();
+        self.pmfs.clear();
+    }
+}
+
+/// A sketch wrapper that memoizes query answers until the sketch changes.
+///
+/// Queries take `&self` and may be issued from several threads. Every
+/// mutation goes through `&mut self` and drops all cached answers.
+#[derive(Debug)]
+pub struct CachedSketch {
+    sketch: KllDoubleSketch,
+    cache: Mutex<Cache>,
+}
+
+impl CachedSketch {
+    /// Wraps a sketch with an empty cache.
+    pub fn new(sketch: KllDoubleSketch) -> Self {
+        CachedSketch {
+            sketch,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    /// Returns the wrapped sketch.
+    pub fn sketch(&self) -> &KllDoubleSketch {
+        &self.sketch
+    }
+
+    /// Unwraps the sketch, discarding the cache.
+    pub fn into_inner(self) -> KllDoubleSketch {
+        self.sketch
+    }
+
+    /// Updates the sketch with a new value and invalidates the cache.
+    pub fn update(&mut self, value: f64) {
+        self.sketch.update(value);
+        self.invalidate();
+    }
+
+    /// Merges another sketch into this one and invalidates the cache.
+    pub fn merge(&mut self, other: &KllDoubleSketch) -> Result<()> {
+        let result = self.sketch.merge(other);
+        self.invalidate();
+        result
+    }
+
+    /// Drops all cached answers.
+    pub fn invalidate(&mut self) {
+        self.cache
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the approximate quantile for a given fraction.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        let key = fraction.to_bits();
+        if let Some(&value) = self.lock().quantiles.get(&key) {
+            return value;
+        }
+        let value = self.sketch.get_quantile(fraction);
+        let mut cache = self.lock();
+        if cache.quantiles.len() >= MAX_CACHED_ENTRIES {
+            cache.quantiles.clear();
+        }
+        cache.quantiles.insert(key, value);
+        value
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        if self.sketch.is_empty() {
+            return vec![];
+        }
+        fractions.iter().map(|&f| self.get_quantile(f)).collect()
+    }
+
+    /// Returns the approximate rank of a value.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        let key = value.to_bits();
+        if let Some(&rank) = self.lock().ranks.get(&key) {
+            return rank;
+        }
+        let rank = self.sketch.get_rank(value);
+        let mut cache = self.lock();
+        if cache.ranks.len() >= MAX_CACHED_ENTRIES {
+            cache.ranks.clear();
+        }
+        cache.ranks.insert(key, rank);
+        rank
+    }
+
+    /// Returns the approximate probability mass between consecutive split points.
+    ///
+    /// The result has `split_points.len() + 1` entries: the mass at or below the
+    /// first split point, the mass in each `(split_points[i - 1], split_points[i]]`
+    /// interval, and the mass above the last split point. Split points must be
+    /// increasing. Returns an empty vector for an empty sketch.
+    pub fn get_pmf(&self, split_points: &[f64]) -> Vec<f64> {
+        if self.sketch.is_empty() {
+            return vec![];
+        }
+        let key: Vec<u64> = split_points.iter().map(|v| v.to_bits()).collect();
+        if let Some(pmf) = self.lock().pmfs.get(&key) {
+            return pmf.clone();
+        }
+
+        let mut pmf = Vec::with_capacity(split_points.len() + 1);
+        let mut previous = 0.0;
+        for &point in split_points {
+            let rank = self.get_rank(point);
+            pmf.push(rank - previous);
+            previous = rank;
+        }
+        pmf.push(1.0 - previous);
+
+        let mut cache = self.lock();
+        if cache.pmfs.len() >= MAX_CACHED_ENTRIES {
+            cache.pmfs.clear();
+        }
+        cache.pmfs.insert(key, pmf.clone());
+        pmf
+    }
+}
+
+impl From<KllDoubleSketch> for CachedSketch {
+    fn from(sketch: KllDoubleSketch) -> Self {
+        CachedSketch::new(sketch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_invalidated_on_update() {
+        let mut cached = CachedSketch::new(KllDoubleSketch::new());
+        for i in 1..=100 {
+            cached.update(i as f64);
+        }
+        assert_eq!(cached.get_quantile(1.0), 100.0);
+        assert_eq!(cached.get_quantile(1.0), 100.0);
+
+        cached.update(1000.0);
+        assert_eq!(cached.get_quantile(1.0), 1000.0);
+    }
+
+    #[test]
+    fn test_pmf_sums_to_one() {
+        let mut cached = CachedSketch::new(KllDoubleSketch::new());
+        for i in 1..=100 {
+            cached.update(i as f64);
+        }
+        let pmf = cached.get_pmf(&[25.0, 50.0, 75.0]);
+        assert_eq!(pmf.len(), 4);
+        assert!((pmf.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!((pmf[0] - 0.25).abs() < 1e-9);
+        assert_eq!(cached.get_pmf(&[25.0, 50.0, 75.0]), pmf);
+
+        let mut other = KllDoubleSketch::new();
+        other.update(0.0);
+        cached.merge(&other).unwrap();
+        assert!(cached.get_pmf(&[25.0, 50.0, 75.0])[0] > pmf[0]);
+    }
+}
@@ -0,0 +1,267 @@
+//! Chart rendering with [plotters](https://docs.rs/plotters).
+//!
+//! The `draw_*` functions draw onto any plotters drawing area, and
+//! [`render_svg`] and [`render_png`] set one up for the common backends:
+//!
+//! ```
+//! use kll_rs::charts::{draw_cdf, render_svg};
+//! use kll_rs::KllDoubleSketch;
+//!
+//! let mut sketch = KllDoubleSketch::new();
+//! for i in 0..1000 {
+//!     sketch.update(i as f64);
+//! }
+//! let svg = render_svg((640, 480), |area| draw_cdf(area, &sketch, Some("latency CDF"))).unwrap();
+//! assert!(svg.starts_with("<svg"));
+//! ```
+//!
+//! Only available with the `plotters` feature. Charts given a title also get
+//! tick labels. Plotters is built without a font engine, so only the SVG
+//! backend can draw text; pass `None` as the title when drawing to a bitmap.
+
+use crate::error::{DataSketchesError, Result};
+use crate::KllDoubleSketch;
+use plotters::coord::types::RangedCoordf64;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+
+fn chart_error<E: std::fmt::Display>(err: E) -> DataSketchesError {
+    DataSketchesError::Unknown(format!("chart rendering failed: {}", err))
+}
+
+/// Returns a plotting range for `[min, max]` that is never zero-width.
+fn span(min: f64, max: f64) -> Range<f64> {
+    if min < max {
+        min..max
+    } else {
+        min - 1.0..max + 1.0
+    }
+}
+
+/// Starts a chart, with room for a caption and tick labels when titled.
+fn builder<'a, 'b, DB: DrawingBackend>(
+    area: &'a DrawingArea<DB, Shift>,
+    title: Option<&str>,
+) -> ChartBuilder<'a, 'b, DB> {
+    let mut builder = ChartBuilder::on(area);
+    builder.margin(10);
+    if let Some(title) = title {
+        builder
+            .caption(title, ("sans-serif", 20))
+            .x_label_area_size(30)
+            .y_label_area_size(60);
+    }
+    builder
+}
+
+/// Draws the grid, with tick labels only when the chart is titled.
+fn draw_mesh<DB: DrawingBackend>(
+    chart: &mut ChartContext<'_, DB, Cartesian2d<RangedCoordf64, RangedCoordf64>>,
+    title: Option<&str>,
+) -> Result<()> {
+    let mut mesh = chart.configure_mesh();
+    if title.is_none() {
+        mesh.x_labels(0).y_labels(0);
+    }
+    mesh.draw().map_err(chart_error)
+}
+
+fn require_values(sketch: &KllDoubleSketch) -> Result<()> {
+    if sketch.is_empty() {
+        return Err(DataSketchesError::InvalidParameter(
+            "cannot chart an empty sketch".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Draws the sketch's cumulative distribution as a step line.
+pub fn draw_cdf<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    sketch: &KllDoubleSketch,
+    title: Option<&str>,
+) -> Result<()> {
+    require_values(sketch)?;
+    let n = sketch.get_n() as f64;
+    let mut cumulative = 0u64;
+    let mut points = Vec::new();
+    for (value, weight) in sketch.to_weighted_values() {
+        points.push((value, cumulative as f64 / n));
+        cumulative += weight;
+        points.push((value, cumulative as f64 / n));
+    }
+
+    let mut chart = builder(area, title)
+        .build_cartesian_2d(
+            span(sketch.get_min_value(), sketch.get_max_value()),
+            0.0..1.0,
+        )
+        .map_err(chart_error)?;
+    draw_mesh(&mut chart, title)?;
+    chart
+        .draw_series(LineSeries::new(points, &BLUE))
+        .map_err(chart_error)?;
+    area.present().map_err(chart_error)
+}
+
+/// Draws the sketch's estimated density as `num_bins` equal-width bars.
+pub fn draw_pdf<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    sketch: &KllDoubleSketch,
+    num_bins: usize,
+    title: Option<&str>,
+) -> Result<()> {
+    require_values(sketch)?;
+    if num_bins == 0 {
+        return Err(DataSketchesError::InvalidParameter(
+            "a density chart needs at least one bin".to_string(),
+        ));
+    }
+    let range = span(sketch.get_min_value(), sketch.get_max_value());
+    let width = (range.end - range.start) / num_bins as f64;
+    let mut previous = 0.0;
+    let bars: Vec<(f64, f64, f64)> = (1..=num_bins)
+        .map(|i| {
+            let low = range.start + width * (i - 1) as f64;
+            let high = if i == num_bins {
+                range.end
+            } else {
+                range.start + width * i as f64
+            };
+            let rank = sketch.get_rank(high);
+            let density = (rank - previous) / width;
+            previous = rank;
+            (low, high, density)
+        })
+        .collect();
+    let peak = bars.iter().map(|b| b.2).fold(0.0, f64::max);
+
+    let mut chart = builder(area, title)
+        .build_cartesian_2d(range, 0.0..peak * 1.05)
+        .map_err(chart_error)?;
+    draw_mesh(&mut chart, title)?;
+    chart
+        .draw_series(bars.into_iter().map(|(low, high, density)| {
+            Rectangle::new([(low, 0.0), (high, density)], BLUE.mix(0.6).filled())
+        }))
+        .map_err(chart_error)?;
+    area.present().map_err(chart_error)
+}
+
+/// Draws one line per fraction across a sequence of interval sketches.
+///
+/// The x axis is the interval index; empty intervals leave a gap in every
+/// line.
+pub fn draw_percentiles_over_time<'a, DB, I>(
+    area: &DrawingArea<DB, Shift>,
+    sketches: I,
+    fractions: &[f64],
+    title: Option<&str>,
+) -> Result<()>
+where
+    DB: DrawingBackend,
+    I: IntoIterator<Item = &'a KllDoubleSketch>,
+{
+    let rows: Vec<Option<Vec<f64>>> = sketches
+        .into_iter()
+        .map(|sketch| (!sketch.is_empty()).then(|| sketch.get_quantiles(fractions)))
+        .collect();
+    let (low, high) = rows
+        .iter()
+        .flatten()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    if low > high {
+        return Err(DataSketchesError::InvalidParameter(
+            "no non-empty intervals to chart".to_string(),
+        ));
+    }
+
+    let mut chart = builder(area, title)
+        .build_cartesian_2d(0.0..rows.len().max(2) as f64 - 1.0, span(low, high))
+        .map_err(chart_error)?;
+    draw_mesh(&mut chart, title)?;
+    for (column, _) in fractions.iter().enumerate() {
+        let color = Palette99::pick(column);
+        // Each run of consecutive non-empty intervals is its own segment.
+        let mut segments = vec![Vec::new()];
+        for (index, row) in rows.iter().enumerate() {
+            match row {
+                Some(quantiles) => segments
+                    .last_mut()
+                    .expect("segments is never empty")
+                    .push((index as f64, quantiles[column])),
+                None => segments.push(Vec::new()),
+            }
+        }
+        for segment in segments.into_iter().filter(|s| !s.is_empty()) {
+            chart
+                .draw_series(LineSeries::new(segment, &color))
+                .map_err(chart_error)?;
+        }
+    }
+    area.present().map_err(chart_error)
+}
+
+/// Renders a chart to an SVG document of `size` pixels.
+pub fn render_svg<F>(size: (u32, u32), draw: F) -> Result<String>
+where
+    F: FnOnce(&DrawingArea<SVGBackend<'_>, Shift>) -> Result<()>,
+{
+    let mut svg = String::new();
+    {
+        let area = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+        area.fill(&WHITE).map_err(chart_error)?;
+        draw(&area)?;
+    }
+    Ok(svg)
+}
+
+/// Renders a chart to a PNG file of `size` pixels.
+pub fn render_png<P, F>(path: P, size: (u32, u32), draw: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&DrawingArea<BitMapBackend<'_>, Shift>) -> Result<()>,
+{
+    let area = BitMapBackend::new(path.as_ref(), size).into_drawing_area();
+    area.fill(&WHITE).map_err(chart_error)?;
+    draw(&area)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_charts() {
+        let mut sketch = KllDoubleSketch::new();
+        assert!(render_svg((320, 240), |area| draw_cdf(area, &sketch, Some("cdf"))).is_err());
+
+        for i in 0..1000 {
+            sketch.update(i as f64);
+        }
+        let cdf = render_svg((320, 240), |area| draw_cdf(area, &sketch, Some("cdf"))).unwrap();
+        assert!(cdf.contains("<polyline"));
+        let pdf = render_svg((320, 240), |area| draw_pdf(area, &sketch, 10, Some("pdf"))).unwrap();
+        assert!(pdf.contains("<rect"));
+
+        let intervals = [sketch.clone(), KllDoubleSketch::new(), sketch];
+        let over_time = render_svg((320, 240), |area| {
+            draw_percentiles_over_time(area, &intervals, &[0.5, 0.99], Some("latency"))
+        })
+        .unwrap();
+        assert!(over_time.contains("<polyline"));
+
+        let path = std::env::temp_dir().join("kll_rs_chart_test.png");
+        render_png(&path, (64, 48), |area| {
+            draw_pdf(area, &intervals[0], 4, None)
+        })
+        .unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        std::fs::remove_file(path).unwrap();
+    }
+}
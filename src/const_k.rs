@@ -0,0 +1,155 @@
+//! A double sketch whose k is part of its type.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::{KParam, MIN_K};
+use crate::traits::Mergeable;
+use crate::KllDoubleSketch;
+use std::ops::Deref;
+
+/// A [`KllDoubleSketch`] with k fixed at compile time.
+///
+/// An invalid `K` fails to compile:
+///
+/// ```compile_fail
+/// let sketch = kll_rs::KllSketchConst::<4>::new(); // k must be at least 8
+/// ```
+///
+/// and sketches can only be merged with sketches of the same `K`:
+///
+/// ```compile_fail
+/// use kll_rs::KllSketchConst;
+///
+/// let mut latency = KllSketchConst::<200>::new();
+/// let sizes = KllSketchConst::<400>::new();
+/// latency.merge(&sizes); // mismatched types
+/// ```
+///
+/// All read-only methods of the underlying sketch are available through
+/// `Deref`; updates and merges go through this type so the k invariant holds.
+///
+/// ```
+/// use kll_rs::KllSketchConst;
+///
+/// type Latency = KllSketchConst<256>;
+///
+/// let mut sketch = Latency::new();
+/// sketch.update(1.0);
+/// assert_eq!(sketch.get_k(), 256);
+/// assert_eq!(sketch.get_quantile(0.5), 1.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct KllSketchConst<const K: u16> {
+    sketch: KllDoubleSketch,
+}
+
+impl<const K: u16> KllSketchConst<K> {
+    /// Evaluated at compile time for every `K` that is instantiated.
+    const VALID_K: () = assert!(K >= MIN_K, "k must be at least 8");
+
+    /// The k of this sketch type.
+    pub const K: u16 = K;
+
+    /// Creates an empty sketch.
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_K;
+        let k = KParam::new(K).expect("k is checked at compile time");
+        KllSketchConst {
+            sketch: KllDoubleSketch::with_k(k),
+        }
+    }
+
+    /// Updates the sketch with a new value.
+    pub fn update(&mut self, value: f64) {
+        self.sketch.update(value);
+    }
+
+    /// Merges another sketch with the same k into this one.
+    pub fn merge(&mut self, other: &KllSketchConst<K>) -> Result<()> {
+        self.sketch.merge(&other.sketch)
+    }
+
+    /// Deserializes a sketch, failing if it was written with a different k.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        Self::try_from(KllDoubleSketch::deserialize(bytes)?)
+    }
+
+    /// Returns the underlying sketch.
+    pub fn into_inner(self) -> KllDoubleSketch {
+        self.sketch
+    }
+}
+
+impl<const K: u16> Default for KllSketchConst<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const K: u16> Mergeable for KllSketchConst<K> {
+    fn empty_like(&self) -> Self {
+        Self::new()
+    }
+
+    fn merge_from(&mut self, other: &Self) -> Result<()> {
+        self.merge(other)
+    }
+}
+
+impl<const K: u16> Deref for KllSketchConst<K> {
+    type Target = KllDoubleSketch;
+
+    fn deref(&self) -> &KllDoubleSketch {
+        &self.sketch
+    }
+}
+
+impl<const K: u16> TryFrom<KllDoubleSketch> for KllSketchConst<K> {
+    type Error = DataSketchesError;
+
+    /// Wraps a sketch, failing unless its k is `K`.
+    fn try_from(sketch: KllDoubleSketch) -> Result<Self> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_K;
+        if sketch.get_k() != K {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "sketch has k = {} but the type requires k = {}",
+                sketch.get_k(),
+                K
+            )));
+        }
+        Ok(KllSketchConst { sketch })
+    }
+}
+
+impl<const K: u16> From<KllSketchConst<K>> for KllDoubleSketch {
+    fn from(sketch: KllSketchConst<K>) -> Self {
+        sketch.sketch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_const_k_sketch() {
+        let mut a = KllSketchConst::<64>::new();
+        let mut b = KllSketchConst::<64>::default();
+        for i in 0..100 {
+            a.update(i as f64);
+            b.update((i + 100) as f64);
+        }
+        a.merge(&b).unwrap();
+        assert_eq!(a.get_n(), 200);
+        assert_eq!(KllSketchConst::<64>::K, 64);
+
+        let bytes = a.serialize().unwrap();
+        assert_eq!(
+            KllSketchConst::<64>::deserialize(&bytes).unwrap().get_n(),
+            200
+        );
+        assert!(KllSketchConst::<128>::deserialize(&bytes).is_err());
+        assert!(KllSketchConst::<64>::try_from(KllDoubleSketch::new_with_k(32).unwrap()).is_err());
+    }
+}
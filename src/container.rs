@@ -0,0 +1,364 @@
+//! An indexed file of many sketches, readable one key at a time.
+//!
+//! Layout, little-endian throughout:
+//!
+//! | Bytes | Field |
+//! |-------|-------|
+//! | 4     | magic `KLLC` |
+//! | 2     | format version, currently 1 |
+//! | 2     | reserved, zero |
+//! | 4     | entry count |
+//! | 4     | table of contents length in bytes |
+//! | ...   | table of contents, sorted by key |
+//! | ...   | sketch payloads, each starting on an 8-byte boundary |
+//!
+//! Each table of contents entry is a `u16` key length, the UTF-8 key, a
+//! one-byte [`SketchKind`], and the `u64` offset from the start of the file
+//! and `u64` length of the payload. A reader only touches the header, the
+//! table and the payloads it is asked for, so a memory-mapped file pages in
+//! just those.
+
+use crate::any::{AnySketch, SketchKind};
+use crate::error::{DataSketchesError, Result};
+use crate::{KllDoubleSketch, KllFloatSketch};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Leading bytes of a container.
+const MAGIC: &[u8; 4] = b"KLLC";
+/// Format version written by [`ContainerWriter`].
+const VERSION: u16 = 1;
+/// Bytes before the table of contents.
+const HEADER_LEN: usize = 16;
+/// Alignment of every payload.
+const ALIGN: usize = 8;
+
+fn align(offset: usize) -> usize {
+    (offset + ALIGN - 1) / ALIGN * ALIGN
+}
+
+fn toc_entry_len(key: &str) -> usize {
+    2 + key.len() + 1 + 8 + 8
+}
+
+/// A sketch type that can be stored in a container.
+pub trait ContainerItem: sealed::Sealed {
+    #[doc(hidden)]
+    fn container_entry(&self) -> Result<(SketchKind, Vec<u8>)>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for crate::KllDoubleSketch {}
+    impl Sealed for crate::KllFloatSketch {}
+    impl Sealed for crate::AnySketch {}
+}
+
+impl ContainerItem for KllDoubleSketch {
+    fn container_entry(&self) -> Result<(SketchKind, Vec<u8>)> {
+        Ok((SketchKind::Double, self.serialize()?))
+    }
+}
+
+impl ContainerItem for KllFloatSketch {
+    fn container_entry(&self) -> Result<(SketchKind, Vec<u8>)> {
+        Ok((SketchKind::Float, self.serialize()?))
+    }
+}
+
+impl ContainerItem for AnySketch {
+    fn container_entry(&self) -> Result<(SketchKind, Vec<u8>)> {
+        Ok((self.kind(), self.serialize()?))
+    }
+}
+
+/// Collects keyed sketches and writes them as one indexed container.
+///
+/// Sketches are serialized as they are inserted, so the writer holds bytes
+/// rather than live sketches until [`write_to`](ContainerWriter::write_to).
+///
+/// ```
+/// use kll_rs::{ContainerReader, ContainerWriter, KllDoubleSketch, KllFloatSketch};
+///
+/// let mut latency = KllDoubleSketch::new();
+/// latency.update(12.5);
+/// let mut size = KllFloatSketch::new();
+/// size.update(512.0);
+///
+/// let mut writer = ContainerWriter::new();
+/// writer.insert("api.latency", &latency)?;
+/// writer.insert("api.size", &size)?;
+/// let bytes = writer.to_bytes()?;
+///
+/// let reader = ContainerReader::new(&bytes)?;
+/// assert_eq!(reader.get("api.latency")?.unwrap().get_n(), 1);
+/// assert!(reader.get("db.latency")?.is_none());
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ContainerWriter {
+    entries: BTreeMap<String, (SketchKind, Vec<u8>)>,
+}
+
+impl ContainerWriter {
+    /// Creates an empty writer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sketch under `key`, replacing any sketch already there.
+    ///
+    /// Fails with [`DataSketchesError::InvalidParameter`] if the key is
+    /// longer than 65535 bytes.
+    pub fn insert<K: Into<String>, S: ContainerItem + ?Sized>(
+        &mut self,
+        key: K,
+        sketch: &S,
+    ) -> Result<()> {
+        let key = key.into();
+        if key.len() > u16::MAX as usize {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "container key of {} bytes exceeds 65535",
+                key.len()
+            )));
+        }
+        self.entries.insert(key, sketch.container_entry()?);
+        Ok(())
+    }
+
+    /// Returns the number of sketches added.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no sketch has been added.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the container to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&self.to_bytes()?).map_err(|err| {
+            DataSketchesError::SerializationError(format!("writing container: {}", err))
+        })
+    }
+
+    /// Returns the container as bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let too_large = || DataSketchesError::SerializationError("container too large".to_string());
+        let toc_len: usize = self.entries.keys().map(|key| toc_entry_len(key)).sum();
+        let count = u32::try_from(self.entries.len()).map_err(|_| too_large())?;
+
+        let mut offsets = Vec::with_capacity(self.entries.len());
+        let mut end = align(HEADER_LEN + toc_len);
+        for (_, payload) in self.entries.values() {
+            offsets.push(end);
+            end = align(end + payload.len());
+        }
+
+        let mut bytes = Vec::with_capacity(end);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&count.to_le_bytes());
+        bytes.extend_from_slice(
+            &u32::try_from(toc_len)
+                .map_err(|_| too_large())?
+                .to_le_bytes(),
+        );
+        for ((key, (kind, payload)), offset) in self.entries.iter().zip(&offsets) {
+            bytes.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.push(*kind as u8);
+            bytes.extend_from_slice(&(*offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        }
+        for ((_, payload), offset) in self.entries.values().zip(offsets) {
+            bytes.resize(offset, 0);
+            bytes.extend_from_slice(payload);
+        }
+        bytes.resize(end, 0);
+        Ok(bytes)
+    }
+}
+
+/// One table of contents entry.
+#[derive(Debug, Clone, Copy)]
+struct Entry<'a> {
+    key: &'a str,
+    kind: SketchKind,
+    payload: &'a [u8],
+}
+
+/// Random access to the sketches in a container, by key.
+///
+/// Borrows the container bytes, typically a memory-mapped file. Opening
+/// reads and checks only the header and table of contents; a payload is read
+/// when its key is looked up.
+#[derive(Debug, Clone)]
+pub struct ContainerReader<'a> {
+    entries: Vec<Entry<'a>>,
+}
+
+impl<'a> ContainerReader<'a> {
+    /// Opens a container written by [`ContainerWriter`].
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let invalid =
+            |msg: &str| DataSketchesError::DeserializationError(format!("container: {}", msg));
+        let header = bytes
+            .get(..HEADER_LEN)
+            .ok_or_else(|| invalid("truncated header"))?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let count = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let toc_len = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        let mut toc = bytes
+            .get(HEADER_LEN..HEADER_LEN + toc_len)
+            .ok_or_else(|| invalid("truncated table of contents"))?;
+
+        let mut take = |len: usize| -> Result<&'a [u8]> {
+            if toc.len() < len {
+                return Err(invalid("truncated table of contents"));
+            }
+            let (head, rest) = toc.split_at(len);
+            toc = rest;
+            Ok(head)
+        };
+        let mut entries: Vec<Entry<'a>> =
+            Vec::with_capacity(count.min(toc_len / toc_entry_len("")));
+        for _ in 0..count {
+            let key_len = u16::from_le_bytes(take(2)?.try_into().unwrap()) as usize;
+            let key =
+                std::str::from_utf8(take(key_len)?).map_err(|_| invalid("key is not UTF-8"))?;
+            let kind = SketchKind::from_tag(take(1)?[0])?;
+            let offset = u64::from_le_bytes(take(8)?.try_into().unwrap());
+            let len = u64::from_le_bytes(take(8)?.try_into().unwrap());
+            let payload = usize::try_from(offset)
+                .ok()
+                .zip(usize::try_from(len).ok())
+                .and_then(|(offset, len)| bytes.get(offset..offset.checked_add(len)?))
+                .ok_or_else(|| invalid(&format!("payload of {:?} out of bounds", key)))?;
+            if entries.last().is_some_and(|last| last.key >= key) {
+                return Err(invalid("keys out of order"));
+            }
+            entries.push(Entry { key, kind, payload });
+        }
+        if !toc.is_empty() {
+            return Err(invalid("trailing bytes in table of contents"));
+        }
+        Ok(ContainerReader { entries })
+    }
+
+    /// Returns the number of sketches in the container.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the container holds no sketches.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the keys in sorted order.
+    pub fn keys(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.entries.iter().map(|entry| entry.key)
+    }
+
+    /// Returns true if the container holds a sketch under `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Returns the item type and serialized bytes stored under `key`,
+    /// borrowed from the container.
+    pub fn get_raw(&self, key: &str) -> Option<(SketchKind, &'a [u8])> {
+        self.find(key).map(|entry| (entry.kind, entry.payload))
+    }
+
+    /// Deserializes the sketch stored under `key`, or returns `None` if there
+    /// is none.
+    pub fn get(&self, key: &str) -> Result<Option<AnySketch>> {
+        self.get_raw(key)
+            .map(|(kind, payload)| match kind {
+                SketchKind::Float => KllFloatSketch::deserialize(payload).map(AnySketch::Float),
+                SketchKind::Double => KllDoubleSketch::deserialize(payload).map(AnySketch::Double),
+            })
+            .transpose()
+    }
+
+    fn find(&self, key: &str) -> Option<&Entry<'a>> {
+        self.entries
+            .binary_search_by(|entry| entry.key.cmp(key))
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_round_trip() {
+        let mut writer = ContainerWriter::new();
+        for series in 0..20u32 {
+            let mut sketch = KllFloatSketch::new();
+            for i in 0..=series * 100 {
+                sketch.update(i as f32);
+            }
+            writer
+                .insert(format!("series.{:02}", series), &sketch)
+                .unwrap();
+        }
+        let mut double = KllDoubleSketch::new();
+        double.update(1.5);
+        writer.insert("double", &AnySketch::from(double)).unwrap();
+        assert!(writer
+            .insert("x".repeat(70_000), &KllDoubleSketch::new())
+            .is_err());
+        assert_eq!(writer.len(), 21);
+
+        let mut bytes = Vec::new();
+        writer.write_to(&mut bytes).unwrap();
+        let reader = ContainerReader::new(&bytes).unwrap();
+        assert_eq!(reader.len(), 21);
+        assert_eq!(reader.keys().next(), Some("double"));
+
+        let sketch = reader.get("series.07").unwrap().unwrap();
+        assert_eq!((sketch.kind(), sketch.get_n()), (SketchKind::Float, 701));
+        assert_eq!(reader.get("double").unwrap().unwrap().get_max_value(), 1.5);
+        assert!(reader.get("series.20").unwrap().is_none());
+        for key in reader.keys() {
+            let (_, payload) = reader.get_raw(key).unwrap();
+            let offset = payload.as_ptr() as usize - bytes.as_ptr() as usize;
+            assert_eq!(offset % ALIGN, 0);
+        }
+    }
+
+    #[test]
+    fn test_rejects_damaged_containers() {
+        let mut writer = ContainerWriter::new();
+        writer.insert("a", &KllDoubleSketch::new()).unwrap();
+        writer.insert("b", &KllDoubleSketch::new()).unwrap();
+        let bytes = writer.to_bytes().unwrap();
+        assert!(ContainerReader::new(&bytes).is_ok());
+
+        assert!(ContainerReader::new(&bytes[..10]).is_err());
+        assert!(ContainerReader::new(&bytes[..bytes.len() - 8]).is_err());
+        let mut swapped = bytes.clone();
+        let (a, b) = (HEADER_LEN + 2, HEADER_LEN + toc_entry_len("a") + 2);
+        swapped.swap(a, b);
+        assert!(ContainerReader::new(&swapped).is_err());
+        let mut magic = bytes;
+        magic[0] = b'X';
+        assert!(ContainerReader::new(&magic).is_err());
+
+        let empty = ContainerWriter::new().to_bytes().unwrap();
+        assert!(ContainerReader::new(&empty).unwrap().is_empty());
+    }
+}
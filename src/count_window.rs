@@ -0,0 +1,201 @@
+//! Quantiles over the most recent N observations.
+
+use crate::error::{DataSketchesError, Result};
+use crate::events::event;
+use crate::kparam::KParam;
+use crate::traits::SketchLike;
+use crate::KllDoubleSketch;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// Approximate quantiles over the last `window` observations, whatever the
+/// time span they arrived in.
+///
+/// Observations go into a pane of `window / panes` values (rounded up); when
+/// the pane fills it is sealed and a new one starts, and the oldest sealed
+/// pane is dropped once enough newer ones cover the window. Queries merge the
+/// current pane with the sealed ones, so once warm they cover at least the
+/// last `window` observations. When `panes` divides `window` the overshoot is
+/// less than one pane, otherwise less than two; more panes make it smaller at
+/// the cost of more sketches.
+///
+/// ```
+/// use kll_rs::{CountWindowSketch, KParam};
+///
+/// let mut ticks = CountWindowSketch::new(10_000, 10, KParam::default())?;
+/// for i in 0..50_000 {
+///     ticks.update(i as f64);
+/// }
+/// assert!(ticks.get_n() >= 10_000 && ticks.get_n() < 11_000);
+/// assert!(ticks.get_quantile(0.0) >= 39_000.0);
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct CountWindowSketch {
+    k: KParam,
+    window: u64,
+    pane_len: u64,
+    /// Sealed panes, oldest first.
+    sealed: VecDeque<KllDoubleSketch>,
+    /// Union of `sealed`, rebuilt whenever a pane is sealed or dropped.
+    sealed_union: KllDoubleSketch,
+    current: KllDoubleSketch,
+}
+
+impl CountWindowSketch {
+    /// Creates a window over the last `window` observations, split into
+    /// `panes` panes whose sketches have the given k.
+    pub fn new(window: u64, panes: u64, k: KParam) -> Result<Self> {
+        if window == 0 || panes == 0 || panes > window {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "count window needs 1 <= panes <= window, got {} panes over {}",
+                panes, window
+            )));
+        }
+        Ok(CountWindowSketch {
+            k,
+            window,
+            pane_len: window / panes + u64::from(window % panes != 0),
+            sealed: VecDeque::new(),
+            sealed_union: KllDoubleSketch::with_k(k),
+            current: KllDoubleSketch::with_k(k),
+        })
+    }
+
+    /// Returns the number of observations the window is meant to cover.
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    /// Returns the number of observations per pane.
+    pub fn pane_len(&self) -> u64 {
+        self.pane_len
+    }
+
+    /// Adds an observation, sealing the current pane if it is full.
+    pub fn update(&mut self, value: f64) {
+        self.current.update(value);
+        if self.current.get_n() == self.pane_len {
+            let start = Instant::now();
+            let pane = std::mem::replace(&mut self.current, KllDoubleSketch::with_k(self.k));
+            self.sealed.push_back(pane);
+            // Keep just enough sealed panes to cover the window on their own.
+            let mut dropped = 0;
+            while (self.sealed.len() as u64 - 1) * self.pane_len >= self.window {
+                self.sealed.pop_front();
+                dropped += 1;
+            }
+            let mut union = KllDoubleSketch::with_k(self.k);
+            for pane in &self.sealed {
+                union.merge(pane).expect("panes share the same k");
+            }
+            self.sealed_union = union;
+            event!(
+                debug,
+                "rotated count window pane",
+                panes = self.sealed.len(),
+                dropped = dropped,
+                micros = start.elapsed().as_micros() as u64,
+            );
+        }
+    }
+
+    /// Returns one sketch of the observations currently in the window.
+    pub fn to_sketch(&self) -> KllDoubleSketch {
+        let mut sketch = self.sealed_union.clone();
+        sketch.merge(&self.current).expect("panes share the same k");
+        sketch
+    }
+
+    /// Returns the number of observations covered, at least the window
+    /// length once that many have been seen.
+    pub fn get_n(&self) -> u64 {
+        self.sealed_union.get_n() + self.current.get_n()
+    }
+
+    /// Returns true if nothing has been observed.
+    pub fn is_empty(&self) -> bool {
+        self.get_n() == 0
+    }
+
+    /// Returns the approximate quantile of the window, or NaN if it is empty.
+    ///
+    /// Use [`to_sketch`](Self::to_sketch) to answer several queries from one
+    /// merge.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        self.to_sketch().get_quantile(fraction)
+    }
+
+    /// Returns the fraction of the window at or below `value`, or NaN if it
+    /// is empty.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.to_sketch().get_rank(value)
+    }
+}
+
+impl SketchLike for CountWindowSketch {
+    fn update(&mut self, value: f64) {
+        CountWindowSketch::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        CountWindowSketch::get_n(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        CountWindowSketch::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        self.to_sketch().get_quantiles(fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        CountWindowSketch::get_rank(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_window() {
+        assert!(CountWindowSketch::new(0, 1, KParam::default()).is_err());
+        assert!(CountWindowSketch::new(10, 11, KParam::default()).is_err());
+
+        let mut window = CountWindowSketch::new(1000, 4, KParam::default()).unwrap();
+        assert!(window.get_quantile(0.5).is_nan());
+        for i in 0..600 {
+            window.update(i as f64);
+        }
+        assert_eq!(window.get_n(), 600);
+
+        for i in 600..10_000 {
+            window.update(i as f64);
+            let n = window.get_n();
+            if i < 1000 {
+                assert_eq!(n, i + 1);
+            } else {
+                assert!((1000..1250).contains(&n), "{} covers {}", i, n);
+            }
+        }
+        // 10,000 values end on a pane boundary, so exactly the last 1,000.
+        let sketch = window.to_sketch();
+        assert_eq!(sketch.get_n(), 1000);
+        assert_eq!(sketch.get_min_value(), 9000.0);
+        assert_eq!(sketch.get_max_value(), 9999.0);
+    }
+
+    #[test]
+    fn test_uneven_panes() {
+        let mut window = CountWindowSketch::new(10, 3, KParam::default()).unwrap();
+        assert_eq!(window.pane_len(), 4);
+        for i in 0..100 {
+            window.update(i as f64);
+            assert!(window.get_n() < 10 + 2 * 4, "{}", window.get_n());
+        }
+        assert!(window.get_n() >= 10);
+        assert_eq!(window.to_sketch().get_max_value(), 99.0);
+    }
+}
@@ -0,0 +1,149 @@
+//! Encrypted serialization, for sketches kept at rest.
+//!
+//! The serialized sketch is sealed with ChaCha20-Poly1305 in a versioned
+//! envelope:
+//!
+//! ```text
+//! magic "KLLE" | version u8 | algorithm u8 | nonce [u8; 12] | ciphertext and 16-byte tag
+//! ```
+//!
+//! The first six bytes are authenticated along with the ciphertext, so a
+//! tampered header fails decryption like a tampered payload. Every envelope
+//! gets a fresh random nonce, so one key can seal any number of sketches.
+//! With the `zeroize` feature the plaintext copies are zeroed once used.
+
+use crate::error::{DataSketchesError, Result};
+use crate::sketch_type::ScratchBytes;
+use crate::{KllDoubleSketch, KllFloatSketch};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+/// Length of the keys accepted by the encrypted serialization methods.
+pub const ENCRYPTION_KEY_LEN: usize = 32;
+
+const MAGIC: &[u8; 4] = b"KLLE";
+const ENVELOPE_VERSION: u8 = 1;
+const ALGORITHM_CHACHA20_POLY1305: u8 = 1;
+const HEADER_LEN: usize = 6;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+fn header() -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    header[4] = ENVELOPE_VERSION;
+    header[5] = ALGORITHM_CHACHA20_POLY1305;
+    header
+}
+
+/// Seals `plaintext` into an envelope under `key`.
+fn seal(key: &[u8; ENCRYPTION_KEY_LEN], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let header = header();
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| DataSketchesError::SerializationError("encryption failed".to_string()))?;
+    let mut envelope = Vec::with_capacity(HEADER_LEN + NONCE_LEN + ciphertext.len());
+    envelope.extend_from_slice(&header);
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Opens an envelope sealed by [`seal`], checking its framing and tag.
+fn open(key: &[u8; ENCRYPTION_KEY_LEN], envelope: &[u8]) -> Result<Vec<u8>> {
+    let error = |message: String| DataSketchesError::DeserializationError(message);
+    if envelope.len() < HEADER_LEN + NONCE_LEN + TAG_LEN {
+        return Err(error(format!(
+            "{} bytes are too short for an encrypted sketch",
+            envelope.len()
+        )));
+    }
+    let (header, rest) = envelope.split_at(HEADER_LEN);
+    if &header[..4] != MAGIC {
+        return Err(error("not an encrypted sketch envelope".to_string()));
+    }
+    if header[4] != ENVELOPE_VERSION {
+        return Err(error(format!("unsupported envelope version {}", header[4])));
+    }
+    if header[5] != ALGORITHM_CHACHA20_POLY1305 {
+        return Err(error(format!("unsupported cipher {}", header[5])));
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| error("wrong key or corrupted encrypted sketch".to_string()))
+}
+
+macro_rules! encrypted_serialization {
+    ($Sketch:ident) => {
+        impl $Sketch {
+            /// Serializes the sketch and encrypts it with ChaCha20-Poly1305
+            /// under a 32-byte key.
+            pub fn serialize_encrypted(&self, key: &[u8; ENCRYPTION_KEY_LEN]) -> Result<Vec<u8>> {
+                let plaintext = ScratchBytes::from(self.serialize()?);
+                seal(key, &plaintext)
+            }
+
+            /// Decrypts and deserializes bytes from
+            /// [`serialize_encrypted`](Self::serialize_encrypted), failing if
+            /// the key is wrong or the bytes were altered.
+            pub fn deserialize_encrypted(
+                bytes: &[u8],
+                key: &[u8; ENCRYPTION_KEY_LEN],
+            ) -> Result<Self> {
+                let plaintext = ScratchBytes::from(open(key, bytes)?);
+                Self::deserialize(&plaintext)
+            }
+        }
+    };
+}
+
+encrypted_serialization!(KllFloatSketch);
+encrypted_serialization!(KllDoubleSketch);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let key = [7u8; ENCRYPTION_KEY_LEN];
+        let mut sketch = KllDoubleSketch::new();
+        for i in 1..=100 {
+            sketch.update(i as f64);
+        }
+        let sealed = sketch.serialize_encrypted(&key).unwrap();
+        assert_eq!(&sealed[..4], MAGIC);
+        assert_ne!(sealed, sketch.serialize_encrypted(&key).unwrap());
+        let restored = KllDoubleSketch::deserialize_encrypted(&sealed, &key).unwrap();
+        assert_eq!(restored.get_quantile(0.5), 50.0);
+
+        assert!(KllDoubleSketch::deserialize_encrypted(&sealed, &[8u8; 32]).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(KllDoubleSketch::deserialize_encrypted(&tampered, &key).is_err());
+        let mut reframed = sealed.clone();
+        reframed[4] = 2;
+        assert!(KllDoubleSketch::deserialize_encrypted(&reframed, &key).is_err());
+        assert!(KllDoubleSketch::deserialize_encrypted(&sealed[..20], &key).is_err());
+
+        let float = KllFloatSketch::new().serialize_encrypted(&key).unwrap();
+        assert!(KllFloatSketch::deserialize_encrypted(&float, &key)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+//! Conversion to and from DDSketch logarithmic buckets.
+//!
+//! DDSketch stores counts in buckets whose bounds grow by a factor `gamma`,
+//! which bounds the relative error of every quantile by
+//! `(gamma - 1) / (gamma + 1)`. This module uses the mapping of the DDSketch
+//! paper and of the Java and Python libraries: positive values `v` go to
+//! bucket `ceil(log_gamma(v)) + index_offset`, which covers
+//! `(gamma^(i-1), gamma^i]`; negative values go to the bucket of `-v` in a
+//! separate store; zero has a count of its own. Mappings that round down
+//! instead, such as the Go library's, line up with an `index_offset` one
+//! lower than theirs.
+//!
+//! Converting a KLL sketch to buckets keeps the relative error bound of the
+//! buckets on top of the sketch's rank error. Converting buckets to a KLL
+//! sketch places every count at the bucket's representative value.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+use std::collections::BTreeMap;
+
+/// Counts in DDSketch logarithmic buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DdBuckets {
+    /// Ratio between the bounds of consecutive buckets.
+    pub gamma: f64,
+    /// Added to every bucket index.
+    pub index_offset: i32,
+    /// Count of values too close to zero to index.
+    pub zero_count: f64,
+    /// Counts of positive values by bucket index.
+    pub positive: BTreeMap<i32, f64>,
+    /// Counts of negative values, by the bucket index of their magnitude.
+    pub negative: BTreeMap<i32, f64>,
+}
+
+impl DdBuckets {
+    /// Creates empty buckets with the given growth factor.
+    pub fn new(gamma: f64) -> Result<Self> {
+        if !(gamma > 1.0 && gamma.is_finite()) {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "DDSketch gamma must be finite and greater than 1, got {}",
+                gamma
+            )));
+        }
+        Ok(DdBuckets {
+            gamma,
+            index_offset: 0,
+            zero_count: 0.0,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+        })
+    }
+
+    /// Creates empty buckets guaranteeing the given relative accuracy, such
+    /// as `0.01` for 1%.
+    pub fn with_relative_accuracy(accuracy: f64) -> Result<Self> {
+        if !(accuracy > 0.0 && accuracy < 1.0) {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "DDSketch relative accuracy must be between 0 and 1, got {}",
+                accuracy
+            )));
+        }
+        Self::new((1.0 + accuracy) / (1.0 - accuracy))
+    }
+
+    /// Returns the relative accuracy of the buckets.
+    pub fn relative_accuracy(&self) -> f64 {
+        (self.gamma - 1.0) / (self.gamma + 1.0)
+    }
+
+    /// Returns the index of the bucket holding a positive magnitude.
+    pub fn index(&self, magnitude: f64) -> i32 {
+        (magnitude.ln() / self.gamma.ln()).ceil() as i32 + self.index_offset
+    }
+
+    /// Returns the value standing for bucket `index`, within the relative
+    /// accuracy of everything in the bucket.
+    pub fn value(&self, index: i32) -> f64 {
+        let upper = self.gamma.powi(index - self.index_offset);
+        2.0 * upper / (self.gamma + 1.0)
+    }
+
+    /// Adds `count` occurrences of `value`.
+    pub fn add(&mut self, value: f64, count: f64) {
+        if value.abs() < self.min_indexable() {
+            self.zero_count += count;
+        } else if value > 0.0 {
+            *self.positive.entry(self.index(value)).or_insert(0.0) += count;
+        } else {
+            *self.negative.entry(self.index(-value)).or_insert(0.0) += count;
+        }
+    }
+
+    /// Returns the total count.
+    pub fn count(&self) -> f64 {
+        self.zero_count + self.positive.values().sum::<f64>() + self.negative.values().sum::<f64>()
+    }
+
+    /// Smallest magnitude whose bucket bounds are representable.
+    fn min_indexable(&self) -> f64 {
+        f64::MIN_POSITIVE * self.gamma
+    }
+}
+
+impl KllDoubleSketch {
+    /// Converts the sketch into DDSketch buckets with growth factor `gamma`.
+    ///
+    /// Every retained item lands in its bucket with its weight as the count.
+    pub fn to_dd_buckets(&self, gamma: f64) -> Result<DdBuckets> {
+        let mut buckets = DdBuckets::new(gamma)?;
+        for (item, weight) in self.to_weighted_values() {
+            buckets.add(item, weight as f64);
+        }
+        Ok(buckets)
+    }
+
+    /// Builds a sketch with the given k from DDSketch buckets.
+    ///
+    /// Each bucket's count is rounded to a whole number of values placed at
+    /// the bucket's representative value. The work grows with the number of
+    /// buckets and the logarithm of their counts, not with the total count.
+    pub fn from_dd_buckets(buckets: &DdBuckets, k: KParam) -> Result<Self> {
+        DdBuckets::new(buckets.gamma)?;
+        let mut sketch = KllDoubleSketch::with_k(k);
+        let negative = buckets
+            .negative
+            .iter()
+            .rev()
+            .map(|(&index, &count)| (-buckets.value(index), count));
+        let positive = buckets
+            .positive
+            .iter()
+            .map(|(&index, &count)| (buckets.value(index), count));
+        let zero = std::iter::once((0.0, buckets.zero_count));
+        for (value, count) in negative.chain(zero).chain(positive) {
+            if !(count >= 0.0 && count.is_finite()) {
+                return Err(DataSketchesError::InvalidParameter(format!(
+                    "DDSketch bucket at {} has count {}",
+                    value, count
+                )));
+            }
+            add_repeated(&mut sketch, value, count.round() as u64)?;
+        }
+        Ok(sketch)
+    }
+}
+
+/// Adds `count` copies of `value` with a logarithmic number of merges, by
+/// doubling a sketch of the value and merging in the powers of two that make
+/// up `count`.
+fn add_repeated(sketch: &mut KllDoubleSketch, value: f64, count: u64) -> Result<()> {
+    if count == 0 {
+        return Ok(());
+    }
+    let mut power = KllDoubleSketch::new_with_k(sketch.get_k())?;
+    power.update(value);
+    let mut remaining = count;
+    loop {
+        if remaining & 1 == 1 {
+            sketch.merge(&power)?;
+        }
+        remaining >>= 1;
+        if remaining == 0 {
+            return Ok(());
+        }
+        let copy = power.clone();
+        power.merge(&copy)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dd_bucket_mapping() {
+        assert!(DdBuckets::new(1.0).is_err());
+        assert!(DdBuckets::with_relative_accuracy(0.0).is_err());
+
+        let buckets = DdBuckets::with_relative_accuracy(0.01).unwrap();
+        assert!((buckets.relative_accuracy() - 0.01).abs() < 1e-12);
+        for value in [1e-9, 0.37, 1.0, 12.5, 4096.0, 7.3e12] {
+            let estimate = buckets.value(buckets.index(value));
+            assert!(
+                ((estimate - value) / value).abs() <= 0.01 + 1e-9,
+                "{} -> {}",
+                value,
+                estimate
+            );
+        }
+        assert_eq!(buckets.index(buckets.gamma.powi(5)), 5);
+    }
+
+    #[test]
+    fn test_dd_round_trip() {
+        let mut sketch = KllDoubleSketch::new();
+        for i in -500..=2000 {
+            sketch.update(i as f64 * 0.5);
+        }
+        let buckets = sketch.to_dd_buckets(1.02).unwrap();
+        assert_eq!(buckets.count(), sketch.get_n() as f64);
+        assert!(!buckets.negative.is_empty() && !buckets.positive.is_empty());
+
+        let restored = KllDoubleSketch::from_dd_buckets(&buckets, KParam::default()).unwrap();
+        assert_eq!(restored.get_n(), sketch.get_n());
+        let accuracy = buckets.relative_accuracy();
+        // Both sketches compact independently, each within its rank error
+        // of the 1250-wide input range.
+        let rank_slack = 2.0 * sketch.get_normalized_rank_error(false) * 1250.0;
+        for fraction in [0.05, 0.25, 0.5, 0.9, 0.99] {
+            let expected = sketch.get_quantile(fraction);
+            let actual = restored.get_quantile(fraction);
+            assert!(
+                (actual - expected).abs() <= expected.abs() * accuracy + rank_slack,
+                "{}: {} vs {}",
+                fraction,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_large_bucket_counts() {
+        let mut buckets = DdBuckets::new(1.05).unwrap();
+        buckets.add(100.0, 3_000_000_000.0);
+        buckets.add(200.0, 1_000_000_000.0);
+        let sketch = KllDoubleSketch::from_dd_buckets(&buckets, KParam::default()).unwrap();
+        assert_eq!(sketch.get_n(), 4_000_000_000);
+        assert!((sketch.get_quantile(0.5) - 100.0).abs() < 5.0);
+        assert!((sketch.get_quantile(0.9) - 200.0).abs() < 10.0);
+
+        buckets.add(1.0, -1.0);
+        assert!(KllDoubleSketch::from_dd_buckets(&buckets, KParam::default()).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Process-wide default parameters.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use std::sync::OnceLock;
+
+/// k used by `new()` when no process-wide default has been set.
+pub const DEFAULT_K: u16 = 200;
+
+static PROCESS_DEFAULT_K: OnceLock<u16> = OnceLock::new();
+
+/// Sets the k used by `new()` and `Default` for every sketch in the process.
+///
+/// The default is fixed the first time it is read, so this must be called
+/// before any sketch is created with `new()`. Fails if the default has
+/// already been fixed or `k` is smaller than 8.
+pub fn set_default_k(k: u16) -> Result<()> {
+    KParam::new(k)?;
+    PROCESS_DEFAULT_K
+        .set(k)
+        .map_err(|_| DataSketchesError::InvalidParameter("default k is already in use".to_string()))
+}
+
+/// Returns the k used by `new()` and `Default`.
+pub fn default_k() -> u16 {
+    *PROCESS_DEFAULT_K.get_or_init(|| DEFAULT_K)
+}
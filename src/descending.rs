@@ -0,0 +1,158 @@
+//! Quantiles over a descending ordering of the values.
+
+use crate::error::Result;
+use crate::kparam::KParam;
+use crate::traits::SketchLike;
+use crate::KllDoubleSketch;
+
+/// A sketch whose ranks and quantiles run from the largest value down.
+///
+/// Suits rankings where the best score comes first: `get_quantile(0.01)` is
+/// the score of the top 1% and `get_rank(score)` the fraction of values at or
+/// above `score`. The values are kept negated in an ordinary
+/// [`KllDoubleSketch`], so the accuracy guarantees are unchanged.
+///
+/// ```
+/// use kll_rs::DescendingSketch;
+///
+/// let mut scores = DescendingSketch::new();
+/// for score in 1..=100 {
+///     scores.update(score as f64);
+/// }
+/// assert_eq!(scores.get_quantile(0.1), 91.0);
+/// assert_eq!(scores.get_rank(91.0), 0.1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DescendingSketch {
+    negated: KllDoubleSketch,
+}
+
+impl DescendingSketch {
+    /// Creates an empty sketch with the default k.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty sketch with the given k.
+    pub fn with_k(k: KParam) -> Self {
+        DescendingSketch {
+            negated: KllDoubleSketch::with_k(k),
+        }
+    }
+
+    /// Updates the sketch with a new value.
+    pub fn update(&mut self, value: f64) {
+        self.negated.update(-value);
+    }
+
+    /// Merges another descending sketch into this one.
+    pub fn merge(&mut self, other: &DescendingSketch) -> Result<()> {
+        self.negated.merge(&other.negated)
+    }
+
+    /// Returns the number of values processed.
+    pub fn get_n(&self) -> u64 {
+        self.negated.get_n()
+    }
+
+    /// Returns true if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.negated.is_empty()
+    }
+
+    /// Returns the value `fraction` of the way down from the largest value,
+    /// or NaN if the sketch is empty.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        -self.negated.get_quantile(fraction)
+    }
+
+    /// Returns quantiles for multiple fractions, in the order given.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        self.negated
+            .get_quantiles(fractions)
+            .into_iter()
+            .map(|value| -value)
+            .collect()
+    }
+
+    /// Returns the fraction of values greater than or equal to `value`, or
+    /// NaN if the sketch is empty.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.negated.get_rank(-value)
+    }
+
+    /// Returns the largest value, which comes first in this ordering.
+    pub fn first(&self) -> f64 {
+        -self.negated.get_min_value()
+    }
+
+    /// Returns the smallest value, which comes last in this ordering.
+    pub fn last(&self) -> f64 {
+        -self.negated.get_max_value()
+    }
+
+    /// Returns the underlying sketch, which holds the negated values.
+    pub fn as_negated(&self) -> &KllDoubleSketch {
+        &self.negated
+    }
+
+    /// Wraps a sketch of negated values, such as one returned by
+    /// [`into_negated`](Self::into_negated) and stored.
+    pub fn from_negated(negated: KllDoubleSketch) -> Self {
+        DescendingSketch { negated }
+    }
+
+    /// Returns the underlying sketch, which holds the negated values.
+    pub fn into_negated(self) -> KllDoubleSketch {
+        self.negated
+    }
+}
+
+impl SketchLike for DescendingSketch {
+    fn update(&mut self, value: f64) {
+        DescendingSketch::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        DescendingSketch::get_n(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        DescendingSketch::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        DescendingSketch::get_quantiles(self, fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        DescendingSketch::get_rank(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descending_order() {
+        let mut a = DescendingSketch::with_k(KParam::new(200).unwrap());
+        let mut b = DescendingSketch::with_k(KParam::new(200).unwrap());
+        for score in 1..=50 {
+            a.update(score as f64);
+            b.update((score + 50) as f64);
+        }
+        a.merge(&b).unwrap();
+        assert_eq!(a.get_n(), 100);
+        assert_eq!((a.first(), a.last()), (100.0, 1.0));
+        assert_eq!(a.get_quantiles(&[0.0, 0.5, 1.0]), vec![100.0, 51.0, 1.0]);
+        assert_eq!(a.get_rank(100.0), 0.01);
+        assert_eq!(a.get_rank(1.0), 1.0);
+
+        let bytes = a.as_negated().serialize().unwrap();
+        let restored =
+            DescendingSketch::from_negated(KllDoubleSketch::deserialize(&bytes).unwrap());
+        assert_eq!(restored.get_quantile(0.5), 51.0);
+        assert!(DescendingSketch::new().get_quantile(0.5).is_nan());
+    }
+}
@@ -0,0 +1,324 @@
+//! Crash-safe sketches backed by a write-ahead log and periodic snapshots.
+//!
+//! A [`DurableSketch`] keeps two files in its directory:
+//!
+//! - `snapshot`, the serialized sketch as of the last checkpoint, prefixed
+//!   with the checkpoint generation;
+//! - `wal`, the values recorded since that checkpoint, one checksummed
+//!   record per value, after a header naming the generation it extends.
+//!
+//! A checkpoint writes the new snapshot under a temporary name and renames it
+//! into place, then starts a fresh log the same way. Recovery loads the
+//! snapshot and replays the log only if it extends that snapshot, so a crash
+//! between the two renames never counts a value twice. A record cut short by
+//! a crash ends the replay and is truncated away.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Updates between automatic checkpoints unless configured otherwise.
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const WAL_FILE: &str = "wal";
+const HEADER_LEN: usize = 8;
+const RECORD_LEN: usize = 12;
+
+/// A [`KllDoubleSketch`] whose updates survive a crash of the process.
+///
+/// Every update is appended to the log before it is applied, so after a
+/// crash [`open`](Self::open) recovers every value whose write returned.
+/// Writes reach the operating system immediately; call [`sync`](Self::sync)
+/// to also force them to disk ahead of a power loss.
+///
+/// ```
+/// use kll_rs::{DurableSketch, KParam};
+///
+/// # let dir = std::env::temp_dir().join(format!("kll_rs_durable_doc_{}", std::process::id()));
+/// let mut sketch = DurableSketch::open(&dir, KParam::default())?;
+/// sketch.update(12.5)?;
+/// sketch.update(40.0)?;
+/// drop(sketch);
+///
+/// let recovered = DurableSketch::open(&dir, KParam::default())?;
+/// assert_eq!(recovered.sketch().get_n(), 2);
+/// # std::fs::remove_dir_all(dir).unwrap();
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug)]
+pub struct DurableSketch {
+    dir: PathBuf,
+    sketch: KllDoubleSketch,
+    wal: File,
+    generation: u64,
+    pending: u64,
+    checkpoint_interval: u64,
+}
+
+impl DurableSketch {
+    /// Opens the sketch stored in `dir`, creating the directory and an empty
+    /// sketch with the given k if there is nothing to recover.
+    ///
+    /// The k of a recovered snapshot takes precedence over `k`.
+    pub fn open<P: AsRef<Path>>(dir: P, k: KParam) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| io_error(&dir, e))?;
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        let (generation, mut sketch) = match read_file(&snapshot_path)? {
+            Some(bytes) => read_snapshot(&snapshot_path, &bytes)?,
+            None => (0, KllDoubleSketch::with_k(k)),
+        };
+
+        let wal_path = dir.join(WAL_FILE);
+        let mut pending = 0;
+        match read_file(&wal_path)? {
+            Some(bytes) if header_generation(&bytes) == Some(generation) => {
+                let records = bytes[HEADER_LEN..].chunks_exact(RECORD_LEN);
+                for record in records {
+                    match decode_record(record) {
+                        Some(value) => sketch.update(value),
+                        None => break,
+                    }
+                    pending += 1;
+                }
+                let valid = HEADER_LEN as u64 + pending * RECORD_LEN as u64;
+                if valid < bytes.len() as u64 {
+                    let wal = OpenOptions::new()
+                        .write(true)
+                        .open(&wal_path)
+                        .map_err(|e| io_error(&wal_path, e))?;
+                    wal.set_len(valid).map_err(|e| io_error(&wal_path, e))?;
+                }
+            }
+            // Missing, torn header, or left over from before the snapshot.
+            _ => start_wal(&dir, generation)?,
+        }
+
+        let wal = OpenOptions::new()
+            .append(true)
+            .open(&wal_path)
+            .map_err(|e| io_error(&wal_path, e))?;
+        Ok(DurableSketch {
+            dir,
+            sketch,
+            wal,
+            generation,
+            pending,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+        })
+    }
+
+    /// Sets how many updates are logged before a checkpoint is taken
+    /// automatically. Zero disables automatic checkpoints.
+    pub fn set_checkpoint_interval(&mut self, updates: u64) {
+        self.checkpoint_interval = updates;
+    }
+
+    /// Logs a value and then adds it to the sketch.
+    pub fn update(&mut self, value: f64) -> Result<()> {
+        self.update_batch(&[value])
+    }
+
+    /// Logs several values with a single write and then adds them to the sketch.
+    pub fn update_batch(&mut self, values: &[f64]) -> Result<()> {
+        let mut buf = Vec::with_capacity(values.len() * RECORD_LEN);
+        for &value in values {
+            encode_record(&mut buf, value);
+        }
+        let path = self.dir.join(WAL_FILE);
+        self.wal.write_all(&buf).map_err(|e| io_error(&path, e))?;
+        for &value in values {
+            self.sketch.update(value);
+        }
+        self.pending += values.len() as u64;
+        if self.checkpoint_interval > 0 && self.pending >= self.checkpoint_interval {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a snapshot of the sketch and starts an empty log.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let generation = self.generation + 1;
+        let mut snapshot = generation.to_le_bytes().to_vec();
+        snapshot.extend_from_slice(&self.sketch.serialize()?);
+        replace_file(&self.dir, SNAPSHOT_FILE, &snapshot)?;
+        start_wal(&self.dir, generation)?;
+
+        let path = self.dir.join(WAL_FILE);
+        self.wal = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| io_error(&path, e))?;
+        self.generation = generation;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Forces the logged values to disk.
+    pub fn sync(&self) -> Result<()> {
+        self.wal
+            .sync_data()
+            .map_err(|e| io_error(&self.dir.join(WAL_FILE), e))
+    }
+
+    /// Returns the recovered and updated sketch.
+    pub fn sketch(&self) -> &KllDoubleSketch {
+        &self.sketch
+    }
+
+    /// Returns the number of values logged since the last checkpoint.
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// Returns the directory holding the snapshot and the log.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Releases the files and returns the sketch.
+    pub fn into_inner(self) -> KllDoubleSketch {
+        self.sketch
+    }
+}
+
+fn read_snapshot(path: &Path, bytes: &[u8]) -> Result<(u64, KllDoubleSketch)> {
+    let generation = header_generation(bytes).ok_or_else(|| {
+        DataSketchesError::DeserializationError(format!("{}: truncated snapshot", path.display()))
+    })?;
+    Ok((
+        generation,
+        KllDoubleSketch::deserialize(&bytes[HEADER_LEN..])?,
+    ))
+}
+
+fn header_generation(bytes: &[u8]) -> Option<u64> {
+    let header = bytes.get(..HEADER_LEN)?;
+    Some(u64::from_le_bytes(
+        header.try_into().expect("header is 8 bytes"),
+    ))
+}
+
+fn encode_record(buf: &mut Vec<u8>, value: f64) {
+    let bytes = value.to_le_bytes();
+    buf.extend_from_slice(&bytes);
+    buf.extend_from_slice(&checksum(&bytes).to_le_bytes());
+}
+
+fn decode_record(record: &[u8]) -> Option<f64> {
+    let (value, sum) = record.split_at(8);
+    let sum = u32::from_le_bytes(sum.try_into().expect("checksum is 4 bytes"));
+    (checksum(value) == sum)
+        .then(|| f64::from_le_bytes(value.try_into().expect("value is 8 bytes")))
+}
+
+/// FNV-1a, enough to tell a complete record from a torn or zeroed one.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn start_wal(dir: &Path, generation: u64) -> Result<()> {
+    replace_file(dir, WAL_FILE, &generation.to_le_bytes())
+}
+
+/// Atomically replaces `dir/name` with `bytes`.
+fn replace_file(dir: &Path, name: &str, bytes: &[u8]) -> Result<()> {
+    let path = dir.join(name);
+    let tmp = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&tmp).map_err(|e| io_error(&tmp, e))?;
+    file.write_all(bytes).map_err(|e| io_error(&tmp, e))?;
+    file.sync_all().map_err(|e| io_error(&tmp, e))?;
+    fs::rename(&tmp, &path).map_err(|e| io_error(&path, e))?;
+    // Persist the rename itself; directories cannot be opened on Windows.
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| io_error(dir, e))?;
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io_error(path, e)),
+    };
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)
+        .map_err(|e| io_error(path, e))?;
+    Ok(Some(bytes))
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> DataSketchesError {
+    DataSketchesError::Unknown(format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durable_sketch_recovery() {
+        let dir = std::env::temp_dir().join(format!("kll_rs_durable_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let k = KParam::new(64).unwrap();
+
+        let mut sketch = DurableSketch::open(&dir, k).unwrap();
+        sketch.set_checkpoint_interval(100);
+        for i in 0..250 {
+            sketch.update(i as f64).unwrap();
+        }
+        assert_eq!(sketch.pending(), 50);
+        sketch.sync().unwrap();
+        drop(sketch);
+
+        // Tear the last record as a crash mid-write would.
+        let wal = dir.join(WAL_FILE);
+        let len = fs::metadata(&wal).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&wal)
+            .unwrap()
+            .set_len(len - 5)
+            .unwrap();
+
+        let mut sketch = DurableSketch::open(&dir, KParam::default()).unwrap();
+        assert_eq!(sketch.sketch().get_k(), 64);
+        assert_eq!(sketch.sketch().get_n(), 249);
+        assert_eq!(sketch.pending(), 49);
+        sketch.update_batch(&[1000.0, 1001.0]).unwrap();
+        drop(sketch);
+
+        let recovered = DurableSketch::open(&dir, k).unwrap();
+        assert_eq!(recovered.sketch().get_n(), 251);
+        assert_eq!(recovered.sketch().get_max_value(), 1001.0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_log_is_not_replayed() {
+        let dir = std::env::temp_dir().join(format!("kll_rs_durable_stale_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut sketch = DurableSketch::open(&dir, KParam::default()).unwrap();
+        sketch.update_batch(&[1.0, 2.0, 3.0]).unwrap();
+        let stale = fs::read(dir.join(WAL_FILE)).unwrap();
+        sketch.checkpoint().unwrap();
+        drop(sketch);
+
+        // A crash after the snapshot rename but before the new log.
+        fs::write(dir.join(WAL_FILE), stale).unwrap();
+        let recovered = DurableSketch::open(&dir, KParam::default()).unwrap();
+        assert_eq!(recovered.sketch().get_n(), 3);
+        assert_eq!(recovered.pending(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
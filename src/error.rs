@@ -1,9 +1,11 @@
 //! Error types for DataSketches operations.
 
+use crate::registry::QuotaKind;
 use std::fmt;
 
 /// Error type for DataSketches operations.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error), uniffi(flat_error))]
 pub enum DataSketchesError {
     /// An error occurred during sketch creation.
     CreationError(String),
@@ -13,8 +15,14 @@ pub enum DataSketchesError {
     DeserializationError(String),
     /// An invalid parameter was provided.
     InvalidParameter(String),
+    /// Two sketches cannot be merged without losing accuracy or meaning.
+    IncompatibleMerge(String),
+    /// A new series would exceed a registry quota of `limit`.
+    QuotaExceeded { quota: QuotaKind, limit: usize },
     /// A null pointer was encountered.
     NullPointer,
+    /// An output buffer was too small; `needed` bytes are required.
+    BufferTooSmall { needed: usize, available: usize },
     /// An unknown error occurred.
     Unknown(String),
 }
@@ -28,7 +36,16 @@ impl fmt::Display for DataSketchesError {
                 write!(f, "Deserialization error: {}", msg)
             }
             DataSketchesError::InvalidParameter(msg) => write!(f, "Invalid parameter: {}", msg),
+            DataSketchesError::IncompatibleMerge(msg) => write!(f, "Incompatible merge: {}", msg),
+            DataSketchesError::QuotaExceeded { quota, limit } => {
+                write!(f, "Quota exceeded: {} limit of {}", quota, limit)
+            }
             DataSketchesError::NullPointer => write!(f, "Null pointer encountered"),
+            DataSketchesError::BufferTooSmall { needed, available } => write!(
+                f,
+                "Buffer too small: {} bytes needed, {} available",
+                needed, available
+            ),
             DataSketchesError::Unknown(msg) => write!(f, "Unknown error: {}", msg),
         }
     }
@@ -0,0 +1,60 @@
+//! Diagnostic events from the windowing, registry and export subsystems,
+//! emitted through `log` and `tracing` when those features are enabled.
+
+/// Emits an event at `$level` (`debug`, `warn`, ...) with `key = value`
+/// fields. Each value is evaluated once, whether or not anything listens.
+///
+/// With `tracing` the fields are recorded as structured fields; with `log`
+/// they are appended to the message as `key=value`.
+macro_rules! event {
+    ($level:ident, $message:literal $(, $key:ident = $value:expr)+ $(,)?) => {{
+        // `key = key` is allowed, like the field shorthand of `tracing`.
+        $(#[allow(clippy::redundant_locals)] let $key = $value;)+
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($key,)+ $message);
+        #[cfg(feature = "log")]
+        log::$level!(concat!($message $(, " ", stringify!($key), "={}")+) $(, $key)+);
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        let _ = ($($key,)+);
+    }};
+}
+
+pub(crate) use event;
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use crate::{CountWindowSketch, KParam};
+    use std::sync::Mutex;
+
+    static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if record.target() == "kll_rs::count_window" {
+                MESSAGES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_log_events() {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+        let mut window = CountWindowSketch::new(100, 2, KParam::default()).unwrap();
+        for i in 0..200 {
+            window.update(i as f64);
+        }
+        let messages = MESSAGES.lock().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert!(messages[0].starts_with("rotated count window pane panes=1 dropped=0 micros="));
+        assert!(messages[3].starts_with("rotated count window pane panes=2 dropped=1 micros="));
+    }
+}
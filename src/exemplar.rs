@@ -0,0 +1,369 @@
+//! Example values from the tail of a distribution, kept next to its sketch.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::traits::SketchLike;
+use crate::KllDoubleSketch;
+
+/// A sampled tail value, with the attachment recorded alongside it.
+///
+/// Attachments are opaque bytes, such as a trace or request id, that link
+/// the value back to where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    /// The sampled value.
+    pub value: f64,
+    /// Bytes passed to `update_with_attachment`, or `None` for plain updates.
+    pub attachment: Option<Vec<u8>>,
+}
+
+/// Updates between recomputations of the tail cutoff, once the sketch has
+/// seen that many values.
+const REFRESH_INTERVAL: u64 = 256;
+
+/// A sketch that also keeps a bounded sample of raw values from its tail.
+///
+/// Values at or above the quantile at `tail_rank` are sampled into a
+/// reservoir of at most `capacity` values, so a spike in a high percentile
+/// comes with actual values that caused it. The cutoff is recomputed every
+/// 256 updates; sampled values that fall below it as the distribution shifts
+/// are dropped and their slots refilled by later tail values, which keeps the
+/// sample close to, though not exactly, uniform over the current tail.
+///
+/// Values recorded with [`update_with_attachment`](Self::update_with_attachment)
+/// keep their attachment, such as a trace id, so a bad percentile can be
+/// followed to a specific request:
+///
+/// ```
+/// use kll_rs::TailExemplarSketch;
+///
+/// let mut latencies = TailExemplarSketch::new(0.99, 5).unwrap();
+/// for i in 0..10_000u32 {
+///     latencies.update_with_attachment((i % 1000) as f64, &i.to_be_bytes());
+/// }
+/// let (p999, exemplar) = latencies.get_quantile_with_exemplar(0.999);
+/// let exemplar = exemplar.unwrap();
+/// assert!(exemplar.value >= latencies.cutoff());
+/// assert!(p999 >= 990.0 && exemplar.attachment.is_some());
+/// ```
+///
+/// ```
+/// use kll_rs::TailExemplarSketch;
+///
+/// let mut latencies = TailExemplarSketch::new(0.99, 5).unwrap();
+/// for i in 0..10_000 {
+///     latencies.update((i % 1000) as f64);
+/// }
+/// latencies.update(60_000.0);
+/// let examples = latencies.tail_examples();
+/// assert_eq!(examples.len(), 5);
+/// assert!(examples.iter().all(|&ms| ms >= latencies.cutoff()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TailExemplarSketch {
+    sketch: KllDoubleSketch,
+    tail_rank: f64,
+    capacity: usize,
+    cutoff: f64,
+    reservoir: Vec<Exemplar>,
+    /// Tail values offered to the reservoir, for reservoir sampling.
+    offered: u64,
+    rng: u64,
+}
+
+impl TailExemplarSketch {
+    /// Creates a sketch with the default k that samples up to `capacity`
+    /// values at or above the quantile at `tail_rank`.
+    ///
+    /// `tail_rank` must lie in `[0, 1)` and `capacity` must be at least 1.
+    pub fn new(tail_rank: f64, capacity: usize) -> Result<Self> {
+        Self::with_sketch(KllDoubleSketch::new(), tail_rank, capacity)
+    }
+
+    /// Creates a sketch with a specific k, as [`new`](Self::new) does.
+    pub fn with_k(k: KParam, tail_rank: f64, capacity: usize) -> Result<Self> {
+        Self::with_sketch(KllDoubleSketch::with_k(k), tail_rank, capacity)
+    }
+
+    fn with_sketch(sketch: KllDoubleSketch, tail_rank: f64, capacity: usize) -> Result<Self> {
+        if !(0.0..1.0).contains(&tail_rank) {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "tail rank {} is outside [0, 1)",
+                tail_rank
+            )));
+        }
+        if capacity == 0 {
+            return Err(DataSketchesError::InvalidParameter(
+                "tail reservoir must hold at least one value".to_string(),
+            ));
+        }
+        Ok(TailExemplarSketch {
+            sketch,
+            tail_rank,
+            capacity,
+            cutoff: f64::NEG_INFINITY,
+            reservoir: Vec::with_capacity(capacity),
+            offered: 0,
+            rng: 0x2545_F491_4F6C_DD1D,
+        })
+    }
+
+    /// Updates the sketch, sampling the value if it lies in the tail.
+    pub fn update(&mut self, value: f64) {
+        self.record(value, None);
+    }
+
+    /// Updates the sketch, sampling the value together with a copy of
+    /// `attachment` if it lies in the tail.
+    ///
+    /// The attachment is only copied when the value is sampled.
+    pub fn update_with_attachment(&mut self, value: f64, attachment: &[u8]) {
+        self.record(value, Some(attachment));
+    }
+
+    fn record(&mut self, value: f64, attachment: Option<&[u8]>) {
+        if value.is_nan() {
+            return;
+        }
+        self.sketch.update(value);
+        let n = self.sketch.get_n();
+        if n < REFRESH_INTERVAL || n % REFRESH_INTERVAL == 0 {
+            self.refresh();
+        }
+        if value >= self.cutoff {
+            self.offer(value, attachment);
+        }
+    }
+
+    /// Merges another tail sketch into this one, pooling both samples.
+    ///
+    /// The pooled sample is filtered by the merged cutoff and thinned at
+    /// random back to this sketch's capacity.
+    pub fn merge(&mut self, other: &TailExemplarSketch) -> Result<()> {
+        self.sketch.merge(&other.sketch)?;
+        self.reservoir.extend_from_slice(&other.reservoir);
+        self.offered += other.offered;
+        self.refresh();
+        while self.reservoir.len() > self.capacity {
+            let evicted = self.random_below(self.reservoir.len() as u64) as usize;
+            self.reservoir.swap_remove(evicted);
+        }
+        Ok(())
+    }
+
+    /// Returns the sampled tail values, all at or above
+    /// [`cutoff`](Self::cutoff), largest first.
+    pub fn tail_examples(&self) -> Vec<f64> {
+        self.current_tail()
+            .into_iter()
+            .map(|exemplar| exemplar.value)
+            .collect()
+    }
+
+    /// Returns the sampled tail values with their attachments, as
+    /// [`tail_examples`](Self::tail_examples) does.
+    pub fn tail_exemplars(&self) -> Vec<Exemplar> {
+        self.current_tail().into_iter().cloned().collect()
+    }
+
+    /// Returns the quantile at `fraction` along with the sampled exemplar
+    /// closest to it.
+    ///
+    /// Only values in the tail are sampled, so the exemplar is `None` for
+    /// fractions below the tail rank, or when nothing has been sampled.
+    pub fn get_quantile_with_exemplar(&self, fraction: f64) -> (f64, Option<&Exemplar>) {
+        let quantile = self.get_quantile(fraction);
+        if quantile.is_nan() || fraction < self.tail_rank {
+            return (quantile, None);
+        }
+        let closest = self.current_tail().into_iter().min_by(|a, b| {
+            (a.value - quantile)
+                .abs()
+                .total_cmp(&(b.value - quantile).abs())
+        });
+        (quantile, closest)
+    }
+
+    /// Returns the samples, largest first.
+    fn current_tail(&self) -> Vec<&Exemplar> {
+        let mut tail: Vec<&Exemplar> = self.reservoir.iter().collect();
+        tail.sort_by(|a, b| b.value.total_cmp(&a.value));
+        tail
+    }
+
+    /// Returns the rank above which values are sampled.
+    pub fn tail_rank(&self) -> f64 {
+        self.tail_rank
+    }
+
+    /// Returns the value at or above which updates are sampled: the quantile
+    /// at the tail rank as of the last recomputation, or negative infinity
+    /// for an empty sketch.
+    pub fn cutoff(&self) -> f64 {
+        self.cutoff
+    }
+
+    /// Returns the most values the reservoir keeps.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of values processed.
+    pub fn get_n(&self) -> u64 {
+        self.sketch.get_n()
+    }
+
+    /// Returns true if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sketch.is_empty()
+    }
+
+    /// Returns the approximate quantile, or NaN if the sketch is empty.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        self.sketch.get_quantile(fraction)
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        self.sketch.get_quantiles(fractions)
+    }
+
+    /// Returns the approximate rank of a value.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.sketch.get_rank(value)
+    }
+
+    /// Returns the underlying sketch.
+    pub fn sketch(&self) -> &KllDoubleSketch {
+        &self.sketch
+    }
+
+    /// Recomputes the cutoff and drops samples that fell below it.
+    fn refresh(&mut self) {
+        self.cutoff = self.sketch.get_quantile(self.tail_rank);
+        let cutoff = self.cutoff;
+        let before = self.reservoir.len();
+        self.reservoir.retain(|exemplar| exemplar.value >= cutoff);
+        let kept = self.reservoir.len();
+        if kept < before {
+            // The sample is uniform over the values offered, so about the same
+            // share of those is still in the tail. Counting only that share
+            // lets the freed slots refill instead of staying empty.
+            let still_offered = self.offered as u128 * kept as u128 / before as u128;
+            self.offered = (still_offered as u64).max(kept as u64);
+        }
+    }
+
+    /// Offers a tail value to the reservoir (Algorithm R).
+    fn offer(&mut self, value: f64, attachment: Option<&[u8]>) {
+        self.offered += 1;
+        let slot = if self.reservoir.len() < self.capacity {
+            self.reservoir.len()
+        } else {
+            match self.random_below(self.offered) {
+                slot if slot < self.capacity as u64 => slot as usize,
+                _ => return,
+            }
+        };
+        let exemplar = Exemplar {
+            value,
+            attachment: attachment.map(<[u8]>::to_vec),
+        };
+        if slot == self.reservoir.len() {
+            self.reservoir.push(exemplar);
+        } else {
+            self.reservoir[slot] = exemplar;
+        }
+    }
+
+    /// Returns a pseudo-random number below `bound` from a splitmix64 stream.
+    fn random_below(&mut self, bound: u64) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) % bound
+    }
+}
+
+impl SketchLike for TailExemplarSketch {
+    fn update(&mut self, value: f64) {
+        TailExemplarSketch::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        TailExemplarSketch::get_n(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        TailExemplarSketch::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        TailExemplarSketch::get_quantiles(self, fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        TailExemplarSketch::get_rank(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_only_the_tail() {
+        assert!(TailExemplarSketch::new(1.0, 4).is_err());
+        assert!(TailExemplarSketch::new(0.9, 0).is_err());
+
+        let mut sketch = TailExemplarSketch::new(0.9, 8).unwrap();
+        for i in 0..20_000 {
+            sketch.update((i % 1000) as f64);
+        }
+        let examples = sketch.tail_examples();
+        assert_eq!(examples.len(), 8);
+        assert!(examples.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(
+            examples.iter().all(|&value| value >= 850.0),
+            "{:?}",
+            examples
+        );
+
+        let mut other = TailExemplarSketch::new(0.9, 8).unwrap();
+        for i in 0..20_000 {
+            other.update(5000.0 + i as f64);
+        }
+        sketch.merge(&other).unwrap();
+        assert_eq!(sketch.get_n(), 40_000);
+        let merged = sketch.tail_examples();
+        assert!(!merged.is_empty() && merged.len() <= 8);
+        assert!(merged.iter().all(|&value| value >= 5000.0), "{:?}", merged);
+    }
+
+    #[test]
+    fn test_attachments_follow_their_values() {
+        let mut sketch = TailExemplarSketch::new(0.95, 4).unwrap();
+        for i in 0..5000u32 {
+            if i % 7 == 0 {
+                sketch.update(i as f64);
+            } else {
+                sketch.update_with_attachment(i as f64, format!("req-{}", i).as_bytes());
+            }
+        }
+        let exemplars = sketch.tail_exemplars();
+        assert_eq!(exemplars.len(), 4);
+        for exemplar in &exemplars {
+            let plain = exemplar.value as u32 % 7 == 0;
+            let expected = (!plain).then(|| format!("req-{}", exemplar.value).into_bytes());
+            assert_eq!(exemplar.attachment, expected);
+        }
+
+        let (quantile, closest) = sketch.get_quantile_with_exemplar(0.99);
+        let closest = closest.unwrap();
+        assert!(exemplars
+            .iter()
+            .all(|e| (e.value - quantile).abs() >= (closest.value - quantile).abs()));
+        assert!(sketch.get_quantile_with_exemplar(0.5).1.is_none());
+    }
+}
@@ -0,0 +1,191 @@
+//! Injected failures for testing error handling, with the `failpoints`
+//! feature.
+
+use crate::error::{DataSketchesError, Result};
+use std::cell::RefCell;
+
+/// A sketch operation that can be made to fail on demand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FailPoint {
+    /// Serializing, failing with [`DataSketchesError::SerializationError`].
+    Serialize,
+    /// Deserializing, failing with [`DataSketchesError::DeserializationError`].
+    Deserialize,
+    /// Allocating a native sketch, for a new sketch's first native call or a
+    /// copy, failing with [`DataSketchesError::CreationError`]. Calls that
+    /// cannot report an error panic, as on a real allocation failure.
+    Allocate,
+    /// Merging, failing with [`DataSketchesError::Unknown`] before either
+    /// sketch changes.
+    Merge,
+}
+
+const POINTS: usize = 4;
+
+impl FailPoint {
+    /// Makes every following call through this point on the calling thread
+    /// fail, until the guard is dropped.
+    ///
+    /// ```
+    /// use kll_rs::testing::FailPoint;
+    /// use kll_rs::{DataSketchesError, KllDoubleSketch};
+    ///
+    /// let sketch = KllDoubleSketch::new();
+    /// let guard = FailPoint::Serialize.fail();
+    /// assert!(matches!(sketch.serialize(), Err(DataSketchesError::SerializationError(_))));
+    /// drop(guard);
+    /// assert!(sketch.serialize().is_ok());
+    /// ```
+    pub fn fail(self) -> FailGuard {
+        self.fail_after(0)
+    }
+
+    /// Lets `successes` calls through this point on the calling thread
+    /// succeed, then fails every later one until the guard is dropped.
+    pub fn fail_after(self, successes: u64) -> FailGuard {
+        let id = ARMED.with(|armed| {
+            let mut armed = armed.borrow_mut();
+            armed.next_id += 1;
+            armed.triggers[self as usize] = Some(Trigger {
+                id: armed.next_id,
+                successes,
+            });
+            armed.next_id
+        });
+        FailGuard { point: self, id }
+    }
+
+    fn error(self) -> DataSketchesError {
+        let message = "injected failure".to_string();
+        match self {
+            FailPoint::Serialize => DataSketchesError::SerializationError(message),
+            FailPoint::Deserialize => DataSketchesError::DeserializationError(message),
+            FailPoint::Allocate => DataSketchesError::CreationError(message),
+            FailPoint::Merge => DataSketchesError::Unknown(message),
+        }
+    }
+}
+
+/// Keeps a [`FailPoint`] armed on its thread until dropped.
+///
+/// Arming a point again replaces the earlier trigger, and dropping the
+/// earlier guard then leaves the newer one in place.
+#[derive(Debug)]
+#[must_use = "the failure is only injected while the guard is alive"]
+pub struct FailGuard {
+    point: FailPoint,
+    id: u64,
+}
+
+impl Drop for FailGuard {
+    fn drop(&mut self) {
+        ARMED.with(|armed| {
+            let slot = &mut armed.borrow_mut().triggers[self.point as usize];
+            if slot.as_ref().is_some_and(|trigger| trigger.id == self.id) {
+                *slot = None;
+            }
+        });
+    }
+}
+
+struct Trigger {
+    id: u64,
+    successes: u64,
+}
+
+#[derive(Default)]
+struct Armed {
+    triggers: [Option<Trigger>; POINTS],
+    next_id: u64,
+}
+
+thread_local! {
+    // Per thread, so tests running in parallel do not fail each other.
+    static ARMED: RefCell<Armed> = RefCell::default();
+}
+
+/// Fails with the error of `point` if it is armed on this thread and out of
+/// successes.
+pub(crate) fn check(point: FailPoint) -> Result<()> {
+    let fire = ARMED.with(
+        |armed| match &mut armed.borrow_mut().triggers[point as usize] {
+            Some(trigger) if trigger.successes > 0 => {
+                trigger.successes -= 1;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        },
+    );
+    if fire {
+        Err(point.error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KllDoubleSketch, KllFloatSketch};
+
+    #[test]
+    fn test_fail_points() {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 0..1000 {
+            sketch.update(i as f64);
+        }
+        let bytes = sketch.serialize().unwrap();
+
+        let guard = FailPoint::Deserialize.fail_after(1);
+        assert!(KllDoubleSketch::deserialize(&bytes).is_ok());
+        assert!(matches!(
+            KllDoubleSketch::deserialize(&bytes),
+            Err(DataSketchesError::DeserializationError(_))
+        ));
+        drop(guard);
+        assert!(KllDoubleSketch::deserialize(&bytes).is_ok());
+
+        let other = sketch.try_clone().unwrap();
+        let mut allocated = KllFloatSketch::new();
+        for i in 0..1000 {
+            allocated.update(i as f32);
+        }
+        {
+            let _merge = FailPoint::Merge.fail();
+            assert!(sketch.merge(&other).is_err());
+            assert_eq!(sketch.get_n(), 1000);
+            let _allocate = FailPoint::Allocate.fail();
+            assert!(matches!(
+                sketch.try_clone(),
+                Err(DataSketchesError::CreationError(_))
+            ));
+            assert!(KllFloatSketch::try_default().is_err());
+
+            // A sketch still buffering its values allocates on first native use.
+            let mut buffered = KllFloatSketch::new();
+            buffered.update(1.0);
+            assert!(matches!(
+                buffered.serialize(),
+                Err(DataSketchesError::CreationError(_))
+            ));
+            assert!(buffered.get_quantiles_into(&[0.5], &mut [0.0]).is_err());
+            assert!(buffered
+                .get_quantiles_evenly_spaced_into(&mut [0.0; 3])
+                .is_err());
+            assert!(buffered.merge(&allocated).is_err());
+            assert_eq!(buffered.get_n(), 1);
+        }
+        sketch.merge(&other).unwrap();
+        assert_eq!(sketch.get_n(), 2000);
+
+        let first = FailPoint::Serialize.fail();
+        let second = FailPoint::Serialize.fail_after(1);
+        drop(first);
+        assert!(sketch.serialize().is_ok());
+        assert!(sketch.serialize().is_err());
+        drop(second);
+        assert!(sketch.serialize().is_ok());
+    }
+}
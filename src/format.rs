@@ -0,0 +1,728 @@
+//! Helpers for inspecting the DataSketches KLL wire format.
+
+use crate::error::{DataSketchesError, Result};
+
+/// Family id written by DataSketches for KLL sketches.
+pub const KLL_FAMILY_ID: u8 = 15;
+
+/// Serial version used for empty and full sketches.
+pub const SERIAL_VERSION_1: u8 = 1;
+
+/// Serial version used for the compact single-item layout.
+pub const SERIAL_VERSION_2: u8 = 2;
+
+pub(crate) const PREAMBLE_INTS_SHORT: u8 = 2;
+pub(crate) const PREAMBLE_INTS_FULL: u8 = 5;
+
+pub(crate) const FLAG_EMPTY: u8 = 1 << 0;
+pub(crate) const FLAG_LEVEL_ZERO_SORTED: u8 = 1 << 1;
+pub(crate) const FLAG_SINGLE_ITEM: u8 = 1 << 2;
+
+pub(crate) const PREAMBLE_BYTES: usize = 8;
+pub(crate) const N_OFFSET: usize = 8;
+pub(crate) const MIN_K_OFFSET: usize = 16;
+pub(crate) const NUM_LEVELS_OFFSET: usize = 18;
+
+/// Offset of the levels array in the full layout.
+pub(crate) const DATA_START: usize = 20;
+
+/// Minimum level width used by datasketches-cpp.
+pub(crate) const MIN_LEVEL_WIDTH: u32 = 8;
+
+fn check_preamble(bytes: &[u8]) -> Result<()> {
+    if bytes.len() < PREAMBLE_BYTES {
+        return Err(DataSketchesError::DeserializationError(format!(
+            "at least {} bytes expected, got {}",
+            PREAMBLE_BYTES,
+            bytes.len()
+        )));
+    }
+    check_family(bytes[2])
+}
+
+fn check_family(family_id: u8) -> Result<()> {
+    if family_id == KLL_FAMILY_ID {
+        return Ok(());
+    }
+    Err(DataSketchesError::DeserializationError(format!(
+        "family id {} ({}) is not a KLL sketch",
+        family_id,
+        family_name(family_id)
+    )))
+}
+
+/// Names the DataSketches family with the given id, for error messages.
+fn family_name(family_id: u8) -> &'static str {
+    match family_id {
+        1 => "theta (alpha)",
+        2 => "theta (quickselect)",
+        3 => "compact theta",
+        4 => "theta union",
+        5 => "theta intersection",
+        6 => "theta A-not-B",
+        7 => "HLL",
+        8 => "classic quantiles",
+        9 => "tuple",
+        10 => "frequent items",
+        11 => "reservoir",
+        12 => "reservoir union",
+        13 => "VarOpt",
+        14 => "VarOpt union",
+        16 => "CPC",
+        17 => "REQ",
+        18 => "count-min",
+        19 => "EBPPS",
+        20 => "t-digest",
+        _ => "unknown",
+    }
+}
+
+/// Rejects bytes that belong to another sketch family, or that hold items
+/// of a different size than `item_size`.
+///
+/// Bytes whose layout cannot be made out are let through, so the native
+/// deserializer reports what is wrong with them.
+pub(crate) fn check_compatible(bytes: &[u8], item_size: usize) -> Result<()> {
+    if let Some(&family_id) = bytes.get(2) {
+        check_family(family_id)?;
+    }
+    match infer_item_size(bytes) {
+        Ok(Some(size)) if size != item_size => {
+            Err(DataSketchesError::DeserializationError(format!(
+                "bytes hold {}-byte items but this sketch type has {}-byte items; \
+                 use deserialize_any to accept either",
+                size, item_size
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the serial version of a serialized KLL sketch.
+///
+/// Fails if the bytes are too short, do not belong to the KLL family, or carry
+/// a serial version the vendored datasketches-cpp cannot read.
+pub fn detect_serial_version(bytes: &[u8]) -> Result<u8> {
+    check_preamble(bytes)?;
+    match bytes[1] {
+        SERIAL_VERSION_1 | SERIAL_VERSION_2 => Ok(bytes[1]),
+        other => Err(DataSketchesError::DeserializationError(format!(
+            "unsupported serial version {}",
+            other
+        ))),
+    }
+}
+
+/// Returns true if the bytes use a layout that the current writer would not produce.
+///
+/// Older writers stored single-item sketches in the full layout; these are
+/// still readable but larger than necessary. Sketches reported here can be
+/// rewritten with `migrate_bytes` on the matching sketch type.
+pub fn needs_migration(bytes: &[u8]) -> Result<bool> {
+    let version = detect_serial_version(bytes)?;
+    let preamble_ints = bytes[0];
+    let flags = bytes[3];
+
+    let (expected_ints, expected_version) = if flags & FLAG_EMPTY != 0 {
+        (PREAMBLE_INTS_SHORT, SERIAL_VERSION_1)
+    } else if flags & FLAG_SINGLE_ITEM != 0 {
+        (PREAMBLE_INTS_SHORT, SERIAL_VERSION_2)
+    } else {
+        if bytes.len() < N_OFFSET + 8 {
+            return Err(DataSketchesError::DeserializationError(
+                "insufficient data for full preamble".to_string(),
+            ));
+        }
+        let mut n = [0u8; 8];
+        n.copy_from_slice(&bytes[N_OFFSET..N_OFFSET + 8]);
+        if u64::from_le_bytes(n) == 1 {
+            return Ok(true);
+        }
+        (PREAMBLE_INTS_FULL, SERIAL_VERSION_1)
+    };
+
+    Ok(preamble_ints != expected_ints || version != expected_version)
+}
+
+// Capacity of a level `depth` levels below the top, before clamping to the
+// minimum width. Mirrors kll_helper::int_cap_aux in datasketches-cpp.
+fn int_cap_aux(k: u16, depth: u8) -> u64 {
+    fn aux(k: u64, depth: u8) -> u64 {
+        let tmp = ((k << 1) << depth) / 3u64.pow(depth as u32);
+        (tmp + 1) >> 1
+    }
+    if depth <= 30 {
+        return aux(k as u64, depth);
+    }
+    let half = depth / 2;
+    aux(aux(k as u64, half), depth - half)
+}
+
+/// Upper bound on the serialized size of a sketch with parameter `k` after `n` updates.
+///
+/// Same formula as `kll_sketch::get_max_serialized_size_bytes`; `n` must stay
+/// below 2^61.
+pub(crate) fn max_serialized_size_bytes(k: u16, n: u64, item_size: usize) -> usize {
+    let num_levels = if n == 0 {
+        1
+    } else {
+        64 - n.leading_zeros() as u8
+    };
+    DATA_START + num_levels as usize * 4 + (total_capacity(k, num_levels) as usize + 2) * item_size
+}
+
+/// Exact serialized size of a sketch with the given shape.
+pub(crate) fn serialized_size_bytes(
+    n: u64,
+    num_levels: u8,
+    num_retained: u32,
+    item_size: usize,
+) -> usize {
+    match n {
+        0 => PREAMBLE_BYTES,
+        1 => PREAMBLE_BYTES + item_size,
+        _ => DATA_START + num_levels as usize * 4 + (num_retained as usize + 2) * item_size,
+    }
+}
+
+/// Capacity of level `height` in a sketch with `num_levels` levels.
+pub(crate) fn level_capacity(k: u16, num_levels: u8, height: u8) -> u64 {
+    int_cap_aux(k, num_levels - height - 1).max(MIN_LEVEL_WIDTH as u64)
+}
+
+/// Total item capacity of a sketch with `num_levels` levels.
+pub(crate) fn total_capacity(k: u16, num_levels: u8) -> u64 {
+    (0..num_levels)
+        .map(|height| level_capacity(k, num_levels, height))
+        .sum()
+}
+
+/// Works out the item size of a serialized sketch from its length.
+///
+/// The KLL preamble does not record the item type, but the layout fixes how
+/// many items follow it. Returns `None` for an empty sketch, which carries no
+/// items to measure.
+pub(crate) fn infer_item_size(bytes: &[u8]) -> Result<Option<usize>> {
+    detect_serial_version(bytes)?;
+    let flags = bytes[3];
+    if flags & FLAG_EMPTY != 0 {
+        return Ok(None);
+    }
+    let candidates = [4usize, 8];
+    let matches = |expected: &dyn Fn(usize) -> usize| {
+        candidates
+            .into_iter()
+            .find(|&size| expected(size) == bytes.len())
+    };
+
+    let found = if flags & FLAG_SINGLE_ITEM != 0 {
+        matches(&|size| PREAMBLE_BYTES + size)
+    } else {
+        if bytes.len() < DATA_START + 4 {
+            return Err(DataSketchesError::DeserializationError(
+                "insufficient data for full preamble".to_string(),
+            ));
+        }
+        let k = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let num_levels = bytes[NUM_LEVELS_OFFSET];
+        if num_levels == 0 || num_levels > 61 {
+            return Err(DataSketchesError::DeserializationError(format!(
+                "invalid number of levels {}",
+                num_levels
+            )));
+        }
+        let mut first = [0u8; 4];
+        first.copy_from_slice(&bytes[DATA_START..DATA_START + 4]);
+        let retained = total_capacity(k, num_levels)
+            .checked_sub(u32::from_le_bytes(first) as u64)
+            .ok_or_else(|| {
+                DataSketchesError::DeserializationError("corrupt levels array".to_string())
+            })? as usize;
+        let header = DATA_START + num_levels as usize * 4;
+        matches(&|size| header + (retained + 2) * size)
+    };
+
+    found.map(Some).ok_or_else(|| {
+        DataSketchesError::DeserializationError(format!(
+            "{} bytes do not match a float or double sketch",
+            bytes.len()
+        ))
+    })
+}
+
+/// Converts serialized bytes between host byte order and the little-endian wire order.
+///
+/// datasketches-cpp copies fields in host order, so on big-endian targets
+/// every multi-byte field is reversed on the way out and on the way back in.
+/// This is a no-op on little-endian targets.
+pub(crate) fn convert_byte_order(bytes: &mut [u8], item_size: usize) -> Result<()> {
+    if cfg!(target_endian = "big") {
+        reverse_field_bytes(bytes, item_size)?;
+    }
+    Ok(())
+}
+
+/// Reverses every multi-byte field of a serialized sketch in place.
+///
+/// Field positions only depend on the flags, the level count and the total
+/// length, all of which read the same in either byte order.
+fn reverse_field_bytes(bytes: &mut [u8], item_size: usize) -> Result<()> {
+    check_preamble(bytes)?;
+    let flags = bytes[3];
+    bytes[4..6].reverse();
+    if flags & FLAG_EMPTY != 0 {
+        return Ok(());
+    }
+    let items_start = if flags & FLAG_SINGLE_ITEM != 0 {
+        PREAMBLE_BYTES
+    } else {
+        if bytes.len() < DATA_START {
+            return Err(DataSketchesError::DeserializationError(
+                "insufficient data for full preamble".to_string(),
+            ));
+        }
+        bytes[N_OFFSET..N_OFFSET + 8].reverse();
+        bytes[MIN_K_OFFSET..MIN_K_OFFSET + 2].reverse();
+        let levels_end = DATA_START + bytes[NUM_LEVELS_OFFSET] as usize * 4;
+        if bytes.len() < levels_end {
+            return Err(DataSketchesError::DeserializationError(
+                "insufficient data for levels array".to_string(),
+            ));
+        }
+        bytes[DATA_START..levels_end]
+            .chunks_exact_mut(4)
+            .for_each(<[u8]>::reverse);
+        levels_end
+    };
+    let items = &mut bytes[items_start..];
+    if items.len() % item_size != 0 {
+        return Err(DataSketchesError::DeserializationError(format!(
+            "{} item bytes are not a whole number of {}-byte items",
+            items.len(),
+            item_size
+        )));
+    }
+    items.chunks_exact_mut(item_size).for_each(<[u8]>::reverse);
+    Ok(())
+}
+
+/// The preamble of a serialized KLL sketch, decoded field by field.
+///
+/// Parsing only reads the header and the min/max items; it does not build a
+/// sketch. Fields that the compact layouts omit are filled with the values
+/// datasketches-cpp implies for them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerializedHeader {
+    /// Number of 32-bit preamble words: 2 for the compact layouts, 5 otherwise.
+    pub preamble_ints: u8,
+    /// Serial version of the layout.
+    pub serial_version: u8,
+    /// Sketch family id, always [`KLL_FAMILY_ID`].
+    pub family_id: u8,
+    /// Raw flags byte.
+    pub flags: u8,
+    /// The sketch has seen no values.
+    pub is_empty: bool,
+    /// Level zero is stored in sorted order.
+    pub is_level_zero_sorted: bool,
+    /// The sketch uses the compact single-item layout.
+    pub is_single_item: bool,
+    /// The k parameter.
+    pub k: u16,
+    /// The m parameter, the minimum level width.
+    pub m: u8,
+    /// Number of values the sketch has seen.
+    pub n: u64,
+    /// Smallest k among the sketches merged into this one.
+    pub min_k: u16,
+    /// Number of levels.
+    pub num_levels: u8,
+    /// Bytes per item, inferred from the length; `None` for an empty sketch.
+    pub item_size: Option<usize>,
+    /// Minimum value, widened to `f64`; `None` for an empty sketch.
+    pub min_value: Option<f64>,
+    /// Maximum value, widened to `f64`; `None` for an empty sketch.
+    pub max_value: Option<f64>,
+}
+
+impl SerializedHeader {
+    /// Parses the header of serialized sketch bytes of either item type.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let item_size = infer_item_size(bytes)?;
+        let flags = bytes[3];
+        let k = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let mut header = SerializedHeader {
+            preamble_ints: bytes[0],
+            serial_version: bytes[1],
+            family_id: bytes[2],
+            flags,
+            is_empty: flags & FLAG_EMPTY != 0,
+            is_level_zero_sorted: flags & FLAG_LEVEL_ZERO_SORTED != 0,
+            is_single_item: flags & FLAG_SINGLE_ITEM != 0,
+            k,
+            m: bytes[6],
+            n: 0,
+            min_k: k,
+            num_levels: 1,
+            item_size,
+            min_value: None,
+            max_value: None,
+        };
+        let Some(size) = item_size else {
+            return Ok(header);
+        };
+        if header.is_single_item {
+            let item = read_item(bytes, PREAMBLE_BYTES, size);
+            header.n = 1;
+            header.min_value = Some(item);
+            header.max_value = Some(item);
+            return Ok(header);
+        }
+        let mut n = [0u8; 8];
+        n.copy_from_slice(&bytes[N_OFFSET..N_OFFSET + 8]);
+        header.n = u64::from_le_bytes(n);
+        header.min_k = u16::from_le_bytes([bytes[MIN_K_OFFSET], bytes[MIN_K_OFFSET + 1]]);
+        header.num_levels = bytes[NUM_LEVELS_OFFSET];
+        let min_offset = DATA_START + header.num_levels as usize * 4;
+        header.min_value = Some(read_item(bytes, min_offset, size));
+        header.max_value = Some(read_item(bytes, min_offset + size, size));
+        Ok(header)
+    }
+}
+
+/// Reads a little-endian `f32` or `f64` item at `offset` as `f64`.
+fn read_item(bytes: &[u8], offset: usize, size: usize) -> f64 {
+    if size == 4 {
+        let mut item = [0u8; 4];
+        item.copy_from_slice(&bytes[offset..offset + 4]);
+        f32::from_le_bytes(item) as f64
+    } else {
+        let mut item = [0u8; 8];
+        item.copy_from_slice(&bytes[offset..offset + 8]);
+        f64::from_le_bytes(item)
+    }
+}
+
+/// Writes `value` as a little-endian `f32` or `f64` item at `offset`.
+fn write_item(bytes: &mut [u8], offset: usize, size: usize, value: f64) {
+    if size == 4 {
+        bytes[offset..offset + 4].copy_from_slice(&(value as f32).to_le_bytes());
+    } else {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Replaces every item of little-endian serialized bytes with `map(item)`.
+///
+/// `map` must be monotonic so the levels stay ordered. A decreasing map also
+/// swaps min and max and reverses each level. Fails if a mapped item is not
+/// finite at the item size.
+pub(crate) fn map_items(
+    bytes: &mut [u8],
+    item_size: usize,
+    decreasing: bool,
+    map: impl Fn(f64) -> f64,
+) -> Result<()> {
+    let header = SerializedHeader::parse(bytes)?;
+    let Some(size) = header.item_size else {
+        return Ok(());
+    };
+    if size != item_size {
+        return Err(DataSketchesError::DeserializationError(format!(
+            "bytes hold {}-byte items, expected {}",
+            size, item_size
+        )));
+    }
+    let mapped = |offset: usize| {
+        let value = map(read_item(bytes, offset, size));
+        let stored = if size == 4 {
+            value as f32 as f64
+        } else {
+            value
+        };
+        if stored.is_finite() {
+            Ok(value)
+        } else {
+            Err(DataSketchesError::InvalidParameter(format!(
+                "item {} maps to {}, which is not a finite {}-byte item",
+                read_item(bytes, offset, size),
+                value,
+                size
+            )))
+        }
+    };
+    if header.is_single_item {
+        let value = mapped(PREAMBLE_BYTES)?;
+        write_item(bytes, PREAMBLE_BYTES, size, value);
+        return Ok(());
+    }
+
+    let levels_end = DATA_START + header.num_levels as usize * 4;
+    let level_offsets: Vec<usize> = bytes[DATA_START..levels_end]
+        .chunks_exact(4)
+        .map(|offset| u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize)
+        .collect();
+    let first = level_offsets[0];
+    let values = (levels_end..bytes.len())
+        .step_by(size)
+        .map(mapped)
+        .collect::<Result<Vec<f64>>>()?;
+    let (extremes, items) = values.split_at(2);
+    let (mut min, mut max) = (extremes[0], extremes[1]);
+    let mut items = items.to_vec();
+    if decreasing {
+        std::mem::swap(&mut min, &mut max);
+        let mut bounds: Vec<usize> = level_offsets.iter().map(|offset| offset - first).collect();
+        bounds.push(items.len());
+        for level in bounds.windows(2) {
+            items[level[0]..level[1]].reverse();
+        }
+    }
+    for (i, value) in [min, max].into_iter().chain(items).enumerate() {
+        write_item(bytes, levels_end + i * size, size, value);
+    }
+    Ok(())
+}
+
+/// Serializes a sketch holding `items`, each standing for `weight` values.
+///
+/// Each weight is split into its binary digits, an item going to level `h`
+/// for every bit `h` set, so the sketch answers exactly as the weighted items
+/// do. If more items result than the levels n allows can hold, the lowest
+/// overfull level is compacted as KLL does, alternating which half of each
+/// pair is kept. Fails on a NaN item, a zero weight or a total above
+/// `u64::MAX`.
+pub(crate) fn weighted_items_bytes(
+    items: &[(f64, u64)],
+    k: u16,
+    item_size: usize,
+) -> Result<Vec<u8>> {
+    let mut n = 0u64;
+    let mut levels: Vec<Vec<f64>> = Vec::new();
+    for &(item, weight) in items {
+        if item.is_nan() || weight == 0 {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "weighted item ({}, {}) needs a non-NaN item and a positive weight",
+                item, weight
+            )));
+        }
+        n = n.checked_add(weight).ok_or_else(|| {
+            DataSketchesError::InvalidParameter("weights sum to more than u64::MAX".to_string())
+        })?;
+        let height = 64 - weight.leading_zeros() as usize;
+        if levels.len() < height {
+            levels.resize(height, Vec::new());
+        }
+        for (bit, level) in levels.iter_mut().enumerate().take(height) {
+            if weight & (1 << bit) != 0 {
+                level.push(item);
+            }
+        }
+    }
+
+    let mut out = vec![0u8; PREAMBLE_BYTES];
+    out[2] = KLL_FAMILY_ID;
+    out[4..6].copy_from_slice(&k.to_le_bytes());
+    out[6] = MIN_LEVEL_WIDTH as u8;
+    if n <= 1 {
+        out[0] = PREAMBLE_INTS_SHORT;
+        if n == 0 {
+            out[1] = SERIAL_VERSION_1;
+            out[3] = FLAG_EMPTY;
+        } else {
+            out[1] = SERIAL_VERSION_2;
+            out[3] = FLAG_SINGLE_ITEM;
+            out.resize(PREAMBLE_BYTES + item_size, 0);
+            write_item(&mut out, PREAMBLE_BYTES, item_size, levels[0][0]);
+        }
+        return Ok(out);
+    }
+
+    // Taken before compaction, which may drop the extremes.
+    let (min, max) = items.iter().fold(
+        (f64::INFINITY, f64::NEG_INFINITY),
+        |(min, max), &(item, _)| (min.min(item), max.max(item)),
+    );
+    // As many levels as a sketch of n values can reach, before compacting.
+    let max_levels = 64 - n.leading_zeros() as usize;
+    let retained = |levels: &[Vec<f64>]| levels.iter().map(Vec::len).sum::<usize>() as u64;
+    while levels.len() < max_levels && retained(&levels) > total_capacity(k, levels.len() as u8) {
+        levels.push(Vec::new());
+    }
+    let mut keep_odd = false;
+    while retained(&levels) > total_capacity(k, levels.len() as u8) {
+        let num_levels = levels.len() as u8;
+        let height = (0..levels.len())
+            .find(|&h| levels[h].len() as u64 >= level_capacity(k, num_levels, h as u8))
+            .expect("levels above their total capacity have an overfull level");
+        if height + 1 == levels.len() {
+            levels.push(Vec::new());
+        }
+        let level = &mut levels[height];
+        level.sort_by(f64::total_cmp);
+        // An odd item out stays behind, as in datasketches-cpp.
+        let pairs = level.split_off(level.len() % 2);
+        let promoted = pairs.iter().skip(keep_odd as usize).step_by(2).copied();
+        levels[height + 1].extend(promoted);
+        keep_odd = !keep_odd;
+    }
+    for level in &mut levels {
+        level.sort_by(f64::total_cmp);
+    }
+
+    let num_levels = levels.len() as u8;
+    out[0] = PREAMBLE_INTS_FULL;
+    out[1] = SERIAL_VERSION_1;
+    out[3] = FLAG_LEVEL_ZERO_SORTED;
+    out.extend_from_slice(&n.to_le_bytes());
+    out.extend_from_slice(&k.to_le_bytes());
+    out.extend_from_slice(&[num_levels, 0]);
+    let mut offset = (total_capacity(k, num_levels) - retained(&levels)) as u32;
+    for level in &levels {
+        out.extend_from_slice(&offset.to_le_bytes());
+        offset += level.len() as u32;
+    }
+    for item in [min, max].into_iter().chain(levels.into_iter().flatten()) {
+        let at = out.len();
+        out.resize(at + item_size, 0);
+        write_item(&mut out, at, item_size, item);
+    }
+    Ok(out)
+}
+
+/// Stream length assumed when sizing a sketch for a memory budget.
+///
+/// Capacity only grows by a few items per doubling of n at this point, so the
+/// bound holds for any realistic stream.
+pub(crate) const BUDGET_HORIZON_N: u64 = 1 << 48;
+
+/// Returns the largest k whose maximum serialized size fits in `bytes`.
+pub(crate) fn largest_k_within(bytes: usize, item_size: usize) -> Option<u16> {
+    let fits = |k: u16| max_serialized_size_bytes(k, BUDGET_HORIZON_N, item_size) <= bytes;
+    if !fits(8) {
+        return None;
+    }
+    let (mut lo, mut hi) = (8u16, u16::MAX);
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if fits(mid) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    Some(lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_rejects_foreign_family() {
+        // Empty sketch preamble carrying the theta family id.
+        let mut bytes = [2u8, 1, 3, 1, 200, 0, 8, 0];
+        assert!(detect_serial_version(&bytes).is_err());
+        bytes[2] = KLL_FAMILY_ID;
+        assert_eq!(detect_serial_version(&bytes).unwrap(), SERIAL_VERSION_1);
+        assert!(!needs_migration(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_detect_rejects_unknown_version() {
+        let bytes = [2u8, 9, KLL_FAMILY_ID, 1, 200, 0, 8, 0];
+        assert!(detect_serial_version(&bytes).is_err());
+        assert!(detect_serial_version(&bytes[..4]).is_err());
+    }
+
+    #[test]
+    fn test_max_serialized_size() {
+        // A single level holds exactly k items.
+        assert_eq!(
+            max_serialized_size_bytes(200, 0, 8),
+            DATA_START + 4 + 202 * 8
+        );
+        assert!(
+            max_serialized_size_bytes(200, 1 << 30, 8) > max_serialized_size_bytes(200, 1 << 20, 8)
+        );
+        assert!(
+            max_serialized_size_bytes(400, 1 << 30, 4) > max_serialized_size_bytes(200, 1 << 30, 4)
+        );
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let empty =
+            SerializedHeader::parse(&crate::KllDoubleSketch::new().serialize().unwrap()).unwrap();
+        assert!(empty.is_empty);
+        assert_eq!((empty.k, empty.n, empty.min_value), (200, 0, None));
+
+        let mut single = crate::KllFloatSketch::new_with_k(64).unwrap();
+        single.update(2.5);
+        let header = SerializedHeader::parse(&single.serialize().unwrap()).unwrap();
+        assert!(header.is_single_item);
+        assert_eq!(header.item_size, Some(4));
+        assert_eq!((header.k, header.n), (64, 1));
+        assert_eq!((header.min_value, header.max_value), (Some(2.5), Some(2.5)));
+
+        let mut full = crate::KllDoubleSketch::new_with_k(8).unwrap();
+        for i in 1..=100 {
+            full.update(i as f64);
+        }
+        let header = SerializedHeader::parse(&full.serialize().unwrap()).unwrap();
+        assert_eq!(header.preamble_ints, PREAMBLE_INTS_FULL);
+        assert_eq!(header.family_id, KLL_FAMILY_ID);
+        assert_eq!((header.n, header.min_k, header.m), (100, 8, 8));
+        assert!(header.num_levels > 1);
+        assert_eq!(header.item_size, Some(8));
+        assert_eq!(
+            (header.min_value, header.max_value),
+            (Some(1.0), Some(100.0))
+        );
+    }
+
+    #[test]
+    fn test_reverse_field_bytes() {
+        let mut sketch = crate::KllDoubleSketch::new_with_k(8).unwrap();
+        for i in 1..=100 {
+            sketch.update(i as f64);
+        }
+        let wire = sketch.serialize().unwrap();
+        let mut swapped = wire.clone();
+        reverse_field_bytes(&mut swapped, 8).unwrap();
+        assert_eq!(&swapped[4..6], &[0, 8]);
+        assert_eq!(swapped[N_OFFSET + 7], 100);
+        reverse_field_bytes(&mut swapped, 8).unwrap();
+        assert_eq!(swapped, wire);
+
+        // Float items do not divide into 8-byte fields.
+        let mut float = crate::KllFloatSketch::new();
+        float.update(1.0);
+        let mut bytes = float.serialize().unwrap();
+        assert!(reverse_field_bytes(&mut bytes, 8).is_err());
+        assert!(reverse_field_bytes(&mut bytes, 4).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_endian = "big")]
+    fn test_big_endian_wire_is_little_endian() {
+        let mut sketch = crate::KllDoubleSketch::new_with_k(300).unwrap();
+        sketch.update(1.5);
+        let bytes = sketch.serialize().unwrap();
+        assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), 300);
+        let header = SerializedHeader::parse(&bytes).unwrap();
+        assert_eq!(header.min_value, Some(1.5));
+        let restored = crate::KllDoubleSketch::deserialize(&bytes).unwrap();
+        assert_eq!(restored.get_k(), 300);
+        assert_eq!(restored.get_quantile(0.5), 1.5);
+    }
+
+    #[test]
+    fn test_sizes_fit_32_bit_targets() {
+        // Even the largest k stays far below 4 GiB, so sizes computed in
+        // usize cannot overflow on 32-bit targets.
+        let largest = max_serialized_size_bytes(u16::MAX, BUDGET_HORIZON_N, 8);
+        assert!(largest < u32::MAX as usize / 1024);
+        assert_eq!(largest_k_within(u32::MAX as usize, 8), Some(u16::MAX));
+    }
+}
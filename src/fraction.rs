@@ -0,0 +1,99 @@
+//! The validated fraction accepted by the quantile queries.
+
+use crate::error::{DataSketchesError, Result};
+use std::fmt;
+
+/// A normalized rank: a finite value in `[0, 1]`.
+///
+/// The raw `f64` query methods check their fractions on every call and
+/// answer NaN for bad ones; the methods taking `Fraction` rely on the check
+/// made here instead.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+#[repr(transparent)]
+pub struct Fraction(f64);
+
+impl Fraction {
+    /// The minimum, `0.0`.
+    pub const ZERO: Fraction = Fraction(0.0);
+
+    /// The median, `0.5`.
+    pub const MEDIAN: Fraction = Fraction(0.5);
+
+    /// The maximum, `1.0`.
+    pub const ONE: Fraction = Fraction(1.0);
+
+    /// Validates `fraction`, failing if it is NaN or outside `[0, 1]`.
+    pub fn new(fraction: f64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "fraction must be in [0, 1], got {}",
+                fraction
+            )));
+        }
+        // Normalize -0.0 so equal fractions compare and print the same.
+        Ok(Fraction(fraction + 0.0))
+    }
+
+    /// Validates a percentile such as `99.9`, failing outside `[0, 100]`.
+    pub fn from_percentile(percentile: f64) -> Result<Self> {
+        Self::new(percentile / 100.0).map_err(|_| {
+            DataSketchesError::InvalidParameter(format!(
+                "percentile must be in [0, 100], got {}",
+                percentile
+            ))
+        })
+    }
+
+    /// Returns the fraction as a plain float.
+    pub const fn get(self) -> f64 {
+        self.0
+    }
+
+    /// Views validated fractions as the raw floats the native API takes.
+    pub(crate) fn as_f64_slice(fractions: &[Fraction]) -> &[f64] {
+        // SAFETY: `Fraction` is a `repr(transparent)` wrapper around `f64`.
+        unsafe { std::slice::from_raw_parts(fractions.as_ptr() as *const f64, fractions.len()) }
+    }
+}
+
+impl TryFrom<f64> for Fraction {
+    type Error = DataSketchesError;
+
+    fn try_from(fraction: f64) -> Result<Self> {
+        Fraction::new(fraction)
+    }
+}
+
+impl From<Fraction> for f64 {
+    fn from(fraction: Fraction) -> Self {
+        fraction.0
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fraction_validation() {
+        for bad in [f64::NAN, -0.1, 1.1, f64::INFINITY] {
+            assert!(Fraction::new(bad).is_err());
+        }
+        assert_eq!(
+            Fraction::new(-0.0).unwrap().get().to_bits(),
+            0.0f64.to_bits()
+        );
+        assert_eq!(Fraction::try_from(0.5).unwrap(), Fraction::MEDIAN);
+        assert_eq!(Fraction::from_percentile(99.0).unwrap().get(), 0.99);
+        assert!(Fraction::from_percentile(101.0).is_err());
+
+        let fractions = [Fraction::ZERO, Fraction::ONE];
+        assert_eq!(Fraction::as_f64_slice(&fractions), &[0.0, 1.0]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Read-only, query-optimized snapshot of a sketch.
+
+/// An immutable snapshot of a sketch answered entirely in Rust.
+///
+/// Created by `freeze` on the sketch types. The retained items are sorted and
+/// their cumulative weights computed once, so every query is a binary search
+/// with no further FFI calls. Ranks are inclusive, matching the sketch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenSketch<T> {
+    items: Vec<T>,
+    cumulative_weights: Vec<u64>,
+    k: u16,
+    n: u64,
+    min: T,
+    max: T,
+}
+
+impl<T: Copy + PartialOrd> FrozenSketch<T> {
+    /// Builds a snapshot from retained items and their weights.
+    pub(crate) fn from_weighted(
+        items: Vec<T>,
+        weights: Vec<u64>,
+        k: u16,
+        n: u64,
+        min: T,
+        max: T,
+    ) -> Self {
+        let mut pairs: Vec<(T, u64)> = items.into_iter().zip(weights).collect();
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut total = 0u64;
+        let (items, cumulative_weights) = pairs
+            .into_iter()
+            .map(|(item, weight)| {
+                total += weight;
+                (item, total)
+            })
+            .unzip();
+
+        FrozenSketch {
+            items,
+            cumulative_weights,
+            k,
+            n,
+            min,
+            max,
+        }
+    }
+
+    /// Returns true if the snapshot holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the k parameter of the sketch this was frozen from.
+    pub fn get_k(&self) -> u16 {
+        self.k
+    }
+
+    /// Returns the number of values processed by the sketch.
+    pub fn get_n(&self) -> u64 {
+        self.n
+    }
+
+    /// Returns the number of retained items.
+    pub fn get_num_retained(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns the minimum value seen by the sketch, or `None` if empty.
+    pub fn get_min_value(&self) -> Option<T> {
+        (!self.is_empty()).then_some(self.min)
+    }
+
+    /// Returns the maximum value seen by the sketch, or `None` if empty.
+    pub fn get_max_value(&self) -> Option<T> {
+        (!self.is_empty()).then_some(self.max)
+    }
+
+    fn total_weight(&self) -> u64 {
+        self.cumulative_weights.last().copied().unwrap_or(0)
+    }
+
+    /// Returns the approximate quantile for a given fraction.
+    ///
+    /// Returns `None` if the snapshot is empty or the fraction is outside `[0, 1]`.
+    pub fn get_quantile(&self, fraction: f64) -> Option<T> {
+        if self.is_empty() || !fraction.is_finite() || !(0.0..=1.0).contains(&fraction) {
+            return None;
+        }
+        let weight = (fraction * self.total_weight() as f64).ceil() as u64;
+        let idx = self.cumulative_weights.partition_point(|&c| c < weight);
+        Some(self.items[idx.min(self.items.len() - 1)])
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<Option<T>> {
+        fractions.iter().map(|&f| self.get_quantile(f)).collect()
+    }
+
+    /// Returns the fraction of values less than or equal to `value`.
+    ///
+    /// Returns `None` if the snapshot is empty.
+    pub fn get_rank(&self, value: T) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let idx = self.items.partition_point(|item| *item <= value);
+        if idx == 0 {
+            return Some(0.0);
+        }
+        Some(self.cumulative_weights[idx - 1] as f64 / self.total_weight() as f64)
+    }
+
+    /// Returns the cumulative distribution at each split point, plus a final 1.0.
+    ///
+    /// Returns `None` if the snapshot is empty.
+    pub fn get_cdf(&self, split_points: &[T]) -> Option<Vec<f64>> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut cdf = split_points
+            .iter()
+            .map(|&point| self.get_rank(point))
+            .collect::<Option<Vec<f64>>>()?;
+        cdf.push(1.0);
+        Some(cdf)
+    }
+
+    /// Returns the probability mass in each interval defined by the split points.
+    ///
+    /// Returns `None` if the snapshot is empty.
+    pub fn get_pmf(&self, split_points: &[T]) -> Option<Vec<f64>> {
+        let mut pmf = self.get_cdf(split_points)?;
+        for i in (1..pmf.len()).rev() {
+            pmf[i] -= pmf[i - 1];
+        }
+        Some(pmf)
+    }
+
+    /// Iterates the retained items in ascending order with their weights.
+    pub fn iter(&self) -> impl Iterator<Item = (T, u64)> + '_ {
+        let mut previous = 0;
+        self.items
+            .iter()
+            .zip(&self.cumulative_weights)
+            .map(move |(&item, &cumulative)| {
+                let weight = cumulative - previous;
+                previous = cumulative;
+                (item, weight)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::KllDoubleSketch;
+
+    #[test]
+    fn test_frozen_matches_sketch() {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 0..10_000 {
+            sketch.update(((i * 7919) % 10_000) as f64);
+        }
+        let fractions = [0.0, 0.1, 0.25, 0.5, 0.75, 0.99, 1.0];
+        let expected = sketch.get_quantiles(&fractions);
+        let expected_rank = sketch.get_rank(5000.0);
+
+        let frozen = sketch.freeze();
+        let actual: Vec<f64> = frozen
+            .get_quantiles(&fractions)
+            .into_iter()
+            .map(Option::unwrap)
+            .collect();
+        assert_eq!(actual, expected);
+        assert_eq!(frozen.get_rank(5000.0), Some(expected_rank));
+        assert_eq!(frozen.get_n(), 10_000);
+        assert_eq!(frozen.iter().map(|(_, w)| w).sum::<u64>(), 10_000);
+
+        let pmf = frozen.get_pmf(&[2500.0, 5000.0]).unwrap();
+        assert!((pmf.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_frozen_empty() {
+        let frozen = KllDoubleSketch::new().freeze();
+        assert!(frozen.is_empty());
+        assert_eq!(frozen.get_quantile(0.5), None);
+        assert_eq!(frozen.get_rank(1.0), None);
+        assert_eq!(frozen.get_cdf(&[1.0]), None);
+    }
+}
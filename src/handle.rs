@@ -0,0 +1,271 @@
+//! The owning handle of a native sketch.
+//!
+//! Every native sketch the safe types hold lives in a [`NativeHandle`], which
+//! is never null and deletes the sketch exactly once when dropped. Native
+//! calls that create sketches go through [`NativeHandle::from_created`], so a
+//! null result becomes an error there instead of a wrapper around null.
+//!
+//! With the `debug-ffi-checks` feature each handle also carries a generation
+//! tag registered in a process-wide table of live sketches. Every pointer
+//! handed to a native call is checked against that table first, so a sketch
+//! used after it was deleted, or released twice through `from_raw`, panics
+//! with the pointer instead of crashing inside the native library.
+
+use crate::error::{DataSketchesError, Result};
+use std::fmt;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::ptr::NonNull;
+
+/// An item type whose native sketches a [`NativeHandle`] can delete.
+pub(crate) trait NativeItem {
+    /// Deletes a native sketch of this item type.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live native sketch of this item type, which is
+    /// not used again.
+    unsafe fn delete(ptr: NonNull<c_void>);
+}
+
+/// Owns one native sketch of item type `T`.
+pub(crate) struct NativeHandle<T: NativeItem> {
+    ptr: NonNull<c_void>,
+    #[cfg(feature = "debug-ffi-checks")]
+    generation: u64,
+    _item: PhantomData<T>,
+}
+
+impl<T: NativeItem> NativeHandle<T> {
+    /// Takes ownership of a pointer just returned by a native call that
+    /// creates a sketch, failing if it is null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null `ptr` must point to a live native sketch of item type `T`
+    /// that nothing else owns.
+    pub(crate) unsafe fn from_created(ptr: *mut c_void) -> Result<Self> {
+        let ptr = NonNull::new(ptr).ok_or(DataSketchesError::NullPointer)?;
+        #[cfg(feature = "leak-tracking")]
+        crate::leak::sketch_created();
+        Ok(NativeHandle {
+            ptr,
+            #[cfg(feature = "debug-ffi-checks")]
+            generation: live::register(ptr),
+            _item: PhantomData,
+        })
+    }
+
+    /// Takes back ownership of a pointer released by
+    /// [`into_raw`](Self::into_raw).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` on a handle of item type `T`, and must
+    /// not be taken back twice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ptr` is null.
+    pub(crate) unsafe fn from_raw(ptr: *mut c_void) -> Self {
+        let ptr = NonNull::new(ptr).expect("from_raw needs a non-null sketch");
+        NativeHandle {
+            ptr,
+            #[cfg(feature = "debug-ffi-checks")]
+            generation: live::reclaim(ptr),
+            _item: PhantomData,
+        }
+    }
+
+    /// Returns the pointer for passing to native calls. The handle keeps
+    /// ownership.
+    pub(crate) fn as_ptr(&self) -> *mut c_void {
+        #[cfg(feature = "debug-ffi-checks")]
+        live::check(self.ptr, self.generation);
+        self.ptr.as_ptr()
+    }
+
+    /// Gives up ownership, returning the pointer without deleting the sketch.
+    pub(crate) fn into_raw(self) -> *mut c_void {
+        let ptr = self.as_ptr();
+        std::mem::forget(self);
+        ptr
+    }
+}
+
+impl<T: NativeItem> Drop for NativeHandle<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "debug-ffi-checks")]
+        live::release(self.ptr, self.generation);
+        // The handle owns the sketch, and this is its last use.
+        unsafe { T::delete(self.ptr) }
+        #[cfg(feature = "leak-tracking")]
+        crate::leak::sketch_deleted();
+    }
+}
+
+/// Asserts, with the `debug-ffi-checks` feature, that a native call is about
+/// to write at most `capacity` items, or has written at most that many.
+#[inline]
+pub(crate) fn check_capacity(call: &str, needed: usize, capacity: usize) {
+    #[cfg(feature = "debug-ffi-checks")]
+    assert!(
+        needed <= capacity,
+        "{} needs {} items but the buffer holds {}",
+        call,
+        needed,
+        capacity
+    );
+    #[cfg(not(feature = "debug-ffi-checks"))]
+    let _ = (call, needed, capacity);
+}
+
+/// The table of live native sketches behind `debug-ffi-checks`.
+#[cfg(feature = "debug-ffi-checks")]
+mod live {
+    use std::collections::BTreeMap;
+    use std::os::raw::c_void;
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Generation of each live sketch, by address.
+    static LIVE: Mutex<BTreeMap<usize, u64>> = Mutex::new(BTreeMap::new());
+    static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+    fn table() -> MutexGuard<'static, BTreeMap<usize, u64>> {
+        // A failed assertion below poisons the lock, but the table is still
+        // consistent.
+        LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Records a sketch the native library just created.
+    pub(super) fn register(ptr: NonNull<c_void>) -> u64 {
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let previous = table().insert(ptr.as_ptr() as usize, generation);
+        assert!(
+            previous.is_none(),
+            "native library returned sketch {:p}, which is still live",
+            ptr
+        );
+        generation
+    }
+
+    /// Returns the generation of a sketch coming back from `into_raw`.
+    pub(super) fn reclaim(ptr: NonNull<c_void>) -> u64 {
+        match table().get(&(ptr.as_ptr() as usize)) {
+            Some(&generation) => generation,
+            None => panic!("from_raw on {:p}, which is not a live sketch", ptr),
+        }
+    }
+
+    /// Panics unless `ptr` is live with the generation of the handle using it.
+    pub(super) fn check(ptr: NonNull<c_void>, generation: u64) {
+        match table().get(&(ptr.as_ptr() as usize)) {
+            Some(&live) if live == generation => {}
+            Some(&live) => panic!(
+                "use of deleted sketch {:p} (generation {}), now reused by generation {}",
+                ptr, generation, live
+            ),
+            None => panic!(
+                "use of deleted sketch {:p} (generation {})",
+                ptr, generation
+            ),
+        }
+    }
+
+    /// Checks and forgets a sketch about to be deleted.
+    pub(super) fn release(ptr: NonNull<c_void>, generation: u64) {
+        check(ptr, generation);
+        table().remove(&(ptr.as_ptr() as usize));
+    }
+}
+
+impl<T: NativeItem> PartialEq for NativeHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T: NativeItem> fmt::Debug for NativeHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("NativeHandle").field(&self.ptr).finish()
+    }
+}
+
+// The native sketches have no thread affinity, and the safe types only share
+// them across threads for reads.
+unsafe impl<T: NativeItem> Send for NativeHandle<T> {}
+unsafe impl<T: NativeItem> Sync for NativeHandle<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DELETED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Boxed stand-in for a native sketch, so the tests run under Miri.
+    struct Boxed;
+
+    impl NativeItem for Boxed {
+        unsafe fn delete(ptr: NonNull<c_void>) {
+            drop(Box::from_raw(ptr.as_ptr() as *mut u64));
+            DELETED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn boxed() -> *mut c_void {
+        Box::into_raw(Box::new(7u64)) as *mut c_void
+    }
+
+    #[test]
+    fn test_handle_ownership() {
+        let before = DELETED.load(Ordering::SeqCst);
+        assert!(matches!(
+            unsafe { NativeHandle::<Boxed>::from_created(std::ptr::null_mut()) },
+            Err(DataSketchesError::NullPointer)
+        ));
+
+        let handle = unsafe { NativeHandle::<Boxed>::from_created(boxed()) }.unwrap();
+        assert_eq!(unsafe { *(handle.as_ptr() as *const u64) }, 7);
+        drop(handle);
+        assert_eq!(DELETED.load(Ordering::SeqCst), before + 1);
+
+        let handles: Vec<_> = (0..3)
+            .map(|_| unsafe { NativeHandle::<Boxed>::from_created(boxed()) }.unwrap())
+            .collect();
+        assert_ne!(handles[0], handles[1]);
+        drop(handles);
+        assert_eq!(DELETED.load(Ordering::SeqCst), before + 4);
+
+        let raw = unsafe { NativeHandle::<Boxed>::from_created(boxed()) }
+            .unwrap()
+            .into_raw();
+        assert_eq!(DELETED.load(Ordering::SeqCst), before + 4);
+        drop(unsafe { NativeHandle::<Boxed>::from_raw(raw) });
+        assert_eq!(DELETED.load(Ordering::SeqCst), before + 5);
+    }
+
+    #[cfg(feature = "debug-ffi-checks")]
+    #[test]
+    #[should_panic(expected = "use of deleted sketch")]
+    fn test_stale_handle_panics() {
+        struct Uncounted;
+
+        impl NativeItem for Uncounted {
+            unsafe fn delete(ptr: NonNull<c_void>) {
+                drop(Box::from_raw(ptr.as_ptr() as *mut u64));
+            }
+        }
+
+        let handle = unsafe { NativeHandle::<Uncounted>::from_created(boxed()) }.unwrap();
+        let raw = handle.into_raw();
+        let first = unsafe { NativeHandle::<Uncounted>::from_raw(raw) };
+        // Never dropped, so a missing check cannot turn into a double free.
+        let second =
+            std::mem::ManuallyDrop::new(unsafe { NativeHandle::<Uncounted>::from_raw(raw) });
+        drop(first);
+        second.as_ptr();
+    }
+}
@@ -0,0 +1,187 @@
+//! Time by value-bucket matrices for latency heatmaps.
+
+use crate::error::{DataSketchesError, Result};
+use crate::KllDoubleSketch;
+
+/// What each heatmap cell holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeatmapValues {
+    /// Estimated number of values in the bucket.
+    #[default]
+    Counts,
+    /// Fraction of the interval's values in the bucket.
+    Fractions,
+}
+
+/// A heatmap matrix with one row per interval and one column per bucket.
+///
+/// With `n` boundaries there are `n + 1` buckets: values at or below the
+/// first boundary, values in each `(boundaries[i - 1], boundaries[i]]`, and
+/// values above the last boundary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heatmap {
+    boundaries: Vec<f64>,
+    rows: Vec<Vec<f64>>,
+}
+
+impl Heatmap {
+    /// Returns the bucket boundaries.
+    pub fn boundaries(&self) -> &[f64] {
+        &self.boundaries
+    }
+
+    /// Returns the matrix rows, oldest interval first.
+    pub fn rows(&self) -> &[Vec<f64>] {
+        &self.rows
+    }
+
+    /// Returns the number of intervals.
+    pub fn num_intervals(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns the number of buckets per interval.
+    pub fn num_buckets(&self) -> usize {
+        self.boundaries.len() + 1
+    }
+}
+
+/// Builds a [`Heatmap`] from a sequence of per-interval sketches.
+///
+/// Every interval is bucketed with the same boundaries, so columns line up
+/// across the whole matrix.
+#[derive(Debug, Clone)]
+pub struct HeatmapBuilder {
+    boundaries: Vec<f64>,
+    values: HeatmapValues,
+}
+
+impl HeatmapBuilder {
+    /// Creates a builder with explicit, strictly increasing bucket boundaries.
+    pub fn new(boundaries: Vec<f64>) -> Result<Self> {
+        if boundaries.is_empty() {
+            return Err(DataSketchesError::InvalidParameter(
+                "heatmap needs at least one boundary".to_string(),
+            ));
+        }
+        if boundaries.iter().any(|b| !b.is_finite()) || boundaries.windows(2).any(|w| w[0] >= w[1])
+        {
+            return Err(DataSketchesError::InvalidParameter(
+                "heatmap boundaries must be finite and strictly increasing".to_string(),
+            ));
+        }
+        Ok(HeatmapBuilder {
+            boundaries,
+            values: HeatmapValues::default(),
+        })
+    }
+
+    /// Creates a builder with `num_boundaries` evenly spaced boundaries from `min` to `max`.
+    pub fn linear(min: f64, max: f64, num_boundaries: usize) -> Result<Self> {
+        if num_boundaries < 2 {
+            return Self::new(vec![min]);
+        }
+        let step = (max - min) / (num_boundaries - 1) as f64;
+        Self::new((0..num_boundaries).map(|i| min + step * i as f64).collect())
+    }
+
+    /// Creates a builder with `num_boundaries` geometrically spaced boundaries from `min` to `max`.
+    ///
+    /// `min` must be positive. This suits latencies, which span several orders
+    /// of magnitude.
+    pub fn exponential(min: f64, max: f64, num_boundaries: usize) -> Result<Self> {
+        if min <= 0.0 {
+            return Err(DataSketchesError::InvalidParameter(
+                "exponential heatmap boundaries must start above zero".to_string(),
+            ));
+        }
+        if num_boundaries < 2 {
+            return Self::new(vec![min]);
+        }
+        let ratio = (max / min).powf(1.0 / (num_boundaries - 1) as f64);
+        Self::new(
+            (0..num_boundaries)
+                .map(|i| min * ratio.powi(i as i32))
+                .collect(),
+        )
+    }
+
+    /// Selects whether cells hold counts or fractions.
+    pub fn values(mut self, values: HeatmapValues) -> Self {
+        self.values = values;
+        self
+    }
+
+    /// Buckets every sketch. Empty sketches produce a row of zeros.
+    pub fn build<'a, I>(&self, sketches: I) -> Heatmap
+    where
+        I: IntoIterator<Item = &'a KllDoubleSketch>,
+    {
+        let rows = sketches
+            .into_iter()
+            .map(|sketch| self.row(sketch))
+            .collect();
+        Heatmap {
+            boundaries: self.boundaries.clone(),
+            rows,
+        }
+    }
+
+    fn row(&self, sketch: &KllDoubleSketch) -> Vec<f64> {
+        if sketch.is_empty() {
+            return vec![0.0; self.boundaries.len() + 1];
+        }
+        let scale = match self.values {
+            HeatmapValues::Counts => sketch.get_n() as f64,
+            HeatmapValues::Fractions => 1.0,
+        };
+        let mut row = Vec::with_capacity(self.boundaries.len() + 1);
+        let mut previous = 0.0;
+        for &boundary in &self.boundaries {
+            let rank = sketch.get_rank(boundary);
+            row.push((rank - previous) * scale);
+            previous = rank;
+        }
+        row.push((1.0 - previous) * scale);
+        row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_counts_and_fractions() {
+        let mut first = KllDoubleSketch::new();
+        let mut second = KllDoubleSketch::new();
+        for i in 0..100 {
+            first.update(i as f64);
+            second.update((i * 2) as f64);
+        }
+        let sketches = [first, KllDoubleSketch::new(), second];
+
+        let builder = HeatmapBuilder::linear(49.0, 149.0, 3).unwrap();
+        assert_eq!(builder.boundaries, vec![49.0, 99.0, 149.0]);
+
+        let counts = builder.build(&sketches);
+        assert_eq!(counts.num_intervals(), 3);
+        assert_eq!(counts.num_buckets(), 4);
+        assert_eq!(counts.rows()[0], vec![50.0, 50.0, 0.0, 0.0]);
+        assert_eq!(counts.rows()[1], vec![0.0; 4]);
+        assert_eq!(counts.rows()[2].iter().sum::<f64>(), 100.0);
+
+        let fractions = builder.values(HeatmapValues::Fractions).build(&sketches);
+        assert_eq!(fractions.rows()[0], vec![0.5, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_heatmap_boundaries() {
+        assert!(HeatmapBuilder::new(vec![]).is_err());
+        assert!(HeatmapBuilder::new(vec![2.0, 1.0]).is_err());
+        assert!(HeatmapBuilder::exponential(0.0, 10.0, 3).is_err());
+
+        let builder = HeatmapBuilder::exponential(1.0, 100.0, 3).unwrap();
+        assert!((builder.boundaries[1] - 10.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,234 @@
+//! Exact-then-approximate quantiles.
+
+use crate::defaults::default_k;
+use crate::error::Result;
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+
+#[derive(Debug, Clone)]
+enum State {
+    /// Raw values, kept sorted.
+    Exact(Vec<f64>),
+    Sketch(KllDoubleSketch),
+}
+
+/// Quantile summary that stores values exactly up to a threshold.
+///
+/// While at most `threshold` values have been seen, queries are answered from
+/// the raw values. The first update beyond the threshold moves everything into
+/// a [`KllDoubleSketch`] and all later queries are approximate. Exact answers
+/// use the same inclusive rank semantics as the sketch, so results do not jump
+/// when the representation changes.
+#[derive(Debug, Clone)]
+pub struct HybridQuantiles {
+    threshold: usize,
+    k: u16,
+    state: State,
+}
+
+impl HybridQuantiles {
+    /// Creates a hybrid summary that switches to a sketch with the default k.
+    pub fn new(threshold: usize) -> Self {
+        HybridQuantiles {
+            threshold,
+            k: default_k(),
+            state: State::Exact(Vec::new()),
+        }
+    }
+
+    /// Creates a hybrid summary that switches to a sketch with the given k.
+    pub fn with_k(threshold: usize, k: u16) -> Result<Self> {
+        Ok(HybridQuantiles {
+            threshold,
+            k: KParam::new(k)?.get(),
+            state: State::Exact(Vec::new()),
+        })
+    }
+
+    /// Returns the number of values stored exactly before switching.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Returns true while answers are computed from the raw values.
+    pub fn is_exact(&self) -> bool {
+        matches!(self.state, State::Exact(_))
+    }
+
+    /// Returns the underlying sketch once the summary has switched.
+    pub fn sketch(&self) -> Option<&KllDoubleSketch> {
+        match &self.state {
+            State::Exact(_) => None,
+            State::Sketch(sketch) => Some(sketch),
+        }
+    }
+
+    /// Updates the summary with a new value. NaN values are ignored.
+    pub fn update(&mut self, value: f64) -> Result<()> {
+        if value.is_nan() {
+            return Ok(());
+        }
+        match &mut self.state {
+            State::Exact(values) => {
+                let pos = values.partition_point(|&v| v <= value);
+                values.insert(pos, value);
+                if values.len() > self.threshold {
+                    self.promote()?;
+                }
+            }
+            State::Sketch(sketch) => sketch.update(value),
+        }
+        Ok(())
+    }
+
+    /// Merges another summary into this one.
+    pub fn merge(&mut self, other: &HybridQuantiles) -> Result<()> {
+        match &other.state {
+            State::Exact(values) => {
+                for &value in values {
+                    self.update(value)?;
+                }
+            }
+            State::Sketch(other_sketch) => {
+                if self.is_exact() {
+                    self.promote()?;
+                }
+                if let State::Sketch(sketch) = &mut self.state {
+                    sketch.merge(other_sketch)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn promote(&mut self) -> Result<()> {
+        if let State::Exact(values) = &self.state {
+            let mut sketch = KllDoubleSketch::new_with_k(self.k)?;
+            for &value in values {
+                sketch.update(value);
+            }
+            self.state = State::Sketch(sketch);
+        }
+        Ok(())
+    }
+
+    /// Returns true if no values have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.get_n() == 0
+    }
+
+    /// Returns the number of values recorded.
+    pub fn get_n(&self) -> u64 {
+        match &self.state {
+            State::Exact(values) => values.len() as u64,
+            State::Sketch(sketch) => sketch.get_n(),
+        }
+    }
+
+    /// Returns the minimum value, or NaN if empty.
+    pub fn get_min_value(&self) -> f64 {
+        match &self.state {
+            State::Exact(values) => values.first().copied().unwrap_or(f64::NAN),
+            State::Sketch(sketch) => sketch.get_min_value(),
+        }
+    }
+
+    /// Returns the maximum value, or NaN if empty.
+    pub fn get_max_value(&self) -> f64 {
+        match &self.state {
+            State::Exact(values) => values.last().copied().unwrap_or(f64::NAN),
+            State::Sketch(sketch) => sketch.get_max_value(),
+        }
+    }
+
+    /// Returns the quantile for a given fraction, or NaN if empty or invalid.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        match &self.state {
+            State::Exact(values) => exact_quantile(values, fraction),
+            State::Sketch(sketch) => sketch.get_quantile(fraction),
+        }
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        match &self.state {
+            State::Exact(values) => {
+                if values.is_empty() {
+                    return vec![];
+                }
+                fractions
+                    .iter()
+                    .map(|&f| exact_quantile(values, f))
+                    .collect()
+            }
+            State::Sketch(sketch) => sketch.get_quantiles(fractions),
+        }
+    }
+
+    /// Returns the fraction of values less than or equal to `value`.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        match &self.state {
+            State::Exact(values) => {
+                if values.is_empty() {
+                    return f64::NAN;
+                }
+                values.partition_point(|&v| v <= value) as f64 / values.len() as f64
+            }
+            State::Sketch(sketch) => sketch.get_rank(value),
+        }
+    }
+}
+
+fn exact_quantile(sorted: &[f64], fraction: f64) -> f64 {
+    if sorted.is_empty() || !fraction.is_finite() || !(0.0..=1.0).contains(&fraction) {
+        return f64::NAN;
+    }
+    // Smallest value whose inclusive cumulative count reaches fraction * n.
+    let target = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[target.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_below_threshold() {
+        let mut hybrid = HybridQuantiles::new(10);
+        for v in [5.0, 1.0, 4.0, 2.0, 3.0] {
+            hybrid.update(v).unwrap();
+        }
+        assert!(hybrid.is_exact());
+        assert_eq!(hybrid.get_quantile(0.0), 1.0);
+        assert_eq!(hybrid.get_quantile(0.5), 3.0);
+        assert_eq!(hybrid.get_quantile(1.0), 5.0);
+        assert_eq!(hybrid.get_rank(2.0), 0.4);
+        assert_eq!(hybrid.get_min_value(), 1.0);
+        assert_eq!(hybrid.get_max_value(), 5.0);
+    }
+
+    #[test]
+    fn test_switches_to_sketch() {
+        let mut hybrid = HybridQuantiles::with_k(100, 64).unwrap();
+        for i in 1..=1000 {
+            hybrid.update(i as f64).unwrap();
+        }
+        assert!(!hybrid.is_exact());
+        assert_eq!(hybrid.get_n(), 1000);
+        assert_eq!(hybrid.sketch().unwrap().get_k(), 64);
+        assert!((hybrid.get_quantile(0.5) - 500.0).abs() < 50.0);
+    }
+
+    #[test]
+    fn test_merge_promotes() {
+        let mut small = HybridQuantiles::new(10);
+        small.update(1.0).unwrap();
+        let mut large = HybridQuantiles::new(10);
+        for i in 0..50 {
+            large.update(i as f64).unwrap();
+        }
+        small.merge(&large).unwrap();
+        assert!(!small.is_exact());
+        assert_eq!(small.get_n(), 51);
+    }
+}
@@ -0,0 +1,205 @@
+//! Choosing at runtime between the native library and the pure-Rust
+//! implementation, when the `native` and `mock` features are both enabled.
+
+use crate::error::{DataSketchesError, Result};
+use std::fmt;
+use std::hint::black_box;
+use std::os::raw::c_void;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Environment variable selecting the implementation, `native` or `rust`.
+pub const IMPLEMENTATION_VAR: &str = "KLL_RS_IMPLEMENTATION";
+
+/// Updates timed per implementation by [`bench_backends`], at least.
+const BENCH_UPDATES: usize = 200_000;
+
+/// Code the sketches run on.
+///
+/// Parses from and displays as `native` and `rust`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Implementation {
+    /// The datasketches-cpp library.
+    Native,
+    /// The pure-Rust implementation behind the `mock` feature.
+    Rust,
+}
+
+impl Implementation {
+    /// Returns the name used by [`IMPLEMENTATION_VAR`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Implementation::Native => "native",
+            Implementation::Rust => "rust",
+        }
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Implementation {
+    type Err = DataSketchesError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "native" => Ok(Implementation::Native),
+            "rust" => Ok(Implementation::Rust),
+            _ => Err(DataSketchesError::InvalidParameter(format!(
+                "unknown implementation {:?}; expected native or rust",
+                name
+            ))),
+        }
+    }
+}
+
+static SELECTED: OnceLock<Implementation> = OnceLock::new();
+
+/// Returns the implementation sketches run on, fixing it if no sketch has
+/// been created yet.
+///
+/// Unless [`set_implementation`] picked one first, it is read from
+/// [`IMPLEMENTATION_VAR`], defaulting to [`Implementation::Native`].
+///
+/// # Panics
+///
+/// If the variable holds anything but `native` or `rust`.
+pub fn implementation() -> Implementation {
+    *SELECTED.get_or_init(|| match std::env::var(IMPLEMENTATION_VAR) {
+        Ok(name) => name
+            .parse()
+            .unwrap_or_else(|err| panic!("{}: {}", IMPLEMENTATION_VAR, err)),
+        Err(_) => Implementation::Native,
+    })
+}
+
+/// Selects the implementation every sketch in the process runs on.
+///
+/// A sketch's handle only means something to the implementation that made
+/// it, so the choice is fixed by the first sketch or by the first call to
+/// this function. Fails with [`DataSketchesError::InvalidParameter`] if a
+/// different implementation is already fixed.
+pub fn set_implementation(selected: Implementation) -> Result<()> {
+    let fixed = *SELECTED.get_or_init(|| selected);
+    if fixed != selected {
+        return Err(DataSketchesError::InvalidParameter(format!(
+            "sketches already run on the {} implementation",
+            fixed
+        )));
+    }
+    Ok(())
+}
+
+/// Update throughput of each implementation, from [`bench_backends`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackendThroughput {
+    /// Updates per second on the native library.
+    pub native: f64,
+    /// Updates per second on the pure-Rust implementation.
+    pub rust: f64,
+}
+
+impl BackendThroughput {
+    /// Returns the pure-Rust throughput as a fraction of the native one;
+    /// above 1 the pure-Rust implementation is faster.
+    pub fn rust_relative(&self) -> f64 {
+        self.rust / self.native
+    }
+}
+
+/// Seconds `$backend` takes to update a fresh sketch with `$sample` `$rounds`
+/// times and read its median.
+macro_rules! time_updates {
+    ($backend:path, $sample:expr, $rounds:expr) => {{
+        use $backend as backend;
+        let mut status = backend::SketchStatus::new();
+        let start = Instant::now();
+        // SAFETY: the sketch is created, used and deleted on this one
+        // backend, and never escapes this block.
+        unsafe {
+            let sketch: *mut c_void =
+                backend::kll_double_sketch_new_with_k_checked(crate::DEFAULT_K, &mut status);
+            if status.is_ok() {
+                for _ in 0..$rounds {
+                    for &value in $sample {
+                        backend::kll_double_sketch_update_checked(sketch, value, &mut status);
+                    }
+                }
+                black_box(backend::kll_double_sketch_get_quantile_checked(
+                    sketch,
+                    0.5,
+                    &mut status,
+                ));
+                backend::kll_double_sketch_delete_checked(sketch, &mut status);
+            }
+        }
+        if status.is_ok() {
+            Ok(start.elapsed().as_secs_f64().max(f64::MIN_POSITIVE))
+        } else {
+            Err(DataSketchesError::Unknown(status.message()))
+        }
+    }};
+}
+
+/// Times both implementations on `sample`, to inform [`set_implementation`].
+///
+/// Each implementation feeds the sample, repeated to at least 200,000
+/// values, to a fresh default-sized `f64` sketch and then reads its median.
+/// Neither the selected implementation nor any existing sketch is affected.
+/// Fails with [`DataSketchesError::InvalidParameter`] if the sample is empty.
+pub fn bench_backends(sample: &[f64]) -> Result<BackendThroughput> {
+    if sample.is_empty() {
+        return Err(DataSketchesError::InvalidParameter(
+            "cannot benchmark an empty sample".to_string(),
+        ));
+    }
+    let rounds = (BENCH_UPDATES + sample.len() - 1) / sample.len();
+    let updates = (rounds * sample.len()) as f64;
+    let native = time_updates!(libdatasketches_sys, sample, rounds)?;
+    let rust = time_updates!(crate::mock, sample, rounds)?;
+    Ok(BackendThroughput {
+        native: updates / native,
+        rust: updates / rust,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implementation_names() {
+        for selected in [Implementation::Native, Implementation::Rust] {
+            assert_eq!(
+                selected.to_string().parse::<Implementation>().unwrap(),
+                selected
+            );
+        }
+        assert!("cpp".parse::<Implementation>().is_err());
+    }
+
+    #[test]
+    fn test_selection_is_fixed() {
+        let fixed = implementation();
+        set_implementation(fixed).unwrap();
+        let other = match fixed {
+            Implementation::Native => Implementation::Rust,
+            Implementation::Rust => Implementation::Native,
+        };
+        assert!(set_implementation(other).is_err());
+        assert_eq!(implementation(), fixed);
+    }
+
+    #[test]
+    fn test_bench_backends() {
+        let sample: Vec<f64> = (0..1000).map(|i| (i * 7919 % 1000) as f64).collect();
+        let throughput = bench_backends(&sample).unwrap();
+        assert!(throughput.native > 0.0 && throughput.rust > 0.0);
+        assert!(throughput.rust_relative().is_finite());
+        assert!(bench_backends(&[]).is_err());
+    }
+}
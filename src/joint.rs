@@ -0,0 +1,283 @@
+//! Conditional quantiles of one metric given another.
+
+use crate::buckets::dedup_bounds;
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+
+/// Pairs buffered per bin before the bins are fixed, by default.
+const DEFAULT_WARMUP_PER_BIN: usize = 100;
+
+/// The joint distribution of two metrics observed together, such as request
+/// latency `y` and payload size `x`.
+///
+/// `x` is split into equi-depth bins, each keeping a sketch of the `y` values
+/// observed with an `x` in it, so the distribution of `y` can be read for any
+/// range of `x`. The first pairs are buffered until the warm-up is complete;
+/// the bin boundaries are then fixed at the `x` quantiles seen so far, and the
+/// buffered pairs replayed into the bins. Until then queries are answered
+/// from the buffer exactly. If the `x` distribution drifts later, bins become
+/// uneven, but queries stay correct to the bin.
+///
+/// ```
+/// use kll_rs::{JointSketch, KParam};
+///
+/// let mut requests = JointSketch::new(10, KParam::default())?;
+/// for i in 0..10_000 {
+///     let size = (i % 1000) as f64;
+///     requests.update(size, size / 10.0 + (i % 7) as f64);
+/// }
+/// // p99 latency of requests in the top size decile.
+/// let p99 = requests.y_given_x_rank(0.9, 1.0)?.get_quantile(0.99);
+/// assert!(p99 >= 95.0);
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct JointSketch {
+    k: KParam,
+    bins: usize,
+    warmup: usize,
+    /// Every `x` observed.
+    x: KllDoubleSketch,
+    /// Pairs seen before the bins were fixed; empty afterwards.
+    pending: Vec<(f64, f64)>,
+    /// Upper bounds of every bin but the last, empty until the bins are fixed.
+    boundaries: Vec<f64>,
+    /// `y` sketch of each bin, empty until the bins are fixed.
+    y_bins: Vec<KllDoubleSketch>,
+}
+
+impl JointSketch {
+    /// Creates a joint sketch with up to `bins` bins over `x`, whose sketches
+    /// have the given k.
+    ///
+    /// The bins are fixed after `100 * bins` pairs unless
+    /// [`with_warmup`](Self::with_warmup) says otherwise.
+    pub fn new(bins: usize, k: KParam) -> Result<Self> {
+        if bins == 0 {
+            return Err(DataSketchesError::InvalidParameter(
+                "joint sketch needs at least one bin".to_string(),
+            ));
+        }
+        Ok(JointSketch {
+            k,
+            bins,
+            warmup: bins.saturating_mul(DEFAULT_WARMUP_PER_BIN),
+            x: KllDoubleSketch::with_k(k),
+            pending: Vec::new(),
+            boundaries: Vec::new(),
+            y_bins: Vec::new(),
+        })
+    }
+
+    /// Sets how many pairs are buffered before the bins are fixed.
+    ///
+    /// Has no effect once they are.
+    pub fn with_warmup(mut self, pairs: usize) -> Self {
+        self.warmup = pairs.max(1);
+        if self.pending.len() >= self.warmup {
+            self.fix_bins();
+        }
+        self
+    }
+
+    /// Records a pair observed together. Pairs with a NaN are ignored.
+    pub fn update(&mut self, x: f64, y: f64) {
+        if x.is_nan() || y.is_nan() {
+            return;
+        }
+        self.x.update(x);
+        if self.is_binned() {
+            let bin = self.bin(x);
+            self.y_bins[bin].update(y);
+        } else {
+            self.pending.push((x, y));
+            if self.pending.len() >= self.warmup {
+                self.fix_bins();
+            }
+        }
+    }
+
+    /// Returns the number of pairs recorded.
+    pub fn get_n(&self) -> u64 {
+        self.x.get_n()
+    }
+
+    /// Returns true if no pair has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    /// Returns true once the bins are fixed.
+    pub fn is_binned(&self) -> bool {
+        !self.y_bins.is_empty()
+    }
+
+    /// Returns the upper bound of every bin but the last, which is unbounded;
+    /// empty until the bins are fixed.
+    ///
+    /// Bin `i` holds the pairs with `x` in `(boundaries[i - 1], boundaries[i]]`.
+    /// Repeated `x` quantiles collapse, so there may be fewer bins than asked.
+    pub fn boundaries(&self) -> &[f64] {
+        &self.boundaries
+    }
+
+    /// Returns the marginal sketch of `x`.
+    pub fn x_sketch(&self) -> &KllDoubleSketch {
+        &self.x
+    }
+
+    /// Returns the marginal sketch of `y`, over every pair.
+    pub fn y_sketch(&self) -> KllDoubleSketch {
+        self.y_given_x(f64::NEG_INFINITY, f64::INFINITY)
+            .expect("the full range is valid")
+    }
+
+    /// Returns a sketch of the `y` values observed with an `x` in
+    /// `[x_min, x_max]`.
+    ///
+    /// Once the bins are fixed, every bin overlapping the range counts in
+    /// full, so the answer covers the range widened to bin edges. Fails with
+    /// [`DataSketchesError::InvalidParameter`] if a bound is NaN or
+    /// `x_min > x_max`.
+    pub fn y_given_x(&self, x_min: f64, x_max: f64) -> Result<KllDoubleSketch> {
+        if x_min.is_nan() || x_max.is_nan() || x_min > x_max {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "invalid x range [{}, {}]",
+                x_min, x_max
+            )));
+        }
+        let mut y = KllDoubleSketch::with_k(self.k);
+        if !self.is_binned() {
+            for &(x, value) in &self.pending {
+                if x_min <= x && x <= x_max {
+                    y.update(value);
+                }
+            }
+            return Ok(y);
+        }
+        for bin in self.bin(x_min)..=self.bin(x_max) {
+            y.merge(&self.y_bins[bin])?;
+        }
+        Ok(y)
+    }
+
+    /// Returns a sketch of the `y` values observed with an `x` whose rank
+    /// among all `x` lies in `[rank_min, rank_max]`; `(0.9, 1.0)` selects the
+    /// top decile of `x`.
+    ///
+    /// Once the bins are fixed, the marginal sketch of `x` gives the rank
+    /// span of each bin, and the bins at least half inside the range count in
+    /// full; if none is, the bin overlapping the range most does. Before
+    /// that, the ranks are turned into an `x` range and answered as
+    /// [`y_given_x`](Self::y_given_x) does. Fails with
+    /// [`DataSketchesError::InvalidParameter`] if a rank is outside `[0, 1]`
+    /// or `rank_min > rank_max`.
+    pub fn y_given_x_rank(&self, rank_min: f64, rank_max: f64) -> Result<KllDoubleSketch> {
+        let valid = |rank: f64| (0.0..=1.0).contains(&rank);
+        if !valid(rank_min) || !valid(rank_max) || rank_min > rank_max {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "invalid x rank range [{}, {}]",
+                rank_min, rank_max
+            )));
+        }
+        if self.is_empty() {
+            return Ok(KllDoubleSketch::with_k(self.k));
+        }
+        if !self.is_binned() {
+            let x_min = self.x.get_quantile(rank_min);
+            let x_max = self.x.get_quantile(rank_max);
+            return self.y_given_x(x_min, x_max);
+        }
+
+        let mut edges = vec![0.0];
+        edges.extend(self.boundaries.iter().map(|&bound| self.x.get_rank(bound)));
+        edges.push(1.0);
+        let overlaps: Vec<(f64, f64)> = edges
+            .windows(2)
+            .map(|span| {
+                let overlap = span[1].min(rank_max) - span[0].max(rank_min);
+                (overlap.max(0.0), span[1] - span[0])
+            })
+            .collect();
+        let mut y = KllDoubleSketch::with_k(self.k);
+        for (bin, &(overlap, width)) in overlaps.iter().enumerate() {
+            if overlap > 0.0 && 2.0 * overlap >= width {
+                y.merge(&self.y_bins[bin])?;
+            }
+        }
+        if y.is_empty() {
+            let nearest = (0..overlaps.len())
+                .max_by(|&a, &b| overlaps[a].0.total_cmp(&overlaps[b].0))
+                .expect("there is at least one bin");
+            y.merge(&self.y_bins[nearest])?;
+        }
+        Ok(y)
+    }
+
+    /// Index of the bin holding `x`.
+    fn bin(&self, x: f64) -> usize {
+        self.boundaries.partition_point(|&bound| bound < x)
+    }
+
+    fn fix_bins(&mut self) {
+        let fractions: Vec<f64> = (1..self.bins)
+            .map(|i| i as f64 / self.bins as f64)
+            .collect();
+        self.boundaries = dedup_bounds(self.x.get_quantiles(&fractions));
+        // The top boundary would leave the maximum alone in the last bin.
+        if self.boundaries.last() == Some(&self.x.get_max_value()) {
+            self.boundaries.pop();
+        }
+        self.y_bins = vec![KllDoubleSketch::with_k(self.k); self.boundaries.len() + 1];
+        for (x, y) in std::mem::take(&mut self.pending) {
+            let bin = self.bin(x);
+            self.y_bins[bin].update(y);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditional_quantiles() {
+        let mut joint = JointSketch::new(10, KParam::new(200).unwrap())
+            .unwrap()
+            .with_warmup(5_000);
+        // y grows with x, plus a spike for the largest tenth of x.
+        let pair = |i: u64| {
+            let x = (i * 7919 % 10_000) as f64;
+            let y = if x >= 9_000.0 { 1_000.0 + x } else { x / 10.0 };
+            (x, y)
+        };
+        for i in 0..1_000 {
+            let (x, y) = pair(i);
+            joint.update(x, y);
+        }
+        assert!(!joint.is_binned());
+        let exact = joint.y_given_x(9_000.0, f64::INFINITY).unwrap();
+        assert!(exact.get_min_value() >= 10_000.0);
+
+        for i in 1_000..50_000 {
+            let (x, y) = pair(i);
+            joint.update(x, y);
+        }
+        joint.update(f64::NAN, 1.0);
+        assert!(joint.is_binned());
+        assert_eq!(joint.get_n(), 50_000);
+        assert_eq!(joint.boundaries().len(), 9);
+
+        let top = joint.y_given_x_rank(0.9, 1.0).unwrap();
+        assert!((top.get_n() as f64 - 5_000.0).abs() < 500.0);
+        assert!(top.get_quantile(0.05) >= 9_500.0);
+        let rest = joint.y_given_x_rank(0.0, 0.9).unwrap();
+        assert!(rest.get_quantile(0.99) < 1_000.0);
+        assert_eq!(joint.y_sketch().get_n(), 50_000);
+
+        assert!(joint.y_given_x(2.0, 1.0).is_err());
+        assert!(joint.y_given_x_rank(0.5, 1.5).is_err());
+        assert!(JointSketch::new(0, KParam::default()).is_err());
+    }
+}
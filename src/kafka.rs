@@ -0,0 +1,614 @@
+//! Windowed aggregation of a Kafka topic into sketches.
+//!
+//! A [`SketchAggregator`] consumes records whose key names a series and whose
+//! payload is a value, keeps one sketch per key and tumbling window of record
+//! time, and produces each window's sketch to an output topic once the
+//! window has closed. Output records carry the input key, the serialized
+//! sketch as payload, the window start as timestamp, and `window-start-ms`
+//! and `window-end-ms` headers.
+//!
+//! Offsets are committed only up to the first record of the oldest window
+//! still open, after the closed windows before it were delivered, so a
+//! restart replays every record that was not yet part of an emitted window.
+//! Each commit also records, as offset metadata, the start of the oldest
+//! window not yet emitted; replayed records of earlier windows, which were
+//! emitted before the restart, are skipped as late rather than aggregated
+//! into a partial copy. Emission is therefore at least once: a window can be
+//! produced twice, in full, if the aggregator stops between producing it and
+//! committing.
+//!
+//! ```no_run
+//! use kll_rs::kafka::SketchAggregator;
+//! use std::sync::atomic::AtomicBool;
+//! use std::time::Duration;
+//!
+//! let stop = AtomicBool::new(false);
+//! SketchAggregator::new("localhost:9092", "latency-rollups", "latency", "latency-1m")
+//!     .window(Duration::from_secs(60))
+//!     .allowed_lateness(Duration::from_secs(10))
+//!     .run(&stop)?;
+//! # Ok::<(), kll_rs::DataSketchesError>(())
+//! ```
+
+use crate::error::{DataSketchesError, Result};
+use crate::events::event;
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{DeliveryResult, Header, Message, OwnedHeaders};
+use rdkafka::producer::{BaseProducer, BaseRecord, Producer, ProducerContext};
+use rdkafka::{ClientContext, Offset, TopicPartitionList};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long one poll of the input topic waits for a record.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long an emission waits for the output topic to acknowledge it.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long reading a partition's committed offset waits for the broker.
+const COMMITTED_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How record payloads encode their value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueFormat {
+    /// A decimal number in UTF-8, surrounding whitespace allowed.
+    #[default]
+    Text,
+    /// An 8-byte big-endian IEEE 754 double.
+    BigEndianF64,
+}
+
+impl ValueFormat {
+    /// Decodes a payload, or returns `None` if it does not hold a value.
+    pub fn decode(self, payload: &[u8]) -> Option<f64> {
+        let value = match self {
+            ValueFormat::Text => std::str::from_utf8(payload).ok()?.trim().parse().ok()?,
+            ValueFormat::BigEndianF64 => f64::from_be_bytes(payload.try_into().ok()?),
+        };
+        Some(value).filter(|value: &f64| !value.is_nan())
+    }
+}
+
+/// Counts of records a [`SketchAggregator`] has handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AggregatorStats {
+    /// Records added to a window.
+    pub records: u64,
+    /// Records skipped because their payload did not decode.
+    pub undecodable: u64,
+    /// Records skipped because their window had already been emitted.
+    pub late: u64,
+    /// Window sketches produced to the output topic.
+    pub emitted: u64,
+}
+
+/// One closed window of one key.
+#[derive(Debug, Clone)]
+struct Rollup {
+    key: String,
+    start: i64,
+    sketch: KllDoubleSketch,
+}
+
+/// The open windows and the offsets they still depend on; everything but
+/// the Kafka clients.
+#[derive(Debug)]
+struct Windows {
+    k: KParam,
+    window: i64,
+    lateness: i64,
+    /// Sketches by window start and key.
+    open: BTreeMap<(i64, String), KllDoubleSketch>,
+    /// First offset of each partition in each open window, by window start.
+    first_offsets: BTreeMap<i64, HashMap<(String, i32), i64>>,
+    /// Offset after the last record consumed, by partition.
+    next_offsets: HashMap<(String, i32), i64>,
+    /// Newest record time seen.
+    watermark: i64,
+    /// Start of the oldest window an earlier run had not emitted, from the
+    /// committed offset metadata.
+    restored: i64,
+    stats: AggregatorStats,
+}
+
+impl Windows {
+    fn new(k: KParam, window: Duration, lateness: Duration) -> Self {
+        Windows {
+            k,
+            window: (window.as_millis() as i64).max(1),
+            lateness: lateness.as_millis() as i64,
+            open: BTreeMap::new(),
+            first_offsets: BTreeMap::new(),
+            next_offsets: HashMap::new(),
+            watermark: i64::MIN,
+            restored: i64::MIN,
+            stats: AggregatorStats::default(),
+        }
+    }
+
+    /// True once a window starting at `start` may no longer change.
+    fn is_closed(&self, start: i64) -> bool {
+        start < self.restored
+            || start
+                .saturating_add(self.window)
+                .saturating_add(self.lateness)
+                <= self.watermark
+    }
+
+    /// Returns the start of the oldest window not closed yet; every window
+    /// before it has been or is about to be emitted.
+    fn emitted_before(&self) -> i64 {
+        if self.watermark == i64::MIN {
+            return self.restored;
+        }
+        let last = self
+            .watermark
+            .saturating_sub(self.window)
+            .saturating_sub(self.lateness);
+        let closed = last - last.rem_euclid(self.window) + self.window;
+        closed.max(self.restored)
+    }
+
+    /// Marks the windows before `emitted_before` as emitted by an earlier
+    /// run, dropping whatever this run aggregated into them.
+    fn restore(&mut self, emitted_before: i64) {
+        if emitted_before <= self.restored {
+            return;
+        }
+        self.restored = emitted_before;
+        while let Some(((start, _), _)) = self.open.first_key_value() {
+            if *start >= emitted_before {
+                break;
+            }
+            let (_, sketch) = self.open.pop_first().expect("just seen");
+            self.stats.records -= sketch.get_n();
+            self.stats.late += sketch.get_n();
+        }
+        self.first_offsets = self.first_offsets.split_off(&emitted_before);
+    }
+
+    fn record(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+        time: i64,
+        key: &str,
+        value: Option<f64>,
+    ) {
+        let position = (topic.to_string(), partition);
+        self.next_offsets.insert(position.clone(), offset + 1);
+        self.watermark = self.watermark.max(time);
+        let Some(value) = value else {
+            self.stats.undecodable += 1;
+            return;
+        };
+        let start = time - time.rem_euclid(self.window);
+        if self.is_closed(start) {
+            self.stats.late += 1;
+            return;
+        }
+        let k = self.k;
+        self.open
+            .entry((start, key.to_string()))
+            .or_insert_with(|| KllDoubleSketch::with_k(k))
+            .update(value);
+        self.first_offsets
+            .entry(start)
+            .or_default()
+            .entry(position)
+            .or_insert(offset);
+        self.stats.records += 1;
+    }
+
+    /// Removes and returns the windows that have closed, oldest first.
+    fn drain_closed(&mut self) -> Vec<Rollup> {
+        let mut closed = Vec::new();
+        while let Some(((start, _), _)) = self.open.first_key_value() {
+            if !self.is_closed(*start) {
+                break;
+            }
+            let ((start, key), sketch) = self.open.pop_first().expect("just seen");
+            self.first_offsets.remove(&start);
+            closed.push(Rollup { key, start, sketch });
+        }
+        closed
+    }
+
+    /// Returns the offset to commit for each partition consumed: the first
+    /// one an open window still needs, or else the next one to consume.
+    /// Offsets are committed with [`emitted_before`](Self::emitted_before)
+    /// as metadata.
+    fn committable(&self) -> Vec<(String, i32, i64)> {
+        let mut offsets = self.next_offsets.clone();
+        for firsts in self.first_offsets.values() {
+            for (position, &first) in firsts {
+                let offset = offsets.entry(position.clone()).or_insert(first);
+                *offset = (*offset).min(first);
+            }
+        }
+        let mut offsets: Vec<_> = offsets
+            .into_iter()
+            .map(|((topic, partition), offset)| (topic, partition, offset))
+            .collect();
+        offsets.sort();
+        offsets
+    }
+}
+
+/// Counts deliveries to the output topic that failed.
+struct DeliveryContext {
+    failures: AtomicU64,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if result.is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn kafka_error(err: KafkaError) -> DataSketchesError {
+    DataSketchesError::Unknown(format!("kafka: {}", err))
+}
+
+/// Reads the metadata committed for a partition by an earlier run, so the
+/// windows it emitted are not aggregated again from replayed records.
+fn restore(
+    consumer: &BaseConsumer,
+    windows: &mut Windows,
+    topic: &str,
+    partition: i32,
+) -> Result<()> {
+    let mut query = TopicPartitionList::new();
+    query.add_partition(topic, partition);
+    let committed = consumer
+        .committed_offsets(query, COMMITTED_TIMEOUT)
+        .map_err(kafka_error)?;
+    let emitted_before = committed
+        .find_partition(topic, partition)
+        .and_then(|element| element.metadata().parse().ok());
+    if let Some(emitted_before) = emitted_before {
+        windows.restore(emitted_before);
+    }
+    Ok(())
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
+/// Consumes `(key, value)` records from one topic and produces per-key,
+/// per-window sketches to another.
+///
+/// Windows are tumbling and aligned to the Unix epoch, in record time; a
+/// record without a timestamp counts at the time it is consumed. A window
+/// closes once a record at least `allowed_lateness` past its end has been
+/// seen on any partition, and is emitted at the next emission, every
+/// `emit_interval`. Records for a window already emitted are dropped and
+/// counted as late.
+#[derive(Debug, Clone)]
+pub struct SketchAggregator {
+    brokers: String,
+    group_id: String,
+    input_topic: String,
+    output_topic: String,
+    window: Duration,
+    lateness: Duration,
+    emit_interval: Duration,
+    k: KParam,
+    format: ValueFormat,
+    client_config: Vec<(String, String)>,
+}
+
+impl SketchAggregator {
+    /// Creates an aggregator reading `input_topic` and writing
+    /// `output_topic` on `brokers`, in consumer group `group_id`.
+    ///
+    /// Defaults to one-minute windows, no lateness, emission every second,
+    /// the default k, and text values.
+    pub fn new(brokers: &str, group_id: &str, input_topic: &str, output_topic: &str) -> Self {
+        SketchAggregator {
+            brokers: brokers.to_string(),
+            group_id: group_id.to_string(),
+            input_topic: input_topic.to_string(),
+            output_topic: output_topic.to_string(),
+            window: Duration::from_secs(60),
+            lateness: Duration::ZERO,
+            emit_interval: Duration::from_secs(1),
+            k: KParam::default(),
+            format: ValueFormat::default(),
+            client_config: Vec::new(),
+        }
+    }
+
+    /// Sets the window length, at least a millisecond.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Sets how long past its end a window still accepts records.
+    pub fn allowed_lateness(mut self, lateness: Duration) -> Self {
+        self.lateness = lateness;
+        self
+    }
+
+    /// Sets how often closed windows are emitted and offsets committed.
+    pub fn emit_interval(mut self, interval: Duration) -> Self {
+        self.emit_interval = interval;
+        self
+    }
+
+    /// Sets the k of the window sketches.
+    pub fn k(mut self, k: KParam) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Sets how record payloads encode their value.
+    pub fn value_format(mut self, format: ValueFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Passes a librdkafka property, such as `security.protocol`, to both
+    /// the consumer and the producer.
+    pub fn client_config(mut self, key: &str, value: &str) -> Self {
+        self.client_config
+            .push((key.to_string(), value.to_string()));
+        self
+    }
+
+    fn config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+        for (key, value) in &self.client_config {
+            config.set(key, value);
+        }
+        config
+    }
+
+    /// Aggregates until `stop` is set, then returns the counts of records
+    /// handled.
+    ///
+    /// Windows still open when stopping are not emitted; their records are
+    /// read again on the next start. Fails if a client cannot be created,
+    /// or if consuming, producing or committing fails, leaving the offsets
+    /// of every window not yet delivered uncommitted.
+    pub fn run(&self, stop: &AtomicBool) -> Result<AggregatorStats> {
+        let mut consumer_config = self.config();
+        consumer_config
+            .set("group.id", &self.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest");
+        let consumer: BaseConsumer = consumer_config.create().map_err(kafka_error)?;
+        consumer
+            .subscribe(&[&self.input_topic])
+            .map_err(kafka_error)?;
+        let producer: BaseProducer<DeliveryContext> = self
+            .config()
+            .create_with_context(DeliveryContext {
+                failures: AtomicU64::new(0),
+            })
+            .map_err(kafka_error)?;
+
+        let mut windows = Windows::new(self.k, self.window, self.lateness);
+        let mut restored = HashSet::new();
+        let mut last_emit = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            if let Some(message) = consumer.poll(POLL_TIMEOUT) {
+                let message = message.map_err(kafka_error)?;
+                let position = (message.topic().to_string(), message.partition());
+                if !restored.contains(&position) {
+                    restore(&consumer, &mut windows, &position.0, position.1)?;
+                    restored.insert(position);
+                }
+                let key = message
+                    .key()
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                let value = message.payload().and_then(|p| self.format.decode(p));
+                let time = message.timestamp().to_millis().unwrap_or_else(now_millis);
+                windows.record(
+                    message.topic(),
+                    message.partition(),
+                    message.offset(),
+                    time,
+                    &key,
+                    value,
+                );
+            }
+            if last_emit.elapsed() >= self.emit_interval {
+                self.emit(&mut windows, &consumer, &producer)?;
+                last_emit = Instant::now();
+            }
+        }
+        Ok(windows.stats)
+    }
+
+    /// Produces the closed windows, waits for their delivery, then commits
+    /// the offsets no open window needs.
+    fn emit(
+        &self,
+        windows: &mut Windows,
+        consumer: &BaseConsumer,
+        producer: &BaseProducer<DeliveryContext>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let rollups = windows.drain_closed();
+        for rollup in &rollups {
+            let payload = rollup.sketch.serialize()?;
+            let window_start = rollup.start.to_string();
+            let window_end = (rollup.start + windows.window).to_string();
+            let headers = OwnedHeaders::new()
+                .insert(Header {
+                    key: "window-start-ms",
+                    value: Some(&window_start),
+                })
+                .insert(Header {
+                    key: "window-end-ms",
+                    value: Some(&window_end),
+                });
+            let mut record = BaseRecord::to(&self.output_topic)
+                .key(&rollup.key)
+                .payload(&payload)
+                .timestamp(rollup.start)
+                .headers(headers);
+            loop {
+                match producer.send(record) {
+                    Ok(()) => break,
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), back)) => {
+                        producer.poll(POLL_TIMEOUT);
+                        record = back;
+                    }
+                    Err((err, _)) => return Err(kafka_error(err)),
+                }
+            }
+        }
+        producer.flush(FLUSH_TIMEOUT).map_err(kafka_error)?;
+        let failures = producer.context().failures.swap(0, Ordering::Relaxed);
+        if failures > 0 {
+            return Err(DataSketchesError::Unknown(format!(
+                "kafka: {} window sketches were not delivered",
+                failures
+            )));
+        }
+        windows.stats.emitted += rollups.len() as u64;
+
+        let mut offsets = TopicPartitionList::new();
+        let emitted_before = windows.emitted_before().to_string();
+        for (topic, partition, offset) in windows.committable() {
+            offsets
+                .add_partition_offset(&topic, partition, Offset::Offset(offset))
+                .map_err(kafka_error)?;
+            if let Some(mut element) = offsets.find_partition(&topic, partition) {
+                element.set_metadata(&emitted_before);
+            }
+        }
+        if offsets.count() > 0 {
+            consumer
+                .commit(&offsets, CommitMode::Sync)
+                .map_err(kafka_error)?;
+        }
+        if !rollups.is_empty() {
+            event!(
+                debug,
+                "emitted kafka window sketches",
+                sketches = rollups.len(),
+                open = windows.open.len(),
+                micros = start.elapsed().as_micros() as u64,
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_formats() {
+        assert_eq!(ValueFormat::Text.decode(b" 12.5\n"), Some(12.5));
+        assert_eq!(ValueFormat::Text.decode(b"abc"), None);
+        assert_eq!(ValueFormat::Text.decode(b"NaN"), None);
+        assert_eq!(
+            ValueFormat::BigEndianF64.decode(&2.25f64.to_be_bytes()),
+            Some(2.25)
+        );
+        assert_eq!(ValueFormat::BigEndianF64.decode(b"2.25"), None);
+    }
+
+    #[test]
+    fn test_windows_and_offsets() {
+        let mut windows = Windows::new(
+            KParam::default(),
+            Duration::from_millis(1000),
+            Duration::from_millis(500),
+        );
+        windows.record("in", 0, 10, 100, "a", Some(1.0));
+        windows.record("in", 0, 11, 900, "b", Some(2.0));
+        windows.record("in", 1, 5, 1200, "a", Some(3.0));
+        windows.record("in", 1, 6, 1300, "a", None);
+        assert!(windows.drain_closed().is_empty());
+        assert_eq!(
+            windows.committable(),
+            vec![("in".to_string(), 0, 10), ("in".to_string(), 1, 5)]
+        );
+
+        // At 1500 the first window is past its lateness and closes.
+        windows.record("in", 0, 12, 1500, "a", Some(4.0));
+        let closed = windows.drain_closed();
+        let keys: Vec<_> = closed.iter().map(|r| (r.start, r.key.as_str())).collect();
+        assert_eq!(keys, vec![(0, "a"), (0, "b")]);
+        assert_eq!(closed[0].sketch.get_n(), 1);
+        assert_eq!(
+            windows.committable(),
+            vec![("in".to_string(), 0, 12), ("in".to_string(), 1, 5)]
+        );
+
+        windows.record("in", 0, 13, 999, "a", Some(5.0));
+        assert_eq!(windows.stats.late, 1);
+        assert_eq!(windows.stats.undecodable, 1);
+        assert_eq!(windows.stats.records, 4);
+
+        windows.record("in", 0, 14, 3000, "c", Some(6.0));
+        let closed = windows.drain_closed();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].sketch.get_n(), 2);
+        assert_eq!(
+            windows.committable(),
+            vec![("in".to_string(), 0, 14), ("in".to_string(), 1, 7)]
+        );
+    }
+
+    #[test]
+    fn test_restart_skips_emitted_windows() {
+        let window = Duration::from_millis(1000);
+        let lateness = Duration::from_millis(500);
+        let mut first = Windows::new(KParam::default(), window, lateness);
+        first.record("in", 0, 0, 100, "a", Some(1.0));
+        first.record("in", 0, 1, 1100, "a", Some(2.0));
+        first.record("in", 0, 2, 1600, "a", Some(3.0));
+        assert_eq!(first.drain_closed().len(), 1);
+        // A late record for the emitted window, after the first record of
+        // the open one.
+        first.record("in", 0, 3, 200, "a", Some(4.0));
+        assert_eq!(first.stats.late, 1);
+        assert_eq!(first.committable(), vec![("in".to_string(), 0, 1)]);
+        assert_eq!(first.emitted_before(), 1000);
+
+        // The next run replays from the committed offset.
+        let mut second = Windows::new(KParam::default(), window, lateness);
+        second.record("in", 0, 1, 1100, "a", Some(2.0));
+        second.restore(1000);
+        second.record("in", 0, 2, 1600, "a", Some(3.0));
+        second.record("in", 0, 3, 200, "a", Some(4.0));
+        second.record("in", 0, 4, 2600, "a", Some(5.0));
+        let closed = second.drain_closed();
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].start, closed[0].sketch.get_n()), (1000, 2));
+        assert_eq!((second.stats.records, second.stats.late), (3, 1));
+
+        // Restoring drops whatever was aggregated into emitted windows.
+        let mut third = Windows::new(KParam::default(), window, lateness);
+        third.record("in", 0, 3, 200, "a", Some(4.0));
+        third.restore(1000);
+        assert!(third.open.is_empty() && third.first_offsets.is_empty());
+        assert_eq!((third.stats.records, third.stats.late), (0, 1));
+        assert_eq!(third.emitted_before(), 1000);
+    }
+}
@@ -0,0 +1,86 @@
+//! The validated `k` parameter.
+
+use crate::defaults::default_k;
+use crate::error::{DataSketchesError, Result};
+use std::fmt;
+
+/// Smallest k accepted by the sketches.
+pub const MIN_K: u16 = 8;
+
+/// Largest k accepted by the sketches.
+pub const MAX_K: u16 = u16::MAX;
+
+/// The accuracy parameter of a KLL sketch, checked against the DataSketches
+/// limits.
+///
+/// Larger values give better accuracy but use more memory; at k = 200 the
+/// normalized rank error is about 1.65%, and
+/// `KllDoubleSketch::get_normalized_rank_error` gives it for any k. `Default`
+/// gives the process-wide default set with `set_default_k`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KParam(u16);
+
+impl KParam {
+    /// The smallest valid k.
+    pub const MIN: KParam = KParam(MIN_K);
+
+    /// The largest valid k.
+    pub const MAX: KParam = KParam(MAX_K);
+
+    /// Validates `k`, failing if it is below [`MIN_K`].
+    pub fn new(k: u16) -> Result<Self> {
+        if k < MIN_K {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "k must be between {} and {}, got {}",
+                MIN_K, MAX_K, k
+            )));
+        }
+        Ok(KParam(k))
+    }
+
+    /// Returns k as a plain integer.
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl Default for KParam {
+    fn default() -> Self {
+        KParam(default_k())
+    }
+}
+
+impl TryFrom<u16> for KParam {
+    type Error = DataSketchesError;
+
+    fn try_from(k: u16) -> Result<Self> {
+        KParam::new(k)
+    }
+}
+
+impl From<KParam> for u16 {
+    fn from(k: KParam) -> Self {
+        k.0
+    }
+}
+
+impl fmt::Display for KParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_k_limits() {
+        assert!(KParam::new(7).is_err());
+        assert!(KParam::try_from(0).is_err());
+        assert_eq!(KParam::new(MIN_K).unwrap(), KParam::MIN);
+        assert_eq!(KParam::new(MAX_K).unwrap().get(), 65535);
+        assert_eq!(u16::from(KParam::new(200).unwrap()), 200);
+        assert_eq!(KParam::default().get(), default_k());
+    }
+}
@@ -0,0 +1,94 @@
+//! A count of live native sketches, for the `leak-tracking` feature.
+//!
+//! Every native sketch is counted from the moment the native library creates
+//! it until its owning sketch is dropped. A sketch released with `into_raw`
+//! stays counted until it comes back through `from_raw` and is dropped.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static LIVE_SKETCHES: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn sketch_created() {
+    LIVE_SKETCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn sketch_deleted() {
+    LIVE_SKETCHES.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Returns the number of native sketches currently alive in the process.
+///
+/// Sketches still holding their first values inline have no native sketch
+/// yet and are not counted. A count that keeps growing while the number of
+/// sketches the program holds does not points at a leak.
+pub fn live_sketch_count() -> usize {
+    LIVE_SKETCHES.load(Ordering::Relaxed)
+}
+
+/// Checks that a piece of code frees every native sketch it creates.
+///
+/// The count is process-wide, so sketches created or dropped by other
+/// threads meanwhile show up too; run leak checks in their own test binary
+/// or with `--test-threads=1`.
+///
+/// ```
+/// use kll_rs::testing::LeakCheck;
+/// use kll_rs::KllDoubleSketch;
+///
+/// let check = LeakCheck::new();
+/// let sketch = KllDoubleSketch::try_default().unwrap();
+/// assert_eq!(check.leaked(), 1);
+/// drop(sketch);
+/// check.assert_no_leaks();
+/// ```
+#[derive(Debug)]
+pub struct LeakCheck {
+    baseline: usize,
+}
+
+impl LeakCheck {
+    /// Starts a check from the current number of live sketches.
+    pub fn new() -> Self {
+        LeakCheck {
+            baseline: live_sketch_count(),
+        }
+    }
+
+    /// Returns how many more native sketches are alive than when the check
+    /// started.
+    pub fn leaked(&self) -> usize {
+        live_sketch_count().saturating_sub(self.baseline)
+    }
+
+    /// Panics if any native sketch created since the check started is still
+    /// alive.
+    #[track_caller]
+    pub fn assert_no_leaks(self) {
+        let leaked = self.leaked();
+        assert!(leaked == 0, "{} native sketches leaked", leaked);
+    }
+}
+
+impl Default for LeakCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KllFloatSketch;
+
+    #[test]
+    fn test_counts_live_sketches() {
+        // Other tests create sketches concurrently, so only check lower bounds.
+        let sketch = KllFloatSketch::try_default().unwrap();
+        let copy = sketch.try_clone().unwrap();
+        assert!(live_sketch_count() >= 2);
+        let raw = copy.into_raw();
+        assert!(live_sketch_count() >= 2);
+        drop(unsafe { KllFloatSketch::from_raw(raw) });
+        drop(sketch);
+    }
+}
@@ -0,0 +1,334 @@
+//! A single-writer sketch whose readers never wait.
+//!
+//! The writer owns two copies of the sketch. Readers look at one while the
+//! writer changes the other; [`WriteHandle::publish`] swaps them, waits for
+//! the readers still on the old copy to finish, and brings that copy up to
+//! date by cloning the new one. Reads take no lock and never block on the
+//! writer, at the cost of twice the memory and a clone per publish.
+
+use crate::error::Result;
+use crate::sync::{
+    fence, spin_loop, yield_now, Arc, AtomicU64, AtomicUsize, Mutex, Ordering, UnsafeCell,
+};
+use crate::KllDoubleSketch;
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+struct Shared {
+    copies: [UnsafeCell<KllDoubleSketch>; 2],
+    /// Index of the copy readers use.
+    readable: AtomicUsize,
+    /// One counter per read handle, odd while it is reading.
+    epochs: Mutex<Vec<Arc<AtomicU64>>>,
+}
+
+// Readers only touch `copies[readable]` and the writer only mutates the other
+// copy, after every reader that could still see it has left.
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn register(&self) -> Arc<AtomicU64> {
+        let epoch = Arc::new(AtomicU64::new(0));
+        self.epochs.lock().unwrap().push(Arc::clone(&epoch));
+        epoch
+    }
+}
+
+/// The writing side of a left-right sketch.
+///
+/// ```
+/// use kll_rs::{KllDoubleSketch, WriteHandle};
+///
+/// let mut writer = WriteHandle::new(KllDoubleSketch::new());
+/// let reader = writer.read_handle();
+/// writer.update(4.0);
+/// assert_eq!(reader.read(|sketch| sketch.get_n()), 0);
+/// writer.publish();
+/// assert_eq!(reader.read(|sketch| sketch.get_n()), 1);
+/// ```
+pub struct WriteHandle {
+    shared: Arc<Shared>,
+    dirty: bool,
+}
+
+impl WriteHandle {
+    /// Starts publishing `sketch` to readers.
+    pub fn new(sketch: KllDoubleSketch) -> Self {
+        WriteHandle {
+            shared: Arc::new(Shared {
+                copies: [UnsafeCell::new(sketch.clone()), UnsafeCell::new(sketch)],
+                readable: AtomicUsize::new(0),
+                epochs: Mutex::new(Vec::new()),
+            }),
+            dirty: false,
+        }
+    }
+
+    /// Returns a new read handle. Handles can also be cloned.
+    pub fn read_handle(&self) -> ReadHandle {
+        ReadHandle::new(Arc::clone(&self.shared))
+    }
+
+    /// Updates the writer's copy with a new value.
+    pub fn update(&mut self, value: f64) {
+        self.pending_mut().update(value);
+    }
+
+    /// Updates the writer's copy with every value of `values`.
+    pub fn update_batch(&mut self, values: &[f64]) {
+        let sketch = self.pending_mut();
+        for &value in values {
+            sketch.update(value);
+        }
+    }
+
+    /// Merges another sketch into the writer's copy.
+    pub fn merge(&mut self, other: &KllDoubleSketch) -> Result<()> {
+        self.pending_mut().merge(other)
+    }
+
+    /// Returns the writer's copy, with the changes readers do not see yet.
+    pub fn pending(&self) -> &KllDoubleSketch {
+        let index = 1 - self.shared.readable.load(Ordering::Relaxed);
+        // Only the writer mutates this copy, and readers never see it.
+        self.shared.copies[index].with(|sketch| unsafe { &*sketch })
+    }
+
+    /// Returns true if there are changes readers do not see yet.
+    pub fn has_pending(&self) -> bool {
+        self.dirty
+    }
+
+    /// Makes every change so far visible to readers.
+    ///
+    /// Waits for reads that started on the previous copy to finish; reads
+    /// starting meanwhile already see the new one.
+    pub fn publish(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        let old = self.shared.readable.load(Ordering::Relaxed);
+        self.shared.readable.store(1 - old, Ordering::Release);
+        self.wait_for_readers();
+        // No reader can reach the old copy any more.
+        let fresh = self.shared.copies[1 - old].with(|sketch| unsafe { (*sketch).clone() });
+        self.shared.copies[old].with_mut(|sketch| unsafe { *sketch = fresh });
+        self.dirty = false;
+    }
+
+    fn pending_mut(&mut self) -> &mut KllDoubleSketch {
+        self.dirty = true;
+        let index = 1 - self.shared.readable.load(Ordering::Relaxed);
+        self.shared.copies[index].with_mut(|sketch| unsafe { &mut *sketch })
+    }
+
+    /// Waits until every reader that was reading when the copies swapped
+    /// has left its read.
+    fn wait_for_readers(&self) {
+        // Pairs with the fence in `ReadGuard::enter`: either the reader sees
+        // the swap, or this sees the reader's odd epoch.
+        fence(Ordering::SeqCst);
+        let mut epochs = self.shared.epochs.lock().unwrap();
+        epochs.retain(|epoch| Arc::strong_count(epoch) > 1);
+        for epoch in epochs.iter() {
+            let seen = epoch.load(Ordering::Acquire);
+            if seen % 2 == 0 {
+                continue;
+            }
+            let mut spins = 0u32;
+            while epoch.load(Ordering::Acquire) == seen {
+                if spins < 100 {
+                    spin_loop();
+                    spins += 1;
+                } else {
+                    yield_now();
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for WriteHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriteHandle")
+            .field("pending", self.pending())
+            .field("dirty", &self.dirty)
+            .finish()
+    }
+}
+
+/// The reading side of a left-right sketch.
+///
+/// Reads never take a lock or wait for the writer. A handle is meant for one
+/// thread; clone it for each reading thread.
+pub struct ReadHandle {
+    shared: Arc<Shared>,
+    epoch: Arc<AtomicU64>,
+    /// Nesting depth of reads on this handle.
+    depth: Cell<u32>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl ReadHandle {
+    fn new(shared: Arc<Shared>) -> Self {
+        let epoch = shared.register();
+        ReadHandle {
+            shared,
+            epoch,
+            depth: Cell::new(0),
+            _not_sync: PhantomData,
+        }
+    }
+
+    /// Runs `f` on the most recently published sketch.
+    ///
+    /// The writer's next publish waits for `f` to return, so keep it short;
+    /// clone the sketch to hold on to it.
+    pub fn read<R>(&self, f: impl FnOnce(&KllDoubleSketch) -> R) -> R {
+        let _guard = ReadGuard::enter(self);
+        let index = self.shared.readable.load(Ordering::Acquire);
+        // The writer leaves this copy alone until the guard is dropped.
+        self.shared.copies[index].with(|sketch| f(unsafe { &*sketch }))
+    }
+
+    /// Returns a copy of the most recently published sketch.
+    pub fn snapshot(&self) -> KllDoubleSketch {
+        self.read(KllDoubleSketch::clone)
+    }
+}
+
+impl Clone for ReadHandle {
+    fn clone(&self) -> Self {
+        ReadHandle::new(Arc::clone(&self.shared))
+    }
+}
+
+impl std::fmt::Debug for ReadHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadHandle").finish_non_exhaustive()
+    }
+}
+
+/// Marks its handle as reading for as long as it lives, panics included.
+struct ReadGuard<'a> {
+    handle: &'a ReadHandle,
+}
+
+impl<'a> ReadGuard<'a> {
+    fn enter(handle: &'a ReadHandle) -> Self {
+        let depth = handle.depth.get();
+        if depth == 0 {
+            handle.epoch.fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+        }
+        handle.depth.set(depth + 1);
+        ReadGuard { handle }
+    }
+}
+
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        let depth = self.handle.depth.get() - 1;
+        self.handle.depth.set(depth);
+        if depth == 0 {
+            self.handle.epoch.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_swaps_copies() {
+        let mut writer = WriteHandle::new(KllDoubleSketch::new());
+        let reader = writer.read_handle();
+        writer.update_batch(&[1.0, 2.0, 3.0]);
+        assert!(writer.has_pending());
+        assert_eq!(writer.pending().get_n(), 3);
+        assert!(reader.read(|sketch| sketch.is_empty()));
+
+        writer.publish();
+        assert!(!writer.has_pending());
+        assert_eq!(reader.read(|sketch| sketch.get_quantile(0.5)), 2.0);
+        writer.update(4.0);
+        writer.publish();
+        let nested = reader.read(|outer| reader.read(|inner| inner.get_n()) + outer.get_n());
+        assert_eq!(nested, 8);
+        assert_eq!(reader.clone().snapshot().get_max_value(), 4.0);
+    }
+
+    #[test]
+    fn test_readers_see_whole_publishes() {
+        let mut writer = WriteHandle::new(KllDoubleSketch::new());
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = writer.read_handle();
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..2000 {
+                        let n = reader.read(|sketch| sketch.get_n());
+                        assert!(n % 10 == 0 && n >= last, "{} after {}", n, last);
+                        last = n;
+                    }
+                })
+            })
+            .collect();
+        for round in 0..500 {
+            let batch: Vec<f64> = (0..10).map(|i| (round * 10 + i) as f64).collect();
+            writer.update_batch(&batch);
+            writer.publish();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(writer.read_handle().snapshot().get_n(), 5000);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+
+    #[test]
+    fn loom_read_during_publish() {
+        loom::model(|| {
+            let mut writer = WriteHandle::new(KllDoubleSketch::new());
+            let reader = writer.read_handle();
+            let thread = loom::thread::spawn(move || {
+                let first = reader.read(|sketch| sketch.get_n());
+                let second = reader.snapshot().get_n();
+                assert!(first == 0 || first == 2, "{}", first);
+                assert!(second >= first);
+            });
+            writer.update_batch(&[1.0, 2.0]);
+            writer.publish();
+            thread.join().unwrap();
+            assert_eq!(writer.read_handle().snapshot().get_n(), 2);
+        });
+    }
+
+    #[test]
+    fn loom_two_publishes_and_readers() {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(|| {
+            let mut writer = WriteHandle::new(KllDoubleSketch::new());
+            let readers: Vec<_> = (0..2)
+                .map(|_| {
+                    let reader = writer.read_handle();
+                    loom::thread::spawn(move || reader.read(|sketch| sketch.get_n()))
+                })
+                .collect();
+            writer.update(1.0);
+            writer.publish();
+            writer.update(2.0);
+            writer.publish();
+            for reader in readers {
+                assert!(reader.join().unwrap() <= 2);
+            }
+            assert_eq!(writer.pending().get_n(), 2);
+        });
+    }
+}
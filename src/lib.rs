@@ -1,9 +1,144 @@
 //! `dsrs-kll` contains bindings for KLL sketches from [Apache DataSketches](https://github.com/apache/datasketches-cpp).
 
+mod adaptive;
+mod any;
+mod ascii;
+mod backend;
+#[cfg(feature = "bootstrap")]
+mod bootstrap;
+mod buckets;
+mod cached;
+#[cfg(feature = "plotters")]
+pub mod charts;
+mod const_k;
+mod container;
+mod count_window;
+#[cfg(feature = "crypto")]
+mod crypto;
+#[cfg(feature = "ddsketch")]
+mod ddsketch;
+mod defaults;
+mod descending;
+mod durable;
 mod error;
+mod events;
+mod exemplar;
+#[cfg(feature = "failpoints")]
+mod failpoints;
+mod format;
+mod fraction;
+mod frozen;
+mod handle;
+mod heatmap;
+mod hybrid;
+#[cfg(all(feature = "native", feature = "mock"))]
+mod implementation;
+mod joint;
+#[cfg(feature = "kafka")]
+pub mod kafka;
 mod kll_double_sketch;
 mod kll_float_sketch;
+mod kparam;
+#[cfg(feature = "leak-tracking")]
+mod leak;
+mod left_right;
+mod line_protocol;
+#[cfg(any(feature = "mock", test))]
+mod mock;
+mod moments;
+mod monitor;
+mod nonempty;
+mod observer;
+mod piecewise;
+pub mod prelude;
+mod range;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+mod registry;
+mod replay;
+mod rollup;
+mod rollup_store;
+mod search_criteria;
+mod seed;
+mod serde_form;
+mod sketch_type;
+mod slo;
+#[cfg(feature = "instrumented")]
+mod stats;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+mod status;
+mod summary;
+mod sync;
+mod sys;
+pub mod testing;
+mod timing;
+mod traits;
+#[cfg(feature = "uniffi")]
+mod uniffi_bindings;
+mod union;
+mod version;
 
+pub use adaptive::AdaptiveSketch;
+pub use any::{deserialize_any, AnySketch, SketchKind};
+pub use backend::{BackendKind, QuantileBackend};
+#[cfg(feature = "bootstrap")]
+pub use bootstrap::BOOTSTRAP_CONFIDENCE;
+pub use buckets::BucketStrategy;
+pub use cached::CachedSketch;
+pub use const_k::KllSketchConst;
+pub use container::{ContainerItem, ContainerReader, ContainerWriter};
+pub use count_window::CountWindowSketch;
+#[cfg(feature = "crypto")]
+pub use crypto::ENCRYPTION_KEY_LEN;
+#[cfg(feature = "ddsketch")]
+pub use ddsketch::DdBuckets;
+pub use defaults::{default_k, set_default_k, DEFAULT_K};
+pub use descending::DescendingSketch;
+pub use durable::{DurableSketch, DEFAULT_CHECKPOINT_INTERVAL};
 pub use error::DataSketchesError;
+pub use exemplar::{Exemplar, TailExemplarSketch};
+pub use format::{
+    detect_serial_version, needs_migration, SerializedHeader, KLL_FAMILY_ID, SERIAL_VERSION_1,
+    SERIAL_VERSION_2,
+};
+pub use fraction::Fraction;
+pub use frozen::FrozenSketch;
+pub use heatmap::{Heatmap, HeatmapBuilder, HeatmapValues};
+pub use hybrid::HybridQuantiles;
+#[cfg(all(feature = "native", feature = "mock"))]
+pub use implementation::{
+    bench_backends, implementation, set_implementation, BackendThroughput, Implementation,
+    IMPLEMENTATION_VAR,
+};
+pub use joint::JointSketch;
 pub use kll_double_sketch::KllDoubleSketch;
 pub use kll_float_sketch::KllFloatSketch;
+pub use kparam::{KParam, MAX_K, MIN_K};
+#[cfg(feature = "leak-tracking")]
+pub use leak::live_sketch_count;
+pub use left_right::{ReadHandle, WriteHandle};
+pub use moments::{FullStats, MomentsSketch};
+pub use monitor::{Comparison, MonitorEvent, QuantileMonitor, Rule};
+pub use nonempty::NonEmptySketch;
+pub use piecewise::PiecewiseMap;
+pub use range::RangeInclusivity;
+pub use registry::{QuotaKind, QuotaStats, RegistryQuota, SeriesKey, SketchRegistry};
+pub use replay::{read_log, Replay, UpdateRecord};
+pub use rollup::RollupTree;
+pub use rollup_store::{Resolution, RollupRange, RollupStore};
+pub use search_criteria::SearchCriteria;
+pub use seed::set_compaction_seed;
+pub use serde_form::{serde_form, serde_raw, set_serde_form, RawSerde, SerdeForm};
+pub use slo::SloTracker;
+#[cfg(feature = "instrumented")]
+pub use stats::{reset_stats, stats, FfiCall, FfiStats};
+pub use summary::{SummaryFormat, SummaryStyle};
+#[cfg(feature = "dynamic")]
+pub use sys::load_native_library;
+pub use traits::{Mergeable, QuantileSketch, SketchLike};
+pub use union::KllUnion;
+pub use version::{version, VersionInfo};
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
@@ -0,0 +1,104 @@
+//! InfluxDB line protocol output.
+//!
+//! A sketch becomes one point whose fields are the count, the minimum, the
+//! maximum and one float field per requested percentile:
+//!
+//! ```text
+//! latency,host=a,route=/users count=1000i,min=0.4,max=812,p50=12.5,p99=140 1700000000000000000
+//! ```
+//!
+//! Tags are sorted by key, as InfluxDB recommends, and tags with an empty key
+//! or value are left out. An empty sketch has only the `count=0i` field.
+
+use crate::summary::percentile_label;
+use std::io::{self, Write};
+
+/// The values of one point, read off a sketch.
+pub(crate) struct Point<'a> {
+    pub(crate) measurement: &'a str,
+    pub(crate) tags: Vec<(&'a str, &'a str)>,
+    pub(crate) n: u64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    /// Fraction and quantile pairs.
+    pub(crate) quantiles: Vec<(f64, f64)>,
+    /// Nanoseconds since the Unix epoch.
+    pub(crate) timestamp: Option<i64>,
+}
+
+impl Point<'_> {
+    /// Writes the point and its terminating newline.
+    pub(crate) fn write<W: Write + ?Sized>(mut self, out: &mut W) -> io::Result<()> {
+        write!(out, "{}", escape(self.measurement, &[',', ' ']))?;
+        self.tags
+            .retain(|(key, value)| !key.is_empty() && !value.is_empty());
+        self.tags.sort();
+        for (key, value) in &self.tags {
+            write!(out, ",{}={}", escape_key(key), escape_key(value))?;
+        }
+        write!(out, " count={}i", self.n)?;
+        if self.n > 0 {
+            let fields = [("min".to_string(), self.min), ("max".to_string(), self.max)]
+                .into_iter()
+                .chain(
+                    self.quantiles
+                        .iter()
+                        .map(|&(fraction, value)| (field_label(fraction), value)),
+                );
+            for (field, value) in fields.filter(|(_, value)| value.is_finite()) {
+                write!(out, ",{}={}", field, value)?;
+            }
+        }
+        if let Some(timestamp) = self.timestamp {
+            write!(out, " {}", timestamp)?;
+        }
+        writeln!(out)
+    }
+}
+
+/// Names a percentile field, e.g. `0.999` as `p99_9`.
+fn field_label(fraction: f64) -> String {
+    percentile_label(fraction).replace('.', "_")
+}
+
+fn escape_key(text: &str) -> String {
+    escape(text, &[',', '=', ' '])
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::KllDoubleSketch;
+
+    #[test]
+    fn test_line_protocol_escaping() {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 1..=100 {
+            sketch.update(i as f64);
+        }
+        let line = sketch.to_line_protocol(
+            "http latency,v2",
+            &[("route", "/a b"), ("host", "x=1"), ("empty", "")],
+            &[0.5, 0.999],
+            Some(1_700_000_000_000_000_000),
+        );
+        assert_eq!(
+            line,
+            "http\\ latency\\,v2,host=x\\=1,route=/a\\ b \
+             count=100i,min=1,max=100,p50=50,p99_9=100 1700000000000000000\n"
+        );
+
+        let empty = KllDoubleSketch::new().to_line_protocol("latency", &[], &[0.5], None);
+        assert_eq!(empty, "latency count=0i\n");
+    }
+}
@@ -0,0 +1,1214 @@
+//! Pure-Rust stand-in for the native library, used by the `mock` feature.
+//!
+//! It provides the same C functions as `libdatasketches_sys`, so the sketch
+//! types run unchanged on top of it. The algorithm follows datasketches-cpp
+//! closely: the same level capacities, one compaction per full update, and
+//! the same wire format. It is not bit-for-bit identical, since compaction
+//! draws from a different random generator, and it is not tuned for speed.
+#![cfg_attr(not(feature = "mock"), allow(dead_code))]
+
+use crate::format::{
+    level_capacity, max_serialized_size_bytes, total_capacity, DATA_START, FLAG_EMPTY,
+    FLAG_LEVEL_ZERO_SORTED, FLAG_SINGLE_ITEM, KLL_FAMILY_ID, MIN_K_OFFSET, MIN_LEVEL_WIDTH,
+    NUM_LEVELS_OFFSET, N_OFFSET, PREAMBLE_BYTES, PREAMBLE_INTS_FULL, PREAMBLE_INTS_SHORT,
+    SERIAL_VERSION_1, SERIAL_VERSION_2,
+};
+use libc::size_t;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::os::raw::{c_char, c_void};
+
+// With the native library also compiled in, `version()` reports its
+// constants instead of these.
+
+/// Reported in place of the vendored datasketches-cpp version.
+#[cfg_attr(feature = "native", allow(dead_code))]
+pub const DATASKETCHES_CPP_VERSION: &str = "mock";
+
+/// Reported in place of the vendored datasketches-cpp commit.
+#[cfg_attr(feature = "native", allow(dead_code))]
+pub const DATASKETCHES_CPP_COMMIT: &str = "mock";
+
+/// The mock follows the current datasketches-cpp API.
+#[cfg_attr(feature = "native", allow(dead_code))]
+pub const DATASKETCHES_CPP_API_MAJOR: u8 = 5;
+
+#[allow(non_camel_case_types)]
+pub type kll_compaction_callback_t =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, level: u8, items_compacted: u32)>;
+
+pub const SKETCH_STATUS_MESSAGE_LEN: usize = 256;
+pub const SKETCH_STATUS_OK: i32 = 0;
+pub const SKETCH_STATUS_NULL_POINTER: i32 = 1;
+pub const SKETCH_STATUS_INVALID_ARGUMENT: i32 = 2;
+pub const SKETCH_STATUS_INVALID_STATE: i32 = 3;
+pub const SKETCH_STATUS_OUT_OF_MEMORY: i32 = 4;
+// Kept for parity with the native codes; the mock never fails this way.
+#[allow(dead_code)]
+pub const SKETCH_STATUS_UNKNOWN: i32 = 5;
+
+/// Same layout as `SketchStatus` in `libdatasketches_sys`.
+#[cfg(not(feature = "native"))]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SketchStatus {
+    pub code: i32,
+    pub message: [c_char; SKETCH_STATUS_MESSAGE_LEN],
+}
+
+// With the native library compiled in, both report through the same status
+// type, so calls can go to either at runtime.
+#[cfg(feature = "native")]
+pub use libdatasketches_sys::SketchStatus;
+
+const MIN_K: u16 = 8;
+
+thread_local! {
+    static RNG: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish());
+}
+
+/// Returns a random bit from the thread's splitmix64 generator.
+fn random_bit() -> usize {
+    RNG.with(|state| {
+        let next = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state.set(next);
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) & 1) as usize
+    })
+}
+
+pub unsafe fn kll_set_random_seed(seed: u64) {
+    RNG.with(|state| state.set(seed));
+}
+
+pub unsafe fn kll_set_random_seed_checked(seed: u64, status: *mut SketchStatus) {
+    kll_set_random_seed(seed);
+    report(status, SKETCH_STATUS_OK, "");
+}
+
+/// Writes a code and a truncated, NUL-terminated message to `status`.
+unsafe fn report(status: *mut SketchStatus, code: i32, message: &str) {
+    let Some(status) = status.as_mut() else {
+        return;
+    };
+    status.code = code;
+    let len = message.len().min(SKETCH_STATUS_MESSAGE_LEN - 1);
+    for (out, &byte) in status.message.iter_mut().zip(&message.as_bytes()[..len]) {
+        *out = byte as c_char;
+    }
+    status.message[len] = 0;
+}
+
+/// Reports whether the handles are all non-null, then runs the unchecked
+/// function, which returns its fallback for null handles.
+unsafe fn with_handles<R>(
+    status: *mut SketchStatus,
+    handles: &[*mut c_void],
+    f: impl FnOnce() -> R,
+) -> R {
+    if handles.iter().any(|h| h.is_null()) {
+        report(status, SKETCH_STATUS_NULL_POINTER, "sketch handle is null");
+    } else {
+        report(status, SKETCH_STATUS_OK, "");
+    }
+    f()
+}
+
+/// Like `with_handles`, additionally failing like the native library if the
+/// sketch is empty.
+unsafe fn with_non_empty<T, R>(
+    status: *mut SketchStatus,
+    ptr: *mut c_void,
+    fallback: R,
+    f: impl FnOnce() -> R,
+) -> R {
+    if sketch::<T>(ptr).is_some_and(|s| s.n == 0) {
+        report(
+            status,
+            SKETCH_STATUS_INVALID_STATE,
+            "operation is undefined for an empty sketch",
+        );
+        return fallback;
+    }
+    with_handles(status, &[ptr], f)
+}
+
+/// Reports a null output of a constructor as `code`.
+unsafe fn non_null(
+    status: *mut SketchStatus,
+    ptr: *mut c_void,
+    code: i32,
+    message: &str,
+) -> *mut c_void {
+    if ptr.is_null() {
+        report(status, code, message);
+    } else {
+        report(status, SKETCH_STATUS_OK, "");
+    }
+    ptr
+}
+
+/// Reports a null buffer argument.
+unsafe fn buffers_ok<P>(status: *mut SketchStatus, buffers: &[*const P]) -> bool {
+    if buffers.iter().any(|b| b.is_null()) {
+        report(status, SKETCH_STATUS_INVALID_ARGUMENT, "buffer is null");
+        return false;
+    }
+    true
+}
+
+/// An item type the mock can sketch.
+trait Item: Copy + PartialOrd + Default + 'static {
+    const SIZE: usize;
+    fn is_nan(self) -> bool;
+    fn total_cmp(&self, other: &Self) -> Ordering;
+    fn write(self, out: &mut Vec<u8>);
+    fn read(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_item {
+    ($t:ty) => {
+        impl Item for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            fn is_nan(self) -> bool {
+                <$t>::is_nan(self)
+            }
+
+            fn total_cmp(&self, other: &Self) -> Ordering {
+                <$t>::total_cmp(self, other)
+            }
+
+            fn write(self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read(bytes: &[u8]) -> Self {
+                let mut raw = [0u8; std::mem::size_of::<$t>()];
+                raw.copy_from_slice(&bytes[..Self::SIZE]);
+                <$t>::from_le_bytes(raw)
+            }
+        }
+    };
+}
+
+impl_item!(f32);
+impl_item!(f64);
+
+struct Sketch<T> {
+    k: u16,
+    min_k: u16,
+    n: u64,
+    /// Items of weight `2^h` at index `h`. Levels above zero are sorted.
+    levels: Vec<Vec<T>>,
+    min: T,
+    max: T,
+    callback: kll_compaction_callback_t,
+    user_data: *mut c_void,
+}
+
+impl<T: Item> Sketch<T> {
+    fn new(k: u16) -> Option<Self> {
+        if k < MIN_K {
+            return None;
+        }
+        Some(Sketch {
+            k,
+            min_k: k,
+            n: 0,
+            levels: vec![Vec::new()],
+            min: T::default(),
+            max: T::default(),
+            callback: None,
+            user_data: std::ptr::null_mut(),
+        })
+    }
+
+    /// Copies the sketch without its compaction callback, like the wrapper.
+    fn copy(&self) -> Self {
+        Sketch {
+            k: self.k,
+            min_k: self.min_k,
+            n: self.n,
+            levels: self.levels.clone(),
+            min: self.min,
+            max: self.max,
+            callback: None,
+            user_data: std::ptr::null_mut(),
+        }
+    }
+
+    fn num_levels(&self) -> u8 {
+        self.levels.len() as u8
+    }
+
+    fn num_retained(&self) -> u32 {
+        self.levels.iter().map(|level| level.len() as u32).sum()
+    }
+
+    fn track_range(&mut self, low: T, high: T) {
+        if self.n == 0 || low < self.min {
+            self.min = low;
+        }
+        if self.n == 0 || high > self.max {
+            self.max = high;
+        }
+    }
+
+    /// Adds a value, returning whether room had to be made by compacting.
+    fn update(&mut self, value: T) -> bool {
+        if value.is_nan() {
+            return false;
+        }
+        let full = self.num_retained() as u64 >= total_capacity(self.k, self.num_levels());
+        if full {
+            let (level, items) = self.compact_one();
+            if let Some(callback) = self.callback {
+                unsafe { callback(self.user_data, level, items) };
+            }
+        }
+        self.track_range(value, value);
+        self.n += 1;
+        self.levels[0].push(value);
+        full
+    }
+
+    /// Compacts the lowest full level, returning it and the items taken from it.
+    fn compact_one(&mut self) -> (u8, u32) {
+        let num_levels = self.num_levels();
+        let height = (0..num_levels)
+            .find(|&h| {
+                self.levels[h as usize].len() as u64 >= level_capacity(self.k, num_levels, h)
+            })
+            .unwrap_or(0) as usize;
+        if height + 1 == self.levels.len() {
+            self.levels.push(Vec::new());
+        }
+
+        let mut items = std::mem::take(&mut self.levels[height]);
+        items.sort_by(T::total_cmp);
+        // An odd item out stays behind at this level.
+        let leftover = items.len() % 2;
+        let offset = random_bit();
+        let promoted: Vec<T> = items[leftover..]
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .copied()
+            .collect();
+        let compacted = (items.len() - leftover) as u32;
+        items.truncate(leftover);
+        self.levels[height] = items;
+
+        let above = &mut self.levels[height + 1];
+        above.extend(promoted);
+        above.sort_by(T::total_cmp);
+        (height as u8, compacted)
+    }
+
+    /// Merges `other` in, returning the number of compactions it took.
+    fn merge(&mut self, other: &Sketch<T>) -> u32 {
+        if other.n == 0 {
+            return 0;
+        }
+        self.track_range(other.min, other.max);
+        self.min_k = self.min_k.min(other.min_k);
+        // Unweighted items go in one at a time, compacting as updates do.
+        let mut compactions = 0;
+        for &item in &other.levels[0] {
+            if self.num_retained() as u64 >= total_capacity(self.k, self.num_levels()) {
+                self.compact_one();
+                compactions += 1;
+            }
+            self.levels[0].push(item);
+        }
+        self.n += other.n;
+        for (height, items) in other.levels.iter().enumerate().skip(1) {
+            if self.levels.len() <= height {
+                self.levels.push(Vec::new());
+            }
+            self.levels[height].extend_from_slice(items);
+            self.levels[height].sort_by(T::total_cmp);
+        }
+        while self.num_retained() as u64 > total_capacity(self.k, self.num_levels()) {
+            self.compact_one();
+            compactions += 1;
+        }
+        compactions
+    }
+
+    /// Returns the retained items in order with their cumulative weights.
+    fn sorted_view(&self) -> Vec<(T, u64)> {
+        let mut weighted: Vec<(T, u64)> = self
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(h, items)| items.iter().map(move |&item| (item, 1u64 << h)))
+            .collect();
+        weighted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut cumulative = 0;
+        for entry in &mut weighted {
+            cumulative += entry.1;
+            entry.1 = cumulative;
+        }
+        weighted
+    }
+
+    fn quantile(&self, rank: f64, inclusive: bool) -> T {
+        if self.n == 0 || !(0.0..=1.0).contains(&rank) {
+            return T::default();
+        }
+        let view = self.sorted_view();
+        let entry = if inclusive {
+            let weight = (rank * self.n as f64).ceil() as u64;
+            view.iter().find(|entry| entry.1 >= weight)
+        } else {
+            let weight = rank * self.n as f64;
+            view.iter().find(|entry| entry.1 as f64 > weight)
+        };
+        entry.or(view.last()).map_or(T::default(), |entry| entry.0)
+    }
+
+    fn rank(&self, value: T, inclusive: bool) -> f64 {
+        if self.n == 0 {
+            return 0.0;
+        }
+        let counted = |item: T| {
+            if inclusive {
+                item <= value
+            } else {
+                item < value
+            }
+        };
+        let below: u64 = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(h, items)| (items.iter().filter(|&&item| counted(item)).count() as u64) << h)
+            .sum();
+        below as f64 / self.n as f64
+    }
+
+    /// Returns the CDF at the split points followed by 1, or `None` unless
+    /// they are increasing and free of NaN.
+    fn cdf(&self, split_points: &[T]) -> Option<Vec<f64>> {
+        let increasing = split_points.windows(2).all(|w| w[0] < w[1]);
+        if !increasing || split_points.iter().any(|p| p.is_nan()) {
+            return None;
+        }
+        let mut cdf: Vec<f64> = split_points.iter().map(|&p| self.rank(p, true)).collect();
+        cdf.push(1.0);
+        Some(cdf)
+    }
+
+    fn pmf(&self, split_points: &[T]) -> Option<Vec<f64>> {
+        let mut pmf = self.cdf(split_points)?;
+        for i in (1..pmf.len()).rev() {
+            pmf[i] -= pmf[i - 1];
+        }
+        Some(pmf)
+    }
+
+    fn normalized_rank_error(&self, pmf: bool) -> f64 {
+        let k = self.min_k as f64;
+        if pmf {
+            2.446 / k.powf(0.9433)
+        } else {
+            2.296 / k.powf(0.9723)
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (preamble_ints, version, flags) = match self.n {
+            0 => (PREAMBLE_INTS_SHORT, SERIAL_VERSION_1, FLAG_EMPTY),
+            1 => (PREAMBLE_INTS_SHORT, SERIAL_VERSION_2, FLAG_SINGLE_ITEM),
+            _ => (PREAMBLE_INTS_FULL, SERIAL_VERSION_1, 0),
+        };
+        out.extend_from_slice(&[preamble_ints, version, KLL_FAMILY_ID, flags]);
+        out.extend_from_slice(&self.k.to_le_bytes());
+        out.extend_from_slice(&[MIN_LEVEL_WIDTH as u8, 0]);
+        match self.n {
+            0 => {}
+            1 => self.min.write(&mut out),
+            _ => {
+                out.extend_from_slice(&self.n.to_le_bytes());
+                out.extend_from_slice(&self.min_k.to_le_bytes());
+                out.extend_from_slice(&[self.num_levels(), 0]);
+                let capacity = total_capacity(self.k, self.num_levels()) as u32;
+                let mut offset = capacity - self.num_retained();
+                for level in &self.levels {
+                    out.extend_from_slice(&offset.to_le_bytes());
+                    offset += level.len() as u32;
+                }
+                self.min.write(&mut out);
+                self.max.write(&mut out);
+                for item in self.levels.iter().flatten() {
+                    item.write(&mut out);
+                }
+            }
+        }
+        out
+    }
+
+    fn deserialize(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < PREAMBLE_BYTES || bytes[2] != KLL_FAMILY_ID {
+            return None;
+        }
+        if bytes[1] != SERIAL_VERSION_1 && bytes[1] != SERIAL_VERSION_2 {
+            return None;
+        }
+        let flags = bytes[3];
+        let mut sketch = Self::new(u16::from_le_bytes([bytes[4], bytes[5]]))?;
+        if flags & FLAG_EMPTY != 0 {
+            return Some(sketch);
+        }
+        if flags & FLAG_SINGLE_ITEM != 0 {
+            let item = bytes.get(PREAMBLE_BYTES..PREAMBLE_BYTES + T::SIZE)?;
+            sketch.update(T::read(item));
+            return Some(sketch);
+        }
+
+        let header = bytes.get(..DATA_START)?;
+        let mut n = [0u8; 8];
+        n.copy_from_slice(&header[N_OFFSET..N_OFFSET + 8]);
+        sketch.n = u64::from_le_bytes(n);
+        sketch.min_k = u16::from_le_bytes([header[MIN_K_OFFSET], header[MIN_K_OFFSET + 1]]);
+        let num_levels = header[NUM_LEVELS_OFFSET];
+        if num_levels == 0 || num_levels > 61 {
+            return None;
+        }
+        let capacity = total_capacity(sketch.k, num_levels) as u32;
+        let levels_end = DATA_START + num_levels as usize * 4;
+        let mut offsets: Vec<u32> = bytes
+            .get(DATA_START..levels_end)?
+            .chunks_exact(4)
+            .map(|raw| u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+            .collect();
+        offsets.push(capacity);
+        if offsets.windows(2).any(|w| w[0] > w[1]) {
+            return None;
+        }
+
+        let mut items = bytes.get(levels_end..)?.chunks_exact(T::SIZE).map(T::read);
+        sketch.min = items.next()?;
+        sketch.max = items.next()?;
+        sketch.levels = offsets
+            .windows(2)
+            .map(|w| items.by_ref().take((w[1] - w[0]) as usize).collect())
+            .collect();
+        if sketch.num_retained() != capacity - offsets[0] {
+            return None;
+        }
+        if flags & FLAG_LEVEL_ZERO_SORTED == 0 {
+            sketch.levels[0].sort_by(T::total_cmp);
+        }
+        Some(sketch)
+    }
+}
+
+unsafe fn sketch<'a, T>(ptr: *mut c_void) -> Option<&'a mut Sketch<T>> {
+    (ptr as *mut Sketch<T>).as_mut()
+}
+
+fn into_raw<T>(sketch: Option<Sketch<T>>) -> *mut c_void {
+    sketch.map_or(std::ptr::null_mut(), |s| {
+        Box::into_raw(Box::new(s)) as *mut c_void
+    })
+}
+
+type Distribution<T> = fn(&Sketch<T>, &[T]) -> Option<Vec<f64>>;
+
+/// Writes a PMF or CDF to `results`, leaving it untouched on failure.
+unsafe fn distribution<T: Item>(
+    ptr: *mut c_void,
+    split_points: *const T,
+    num_split_points: u32,
+    results: *mut f64,
+    f: Distribution<T>,
+) -> Option<()> {
+    let s = sketch::<T>(ptr)?;
+    if results.is_null() || (split_points.is_null() && num_split_points > 0) || s.n == 0 {
+        return None;
+    }
+    let split_points = if num_split_points == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(split_points, num_split_points as usize)
+    };
+    let values = f(s, split_points)?;
+    std::ptr::copy_nonoverlapping(values.as_ptr(), results, values.len());
+    Some(())
+}
+
+/// Like `distribution`, reporting why it failed.
+unsafe fn checked_distribution<T: Item>(
+    ptr: *mut c_void,
+    split_points: *const T,
+    num_split_points: u32,
+    results: *mut f64,
+    status: *mut SketchStatus,
+    f: Distribution<T>,
+) {
+    if distribution(ptr, split_points, num_split_points, results, f).is_some() {
+        report(status, SKETCH_STATUS_OK, "");
+    } else if ptr.is_null() {
+        report(status, SKETCH_STATUS_NULL_POINTER, "sketch handle is null");
+    } else if results.is_null() || (split_points.is_null() && num_split_points > 0) {
+        report(status, SKETCH_STATUS_INVALID_ARGUMENT, "buffer is null");
+    } else if sketch::<T>(ptr).is_some_and(|s| s.n == 0) {
+        report(
+            status,
+            SKETCH_STATUS_INVALID_STATE,
+            "operation is undefined for an empty sketch",
+        );
+    } else {
+        report(
+            status,
+            SKETCH_STATUS_INVALID_ARGUMENT,
+            "split points must be unique and increasing",
+        );
+    }
+}
+
+/// Defines the C function set for one item type, named like the native
+/// `kll_<t>_sketch_*` functions.
+macro_rules! mock_functions {
+    ($item:ty, $t:ident) => {
+        paste::paste! {
+            // Kept for parity with the native API; the safe types always pass k.
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_new>]() -> *mut c_void {
+                into_raw(Sketch::<$item>::new(200))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_new_with_k>](k: u16) -> *mut c_void {
+                into_raw(Sketch::<$item>::new(k))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_copy>](ptr: *mut c_void) -> *mut c_void {
+                into_raw(sketch::<$item>(ptr).map(|s| s.copy()))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_delete>](ptr: *mut c_void) {
+                if !ptr.is_null() {
+                    drop(Box::from_raw(ptr as *mut Sketch<$item>));
+                }
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_update>](ptr: *mut c_void, value: $item) {
+                [<kll_ $t _sketch_update_tracked>](ptr, value);
+            }
+
+            // Kept for parity with the native API; the sketches merge with merge_tracked.
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_merge>](ptr: *mut c_void, other: *mut c_void) {
+                [<kll_ $t _sketch_merge_tracked>](ptr, other);
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_is_empty>](ptr: *mut c_void) -> bool {
+                sketch::<$item>(ptr).map_or(true, |s| s.n == 0)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_k>](ptr: *mut c_void) -> u16 {
+                sketch::<$item>(ptr).map_or(0, |s| s.k)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_n>](ptr: *mut c_void) -> u64 {
+                sketch::<$item>(ptr).map_or(0, |s| s.n)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_num_retained>](ptr: *mut c_void) -> u32 {
+                sketch::<$item>(ptr).map_or(0, |s| s.num_retained())
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_is_estimation_mode>](ptr: *mut c_void) -> bool {
+                sketch::<$item>(ptr).map_or(false, |s| s.levels.len() > 1)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_min_value>](ptr: *mut c_void) -> $item {
+                sketch::<$item>(ptr).map_or(0.0, |s| s.min)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_max_value>](ptr: *mut c_void) -> $item {
+                sketch::<$item>(ptr).map_or(0.0, |s| s.max)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantile>](ptr: *mut c_void, fraction: f64) -> $item {
+                sketch::<$item>(ptr).map_or(0.0, |s| s.quantile(fraction, true))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_rank>](ptr: *mut c_void, value: $item) -> f64 {
+                sketch::<$item>(ptr).map_or(0.0, |s| s.rank(value, true))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_serialize>](ptr: *mut c_void, size: *mut size_t) -> *mut u8 {
+                let (Some(s), false) = (sketch::<$item>(ptr), size.is_null()) else {
+                    return std::ptr::null_mut();
+                };
+                let bytes = s.serialize();
+                // Freed by the caller with libc::free, like the wrapper's buffers.
+                let out = libc::malloc(bytes.len()) as *mut u8;
+                if out.is_null() {
+                    return out;
+                }
+                std::ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+                *size = bytes.len();
+                out
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_deserialize>](data: *const u8, size: size_t) -> *mut c_void {
+                if data.is_null() || size == 0 {
+                    return std::ptr::null_mut();
+                }
+                into_raw(Sketch::<$item>::deserialize(std::slice::from_raw_parts(
+                    data, size,
+                )))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantiles>](
+                ptr: *mut c_void,
+                fractions: *const f64,
+                num_fractions: size_t,
+                results: *mut $item,
+            ) {
+                let Some(s) = sketch::<$item>(ptr) else { return };
+                if fractions.is_null() || results.is_null() {
+                    return;
+                }
+                let fractions = std::slice::from_raw_parts(fractions, num_fractions);
+                let results = std::slice::from_raw_parts_mut(results, num_fractions);
+                for (result, &fraction) in results.iter_mut().zip(fractions) {
+                    *result = s.quantile(fraction, true);
+                }
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantiles_evenly_spaced>](ptr: *mut c_void, num: u32, results: *mut $item) {
+                let Some(s) = sketch::<$item>(ptr) else { return };
+                if results.is_null() || num == 0 {
+                    return;
+                }
+                let results = std::slice::from_raw_parts_mut(results, num as usize);
+                for (i, result) in results.iter_mut().enumerate() {
+                    *result = s.quantile(i as f64 / (num - 1) as f64, true);
+                }
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_retained_items>](
+                ptr: *mut c_void,
+                items: *mut $item,
+                weights: *mut u64,
+                capacity: u32,
+            ) -> u32 {
+                let Some(s) = sketch::<$item>(ptr) else { return 0 };
+                if items.is_null() {
+                    return 0;
+                }
+                let retained = s
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(h, level)| level.iter().map(move |&item| (item, 1u64 << h)))
+                    .take(capacity as usize);
+                let mut count = 0;
+                for (i, (item, weight)) in retained.enumerate() {
+                    *items.add(i) = item;
+                    if !weights.is_null() {
+                        *weights.add(i) = weight;
+                    }
+                    count += 1;
+                }
+                count
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_normalized_rank_error>](ptr: *mut c_void, pmf: bool) -> f64 {
+                sketch::<$item>(ptr).map_or(0.0, |s| s.normalized_rank_error(pmf))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_set_compaction_callback>](
+                ptr: *mut c_void,
+                callback: kll_compaction_callback_t,
+                user_data: *mut c_void,
+            ) {
+                if let Some(s) = sketch::<$item>(ptr) {
+                    s.callback = callback;
+                    s.user_data = user_data;
+                }
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_update_tracked>](ptr: *mut c_void, value: $item) -> bool {
+                sketch::<$item>(ptr).map_or(false, |s| s.update(value))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_merge_tracked>](ptr: *mut c_void, other: *mut c_void) -> u32 {
+                if ptr == other {
+                    match sketch::<$item>(ptr) {
+                        Some(s) => {
+                            let copy = s.copy();
+                            s.merge(&copy)
+                        }
+                        None => 0,
+                    }
+                } else if let (Some(s), Some(o)) = (sketch::<$item>(ptr), sketch::<$item>(other)) {
+                    s.merge(o)
+                } else {
+                    0
+                }
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_items_discarded>](ptr: *mut c_void) -> u64 {
+                sketch::<$item>(ptr).map_or(0, |s| s.n - s.num_retained() as u64)
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_level_capacities>](
+                ptr: *mut c_void,
+                capacities: *mut u32,
+                capacity: u8,
+            ) -> u8 {
+                let Some(s) = sketch::<$item>(ptr) else { return 0 };
+                if capacities.is_null() {
+                    return 0;
+                }
+                let num_levels = s.num_levels();
+                for h in 0..num_levels.min(capacity) {
+                    *capacities.add(h as usize) = level_capacity(s.k, num_levels, h) as u32;
+                }
+                num_levels
+            }
+
+            // Not called by the sketches; kept for parity with the native API.
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_get_pmf>](
+                ptr: *mut c_void,
+                split_points: *const $item,
+                num_split_points: u32,
+                results: *mut f64,
+            ) {
+                distribution::<$item>(ptr, split_points, num_split_points, results, Sketch::pmf);
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_get_cdf>](
+                ptr: *mut c_void,
+                split_points: *const $item,
+                num_split_points: u32,
+                results: *mut f64,
+            ) {
+                distribution::<$item>(ptr, split_points, num_split_points, results, Sketch::cdf);
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_sorted_view_copy>](
+                ptr: *mut c_void,
+                items: *mut $item,
+                cumulative_weights: *mut u64,
+                capacity: u32,
+            ) -> u32 {
+                let Some(s) = sketch::<$item>(ptr) else { return 0 };
+                if items.is_null() {
+                    return 0;
+                }
+                let view = s.sorted_view();
+                for (i, &(item, cumulative)) in view.iter().take(capacity as usize).enumerate() {
+                    *items.add(i) = item;
+                    if !cumulative_weights.is_null() {
+                        *cumulative_weights.add(i) = cumulative;
+                    }
+                }
+                view.len() as u32
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_max_serialized_size>](k: u16, n: u64) -> size_t {
+                if k < MIN_K {
+                    return 0;
+                }
+                max_serialized_size_bytes(k, n, std::mem::size_of::<$item>())
+            }
+
+            // The checked variants. Null handles, null buffers and the errors
+            // the native library throws are reported in `status`.
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_new_checked>](status: *mut SketchStatus) -> *mut c_void {
+                non_null(status, [<kll_ $t _sketch_new>](), SKETCH_STATUS_OUT_OF_MEMORY, "allocation failed")
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_new_with_k_checked>](k: u16, status: *mut SketchStatus) -> *mut c_void {
+                let ptr = [<kll_ $t _sketch_new_with_k>](k);
+                non_null(status, ptr, SKETCH_STATUS_INVALID_ARGUMENT, "k must be at least 8")
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_copy_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> *mut c_void {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_copy>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_delete_checked>](ptr: *mut c_void, status: *mut SketchStatus) {
+                [<kll_ $t _sketch_delete>](ptr);
+                report(status, SKETCH_STATUS_OK, "");
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_update_checked>](ptr: *mut c_void, value: $item, status: *mut SketchStatus) {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_update>](ptr, value))
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_merge_checked>](ptr: *mut c_void, other: *mut c_void, status: *mut SketchStatus) {
+                with_handles(status, &[ptr, other], || [<kll_ $t _sketch_merge>](ptr, other))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_is_empty_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> bool {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_is_empty>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_k_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> u16 {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_get_k>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_n_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> u64 {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_get_n>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_num_retained_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> u32 {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_get_num_retained>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_is_estimation_mode_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> bool {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_is_estimation_mode>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_min_value_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> $item {
+                with_non_empty::<$item, _>(status, ptr, 0.0, || [<kll_ $t _sketch_get_min_value>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_max_value_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> $item {
+                with_non_empty::<$item, _>(status, ptr, 0.0, || [<kll_ $t _sketch_get_max_value>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantile_checked>](ptr: *mut c_void, fraction: f64, status: *mut SketchStatus) -> $item {
+                if !(0.0..=1.0).contains(&fraction) {
+                    report(status, SKETCH_STATUS_INVALID_ARGUMENT, "normalized rank cannot be less than 0 or greater than 1");
+                    return 0.0;
+                }
+                with_non_empty::<$item, _>(status, ptr, 0.0, || [<kll_ $t _sketch_get_quantile>](ptr, fraction))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_rank_checked>](ptr: *mut c_void, value: $item, status: *mut SketchStatus) -> f64 {
+                with_non_empty::<$item, _>(status, ptr, 0.0, || [<kll_ $t _sketch_get_rank>](ptr, value))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantile_criteria_checked>](
+                ptr: *mut c_void,
+                fraction: f64,
+                inclusive: bool,
+                status: *mut SketchStatus,
+            ) -> $item {
+                if !(0.0..=1.0).contains(&fraction) {
+                    report(status, SKETCH_STATUS_INVALID_ARGUMENT, "normalized rank cannot be less than 0 or greater than 1");
+                    return 0.0;
+                }
+                with_non_empty::<$item, _>(status, ptr, 0.0, || {
+                    sketch::<$item>(ptr).map_or(0.0, |s| s.quantile(fraction, inclusive))
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_rank_criteria_checked>](
+                ptr: *mut c_void,
+                value: $item,
+                inclusive: bool,
+                status: *mut SketchStatus,
+            ) -> f64 {
+                with_non_empty::<$item, _>(status, ptr, 0.0, || {
+                    sketch::<$item>(ptr).map_or(0.0, |s| s.rank(value, inclusive))
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_serialize_checked>](ptr: *mut c_void, size: *mut size_t, status: *mut SketchStatus) -> *mut u8 {
+                if !buffers_ok(status, &[size]) {
+                    return std::ptr::null_mut();
+                }
+                let data = with_handles(status, &[ptr], || [<kll_ $t _sketch_serialize>](ptr, size));
+                if data.is_null() && !ptr.is_null() {
+                    report(status, SKETCH_STATUS_OUT_OF_MEMORY, "allocation failed");
+                }
+                data
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_deserialize_checked>](data: *const u8, size: size_t, status: *mut SketchStatus) -> *mut c_void {
+                if !buffers_ok(status, &[data]) {
+                    return std::ptr::null_mut();
+                }
+                let ptr = [<kll_ $t _sketch_deserialize>](data, size);
+                non_null(status, ptr, SKETCH_STATUS_INVALID_ARGUMENT, "invalid serialized sketch")
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantiles_checked>](
+                ptr: *mut c_void,
+                fractions: *const f64,
+                num_fractions: size_t,
+                results: *mut $item,
+                status: *mut SketchStatus,
+            ) {
+                if num_fractions > 0 && !buffers_ok(status, &[fractions, results as *const f64]) {
+                    return;
+                }
+                with_handles(status, &[ptr], || {
+                    [<kll_ $t _sketch_get_quantiles>](ptr, fractions, num_fractions, results)
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantiles_criteria_checked>](
+                ptr: *mut c_void,
+                fractions: *const f64,
+                num_fractions: size_t,
+                inclusive: bool,
+                results: *mut $item,
+                status: *mut SketchStatus,
+            ) {
+                if num_fractions > 0 && !buffers_ok(status, &[fractions, results as *const f64]) {
+                    return;
+                }
+                if num_fractions > 0
+                    && std::slice::from_raw_parts(fractions, num_fractions)
+                        .iter()
+                        .any(|fraction| !(0.0..=1.0).contains(fraction))
+                {
+                    report(status, SKETCH_STATUS_INVALID_ARGUMENT, "normalized rank cannot be less than 0 or greater than 1");
+                    return;
+                }
+                with_handles(status, &[ptr], || {
+                    let (Some(s), false) = (sketch::<$item>(ptr), num_fractions == 0) else { return };
+                    let fractions = std::slice::from_raw_parts(fractions, num_fractions);
+                    let results = std::slice::from_raw_parts_mut(results, num_fractions);
+                    for (result, &fraction) in results.iter_mut().zip(fractions) {
+                        *result = s.quantile(fraction, inclusive);
+                    }
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_quantiles_evenly_spaced_checked>](
+                ptr: *mut c_void,
+                num: u32,
+                results: *mut $item,
+                status: *mut SketchStatus,
+            ) {
+                if num > 0 && !buffers_ok(status, &[results]) {
+                    return;
+                }
+                with_handles(status, &[ptr], || {
+                    [<kll_ $t _sketch_get_quantiles_evenly_spaced>](ptr, num, results)
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_retained_items_checked>](
+                ptr: *mut c_void,
+                items: *mut $item,
+                weights: *mut u64,
+                capacity: u32,
+                status: *mut SketchStatus,
+            ) -> u32 {
+                if !buffers_ok(status, &[items]) {
+                    return 0;
+                }
+                with_handles(status, &[ptr], || {
+                    [<kll_ $t _sketch_get_retained_items>](ptr, items, weights, capacity)
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_normalized_rank_error_checked>](ptr: *mut c_void, pmf: bool, status: *mut SketchStatus) -> f64 {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_get_normalized_rank_error>](ptr, pmf))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_set_compaction_callback_checked>](
+                ptr: *mut c_void,
+                callback: kll_compaction_callback_t,
+                user_data: *mut c_void,
+                status: *mut SketchStatus,
+            ) {
+                with_handles(status, &[ptr], || {
+                    [<kll_ $t _sketch_set_compaction_callback>](ptr, callback, user_data)
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_update_tracked_checked>](ptr: *mut c_void, value: $item, status: *mut SketchStatus) -> bool {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_update_tracked>](ptr, value))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_merge_tracked_checked>](ptr: *mut c_void, other: *mut c_void, status: *mut SketchStatus) -> u32 {
+                with_handles(status, &[ptr, other], || [<kll_ $t _sketch_merge_tracked>](ptr, other))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_items_discarded_checked>](ptr: *mut c_void, status: *mut SketchStatus) -> u64 {
+                with_handles(status, &[ptr], || [<kll_ $t _sketch_get_items_discarded>](ptr))
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_level_capacities_checked>](
+                ptr: *mut c_void,
+                capacities: *mut u32,
+                capacity: u8,
+                status: *mut SketchStatus,
+            ) -> u8 {
+                if !buffers_ok(status, &[capacities]) {
+                    return 0;
+                }
+                with_handles(status, &[ptr], || {
+                    [<kll_ $t _sketch_get_level_capacities>](ptr, capacities, capacity)
+                })
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_cdf_checked>](
+                ptr: *mut c_void,
+                split_points: *const $item,
+                num_split_points: u32,
+                results: *mut f64,
+                status: *mut SketchStatus,
+            ) {
+                checked_distribution::<$item>(ptr, split_points, num_split_points, results, status, Sketch::cdf);
+            }
+
+            pub unsafe fn [<kll_ $t _sketch_get_pmf_checked>](
+                ptr: *mut c_void,
+                split_points: *const $item,
+                num_split_points: u32,
+                results: *mut f64,
+                status: *mut SketchStatus,
+            ) {
+                checked_distribution::<$item>(ptr, split_points, num_split_points, results, status, Sketch::pmf);
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_sorted_view_copy_checked>](
+                ptr: *mut c_void,
+                items: *mut $item,
+                cumulative_weights: *mut u64,
+                capacity: u32,
+                status: *mut SketchStatus,
+            ) -> u32 {
+                if !buffers_ok(status, &[items]) {
+                    return 0;
+                }
+                with_handles(status, &[ptr], || {
+                    [<kll_ $t _sketch_sorted_view_copy>](ptr, items, cumulative_weights, capacity)
+                })
+            }
+
+            #[allow(dead_code)]
+            pub unsafe fn [<kll_ $t _sketch_max_serialized_size_checked>](k: u16, n: u64, status: *mut SketchStatus) -> size_t {
+                if k < MIN_K {
+                    report(status, SKETCH_STATUS_INVALID_ARGUMENT, "K must be >= 8 and <= 65535");
+                    return 0;
+                }
+                report(status, SKETCH_STATUS_OK, "");
+                [<kll_ $t _sketch_max_serialized_size>](k, n)
+            }
+        }
+    };
+}
+
+mock_functions!(f32, float);
+mock_functions!(f64, double);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_sketch_tracks_distribution() {
+        unsafe {
+            kll_set_random_seed(1);
+            let sketch = kll_double_sketch_new_with_k(64);
+            for i in 0..100_000 {
+                kll_double_sketch_update(sketch, i as f64);
+            }
+            assert_eq!(kll_double_sketch_get_n(sketch), 100_000);
+            assert!(kll_double_sketch_is_estimation_mode(sketch));
+            assert!(kll_double_sketch_get_num_retained(sketch) < 1_000);
+            let median = kll_double_sketch_get_quantile(sketch, 0.5);
+            assert!((median - 50_000.0).abs() < 3_000.0);
+            let rank = kll_double_sketch_get_rank(sketch, 25_000.0);
+            assert!((rank - 0.25).abs() < 0.03);
+            assert_eq!(kll_double_sketch_get_min_value(sketch), 0.0);
+            assert_eq!(kll_double_sketch_get_max_value(sketch), 99_999.0);
+            kll_double_sketch_delete(sketch);
+        }
+    }
+
+    #[test]
+    fn test_mock_distribution_functions() {
+        unsafe {
+            let sketch = kll_float_sketch_new_with_k(200);
+            for i in 1..=10 {
+                kll_float_sketch_update(sketch, i as f32);
+            }
+            let mut cdf = [0.0; 3];
+            kll_float_sketch_get_cdf(sketch, [3.0, 7.0].as_ptr(), 2, cdf.as_mut_ptr());
+            assert_eq!(cdf, [0.3, 0.7, 1.0]);
+
+            let mut status = SketchStatus {
+                code: SKETCH_STATUS_OK,
+                message: [0; SKETCH_STATUS_MESSAGE_LEN],
+            };
+            let mut pmf = [0.0; 3];
+            kll_float_sketch_get_pmf_checked(
+                sketch,
+                [7.0, 3.0].as_ptr(),
+                2,
+                pmf.as_mut_ptr(),
+                &mut status,
+            );
+            assert_eq!(status.code, SKETCH_STATUS_INVALID_ARGUMENT);
+
+            let mut status = SketchStatus {
+                code: SKETCH_STATUS_OK,
+                message: [0; SKETCH_STATUS_MESSAGE_LEN],
+            };
+            let mut quantiles = [0.0; 2];
+            kll_float_sketch_get_quantiles_criteria_checked(
+                sketch,
+                [0.5, 1.5].as_ptr(),
+                2,
+                false,
+                quantiles.as_mut_ptr(),
+                &mut status,
+            );
+            assert_eq!(status.code, SKETCH_STATUS_INVALID_ARGUMENT);
+
+            let mut items = [0.0; 4];
+            let mut weights = [0; 4];
+            let count = kll_float_sketch_sorted_view_copy(
+                sketch,
+                items.as_mut_ptr(),
+                weights.as_mut_ptr(),
+                4,
+            );
+            assert_eq!(count, 10);
+            assert_eq!(items, [1.0, 2.0, 3.0, 4.0]);
+            assert_eq!(weights, [1, 2, 3, 4]);
+            kll_float_sketch_delete(sketch);
+        }
+    }
+
+    #[test]
+    fn test_mock_wire_format_round_trip() {
+        for n in [0u32, 1, 5, 1_000] {
+            let mut sketch = Sketch::<f32>::new(8).unwrap();
+            for i in 0..n {
+                sketch.update(i as f32);
+            }
+            let bytes = sketch.serialize();
+            assert_eq!(
+                crate::format::SerializedHeader::parse(&bytes).unwrap().n,
+                n as u64
+            );
+            let restored = Sketch::<f32>::deserialize(&bytes).unwrap();
+            assert_eq!(restored.n, sketch.n);
+            assert_eq!(restored.num_retained(), sketch.num_retained());
+            assert_eq!(restored.serialize(), bytes);
+        }
+        assert!(Sketch::<f64>::deserialize(&[2, 1, 3, 1, 8, 0, 8, 0]).is_none());
+    }
+}
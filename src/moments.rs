@@ -0,0 +1,431 @@
+//! Streaming moments, alone or alongside a KLL sketch.
+//!
+//! [`MomentsSketch`] keeps the count, mean and the second to fourth central
+//! moments, updated and merged with the formulas of Pébay (2008), which stay
+//! accurate where the textbook sums of powers cancel catastrophically.
+//! [`FullStats`] pairs it with a [`KllDoubleSketch`] so reports get the mean
+//! and standard deviation next to the percentiles from one structure.
+
+use crate::error::{DataSketchesError, Result};
+use crate::kparam::KParam;
+use crate::traits::{Mergeable, SketchLike};
+use crate::KllDoubleSketch;
+use serde::{Deserialize, Serialize};
+
+/// Length of a serialized [`MomentsSketch`].
+const MOMENTS_BYTES: usize = 40;
+
+/// Exact count, mean, variance, skewness and kurtosis of a stream.
+///
+/// NaN values are ignored, as the KLL sketches ignore them. Statistics of an
+/// empty sketch are NaN, as are those that need more values than were seen.
+///
+/// ```
+/// use kll_rs::MomentsSketch;
+///
+/// let mut moments = MomentsSketch::new();
+/// for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+///     moments.update(value);
+/// }
+/// assert_eq!(moments.mean(), 5.0);
+/// assert!((moments.std_dev() - 2.0).abs() < 1e-12);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MomentsSketch {
+    count: u64,
+    mean: f64,
+    /// Sums of the second, third and fourth powers of deviations from the mean.
+    m2: f64,
+    m3: f64,
+    m4: f64,
+}
+
+impl MomentsSketch {
+    /// Creates an empty accumulator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value.
+    pub fn update(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        let n1 = self.count as f64;
+        self.count += 1;
+        let n = self.count as f64;
+        let delta = value - self.mean;
+        let delta_n = delta / n;
+        let delta_n2 = delta_n * delta_n;
+        let term = delta * delta_n * n1;
+        self.mean += delta_n;
+        self.m4 += term * delta_n2 * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n2 * self.m2
+            - 4.0 * delta_n * self.m3;
+        self.m3 += term * delta_n * (n - 2.0) - 3.0 * delta_n * self.m2;
+        self.m2 += term;
+    }
+
+    /// Combines another accumulator into this one, as if every value it saw
+    /// had been added here.
+    pub fn merge(&mut self, other: &MomentsSketch) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let (na, nb) = (self.count as f64, other.count as f64);
+        let n = na + nb;
+        let delta = other.mean - self.mean;
+        let delta2 = delta * delta;
+        let m2 = self.m2 + other.m2 + delta2 * na * nb / n;
+        let m3 = self.m3
+            + other.m3
+            + delta2 * delta * na * nb * (na - nb) / (n * n)
+            + 3.0 * delta * (na * other.m2 - nb * self.m2) / n;
+        let m4 = self.m4
+            + other.m4
+            + delta2 * delta2 * na * nb * (na * na - na * nb + nb * nb) / (n * n * n)
+            + 6.0 * delta2 * (na * na * other.m2 + nb * nb * self.m2) / (n * n)
+            + 4.0 * delta * (na * other.m3 - nb * self.m3) / n;
+        self.count += other.count;
+        self.mean += delta * nb / n;
+        self.m2 = m2;
+        self.m3 = m3;
+        self.m4 = m4;
+    }
+
+    /// Returns the number of values added.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns true if no values were added.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the sum of the values, or 0 if there are none.
+    pub fn sum(&self) -> f64 {
+        self.mean * self.count as f64
+    }
+
+    /// Returns the mean.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            f64::NAN
+        } else {
+            self.mean
+        }
+    }
+
+    /// Returns the population variance.
+    pub fn variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+
+    /// Returns the sample variance, with Bessel's correction; NaN below two
+    /// values.
+    pub fn sample_variance(&self) -> f64 {
+        if self.count < 2 {
+            f64::NAN
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// Returns the population standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Returns the skewness, zero for a symmetric distribution; NaN if every
+    /// value is the same.
+    pub fn skewness(&self) -> f64 {
+        if self.m2 == 0.0 {
+            return f64::NAN;
+        }
+        (self.count as f64).sqrt() * self.m3 / self.m2.powf(1.5)
+    }
+
+    /// Returns the excess kurtosis, zero for a normal distribution; NaN if
+    /// every value is the same.
+    pub fn kurtosis(&self) -> f64 {
+        if self.m2 == 0.0 {
+            return f64::NAN;
+        }
+        self.count as f64 * self.m4 / (self.m2 * self.m2) - 3.0
+    }
+
+    /// Serializes the accumulator into 40 little-endian bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MOMENTS_BYTES);
+        bytes.extend_from_slice(&self.count.to_le_bytes());
+        for value in [self.mean, self.m2, self.m3, self.m4] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes an accumulator written by [`serialize`](Self::serialize).
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != MOMENTS_BYTES {
+            return Err(DataSketchesError::DeserializationError(format!(
+                "moments take {} bytes, got {}",
+                MOMENTS_BYTES,
+                bytes.len()
+            )));
+        }
+        let word = |i: usize| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            word
+        };
+        Ok(MomentsSketch {
+            count: u64::from_le_bytes(word(0)),
+            mean: f64::from_le_bytes(word(1)),
+            m2: f64::from_le_bytes(word(2)),
+            m3: f64::from_le_bytes(word(3)),
+            m4: f64::from_le_bytes(word(4)),
+        })
+    }
+}
+
+/// A KLL sketch and a [`MomentsSketch`] of the same values.
+///
+/// ```
+/// use kll_rs::FullStats;
+///
+/// let mut stats = FullStats::new();
+/// for i in 1..=100 {
+///     stats.update(i as f64);
+/// }
+/// assert_eq!(stats.mean(), 50.5);
+/// assert_eq!(stats.get_quantile(0.5), 50.0);
+///
+/// let restored = FullStats::deserialize(&stats.serialize()?)?;
+/// assert_eq!(restored.moments(), stats.moments());
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FullStats {
+    sketch: KllDoubleSketch,
+    moments: MomentsSketch,
+}
+
+impl FullStats {
+    /// Creates empty statistics with the default k.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates empty statistics whose sketch has the given k.
+    pub fn with_k(k: KParam) -> Self {
+        FullStats {
+            sketch: KllDoubleSketch::with_k(k),
+            moments: MomentsSketch::new(),
+        }
+    }
+
+    /// Adds a value to both the sketch and the moments.
+    pub fn update(&mut self, value: f64) {
+        self.sketch.update(value);
+        self.moments.update(value);
+    }
+
+    /// Merges other statistics into these, failing as
+    /// [`KllDoubleSketch::merge`] does without changing anything.
+    pub fn merge(&mut self, other: &FullStats) -> Result<()> {
+        self.sketch.merge(&other.sketch)?;
+        self.moments.merge(&other.moments);
+        Ok(())
+    }
+
+    /// Returns the sketch of the values.
+    pub fn sketch(&self) -> &KllDoubleSketch {
+        &self.sketch
+    }
+
+    /// Returns the moments of the values.
+    pub fn moments(&self) -> &MomentsSketch {
+        &self.moments
+    }
+
+    /// Returns the number of values added.
+    pub fn get_n(&self) -> u64 {
+        self.moments.count()
+    }
+
+    /// Returns true if no values were added.
+    pub fn is_empty(&self) -> bool {
+        self.moments.is_empty()
+    }
+
+    /// Returns the exact mean.
+    pub fn mean(&self) -> f64 {
+        self.moments.mean()
+    }
+
+    /// Returns the exact population standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        self.moments.std_dev()
+    }
+
+    /// Returns the approximate quantile, or NaN if empty.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        self.sketch.get_quantile(fraction)
+    }
+
+    /// Returns approximate quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        self.sketch.get_quantiles(fractions)
+    }
+
+    /// Returns the approximate fraction of values at or below `value`.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.sketch.get_rank(value)
+    }
+
+    /// Serializes the moments followed by the sketch in its usual format.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.moments.serialize();
+        bytes.extend_from_slice(&self.sketch.serialize()?);
+        Ok(bytes)
+    }
+
+    /// Deserializes statistics written by [`serialize`](Self::serialize).
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MOMENTS_BYTES {
+            return Err(DataSketchesError::DeserializationError(format!(
+                "statistics take at least {} bytes, got {}",
+                MOMENTS_BYTES,
+                bytes.len()
+            )));
+        }
+        let (moments, sketch) = bytes.split_at(MOMENTS_BYTES);
+        let stats = FullStats {
+            sketch: KllDoubleSketch::deserialize(sketch)?,
+            moments: MomentsSketch::deserialize(moments)?,
+        };
+        if stats.sketch.get_n() != stats.moments.count() {
+            return Err(DataSketchesError::DeserializationError(format!(
+                "moments count {} values but the sketch {}",
+                stats.moments.count(),
+                stats.sketch.get_n()
+            )));
+        }
+        Ok(stats)
+    }
+}
+
+impl SketchLike for FullStats {
+    fn update(&mut self, value: f64) {
+        FullStats::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        FullStats::get_n(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        FullStats::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        FullStats::get_quantiles(self, fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        FullStats::get_rank(self, value)
+    }
+}
+
+impl Mergeable for FullStats {
+    fn empty_like(&self) -> Self {
+        FullStats {
+            sketch: self.sketch.empty_like(),
+            moments: MomentsSketch::new(),
+        }
+    }
+
+    fn merge_from(&mut self, other: &Self) -> Result<()> {
+        self.merge(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() <= 1e-9 * expected.abs().max(1.0)
+    }
+
+    #[test]
+    fn test_moments_match_two_pass() {
+        let values: Vec<f64> = (0..1000)
+            .map(|i| 1e6 + ((i * 7919) % 1000) as f64 / 10.0 + (i % 3) as f64)
+            .collect();
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let central = |p: i32| values.iter().map(|v| (v - mean).powi(p)).sum::<f64>() / n;
+        let variance = central(2);
+
+        let mut whole = MomentsSketch::new();
+        let (mut left, mut right) = (MomentsSketch::new(), MomentsSketch::new());
+        for (i, &value) in values.iter().enumerate() {
+            whole.update(value);
+            let part = if i < 300 { &mut left } else { &mut right };
+            part.update(value);
+        }
+        left.merge(&right);
+        for moments in [whole, left] {
+            assert_eq!(moments.count(), 1000);
+            assert!(close(moments.mean(), mean));
+            assert!(close(moments.variance(), variance));
+            assert!(close(moments.skewness(), central(3) / variance.powf(1.5)));
+            assert!(close(
+                moments.kurtosis(),
+                central(4) / (variance * variance) - 3.0
+            ));
+        }
+
+        let restored = MomentsSketch::deserialize(&whole.serialize()).unwrap();
+        assert_eq!(restored, whole);
+        assert!(MomentsSketch::new().mean().is_nan());
+        assert!(MomentsSketch::deserialize(&[0; 8]).is_err());
+    }
+
+    #[test]
+    fn test_full_stats() {
+        let mut a = FullStats::new();
+        let mut b = a.empty_like();
+        for i in 1..=100 {
+            a.update(i as f64);
+            b.update((i + 100) as f64);
+        }
+        a.update(f64::NAN);
+        a.merge(&b).unwrap();
+        assert_eq!(a.get_n(), 200);
+        assert_eq!(a.sketch().get_n(), 200);
+        assert!(close(a.mean(), 100.5));
+        assert!(close(a.moments().sum(), 20100.0));
+        assert_eq!(a.get_quantile(0.5), 100.0);
+
+        let bytes = a.serialize().unwrap();
+        let restored = FullStats::deserialize(&bytes).unwrap();
+        assert_eq!(restored.moments(), a.moments());
+        assert_eq!(restored.get_quantile(0.5), 100.0);
+
+        let mut tampered = bytes.clone();
+        tampered[0] ^= 1;
+        assert!(FullStats::deserialize(&tampered).is_err());
+        let mut mixed = FullStats::with_k(KParam::new(64).unwrap());
+        for i in 0..1000 {
+            mixed.update(i as f64);
+        }
+        assert!(a.merge(&mixed).is_err());
+        assert_eq!(a.get_n(), 200);
+    }
+}
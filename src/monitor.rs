@@ -0,0 +1,263 @@
+//! Threshold rules evaluated against successive sketch snapshots.
+
+use crate::error::{DataSketchesError, Result};
+use crate::traits::SketchLike;
+
+/// Direction in which a quantile breaches its threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// Breach when the quantile is strictly greater than the threshold.
+    Above,
+    /// Breach when the quantile is strictly less than the threshold.
+    Below,
+}
+
+/// A rule such as "p99 > 250 for 3 consecutive intervals".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    name: String,
+    fraction: f64,
+    comparison: Comparison,
+    threshold: f64,
+    consecutive: usize,
+}
+
+impl Rule {
+    /// Creates a rule that breaches when the quantile at `fraction` exceeds `threshold`.
+    pub fn above(name: impl Into<String>, fraction: f64, threshold: f64) -> Self {
+        Rule {
+            name: name.into(),
+            fraction,
+            comparison: Comparison::Above,
+            threshold,
+            consecutive: 1,
+        }
+    }
+
+    /// Creates a rule that breaches when the quantile at `fraction` drops below `threshold`.
+    pub fn below(name: impl Into<String>, fraction: f64, threshold: f64) -> Self {
+        Rule {
+            name: name.into(),
+            fraction,
+            comparison: Comparison::Below,
+            threshold,
+            consecutive: 1,
+        }
+    }
+
+    /// Requires the breach to hold for `intervals` consecutive evaluations.
+    pub fn for_intervals(mut self, intervals: usize) -> Self {
+        self.consecutive = intervals;
+        self
+    }
+
+    /// Returns the rule name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn breached(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+}
+
+/// Event emitted when a rule changes state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MonitorEvent {
+    /// The rule breached for the required number of consecutive intervals.
+    Triggered {
+        /// Name of the rule.
+        rule: String,
+        /// Quantile observed in the interval that triggered the rule.
+        value: f64,
+        /// Threshold of the rule.
+        threshold: f64,
+    },
+    /// A triggered rule no longer breaches.
+    Resolved {
+        /// Name of the rule.
+        rule: String,
+        /// Quantile observed in the interval that resolved the rule.
+        value: f64,
+    },
+}
+
+#[derive(Debug)]
+struct RuleState {
+    rule: Rule,
+    streak: usize,
+    firing: bool,
+}
+
+type Callback = Box<dyn FnMut(&MonitorEvent) + Send>;
+
+/// Evaluates quantile rules against successive sketch snapshots.
+///
+/// Each call to [`evaluate`](QuantileMonitor::evaluate) counts as one interval.
+/// A rule triggers once after breaching for its required number of
+/// consecutive intervals and resolves on the first interval that does not
+/// breach. Empty sketches never breach.
+#[derive(Default)]
+pub struct QuantileMonitor {
+    rules: Vec<RuleState>,
+    callbacks: Vec<Callback>,
+}
+
+impl std::fmt::Debug for QuantileMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuantileMonitor")
+            .field("rules", &self.rules)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl QuantileMonitor {
+    /// Creates a monitor with no rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule to the monitor.
+    pub fn add_rule(&mut self, rule: Rule) -> Result<&mut Self> {
+        if !rule.fraction.is_finite() || !(0.0..=1.0).contains(&rule.fraction) {
+            return Err(DataSketchesError::InvalidParameter(
+                "rule fraction must be between 0 and 1".to_string(),
+            ));
+        }
+        if rule.consecutive == 0 {
+            return Err(DataSketchesError::InvalidParameter(
+                "rule must require at least one interval".to_string(),
+            ));
+        }
+        self.rules.push(RuleState {
+            rule,
+            streak: 0,
+            firing: false,
+        });
+        Ok(self)
+    }
+
+    /// Registers a callback invoked for every emitted event.
+    pub fn on_event<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&MonitorEvent) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Returns true if the named rule is currently triggered.
+    pub fn is_firing(&self, rule: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|state| state.firing && state.rule.name == rule)
+    }
+
+    /// Evaluates all rules against one interval's snapshot.
+    ///
+    /// Returns the events emitted in this interval, after passing each to the
+    /// registered callbacks.
+    pub fn evaluate<S: SketchLike + ?Sized>(&mut self, sketch: &S) -> Vec<MonitorEvent> {
+        let fractions: Vec<f64> = self.rules.iter().map(|s| s.rule.fraction).collect();
+        let values = sketch.get_quantiles(&fractions);
+
+        let mut events = Vec::new();
+        for (i, state) in self.rules.iter_mut().enumerate() {
+            let value = values.get(i).copied().unwrap_or(f64::NAN);
+            if state.rule.breached(value) {
+                state.streak += 1;
+                if !state.firing && state.streak >= state.rule.consecutive {
+                    state.firing = true;
+                    events.push(MonitorEvent::Triggered {
+                        rule: state.rule.name.clone(),
+                        value,
+                        threshold: state.rule.threshold,
+                    });
+                }
+            } else {
+                state.streak = 0;
+                if state.firing {
+                    state.firing = false;
+                    events.push(MonitorEvent::Resolved {
+                        rule: state.rule.name.clone(),
+                        value,
+                    });
+                }
+            }
+        }
+
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeSketch;
+    use crate::KllDoubleSketch;
+    use std::sync::{Arc, Mutex};
+
+    fn interval(max: u32) -> KllDoubleSketch {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 1..=max {
+            sketch.update(i as f64);
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_consecutive_breaches() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+
+        let mut monitor = QuantileMonitor::new();
+        monitor
+            .add_rule(Rule::above("p99", 0.99, 250.0).for_intervals(3))
+            .unwrap();
+        monitor.on_event(move |event| sink.lock().unwrap().push(event.clone()));
+
+        assert!(monitor.evaluate(&interval(300)).is_empty());
+        assert!(monitor.evaluate(&interval(300)).is_empty());
+        assert!(matches!(
+            monitor.evaluate(&interval(300)).as_slice(),
+            [MonitorEvent::Triggered { .. }]
+        ));
+        assert!(monitor.is_firing("p99"));
+        assert!(monitor.evaluate(&interval(300)).is_empty());
+        assert!(matches!(
+            monitor.evaluate(&interval(100)).as_slice(),
+            [MonitorEvent::Resolved { .. }]
+        ));
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut monitor = QuantileMonitor::new();
+        assert!(monitor.add_rule(Rule::below("bad", 1.5, 1.0)).is_err());
+        assert!(monitor
+            .add_rule(Rule::below("zero", 0.5, 1.0).for_intervals(0))
+            .is_err());
+        assert!(monitor.evaluate(&KllDoubleSketch::new()).is_empty());
+    }
+
+    #[test]
+    fn test_scripted_sketch() {
+        let mut monitor = QuantileMonitor::new();
+        monitor.add_rule(Rule::below("p50", 0.5, 10.0)).unwrap();
+        let slow = FakeSketch::new().with_quantile(0.5, 5.0).with_n(100);
+        assert!(matches!(
+            monitor.evaluate(&slow).as_slice(),
+            [MonitorEvent::Triggered { value, .. }] if *value == 5.0
+        ));
+    }
+}
@@ -0,0 +1,43 @@
+//! Borrowed view of a sketch known to hold at least one value.
+
+/// A sketch that is statically known to be non-empty.
+///
+/// Obtained from `as_non_empty` on the sketch types. Since the emptiness
+/// check happened once up front and queries take a validated
+/// [`Fraction`](crate::Fraction), the query methods return plain values: no
+/// NaN for empty sketches or bad fractions, and no repeated checks.
+///
+/// ```
+/// use kll_rs::{Fraction, KllDoubleSketch};
+///
+/// let mut sketch = KllDoubleSketch::new();
+/// assert!(sketch.as_non_empty().is_none());
+/// sketch.update(4.0);
+/// let view = sketch.as_non_empty().unwrap();
+/// assert_eq!(view.quantile(Fraction::MEDIAN), 4.0);
+/// assert_eq!(view.min(), view.max());
+/// ```
+#[derive(Debug)]
+pub struct NonEmptySketch<'a, S> {
+    sketch: &'a S,
+}
+
+impl<'a, S> NonEmptySketch<'a, S> {
+    /// Wraps a sketch the caller has checked is not empty.
+    pub(crate) fn new_unchecked(sketch: &'a S) -> Self {
+        NonEmptySketch { sketch }
+    }
+
+    /// Returns the underlying sketch.
+    pub fn sketch(&self) -> &'a S {
+        self.sketch
+    }
+}
+
+impl<S> Clone for NonEmptySketch<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for NonEmptySketch<'_, S> {}
@@ -0,0 +1,73 @@
+//! Compaction callbacks shared by the sketch types.
+
+use std::any::Any;
+use std::fmt;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe, RefUnwindSafe, UnwindSafe};
+
+/// A user callback receiving `(level, items_compacted)`.
+///
+/// Sketches keep it boxed so the address handed to the wrapper stays valid
+/// until the callback is replaced or the sketch is dropped.
+///
+/// A panic in the callback must not unwind through the C++ frames that invoke
+/// it. The trampoline catches it and parks the payload here; the sketch
+/// re-raises it with `resume_panic` once the native call has returned.
+pub(crate) struct CompactionObserver {
+    callback: Box<dyn FnMut(u8, u32) + Send>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl CompactionObserver {
+    pub(crate) fn new<F>(callback: F) -> Box<Self>
+    where
+        F: FnMut(u8, u32) + Send + 'static,
+    {
+        Box::new(CompactionObserver {
+            callback: Box::new(callback),
+            panic: None,
+        })
+    }
+
+    /// Re-raises a panic caught during the last native call, if any.
+    pub(crate) fn resume_panic(&mut self) {
+        if let Some(payload) = self.panic.take() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    pub(crate) fn user_data(&mut self) -> *mut c_void {
+        self as *mut Self as *mut c_void
+    }
+}
+
+// The callback only runs from updates through `&mut` sketch access, so a
+// panic elsewhere cannot observe it half-way through a call. This keeps the
+// sketch types unwind safe as they were before observers existed.
+impl UnwindSafe for CompactionObserver {}
+impl RefUnwindSafe for CompactionObserver {}
+
+impl fmt::Debug for CompactionObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CompactionObserver")
+    }
+}
+
+/// Forwards wrapper notifications to the observer registered as `user_data`.
+///
+/// Never unwinds; see [`CompactionObserver`].
+pub(crate) unsafe extern "C" fn compaction_trampoline(
+    user_data: *mut c_void,
+    level: u8,
+    items_compacted: u32,
+) {
+    let observer = &mut *(user_data as *mut CompactionObserver);
+    if observer.panic.is_some() {
+        return;
+    }
+    let callback = &mut observer.callback;
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| callback(level, items_compacted)))
+    {
+        observer.panic = Some(payload);
+    }
+}
@@ -0,0 +1,93 @@
+//! Monotone piecewise-linear maps between distributions.
+
+/// A monotone piecewise-linear function, as built by `quantile_map`.
+///
+/// The map interpolates linearly between its knots and holds the first and
+/// last outputs beyond the ends. Where several source quantiles coincide,
+/// as at a point mass, the value maps to the middle of the target range they
+/// cover.
+///
+/// ```
+/// use kll_rs::KllDoubleSketch;
+///
+/// let mut slow = KllDoubleSketch::new();
+/// let mut fast = KllDoubleSketch::new();
+/// for i in 1..=100 {
+///     slow.update(i as f64 * 2.0);
+///     fast.update(i as f64);
+/// }
+/// let map = slow.quantile_map(&fast, 100);
+/// assert_eq!(map.apply(100.0), 50.0);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PiecewiseMap {
+    /// `(input, output)` pairs with strictly increasing inputs and
+    /// non-decreasing outputs.
+    knots: Vec<(f64, f64)>,
+}
+
+impl PiecewiseMap {
+    /// Builds a map through paired points of two non-decreasing sequences.
+    pub(crate) fn through(inputs: &[f64], outputs: &[f64]) -> Self {
+        let mut knots: Vec<(f64, f64)> = Vec::with_capacity(inputs.len());
+        let mut i = 0;
+        while i < inputs.len().min(outputs.len()) {
+            let x = inputs[i];
+            let run = inputs[i..].iter().take_while(|&&input| input == x).count();
+            let y = (outputs[i] + outputs[i + run - 1]) / 2.0;
+            knots.push((x, y));
+            i += run;
+        }
+        PiecewiseMap { knots }
+    }
+
+    /// Maps `value`, returning NaN for an empty map or a NaN value.
+    pub fn apply(&self, value: f64) -> f64 {
+        let (Some(&(first_x, first_y)), Some(&(last_x, last_y))) =
+            (self.knots.first(), self.knots.last())
+        else {
+            return f64::NAN;
+        };
+        if value.is_nan() {
+            return f64::NAN;
+        }
+        if value <= first_x {
+            return first_y;
+        }
+        if value >= last_x {
+            return last_y;
+        }
+        let upper = self.knots.partition_point(|&(x, _)| x <= value);
+        let ((x0, y0), (x1, y1)) = (self.knots[upper - 1], self.knots[upper]);
+        y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+    }
+
+    /// Returns the `(input, output)` knots, by increasing input.
+    pub fn knots(&self) -> &[(f64, f64)] {
+        &self.knots
+    }
+
+    /// Returns true if the map has no knots, as when a sketch was empty.
+    pub fn is_empty(&self) -> bool {
+        self.knots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolates_and_clamps() {
+        let map =
+            PiecewiseMap::through(&[0.0, 1.0, 1.0, 1.0, 3.0], &[10.0, 20.0, 30.0, 40.0, 50.0]);
+        assert_eq!(map.knots(), &[(0.0, 10.0), (1.0, 30.0), (3.0, 50.0)]);
+        assert_eq!(map.apply(-5.0), 10.0);
+        assert_eq!(map.apply(0.5), 20.0);
+        assert_eq!(map.apply(1.0), 30.0);
+        assert_eq!(map.apply(2.0), 40.0);
+        assert_eq!(map.apply(9.0), 50.0);
+        assert!(map.apply(f64::NAN).is_nan());
+        assert!(PiecewiseMap::default().apply(1.0).is_nan());
+    }
+}
@@ -0,0 +1,13 @@
+//! The most commonly used types, for glob import.
+//!
+//! ```
+//! use kll_rs::prelude::*;
+//!
+//! let mut sketch = KllDoubleSketch::new();
+//! QuantileSketch::update(&mut sketch, 1.0);
+//! ```
+
+pub use crate::{
+    AnySketch, DataSketchesError, FrozenSketch, HeatmapBuilder, HybridQuantiles, KllDoubleSketch,
+    KllFloatSketch, KllUnion, QuantileSketch,
+};
@@ -0,0 +1,47 @@
+//! Which ends of a value range a count includes.
+
+/// Which ends of `low..high` a range query counts.
+///
+/// ```
+/// use kll_rs::{KllDoubleSketch, RangeInclusivity};
+///
+/// let mut latencies = KllDoubleSketch::new();
+/// for ms in [80.0, 100.0, 250.0, 500.0, 900.0] {
+///     latencies.update(ms);
+/// }
+/// let count = |inclusive| latencies.estimate_count_in_range(100.0, 500.0, inclusive);
+/// assert_eq!(count(RangeInclusivity::Closed), 3);
+/// assert_eq!(count(RangeInclusivity::ClosedOpen), 2);
+/// assert_eq!(count(RangeInclusivity::Open), 1);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RangeInclusivity {
+    /// `[low, high]`: both ends count.
+    #[default]
+    Closed,
+    /// `[low, high)`: values equal to `high` do not count, like `low..high`.
+    ClosedOpen,
+    /// `(low, high]`: values equal to `low` do not count, like histogram
+    /// buckets with inclusive upper bounds.
+    OpenClosed,
+    /// `(low, high)`: neither end counts.
+    Open,
+}
+
+impl RangeInclusivity {
+    /// Returns true if values equal to the lower end count.
+    pub fn includes_low(self) -> bool {
+        matches!(
+            self,
+            RangeInclusivity::Closed | RangeInclusivity::ClosedOpen
+        )
+    }
+
+    /// Returns true if values equal to the upper end count.
+    pub fn includes_high(self) -> bool {
+        matches!(
+            self,
+            RangeInclusivity::Closed | RangeInclusivity::OpenClosed
+        )
+    }
+}
@@ -0,0 +1,432 @@
+//! Serialized sketches in Redis, for services that aggregate through a
+//! shared Redis instance.
+//!
+//! Each key holds one sketch in the DataSketches binary format, so sketches
+//! written here can be read by any DataSketches library. [`merge_into`]
+//! folds a local sketch into a key atomically, and [`MergedReader`] caches
+//! the merge of a group of keys.
+//!
+//! ```no_run
+//! use kll_rs::redis_store::{self, MergedReader};
+//! use kll_rs::KllDoubleSketch;
+//! use std::time::Duration;
+//!
+//! let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+//! let mut con = client.get_connection().unwrap();
+//!
+//! let mut local = KllDoubleSketch::new();
+//! local.update(12.5);
+//! redis_store::merge_into(&mut con, "latency:host-a", &local)?;
+//!
+//! let mut reader = MergedReader::<KllDoubleSketch>::new(
+//!     ["latency:host-a", "latency:host-b"],
+//!     Duration::from_secs(10),
+//! );
+//! if let Some(merged) = reader.get(&mut con)? {
+//!     println!("p99 {}", merged.get_quantile(0.99));
+//! }
+//! # Ok::<(), kll_rs::DataSketchesError>(())
+//! ```
+
+use crate::error::{DataSketchesError, Result};
+use crate::traits::QuantileSketch;
+use crate::{KllDoubleSketch, KllFloatSketch};
+use redis::{cmd, ConnectionLike, RedisError};
+use std::time::{Duration, Instant};
+
+/// A sketch type that can be kept in Redis.
+pub trait RedisSketch: QuantileSketch + Clone + sealed::Sealed {
+    #[doc(hidden)]
+    fn to_redis_bytes(&self) -> Result<Vec<u8>>;
+    #[doc(hidden)]
+    fn from_redis_bytes(bytes: &[u8]) -> Result<Self>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for crate::KllDoubleSketch {}
+    impl Sealed for crate::KllFloatSketch {}
+}
+
+impl RedisSketch for KllDoubleSketch {
+    fn to_redis_bytes(&self) -> Result<Vec<u8>> {
+        self.serialize()
+    }
+
+    fn from_redis_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::deserialize(bytes)
+    }
+}
+
+impl RedisSketch for KllFloatSketch {
+    fn to_redis_bytes(&self) -> Result<Vec<u8>> {
+        self.serialize()
+    }
+
+    fn from_redis_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::deserialize(bytes)
+    }
+}
+
+fn redis_error(err: RedisError) -> DataSketchesError {
+    DataSketchesError::Unknown(format!("redis: {}", err))
+}
+
+/// Stores `sketch` at `key`, replacing any value and expiry there.
+pub fn store<S: RedisSketch, C: ConnectionLike>(con: &mut C, key: &str, sketch: &S) -> Result<()> {
+    cmd("SET")
+        .arg(key)
+        .arg(sketch.to_redis_bytes()?)
+        .exec(con)
+        .map_err(redis_error)
+}
+
+/// Stores `sketch` at `key`, expiring after `ttl`, rounded up to a whole
+/// millisecond.
+pub fn store_with_ttl<S: RedisSketch, C: ConnectionLike>(
+    con: &mut C,
+    key: &str,
+    sketch: &S,
+    ttl: Duration,
+) -> Result<()> {
+    let millis = ((ttl.as_nanos() + 999_999) / 1_000_000).max(1) as u64;
+    cmd("SET")
+        .arg(key)
+        .arg(sketch.to_redis_bytes()?)
+        .arg("PX")
+        .arg(millis)
+        .exec(con)
+        .map_err(redis_error)
+}
+
+/// Reads the sketch at `key`, or `None` if the key does not exist.
+pub fn fetch<S: RedisSketch, C: ConnectionLike>(con: &mut C, key: &str) -> Result<Option<S>> {
+    let bytes: Option<Vec<u8>> = cmd("GET").arg(key).query(con).map_err(redis_error)?;
+    bytes.map(|bytes| S::from_redis_bytes(&bytes)).transpose()
+}
+
+/// Reads the sketches at `keys` with one `MGET` and merges them, or returns
+/// `None` if none of the keys exists.
+///
+/// Missing keys are skipped; a key holding anything but a sketch fails the
+/// whole read.
+pub fn fetch_merged<S: RedisSketch, C: ConnectionLike, K: AsRef<str>>(
+    con: &mut C,
+    keys: &[K],
+) -> Result<Option<S>> {
+    if keys.is_empty() {
+        return Ok(None);
+    }
+    let mut mget = cmd("MGET");
+    for key in keys {
+        mget.arg(key.as_ref());
+    }
+    let values: Vec<Option<Vec<u8>>> = mget.query(con).map_err(redis_error)?;
+    let mut merged: Option<S> = None;
+    for bytes in values.into_iter().flatten() {
+        let sketch = S::from_redis_bytes(&bytes)?;
+        match &mut merged {
+            Some(merged) => merged.merge(&sketch)?,
+            None => merged = Some(sketch),
+        }
+    }
+    Ok(merged)
+}
+
+/// Merges `sketch` into the sketch at `key`, creating the key if it does
+/// not exist and keeping its expiry if it does.
+///
+/// The read and write run as a `WATCH` transaction, retried until no other
+/// client changed the key in between, so concurrent merges are never lost.
+/// Needs Redis 6.0 or later, for `SET ... KEEPTTL`.
+pub fn merge_into<S: RedisSketch, C: ConnectionLike>(
+    con: &mut C,
+    key: &str,
+    sketch: &S,
+) -> Result<()> {
+    let mut failure = None;
+    redis::transaction(con, &[key], |con, pipe| {
+        let current: Option<Vec<u8>> = cmd("GET").arg(key).query(con)?;
+        let merged = match current {
+            Some(bytes) => S::from_redis_bytes(&bytes).and_then(|mut stored| {
+                stored.merge(sketch)?;
+                Ok(stored)
+            }),
+            None => Ok(sketch.clone()),
+        };
+        match merged.and_then(|merged| merged.to_redis_bytes()) {
+            Ok(bytes) => pipe
+                .cmd("SET")
+                .arg(key)
+                .arg(bytes)
+                .arg("KEEPTTL")
+                .ignore()
+                .query(con),
+            Err(err) => {
+                // Give up without writing; the error is reported below.
+                failure = Some(err);
+                Ok(Some(()))
+            }
+        }
+    })
+    .map_err(redis_error)?;
+    failure.map_or(Ok(()), Err)
+}
+
+/// The merge of a group of keys, read from Redis at most once per `max_age`.
+///
+/// Suits dashboards and APIs that query the same aggregate often: between
+/// refreshes, reads are answered from the local copy without a round trip.
+#[derive(Debug, Clone)]
+pub struct MergedReader<S> {
+    keys: Vec<String>,
+    max_age: Duration,
+    cached: Option<(Instant, Option<S>)>,
+}
+
+impl<S: RedisSketch> MergedReader<S> {
+    /// Creates a reader merging `keys`, refreshed when its copy is older
+    /// than `max_age`.
+    pub fn new<I, K>(keys: I, max_age: Duration) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        MergedReader {
+            keys: keys.into_iter().map(Into::into).collect(),
+            max_age,
+            cached: None,
+        }
+    }
+
+    /// Returns the keys merged.
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Returns the merged sketch, fetching it first if the local copy is
+    /// missing or stale, or `None` if none of the keys exists.
+    ///
+    /// A failed refresh keeps the previous copy for the next call.
+    pub fn get<C: ConnectionLike>(&mut self, con: &mut C) -> Result<Option<&S>> {
+        let fresh = matches!(&self.cached, Some((at, _)) if at.elapsed() < self.max_age);
+        if !fresh {
+            let merged = fetch_merged(con, &self.keys)?;
+            self.cached = Some((Instant::now(), merged));
+        }
+        Ok(self.cached.as_ref().and_then(|(_, merged)| merged.as_ref()))
+    }
+
+    /// Drops the local copy, so the next [`get`](Self::get) reads Redis.
+    pub fn invalidate(&mut self) {
+        self.cached = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::{RedisResult, Value};
+    use std::collections::HashMap;
+
+    /// Just enough of a Redis server for the commands above.
+    #[derive(Default)]
+    struct FakeRedis {
+        data: HashMap<Vec<u8>, Vec<u8>>,
+        commands: Vec<String>,
+        queued: Option<Vec<Value>>,
+    }
+
+    impl FakeRedis {
+        fn run(&mut self, args: Vec<Vec<u8>>) -> Value {
+            let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+            self.commands.push(
+                args.iter()
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+            let result = match name.as_str() {
+                "MULTI" => {
+                    self.queued = Some(Vec::new());
+                    return Value::Okay;
+                }
+                "EXEC" => return Value::Array(self.queued.take().unwrap_or_default()),
+                "WATCH" | "UNWATCH" => return Value::Okay,
+                "SET" => {
+                    self.data.insert(args[1].clone(), args[2].clone());
+                    Value::Okay
+                }
+                "GET" => self
+                    .data
+                    .get(&args[1])
+                    .map_or(Value::Nil, |v| Value::BulkString(v.clone())),
+                "MGET" => Value::Array(
+                    args[1..]
+                        .iter()
+                        .map(|key| {
+                            self.data
+                                .get(key)
+                                .map_or(Value::Nil, |v| Value::BulkString(v.clone()))
+                        })
+                        .collect(),
+                ),
+                _ => panic!("unexpected command {}", name),
+            };
+            match &mut self.queued {
+                Some(queued) => {
+                    queued.push(result);
+                    Value::SimpleString("QUEUED".to_string())
+                }
+                None => result,
+            }
+        }
+    }
+
+    /// Splits packed RESP commands into their arguments.
+    fn parse(mut packed: &[u8]) -> Vec<Vec<Vec<u8>>> {
+        fn line<'a>(packed: &mut &'a [u8]) -> &'a [u8] {
+            let end = packed.windows(2).position(|w| w == b"\r\n").unwrap();
+            let line = &packed[1..end];
+            *packed = &packed[end + 2..];
+            line
+        }
+        let number = |bytes: &[u8]| {
+            std::str::from_utf8(bytes)
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+        let mut commands = Vec::new();
+        while !packed.is_empty() {
+            let argc = number(line(&mut packed));
+            let args = (0..argc)
+                .map(|_| {
+                    let len = number(line(&mut packed));
+                    let arg = packed[..len].to_vec();
+                    packed = &packed[len + 2..];
+                    arg
+                })
+                .collect();
+            commands.push(args);
+        }
+        commands
+    }
+
+    impl ConnectionLike for FakeRedis {
+        fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+            let mut commands = parse(cmd);
+            Ok(self.run(commands.remove(0)))
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            cmd: &[u8],
+            offset: usize,
+            count: usize,
+        ) -> RedisResult<Vec<Value>> {
+            let replies: Vec<Value> = parse(cmd).into_iter().map(|c| self.run(c)).collect();
+            Ok(replies[offset..offset + count].to_vec())
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    fn sketch(values: std::ops::Range<u32>) -> KllDoubleSketch {
+        let mut sketch = KllDoubleSketch::new();
+        for value in values {
+            sketch.update(value as f64);
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_store_fetch_and_merge() {
+        let mut con = FakeRedis::default();
+        assert!(fetch::<KllDoubleSketch, _>(&mut con, "a")
+            .unwrap()
+            .is_none());
+        store(&mut con, "a", &sketch(0..100)).unwrap();
+        store_with_ttl(
+            &mut con,
+            "b",
+            &sketch(100..200),
+            Duration::from_micros(1500),
+        )
+        .unwrap();
+        assert_eq!(
+            con.commands.last().unwrap().rsplit_once(' ').unwrap().1,
+            "2"
+        );
+        assert_eq!(
+            fetch::<KllDoubleSketch, _>(&mut con, "a")
+                .unwrap()
+                .unwrap()
+                .get_n(),
+            100
+        );
+
+        let merged: KllDoubleSketch = fetch_merged(&mut con, &["a", "missing", "b"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(merged.get_n(), 200);
+        assert!(fetch_merged::<KllDoubleSketch, _, &str>(&mut con, &[])
+            .unwrap()
+            .is_none());
+
+        merge_into(&mut con, "a", &sketch(200..300)).unwrap();
+        merge_into(&mut con, "c", &sketch(0..10)).unwrap();
+        assert!(con.commands.iter().any(|c| c.starts_with("WATCH a")));
+        assert_eq!(
+            fetch::<KllDoubleSketch, _>(&mut con, "a")
+                .unwrap()
+                .unwrap()
+                .get_n(),
+            200
+        );
+        assert_eq!(
+            fetch::<KllDoubleSketch, _>(&mut con, "c")
+                .unwrap()
+                .unwrap()
+                .get_n(),
+            10
+        );
+
+        con.data.insert(b"bad".to_vec(), b"not a sketch".to_vec());
+        assert!(merge_into(&mut con, "bad", &sketch(0..10)).is_err());
+        assert_eq!(con.data[b"bad".as_slice()], b"not a sketch");
+    }
+
+    #[test]
+    fn test_merged_reader_caches() {
+        let mut con = FakeRedis::default();
+        store(&mut con, "a", &sketch(0..100)).unwrap();
+        let mut reader = MergedReader::<KllDoubleSketch>::new(["a", "b"], Duration::from_secs(60));
+        assert_eq!(reader.get(&mut con).unwrap().unwrap().get_n(), 100);
+
+        store(&mut con, "b", &sketch(0..50)).unwrap();
+        let mgets = |con: &FakeRedis| {
+            con.commands
+                .iter()
+                .filter(|c| c.starts_with("MGET"))
+                .count()
+        };
+        assert_eq!(reader.get(&mut con).unwrap().unwrap().get_n(), 100);
+        assert_eq!(mgets(&con), 1);
+
+        reader.invalidate();
+        assert_eq!(reader.get(&mut con).unwrap().unwrap().get_n(), 150);
+        assert_eq!(mgets(&con), 2);
+        assert_eq!(reader.keys(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,480 @@
+//! Named, tagged sketches shared across threads.
+
+use crate::error::{DataSketchesError, Result};
+use crate::events::event;
+use crate::format::{max_serialized_size_bytes, BUDGET_HORIZON_N};
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard};
+
+/// Identifies a series: a metric name and its tags, sorted by tag key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SeriesKey {
+    name: String,
+    tags: Vec<(String, String)>,
+}
+
+impl SeriesKey {
+    /// Creates a key. Tags are sorted, so their order does not matter.
+    pub fn new(name: &str, tags: &[(&str, &str)]) -> Self {
+        let mut tags: Vec<(String, String)> = tags
+            .iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        tags.sort();
+        SeriesKey {
+            name: name.to_string(),
+            tags,
+        }
+    }
+
+    /// Returns the metric name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the tags, sorted by key.
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    /// Returns the namespace of the series: its name up to the first `.`,
+    /// or the whole name if it has none.
+    pub fn namespace(&self) -> &str {
+        self.name.split('.').next().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            write!(f, "{{{}}}", tags.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// The quota a new series would have exceeded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QuotaKind {
+    /// The maximum number of series.
+    Series,
+    /// The maximum estimated memory of all sketches.
+    Memory,
+    /// The maximum number of series in the named namespace.
+    Namespace(String),
+}
+
+impl fmt::Display for QuotaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaKind::Series => f.write_str("series"),
+            QuotaKind::Memory => f.write_str("memory"),
+            QuotaKind::Namespace(namespace) => write!(f, "namespace {}", namespace),
+        }
+    }
+}
+
+/// Limits on what a [`SketchRegistry`] admits.
+///
+/// Quotas apply when a value would create a series; values for existing
+/// series are always recorded. Memory is estimated as the largest size a
+/// sketch of the registry's k can reach, so the limit holds however long the
+/// series are fed.
+///
+/// ```
+/// use kll_rs::{DataSketchesError, KParam, QuotaKind, RegistryQuota, SketchRegistry};
+///
+/// let registry = SketchRegistry::new(KParam::default())
+///     .with_quota(RegistryQuota::new().max_series(1000).namespace_max_series("debug", 10));
+/// for user in 0..20 {
+///     let user = user.to_string();
+///     let _ = registry.try_record("debug.latency", &[("user", &user)], 1.0);
+/// }
+/// assert_eq!(registry.len(), 10);
+/// assert_eq!(registry.quota_stats().namespace_rejections, 10);
+/// assert!(matches!(
+///     registry.try_record("debug.latency", &[("user", "99")], 1.0),
+///     Err(DataSketchesError::QuotaExceeded { quota: QuotaKind::Namespace(_), limit: 10 })
+/// ));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegistryQuota {
+    max_series: Option<usize>,
+    max_memory_bytes: Option<usize>,
+    namespaces: BTreeMap<String, usize>,
+}
+
+impl RegistryQuota {
+    /// Creates a quota without limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the number of series.
+    pub fn max_series(mut self, series: usize) -> Self {
+        self.max_series = Some(series);
+        self
+    }
+
+    /// Limits the estimated memory of all sketches together.
+    pub fn max_memory_bytes(mut self, bytes: usize) -> Self {
+        self.max_memory_bytes = Some(bytes);
+        self
+    }
+
+    /// Limits the number of series in one namespace, as returned by
+    /// [`SeriesKey::namespace`].
+    pub fn namespace_max_series(mut self, namespace: &str, series: usize) -> Self {
+        self.namespaces.insert(namespace.to_string(), series);
+        self
+    }
+}
+
+/// Counts of values a [`SketchRegistry`] turned away, by the quota they
+/// would have exceeded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaStats {
+    /// Values refused by the series limit.
+    pub series_rejections: u64,
+    /// Values refused by the memory limit.
+    pub memory_rejections: u64,
+    /// Values refused by a namespace limit.
+    pub namespace_rejections: u64,
+}
+
+impl QuotaStats {
+    /// Returns the total number of values refused.
+    pub fn total(&self) -> u64 {
+        self.series_rejections + self.memory_rejections + self.namespace_rejections
+    }
+}
+
+#[derive(Debug, Default)]
+struct Series {
+    sketches: BTreeMap<SeriesKey, KllDoubleSketch>,
+    /// Number of series per namespace that has a quota.
+    namespace_counts: BTreeMap<String, usize>,
+    stats: QuotaStats,
+}
+
+/// A thread-safe map from series to sketches, which exporters read from.
+///
+/// ```
+/// use kll_rs::SketchRegistry;
+///
+/// let registry = SketchRegistry::default();
+/// registry.record("http.latency", &[("route", "/users")], 12.5);
+/// registry.record("http.latency", &[("route", "/users")], 40.0);
+///
+/// let series = registry.snapshot();
+/// assert_eq!(series[0].1.get_n(), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct SketchRegistry {
+    k: KParam,
+    quota: RegistryQuota,
+    series: Mutex<Series>,
+}
+
+impl SketchRegistry {
+    /// Creates an empty registry whose sketches have the given k.
+    pub fn new(k: KParam) -> Self {
+        SketchRegistry {
+            k,
+            quota: RegistryQuota::new(),
+            series: Mutex::new(Series::default()),
+        }
+    }
+
+    /// Applies a quota to the series the registry admits.
+    pub fn with_quota(mut self, quota: RegistryQuota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Returns the k of the registry's sketches.
+    pub fn k(&self) -> KParam {
+        self.k
+    }
+
+    /// Returns the quota the registry enforces.
+    pub fn quota(&self) -> &RegistryQuota {
+        &self.quota
+    }
+
+    /// Records a value for a series, creating its sketch on first use.
+    ///
+    /// A value that would create a series beyond the quota is dropped and
+    /// counted in [`quota_stats`](Self::quota_stats).
+    pub fn record(&self, name: &str, tags: &[(&str, &str)], value: f64) {
+        let _ = self.try_record(name, tags, value);
+    }
+
+    /// Records a value for a series identified by a prebuilt key, dropping
+    /// it as [`record`](Self::record) does.
+    pub fn record_key(&self, key: SeriesKey, value: f64) {
+        let _ = self.try_record_key(key, value);
+    }
+
+    /// Records a value for a series, failing with
+    /// [`DataSketchesError::QuotaExceeded`] if creating the series would
+    /// exceed the quota.
+    pub fn try_record(&self, name: &str, tags: &[(&str, &str)], value: f64) -> Result<()> {
+        self.try_record_key(SeriesKey::new(name, tags), value)
+    }
+
+    /// Records a value for a series identified by a prebuilt key, failing
+    /// as [`try_record`](Self::try_record) does.
+    pub fn try_record_key(&self, key: SeriesKey, value: f64) -> Result<()> {
+        let mut series = self.lock();
+        if let Some(sketch) = series.sketches.get_mut(&key) {
+            sketch.update(value);
+            return Ok(());
+        }
+        if let Err((quota, limit)) = self.admit(&series, &key) {
+            let stats = &mut series.stats;
+            match quota {
+                QuotaKind::Series => stats.series_rejections += 1,
+                QuotaKind::Memory => stats.memory_rejections += 1,
+                QuotaKind::Namespace(_) => stats.namespace_rejections += 1,
+            }
+            event!(
+                debug,
+                "registry quota rejected a series",
+                live = series.sketches.len(),
+                limit = limit,
+                rejected = series.stats.total(),
+            );
+            return Err(DataSketchesError::QuotaExceeded { quota, limit });
+        }
+        if self.quota.namespaces.contains_key(key.namespace()) {
+            *series
+                .namespace_counts
+                .entry(key.namespace().to_string())
+                .or_default() += 1;
+        }
+        let mut sketch = KllDoubleSketch::with_k(self.k);
+        sketch.update(value);
+        series.sketches.insert(key, sketch);
+        Ok(())
+    }
+
+    /// Returns the quota and limit that a new series `key` would exceed.
+    fn admit(
+        &self,
+        series: &Series,
+        key: &SeriesKey,
+    ) -> std::result::Result<(), (QuotaKind, usize)> {
+        let len = series.sketches.len();
+        if let Some(max) = self.quota.max_series {
+            if len >= max {
+                return Err((QuotaKind::Series, max));
+            }
+        }
+        if let Some(max) = self.quota.max_memory_bytes {
+            if (len + 1) * self.bytes_per_series() > max {
+                return Err((QuotaKind::Memory, max));
+            }
+        }
+        let namespace = key.namespace();
+        if let Some(&max) = self.quota.namespaces.get(namespace) {
+            if series.namespace_counts.get(namespace).copied().unwrap_or(0) >= max {
+                return Err((QuotaKind::Namespace(namespace.to_string()), max));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the estimated memory of every sketch, the measure the memory
+    /// quota limits.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        self.len() * self.bytes_per_series()
+    }
+
+    fn bytes_per_series(&self) -> usize {
+        max_serialized_size_bytes(self.k.get(), BUDGET_HORIZON_N, std::mem::size_of::<f64>())
+    }
+
+    /// Returns how many values the quota has turned away.
+    pub fn quota_stats(&self) -> QuotaStats {
+        self.lock().stats
+    }
+
+    /// Returns a copy of every series' sketch, ordered by key.
+    pub fn snapshot(&self) -> Vec<(SeriesKey, KllDoubleSketch)> {
+        self.lock()
+            .sketches
+            .iter()
+            .map(|(key, sketch)| (key.clone(), sketch.clone()))
+            .collect()
+    }
+
+    /// Removes and returns every series, leaving the registry empty, so each
+    /// export covers only the values recorded since the previous one.
+    pub fn take(&self) -> Vec<(SeriesKey, KllDoubleSketch)> {
+        let mut series = self.lock();
+        series.namespace_counts.clear();
+        let taken = std::mem::take(&mut series.sketches);
+        event!(debug, "drained registry", series = taken.len());
+        taken.into_iter().collect()
+    }
+
+    /// Formats every series as InfluxDB line protocol, one point per line.
+    ///
+    /// See [`write_line_protocol`](Self::write_line_protocol).
+    pub fn to_line_protocol(&self, fractions: &[f64], timestamp: Option<i64>) -> String {
+        let mut out = Vec::new();
+        self.write_line_protocol(&mut out, fractions, timestamp)
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(out).expect("line protocol is built from UTF-8 strings")
+    }
+
+    /// Writes every series as InfluxDB line protocol, with the series name
+    /// as the measurement and its tags as tags, all at `timestamp` in
+    /// nanoseconds since the Unix epoch.
+    ///
+    /// The series are copied first, so recording is not blocked on `out`.
+    pub fn write_line_protocol<W: Write + ?Sized>(
+        &self,
+        out: &mut W,
+        fractions: &[f64],
+        timestamp: Option<i64>,
+    ) -> io::Result<()> {
+        for (key, sketch) in self.snapshot() {
+            let tags: Vec<(&str, &str)> = key
+                .tags()
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            sketch.write_line_protocol(out, key.name(), &tags, fractions, timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// Removes one series, returning its sketch.
+    pub fn remove(&self, key: &SeriesKey) -> Option<KllDoubleSketch> {
+        let mut series = self.lock();
+        let sketch = series.sketches.remove(key)?;
+        if let Some(count) = series.namespace_counts.get_mut(key.namespace()) {
+            *count -= 1;
+        }
+        event!(
+            debug,
+            "removed registry series",
+            live = series.sketches.len()
+        );
+        Some(sketch)
+    }
+
+    /// Returns the number of series.
+    pub fn len(&self) -> usize {
+        self.lock().sketches.len()
+    }
+
+    /// Returns true if no series has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.lock().sketches.is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Series> {
+        // A panic while holding the lock cannot leave a sketch half-updated.
+        self.series.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_registry_series() {
+        let registry = Arc::new(SketchRegistry::new(KParam::new(64).unwrap()));
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let registry = Arc::clone(&registry);
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        registry.record("latency", &[("host", "a"), ("dc", "eu")], i as f64);
+                        registry.record("latency", &[("dc", "eu"), ("host", "b")], t as f64);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let series = registry.snapshot();
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].0.to_string(), "latency{dc=eu,host=a}");
+        assert_eq!(series[0].1.get_n(), 4000);
+        assert_eq!(series[0].1.get_k(), 64);
+
+        let lines = registry.to_line_protocol(&[0.5], Some(7));
+        assert!(lines.starts_with("latency,dc=eu,host=a count=4000i,min=0,max=999,p50="));
+        assert_eq!(lines.lines().count(), 2);
+
+        assert!(registry
+            .remove(&SeriesKey::new("latency", &[("host", "b"), ("dc", "eu")]))
+            .is_some());
+        assert_eq!(registry.take().len(), 1);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_registry_quotas() {
+        let registry = SketchRegistry::new(KParam::new(64).unwrap()).with_quota(
+            RegistryQuota::new()
+                .max_series(3)
+                .namespace_max_series("debug", 1),
+        );
+        registry.try_record("debug.a", &[], 1.0).unwrap();
+        registry.try_record("debug.a", &[], 2.0).unwrap();
+        match registry.try_record("debug.b", &[], 1.0) {
+            Err(DataSketchesError::QuotaExceeded { quota, limit }) => {
+                assert_eq!(
+                    (quota, limit),
+                    (QuotaKind::Namespace("debug".to_string()), 1)
+                )
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        registry.record("http.a", &[], 1.0);
+        registry.record("http.b", &[], 1.0);
+        registry.record("http.c", &[], 1.0);
+        assert_eq!(registry.len(), 3);
+        assert_eq!(
+            registry.quota_stats(),
+            QuotaStats {
+                series_rejections: 1,
+                memory_rejections: 0,
+                namespace_rejections: 1,
+            }
+        );
+
+        registry.remove(&SeriesKey::new("debug.a", &[]));
+        registry.try_record("debug.b", &[], 1.0).unwrap();
+        assert_eq!(registry.snapshot()[0].0.namespace(), "debug");
+
+        let per_series = registry.estimated_memory_bytes() / registry.len();
+        let small = SketchRegistry::new(KParam::new(64).unwrap())
+            .with_quota(RegistryQuota::new().max_memory_bytes(per_series * 2));
+        for name in ["a", "b", "c"] {
+            small.record(name, &[], 1.0);
+        }
+        assert_eq!(small.len(), 2);
+        assert_eq!(small.quota_stats().memory_rejections, 1);
+        assert!(small.estimated_memory_bytes() <= per_series * 2);
+    }
+}
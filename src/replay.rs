@@ -0,0 +1,317 @@
+//! Rebuilding rollup stores from a recorded log of updates.
+
+use crate::error::{DataSketchesError, Result};
+use crate::events::event;
+use crate::rollup_store::RollupStore;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::io::BufRead;
+use std::sync::mpsc;
+use std::time::Instant;
+
+/// Records handed to a worker thread at a time.
+const BATCH: usize = 4096;
+
+/// One logged update: a value observed for a key at a point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateRecord {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The series the value belongs to.
+    pub key: String,
+    /// The observed value.
+    pub value: f64,
+}
+
+impl UpdateRecord {
+    /// Creates a record.
+    pub fn new<K: Into<String>>(timestamp: u64, key: K, value: f64) -> Self {
+        UpdateRecord {
+            timestamp,
+            key: key.into(),
+            value,
+        }
+    }
+}
+
+/// Reads a text update log, one `timestamp key value` record per line.
+///
+/// Fields are separated by whitespace, so keys cannot contain any. Blank
+/// lines and lines starting with `#` are skipped.
+pub fn read_log<R: BufRead>(reader: R) -> impl Iterator<Item = Result<UpdateRecord>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| match line {
+            Ok(line) => parse_line(&line)
+                .map_err(|msg| {
+                    DataSketchesError::DeserializationError(format!(
+                        "update log line {}: {}",
+                        index + 1,
+                        msg
+                    ))
+                })
+                .transpose(),
+            Err(err) => Some(Err(DataSketchesError::Unknown(format!(
+                "update log line {}: {}",
+                index + 1,
+                err
+            )))),
+        })
+}
+
+fn parse_line(line: &str) -> std::result::Result<Option<UpdateRecord>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [timestamp, key, value] = fields[..] else {
+        return Err(format!("expected 3 fields, found {}", fields.len()));
+    };
+    let timestamp = timestamp
+        .parse()
+        .map_err(|_| format!("invalid timestamp {:?}", timestamp))?;
+    let value = value
+        .parse()
+        .map_err(|_| format!("invalid value {:?}", value))?;
+    Ok(Some(UpdateRecord::new(timestamp, key, value)))
+}
+
+/// Rebuilds one [`RollupStore`] per key from logged updates.
+///
+/// Each key gets a clone of the template store, so retention settings carry
+/// over, and sees its records in log order, exactly as live ingestion would
+/// have applied them. With more than one thread, keys are spread over worker
+/// threads by hash; the records of a key always go to the same worker, so
+/// the windows are the same as with one thread.
+///
+/// ```
+/// use kll_rs::{read_log, Replay, RollupStore};
+///
+/// let log = "0 api 12.0\n60 api 15.5\n60 db 3.0\n";
+/// let stores = Replay::new(RollupStore::default())
+///     .threads(2)
+///     .run(read_log(log.as_bytes()))?;
+/// assert_eq!(stores["api"].query(0, 120)?.sketch.get_n(), 2);
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Replay {
+    template: RollupStore,
+    threads: usize,
+    progress_every: u64,
+}
+
+impl Replay {
+    /// Creates a single-threaded replay that starts every key from `template`.
+    pub fn new(template: RollupStore) -> Self {
+        Replay {
+            template,
+            threads: 1,
+            progress_every: 100_000,
+        }
+    }
+
+    /// Sets the number of worker threads. Zero is treated as one.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets how many records are read between progress reports.
+    pub fn progress_every(mut self, records: u64) -> Self {
+        self.progress_every = records.max(1);
+        self
+    }
+
+    /// Replays `records`, stopping at the first error.
+    pub fn run<I>(&self, records: I) -> Result<BTreeMap<String, RollupStore>>
+    where
+        I: IntoIterator<Item = Result<UpdateRecord>>,
+    {
+        self.run_with_progress(records, |_| {})
+    }
+
+    /// Replays `records`, calling `progress` with the number of records read
+    /// so far every [`progress_every`](Self::progress_every) records and once
+    /// at the end.
+    pub fn run_with_progress<I, F>(
+        &self,
+        records: I,
+        mut progress: F,
+    ) -> Result<BTreeMap<String, RollupStore>>
+    where
+        I: IntoIterator<Item = Result<UpdateRecord>>,
+        F: FnMut(u64),
+    {
+        let start = Instant::now();
+        if self.threads == 1 {
+            let mut stores = BTreeMap::new();
+            let mut count = 0;
+            for record in records {
+                self.apply(&mut stores, record?);
+                count += 1;
+                if count % self.progress_every == 0 {
+                    progress(count);
+                }
+            }
+            progress(count);
+            self.replayed(count, &stores, start);
+            return Ok(stores);
+        }
+
+        std::thread::scope(|scope| {
+            let (senders, workers): (Vec<_>, Vec<_>) = (0..self.threads)
+                .map(|_| {
+                    let (sender, receiver) = mpsc::sync_channel::<Vec<UpdateRecord>>(4);
+                    let worker = scope.spawn(move || {
+                        let mut stores = BTreeMap::new();
+                        for batch in receiver {
+                            for record in batch {
+                                self.apply(&mut stores, record);
+                            }
+                        }
+                        stores
+                    });
+                    (sender, worker)
+                })
+                .unzip();
+
+            let mut batches = vec![Vec::with_capacity(BATCH); self.threads];
+            let mut count = 0;
+            let mut failure = None;
+            for record in records {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(err) => {
+                        failure = Some(err);
+                        break;
+                    }
+                };
+                let shard = self.shard(&record.key);
+                batches[shard].push(record);
+                if batches[shard].len() == BATCH {
+                    // A closed channel means the worker panicked; join reports it.
+                    let _ = senders[shard].send(std::mem::take(&mut batches[shard]));
+                }
+                count += 1;
+                if count % self.progress_every == 0 {
+                    progress(count);
+                }
+            }
+            for (sender, batch) in senders.into_iter().zip(batches) {
+                let _ = sender.send(batch);
+            }
+
+            let mut stores = BTreeMap::new();
+            for worker in workers {
+                stores.extend(worker.join().expect("replay worker panicked"));
+            }
+            match failure {
+                Some(err) => Err(err),
+                None => {
+                    progress(count);
+                    self.replayed(count, &stores, start);
+                    Ok(stores)
+                }
+            }
+        })
+    }
+
+    fn replayed(&self, records: u64, stores: &BTreeMap<String, RollupStore>, start: Instant) {
+        event!(
+            debug,
+            "replayed update log",
+            records = records,
+            keys = stores.len(),
+            threads = self.threads,
+            micros = start.elapsed().as_micros() as u64,
+        );
+    }
+
+    fn apply(&self, stores: &mut BTreeMap<String, RollupStore>, record: UpdateRecord) {
+        stores
+            .entry(record.key)
+            .or_insert_with(|| self.template.clone())
+            .record(record.timestamp, record.value);
+    }
+
+    fn shard(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.threads as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rollup_store::Resolution;
+
+    fn records() -> Vec<UpdateRecord> {
+        (0..20_000u64)
+            .map(|i| UpdateRecord::new(i * 7, format!("key{}", i % 5), (i % 997) as f64))
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_matches_live_ingestion() {
+        let mut template = RollupStore::default();
+        template
+            .set_retention(Resolution::Minute, Some(3600))
+            .unwrap();
+
+        let mut live = BTreeMap::new();
+        for record in records() {
+            live.entry(record.key.clone())
+                .or_insert_with(|| template.clone())
+                .record(record.timestamp, record.value);
+        }
+
+        for threads in [1, 3] {
+            let mut reports = vec![];
+            let replayed = Replay::new(template.clone())
+                .threads(threads)
+                .progress_every(5000)
+                .run_with_progress(records().into_iter().map(Ok), |n| reports.push(n))
+                .unwrap();
+            assert_eq!(reports, vec![5000, 10_000, 15_000, 20_000, 20_000]);
+            assert_eq!(replayed.len(), 5);
+            for (key, store) in &replayed {
+                let expected = &live[key];
+                for resolution in Resolution::ALL {
+                    let counts = |s: &RollupStore| -> Vec<(u64, u64)> {
+                        s.buckets(resolution)
+                            .iter()
+                            .map(|(&start, sketch)| (start, sketch.get_n()))
+                            .collect()
+                    };
+                    assert_eq!(counts(store), counts(expected), "{} {:?}", key, resolution);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_log() {
+        let log = "# backfill\n10 api 1.5\n\n20\tdb -2\n";
+        let records: Vec<_> = read_log(log.as_bytes()).collect::<Result<_>>().unwrap();
+        assert_eq!(
+            records,
+            vec![
+                UpdateRecord::new(10, "api", 1.5),
+                UpdateRecord::new(20, "db", -2.0)
+            ]
+        );
+
+        let bad = "10 api 1.5\n20 db\n30 api 2\n";
+        let err = Replay::new(RollupStore::default())
+            .threads(2)
+            .run(read_log(bad.as_bytes()))
+            .unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(read_log("x api 1".as_bytes()).next().unwrap().is_err());
+    }
+}
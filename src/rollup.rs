@@ -0,0 +1,162 @@
+//! Hierarchical group-by aggregation.
+
+use crate::error::{DataSketchesError, Result};
+use crate::KllDoubleSketch;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    /// Values recorded directly at this node.
+    own: Option<KllDoubleSketch>,
+    /// Union of `own` and every descendant, dropped whenever one changes.
+    merged: Option<KllDoubleSketch>,
+}
+
+impl Node {
+    /// Returns the node at `path`, creating it and invalidating every node on the way.
+    fn descend<S: AsRef<str>>(&mut self, path: &[S]) -> &mut Node {
+        let mut node = self;
+        node.merged = None;
+        for label in path {
+            node = node.children.entry(label.as_ref().to_string()).or_default();
+            node.merged = None;
+        }
+        node
+    }
+
+    fn find_mut<S: AsRef<str>>(&mut self, path: &[S]) -> Result<&mut Node> {
+        let mut node = self;
+        for label in path {
+            node = node.children.get_mut(label.as_ref()).ok_or_else(|| {
+                DataSketchesError::InvalidParameter(format!(
+                    "no rollup node for label {:?}",
+                    label.as_ref()
+                ))
+            })?;
+        }
+        Ok(node)
+    }
+
+    fn refresh(&mut self, empty: &KllDoubleSketch) -> Result<&KllDoubleSketch> {
+        if self.merged.is_none() {
+            let mut merged = empty.clone();
+            if let Some(own) = &self.own {
+                merged.merge(own)?;
+            }
+            for child in self.children.values_mut() {
+                merged.merge(child.refresh(empty)?)?;
+            }
+            self.merged = Some(merged);
+        }
+        Ok(self
+            .merged
+            .as_ref()
+            .expect("merged sketch was just computed"))
+    }
+}
+
+/// Sketches recorded at label paths and rolled up to every ancestor.
+///
+/// Values are recorded at a path such as `["us-east", "az1", "host7"]`. Any
+/// prefix of a recorded path, including the empty path for the root, can be
+/// queried for the union of everything below it. Unions are cached per node;
+/// recording a value only invalidates the nodes on its own path, so repeated
+/// queries re-merge just the branches that changed.
+#[derive(Debug)]
+pub struct RollupTree {
+    root: Node,
+    empty: KllDoubleSketch,
+}
+
+impl RollupTree {
+    /// Creates an empty tree whose sketches use the default k.
+    pub fn new() -> Self {
+        RollupTree {
+            root: Node::default(),
+            empty: KllDoubleSketch::new(),
+        }
+    }
+
+    /// Creates an empty tree whose sketches use the given k.
+    pub fn with_k(k: u16) -> Result<Self> {
+        Ok(RollupTree {
+            root: Node::default(),
+            empty: KllDoubleSketch::new_with_k(k)?,
+        })
+    }
+
+    /// Records a value at `path`.
+    pub fn record<S: AsRef<str>>(&mut self, path: &[S], value: f64) {
+        let own = &mut self.root.descend(path).own;
+        own.get_or_insert_with(|| self.empty.clone()).update(value);
+    }
+
+    /// Merges a sketch into the values recorded at `path`.
+    pub fn merge_at<S: AsRef<str>>(&mut self, path: &[S], sketch: &KllDoubleSketch) -> Result<()> {
+        let own = &mut self.root.descend(path).own;
+        own.get_or_insert_with(|| self.empty.clone()).merge(sketch)
+    }
+
+    /// Returns the union of everything recorded at or below `path`.
+    ///
+    /// Fails if nothing was ever recorded at or below `path`.
+    pub fn rollup<S: AsRef<str>>(&mut self, path: &[S]) -> Result<&KllDoubleSketch> {
+        self.root.find_mut(path)?.refresh(&self.empty)
+    }
+
+    /// Returns the quantile of everything recorded at or below `path`.
+    pub fn get_quantile<S: AsRef<str>>(&mut self, path: &[S], fraction: f64) -> Result<f64> {
+        Ok(self.rollup(path)?.get_quantile(fraction))
+    }
+
+    /// Returns the labels directly below `path`, in sorted order.
+    pub fn children<S: AsRef<str>>(&mut self, path: &[S]) -> Result<Vec<String>> {
+        Ok(self.root.find_mut(path)?.children.keys().cloned().collect())
+    }
+}
+
+impl Default for RollupTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollup_to_ancestors() {
+        let mut tree = RollupTree::new();
+        for i in 0..50 {
+            tree.record(&["us", "az1", "host1"], i as f64);
+            tree.record(&["us", "az2", "host2"], (i + 50) as f64);
+            tree.record(&["eu", "az1", "host3"], (i + 100) as f64);
+        }
+
+        assert_eq!(tree.rollup(&["us", "az1"]).unwrap().get_n(), 50);
+        assert_eq!(tree.rollup(&["us"]).unwrap().get_n(), 100);
+        assert_eq!(tree.rollup::<&str>(&[]).unwrap().get_n(), 150);
+        assert_eq!(tree.get_quantile(&["us"], 1.0).unwrap(), 99.0);
+        assert_eq!(tree.children(&["us"]).unwrap(), vec!["az1", "az2"]);
+
+        // Recording invalidates the cached ancestors.
+        tree.record(&["us", "az1", "host1"], 1000.0);
+        assert_eq!(tree.get_quantile(&["us"], 1.0).unwrap(), 1000.0);
+        assert_eq!(tree.rollup(&["eu"]).unwrap().get_n(), 50);
+        assert_eq!(tree.rollup::<&str>(&[]).unwrap().get_n(), 151);
+    }
+
+    #[test]
+    fn test_unknown_path() {
+        let mut tree = RollupTree::with_k(64).unwrap();
+        assert!(tree.rollup(&["missing"]).is_err());
+
+        let mut sketch = KllDoubleSketch::new();
+        sketch.update(5.0);
+        tree.merge_at(&["a"], &sketch).unwrap();
+        assert_eq!(tree.rollup(&["a"]).unwrap().get_k(), 64);
+        assert_eq!(tree.get_quantile(&["a"], 0.5).unwrap(), 5.0);
+    }
+}
@@ -0,0 +1,327 @@
+//! Per-minute sketches rolled up into hours and days.
+
+use crate::error::{DataSketchesError, Result};
+use crate::events::event;
+use crate::kparam::KParam;
+use crate::KllDoubleSketch;
+use std::collections::BTreeMap;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// Length of the buckets kept by a [`RollupStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Resolution {
+    /// One-minute buckets.
+    Minute,
+    /// One-hour buckets.
+    Hour,
+    /// One-day buckets, in UTC.
+    Day,
+}
+
+impl Resolution {
+    /// All resolutions, finest first.
+    pub const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+    /// Returns the bucket length in seconds.
+    pub const fn seconds(self) -> u64 {
+        match self {
+            Resolution::Minute => MINUTE,
+            Resolution::Hour => HOUR,
+            Resolution::Day => DAY,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn align(self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.seconds()
+    }
+}
+
+/// The sketch answering a [`RollupStore::query`] and the span it covers.
+#[derive(Debug, Clone)]
+pub struct RollupRange {
+    /// Start of the covered span, in seconds since the Unix epoch.
+    pub start: u64,
+    /// End of the covered span, exclusive.
+    pub end: u64,
+    /// Every value recorded in the covered span.
+    pub sketch: KllDoubleSketch,
+}
+
+#[derive(Debug, Clone)]
+struct Level {
+    /// How long buckets are kept after they end, or `None` for ever.
+    retention: Option<u64>,
+    buckets: BTreeMap<u64, KllDoubleSketch>,
+}
+
+/// A time series of sketches at minute, hour and day resolution.
+///
+/// Every value is recorded into its minute, hour and day bucket at once, so
+/// the coarser resolutions are always complete. Each resolution has its own
+/// retention: by default minutes are kept for a day, hours for 30 days and
+/// days for ever. Timestamps are seconds since the Unix epoch; the store
+/// never reads the clock, so the newest timestamp recorded (or passed to
+/// [`advance_to`](Self::advance_to)) stands for "now" when expiring buckets.
+///
+/// ```
+/// use kll_rs::{KParam, RollupStore};
+///
+/// let mut store = RollupStore::new(KParam::default());
+/// for minute in 0..180 {
+///     store.record(minute * 60, minute as f64);
+/// }
+/// let last_two_hours = store.query(3600, 3 * 3600)?;
+/// assert_eq!(last_two_hours.sketch.get_n(), 120);
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RollupStore {
+    k: KParam,
+    levels: [Level; 3],
+    now: u64,
+}
+
+impl RollupStore {
+    /// Creates an empty store whose sketches have the given k.
+    pub fn new(k: KParam) -> Self {
+        let level = |retention| Level {
+            retention,
+            buckets: BTreeMap::new(),
+        };
+        RollupStore {
+            k,
+            levels: [level(Some(DAY)), level(Some(30 * DAY)), level(None)],
+            now: 0,
+        }
+    }
+
+    /// Sets how long buckets of a resolution are kept after they end, in
+    /// seconds, or `None` to keep them for ever.
+    ///
+    /// Retention must not shrink from a finer resolution to a coarser one,
+    /// since queries fall back to coarser buckets once finer ones expire.
+    pub fn set_retention(&mut self, resolution: Resolution, seconds: Option<u64>) -> Result<()> {
+        let mut retentions: Vec<_> = self.levels.iter().map(|level| level.retention).collect();
+        retentions[resolution.index()] = seconds;
+        let longer = |finer: Option<u64>, coarser: Option<u64>| match (finer, coarser) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(finer), Some(coarser)) => finer <= coarser,
+        };
+        if !retentions.windows(2).all(|pair| longer(pair[0], pair[1])) {
+            return Err(DataSketchesError::InvalidParameter(
+                "coarser resolutions must be kept at least as long as finer ones".to_string(),
+            ));
+        }
+        self.levels[resolution.index()].retention = seconds;
+        self.expire();
+        Ok(())
+    }
+
+    /// Returns how long buckets of a resolution are kept after they end.
+    pub fn retention(&self, resolution: Resolution) -> Option<u64> {
+        self.levels[resolution.index()].retention
+    }
+
+    /// Records a value observed at `timestamp`.
+    ///
+    /// Late values still count towards every resolution whose bucket for
+    /// `timestamp` has not expired yet.
+    pub fn record(&mut self, timestamp: u64, value: f64) {
+        self.advance_to(timestamp);
+        for resolution in Resolution::ALL {
+            if !self.is_retained(resolution, resolution.align(timestamp)) {
+                continue;
+            }
+            let k = self.k;
+            self.levels[resolution.index()]
+                .buckets
+                .entry(resolution.align(timestamp))
+                .or_insert_with(|| KllDoubleSketch::with_k(k))
+                .update(value);
+        }
+    }
+
+    /// Moves "now" forward to `timestamp` and drops the buckets that have
+    /// expired. Earlier timestamps are ignored.
+    pub fn advance_to(&mut self, timestamp: u64) {
+        if timestamp > self.now {
+            self.now = timestamp;
+            self.expire();
+        }
+    }
+
+    /// Returns the newest timestamp seen.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Returns the retained buckets of a resolution, keyed by start time.
+    pub fn buckets(&self, resolution: Resolution) -> &BTreeMap<u64, KllDoubleSketch> {
+        &self.levels[resolution.index()].buckets
+    }
+
+    /// Merges the values recorded in `[start, end)`.
+    ///
+    /// The range is covered with as few buckets as possible, preferring
+    /// coarse buckets that fit inside it. Where the minutes needed at an edge
+    /// have expired, the edge is widened to the enclosing hour, or day if the
+    /// hour has expired too; the returned span says what was covered. Time
+    /// before the oldest retained day is left out.
+    pub fn query(&self, start: u64, end: u64) -> Result<RollupRange> {
+        if start >= end {
+            return Err(DataSketchesError::InvalidParameter(format!(
+                "empty rollup range {}..{}",
+                start, end
+            )));
+        }
+        let mut sketch = KllDoubleSketch::with_k(self.k);
+        let mut covered = None::<(u64, u64)>;
+        let mut t = Resolution::Minute.align(start);
+        while t < end {
+            let bucket = Resolution::ALL
+                .into_iter()
+                .rev()
+                .find(|&r| r.align(t) == t && t + r.seconds() <= end && self.is_retained(r, t))
+                .or_else(|| {
+                    Resolution::ALL
+                        .into_iter()
+                        .find(|&r| self.is_retained(r, r.align(t)))
+                });
+            let Some(resolution) = bucket else {
+                // Nothing is kept for this day any more.
+                t = Resolution::Day.align(t) + DAY;
+                continue;
+            };
+            let from = resolution.align(t);
+            if let Some(values) = self.buckets(resolution).get(&from) {
+                sketch.merge(values)?;
+            }
+            t = from + resolution.seconds();
+            covered = Some(match covered {
+                Some((first, _)) => (first, t),
+                None => (from, t),
+            });
+        }
+        let (start, end) = covered.unwrap_or((end, end));
+        Ok(RollupRange { start, end, sketch })
+    }
+
+    fn is_retained(&self, resolution: Resolution, bucket: u64) -> bool {
+        match self.levels[resolution.index()].retention {
+            None => true,
+            Some(retention) => bucket + resolution.seconds() + retention > self.now,
+        }
+    }
+
+    fn expire(&mut self) {
+        for resolution in Resolution::ALL {
+            let Some(retention) = self.levels[resolution.index()].retention else {
+                continue;
+            };
+            // Oldest bucket start that is still retained.
+            let horizon = (self.now + 1).saturating_sub(retention + resolution.seconds());
+            let buckets = &mut self.levels[resolution.index()].buckets;
+            let retained = buckets.split_off(&horizon);
+            let expired = buckets.len();
+            *buckets = retained;
+            if expired > 0 {
+                event!(
+                    debug,
+                    "expired rollup buckets",
+                    bucket_seconds = resolution.seconds(),
+                    expired = expired,
+                    retained = buckets.len(),
+                );
+            }
+        }
+    }
+}
+
+impl Default for RollupStore {
+    fn default() -> Self {
+        Self::new(KParam::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> RollupStore {
+        let mut store = RollupStore::new(KParam::new(64).unwrap());
+        store.set_retention(Resolution::Minute, Some(HOUR)).unwrap();
+        store.set_retention(Resolution::Hour, Some(DAY)).unwrap();
+        // Three days of one value per minute, the value being the minute.
+        for minute in 0..3 * 24 * 60 {
+            store.record(minute * MINUTE, minute as f64);
+        }
+        store
+    }
+
+    #[test]
+    fn test_rollup_retention() {
+        let store = store();
+        assert_eq!(store.now(), 3 * DAY - MINUTE);
+        assert_eq!(store.buckets(Resolution::Minute).len(), 61);
+        assert_eq!(store.buckets(Resolution::Hour).len(), 25);
+        assert_eq!(store.buckets(Resolution::Day).len(), 3);
+
+        let mut invalid = RollupStore::default();
+        assert!(invalid
+            .set_retention(Resolution::Hour, Some(MINUTE))
+            .is_err());
+        assert!(invalid.set_retention(Resolution::Day, Some(DAY)).is_err());
+        assert!(invalid.set_retention(Resolution::Minute, None).is_err());
+    }
+
+    #[test]
+    fn test_rollup_queries() {
+        let store = store();
+
+        // Recent minutes are answered exactly.
+        let recent = store.query(3 * DAY - 10 * MINUTE, 3 * DAY).unwrap();
+        assert_eq!((recent.start, recent.end), (3 * DAY - 10 * MINUTE, 3 * DAY));
+        assert_eq!(recent.sketch.get_n(), 10);
+        assert_eq!(recent.sketch.get_min_value(), (3 * 24 * 60 - 10) as f64);
+
+        // Expired minutes widen the range to the hour, then the day.
+        let hour = store
+            .query(2 * DAY + 90 * MINUTE, 2 * DAY + 100 * MINUTE)
+            .unwrap();
+        assert_eq!((hour.start, hour.end), (2 * DAY + HOUR, 2 * DAY + 2 * HOUR));
+        assert_eq!(hour.sketch.get_n(), 60);
+        let day = store.query(DAY + 90 * MINUTE, DAY + 100 * MINUTE).unwrap();
+        assert_eq!((day.start, day.end), (DAY, 2 * DAY));
+        assert_eq!(day.sketch.get_n(), 24 * 60);
+
+        // Whole days, hours and minutes combine without double counting.
+        let all = store.query(0, 3 * DAY).unwrap();
+        assert_eq!((all.start, all.end), (0, 3 * DAY));
+        assert_eq!(all.sketch.get_n(), 3 * 24 * 60);
+        let mixed = store.query(DAY, 3 * DAY - 5 * MINUTE).unwrap();
+        assert_eq!(mixed.sketch.get_n(), 2 * 24 * 60 - 5);
+
+        assert!(store.query(10, 10).is_err());
+    }
+
+    #[test]
+    fn test_late_values() {
+        let mut store = store();
+        store.record(0, -1.0);
+        assert_eq!(store.query(0, DAY).unwrap().sketch.get_min_value(), -1.0);
+        assert!(store.buckets(Resolution::Hour).get(&0).is_none());
+
+        store.set_retention(Resolution::Day, Some(DAY)).unwrap();
+        assert_eq!(store.buckets(Resolution::Day).len(), 2);
+        let gone = store.query(0, DAY + HOUR).unwrap();
+        assert_eq!((gone.start, gone.end), (DAY, 2 * DAY));
+    }
+}
@@ -0,0 +1,37 @@
+//! Whether rank and quantile queries count items equal to the value.
+
+/// How rank and quantile queries treat items equal to the value searched.
+///
+/// The plain queries such as `get_rank` and `get_quantile` are inclusive;
+/// the `_with` variants take the criteria explicitly.
+///
+/// ```
+/// use kll_rs::{KllDoubleSketch, SearchCriteria};
+///
+/// let mut sketch = KllDoubleSketch::new();
+/// for value in [1.0, 2.0, 2.0, 3.0] {
+///     sketch.update(value);
+/// }
+/// assert_eq!(sketch.get_rank_with(2.0, SearchCriteria::Inclusive), 0.75);
+/// assert_eq!(sketch.get_rank_with(2.0, SearchCriteria::Exclusive), 0.25);
+/// assert_eq!(sketch.get_quantile_with(0.25, SearchCriteria::Inclusive), 1.0);
+/// assert_eq!(sketch.get_quantile_with(0.25, SearchCriteria::Exclusive), 2.0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SearchCriteria {
+    /// A rank is the fraction of items less than or equal to the value, and a
+    /// quantile the smallest item whose inclusive rank reaches the fraction.
+    #[default]
+    Inclusive,
+    /// A rank is the fraction of items strictly less than the value, and a
+    /// quantile the smallest item with more than the fraction of items at or
+    /// below it.
+    Exclusive,
+}
+
+impl SearchCriteria {
+    /// Returns true for [`SearchCriteria::Inclusive`].
+    pub fn is_inclusive(self) -> bool {
+        self == SearchCriteria::Inclusive
+    }
+}
@@ -0,0 +1,23 @@
+//! Control over the randomness used by compaction.
+
+use crate::status::infallible;
+use crate::sys::kll_set_random_seed_checked;
+
+/// Reseeds the compaction randomness of the calling thread.
+///
+/// Compaction keeps a random half of the items it processes. The generator
+/// is per thread, so after this call, feeding the same values to fresh
+/// sketches on this thread produces byte-identical sketches. Sketches with
+/// their own seed (see `set_seed` on the sketch types) reseed the generator
+/// themselves and override this; they also leave the generator reseeded
+/// rather than restoring it, so call this again after using one on the thread.
+pub fn set_compaction_seed(seed: u64) {
+    infallible(|status| unsafe { kll_set_random_seed_checked(seed, status) })
+}
+
+/// Reseeds the thread's generator for a seeded sketch that has performed
+/// `compactions` compactions so far.
+pub(crate) fn reseed(seed: u64, compactions: u64) {
+    let seed = seed ^ compactions.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    infallible(|status| unsafe { kll_set_random_seed_checked(seed, status) })
+}
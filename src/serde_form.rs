@@ -0,0 +1,304 @@
+//! How sketches appear in serde formats: base64 text or an object in
+//! human-readable ones, base64 text or raw bytes in binary ones.
+
+use base64::Engine;
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserializer, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Shape of a sketch in human-readable serde formats.
+///
+/// Binary formats such as MessagePack carry the base64 string unless a field
+/// opts into raw bytes with [`serde_raw`], and deserialization accepts every
+/// shape whichever is selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SerdeForm {
+    /// The serialized bytes as one base64 string.
+    #[default]
+    Compact,
+    /// An object naming the sketch type, k and n next to the base64
+    /// payload: `{"type": "kll_double", "k": 200, "n": 1000, "payload": "..."}`.
+    Described,
+}
+
+static FORM: AtomicU8 = AtomicU8::new(SerdeForm::Compact as u8);
+
+/// Selects the shape sketches serialize to in human-readable formats, for
+/// every sketch in the process.
+///
+/// ```
+/// use kll_rs::{set_serde_form, KllDoubleSketch, SerdeForm};
+///
+/// let mut sketch = KllDoubleSketch::new();
+/// sketch.update(1.0);
+/// set_serde_form(SerdeForm::Described);
+/// let json = serde_json::to_string(&sketch).unwrap();
+/// assert!(json.starts_with(r#"{"type":"kll_double","k":200,"n":1,"payload":"#));
+/// set_serde_form(SerdeForm::Compact);
+/// let restored: KllDoubleSketch = serde_json::from_str(&json).unwrap();
+/// assert_eq!(restored.get_n(), 1);
+/// ```
+pub fn set_serde_form(form: SerdeForm) {
+    FORM.store(form as u8, Ordering::Relaxed);
+}
+
+/// Returns the shape selected with [`set_serde_form`].
+pub fn serde_form() -> SerdeForm {
+    match FORM.load(Ordering::Relaxed) {
+        form if form == SerdeForm::Described as u8 => SerdeForm::Described,
+        _ => SerdeForm::Compact,
+    }
+}
+
+/// What a sketch writes through serde, besides its bytes.
+pub(crate) struct Description {
+    pub(crate) type_name: &'static str,
+    pub(crate) k: u16,
+    pub(crate) n: u64,
+}
+
+/// Serializes sketch bytes in the selected form.
+pub(crate) fn serialize_sketch<S: Serializer>(
+    serializer: S,
+    description: Description,
+    bytes: &[u8],
+) -> Result<S::Ok, S::Error> {
+    let payload = base64::engine::general_purpose::STANDARD.encode(bytes);
+    if !serializer.is_human_readable() || serde_form() == SerdeForm::Compact {
+        return serializer.serialize_str(&payload);
+    }
+    let mut object = serializer.serialize_struct("KllSketch", 4)?;
+    object.serialize_field("type", description.type_name)?;
+    object.serialize_field("k", &description.k)?;
+    object.serialize_field("n", &description.n)?;
+    object.serialize_field("payload", &payload)?;
+    object.end()
+}
+
+/// Sketch bytes read back through serde, with the k and n the described
+/// form claimed for them.
+pub(crate) struct Described<'de> {
+    /// Borrowed from the input when it holds the raw bytes.
+    pub(crate) bytes: Cow<'de, [u8]>,
+    pub(crate) claimed: Option<(u16, u64)>,
+}
+
+/// Deserializes sketch bytes written in either form by a sketch of type
+/// `type_name`.
+///
+/// Binary formats may also hold the raw serialized bytes, as written by
+/// [`serde_raw`]; formats that can lend them out are read without copying.
+pub(crate) fn deserialize_sketch<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    type_name: &'static str,
+) -> Result<Described<'de>, D::Error> {
+    let visitor = SketchVisitor { type_name };
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(visitor)
+    } else {
+        // Formats without type information encode strings and bytes alike,
+        // so asking for bytes reads either.
+        deserializer.deserialize_bytes(visitor)
+    }
+}
+
+/// Returns true if `bytes` could be base64 text rather than a serialized
+/// sketch, whose preamble always starts with a control byte.
+fn is_base64_text(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .all(|&byte| byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'/' | b'='))
+}
+
+const FIELDS: &[&str] = &["type", "k", "n", "payload"];
+
+struct SketchVisitor {
+    type_name: &'static str,
+}
+
+fn decode<E: de::Error>(payload: &str) -> Result<Vec<u8>, E> {
+    base64::engine::general_purpose::STANDARD
+        .decode(payload)
+        .map_err(E::custom)
+}
+
+impl<'de> Visitor<'de> for SketchVisitor {
+    type Value = Described<'de>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a base64 {} sketch or an object describing one",
+            self.type_name
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, payload: &str) -> Result<Described<'de>, E> {
+        Ok(Described {
+            bytes: Cow::Owned(decode(payload)?),
+            claimed: None,
+        })
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, bytes: &'de [u8]) -> Result<Described<'de>, E> {
+        if is_base64_text(bytes) {
+            return self.visit_bytes(bytes);
+        }
+        Ok(Described {
+            bytes: Cow::Borrowed(bytes),
+            claimed: None,
+        })
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Described<'de>, E> {
+        let bytes = if is_base64_text(bytes) {
+            let text = std::str::from_utf8(bytes).map_err(E::custom)?;
+            decode(text)?
+        } else {
+            bytes.to_vec()
+        };
+        Ok(Described {
+            bytes: Cow::Owned(bytes),
+            claimed: None,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Described<'de>, A::Error> {
+        let (mut k, mut n, mut payload) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "type" => {
+                    let found: String = map.next_value()?;
+                    if found != self.type_name {
+                        return Err(de::Error::custom(format!(
+                            "expected a {} sketch, found {}",
+                            self.type_name, found
+                        )));
+                    }
+                }
+                "k" => k = Some(map.next_value()?),
+                "n" => n = Some(map.next_value()?),
+                "payload" => payload = Some(decode(&map.next_value::<String>()?)?),
+                other => return Err(de::Error::unknown_field(other, FIELDS)),
+            }
+        }
+        Ok(Described {
+            bytes: Cow::Owned(payload.ok_or_else(|| de::Error::missing_field("payload"))?),
+            claimed: k.zip(n),
+        })
+    }
+}
+
+/// A sketch type that [`serde_raw`] can write.
+pub trait RawSerde: Sized + sealed::Sealed {
+    #[doc(hidden)]
+    fn raw_bytes(&self) -> crate::error::Result<Vec<u8>>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for crate::KllDoubleSketch {}
+    impl Sealed for crate::KllFloatSketch {}
+}
+
+/// Serde adapter writing sketches as raw bytes instead of base64 text.
+///
+/// For binary formats such as bincode or MessagePack, with
+/// `#[serde(with = "kll_rs::serde_raw")]` on a sketch field. The bytes are a
+/// third smaller than base64 and, for formats that lend out borrowed bytes,
+/// deserialize without an intermediate copy. A sketch's plain `Deserialize`
+/// also reads this form, so the attribute can be added to existing fields.
+pub mod serde_raw {
+    use super::RawSerde;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Serializes the sketch as its raw serialized bytes.
+    pub fn serialize<T: RawSerde, S: Serializer>(
+        sketch: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let bytes = sketch.raw_bytes().map_err(serde::ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    /// Deserializes a sketch from raw bytes, or from any form its plain
+    /// `Deserialize` accepts.
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: RawSerde + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KllDoubleSketch, KllFloatSketch};
+    use base64::Engine;
+
+    #[test]
+    fn test_reads_both_forms() {
+        let mut sketch = KllFloatSketch::new_with_k(64).unwrap();
+        for i in 0..10 {
+            sketch.update(i as f32);
+        }
+        let payload = base64::engine::general_purpose::STANDARD.encode(sketch.serialize().unwrap());
+        let compact = format!("\"{}\"", payload);
+        let described = |k: u16, kind: &str| {
+            format!(
+                r#"{{"type":"{}","k":{},"n":10,"payload":"{}"}}"#,
+                kind, k, payload
+            )
+        };
+
+        for json in [compact, described(64, "kll_float")] {
+            let restored: KllFloatSketch = serde_json::from_str(&json).unwrap();
+            assert_eq!((restored.get_k(), restored.get_n()), (64, 10));
+        }
+        assert!(serde_json::from_str::<KllFloatSketch>(&described(200, "kll_float")).is_err());
+        assert!(serde_json::from_str::<KllDoubleSketch>(&described(64, "kll_float")).is_err());
+        assert!(serde_json::from_str::<KllFloatSketch>(r#"{"type":"kll_float"}"#).is_err());
+
+        let packed = rmp_serde::to_vec(&sketch).unwrap();
+        let unpacked: KllFloatSketch = rmp_serde::from_slice(&packed).unwrap();
+        assert_eq!(unpacked.get_n(), 10);
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Raw {
+        #[serde(with = "crate::serde_raw")]
+        sketch: KllDoubleSketch,
+    }
+
+    #[test]
+    fn test_raw_bytes_in_binary_formats() {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 0..1000 {
+            sketch.update(i as f64);
+        }
+        let raw = Raw { sketch };
+        let bytes = raw.sketch.serialize().unwrap();
+
+        let encoded = bincode::serialize(&raw).unwrap();
+        assert_eq!(encoded.len(), 8 + bytes.len());
+        let plain: KllDoubleSketch = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(plain.get_n(), 1000);
+        let legacy = bincode::serialize(&plain).unwrap();
+        let restored: Raw = bincode::deserialize(&legacy).unwrap();
+        assert_eq!(restored.sketch.get_n(), 1000);
+
+        let packed = rmp_serde::to_vec(&raw).unwrap();
+        let unpacked: Raw = rmp_serde::from_slice(&packed).unwrap();
+        assert_eq!(
+            unpacked.sketch.get_quantile(0.5),
+            raw.sketch.get_quantile(0.5)
+        );
+        assert!(
+            bincode::deserialize::<KllDoubleSketch>(&[3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]).is_err()
+        );
+    }
+}
@@ -0,0 +1,2695 @@
+//! The safe sketch types, generated from one definition per item type.
+//!
+//! `KllFloatSketch` and `KllDoubleSketch` differ only in their item type and
+//! the prefix of the native functions they call, so both are expanded from
+//! [`kll_sketch_type!`]. A new item type needs its functions in the C wrapper
+//! and `libdatasketches_sys`, a [`SketchItem`] impl, and one invocation:
+//!
+//! ```text
+//! kll_sketch_type!(KllInt64Sketch, i64, int64);
+//! ```
+//!
+//! which expects `kll_int64_sketch_*` functions in `crate::sys`.
+
+/// Conversions between an item type and the `f64` used for ranks and reports.
+pub(crate) trait SketchItem: Copy {
+    fn to_f64(self) -> f64;
+    fn from_f64(value: f64) -> Self;
+    /// The largest value less than `self`, like `f64::next_down`, which needs
+    /// a newer compiler than the MSRV. NaN and negative infinity are returned
+    /// unchanged.
+    fn next_below(self) -> Self;
+}
+
+impl SketchItem for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn next_below(self) -> Self {
+        if self.is_nan() || self == f32::NEG_INFINITY {
+            return self;
+        }
+        if self == 0.0 {
+            return -f32::from_bits(1);
+        }
+        let bits = self.to_bits();
+        f32::from_bits(if self > 0.0 { bits - 1 } else { bits + 1 })
+    }
+}
+
+impl SketchItem for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn next_below(self) -> Self {
+        if self.is_nan() || self == f64::NEG_INFINITY {
+            return self;
+        }
+        if self == 0.0 {
+            return -f64::from_bits(1);
+        }
+        let bits = self.to_bits();
+        f64::from_bits(if self > 0.0 { bits - 1 } else { bits + 1 })
+    }
+}
+
+/// A temporary copy of serialized bytes, zeroed on drop with the `zeroize` feature.
+#[cfg(feature = "zeroize")]
+pub(crate) type ScratchBytes = zeroize::Zeroizing<Vec<u8>>;
+#[cfg(not(feature = "zeroize"))]
+pub(crate) type ScratchBytes = Vec<u8>;
+
+/// Returns how far bin `i` stands above the lowest point separating it from
+/// a higher bin, or `None` if it is not a local peak.
+///
+/// Plateaus count once, at their leftmost bin; the ends of the range count as
+/// empty bins.
+pub(crate) fn peak_prominence(mass: &[f64], i: usize) -> Option<f64> {
+    let peak = mass[i];
+    let left = i.checked_sub(1).map_or(0.0, |j| mass[j]);
+    let right = mass.get(i + 1).copied().unwrap_or(0.0);
+    if peak <= left || peak < right {
+        return None;
+    }
+    let base = |bins: &mut dyn Iterator<Item = &f64>| {
+        let mut lowest = peak;
+        for &m in bins {
+            if m > peak {
+                return lowest;
+            }
+            lowest = lowest.min(m);
+        }
+        0.0
+    };
+    let left_base = base(&mut mass[..i].iter().rev());
+    let right_base = base(&mut mass[i + 1..].iter());
+    Some(peak - left_base.max(right_base))
+}
+
+/// Defines a sketch type, its trait impls and its tests.
+///
+/// `$Sketch` is the type name, `$item` the item type and `$t` the infix of
+/// the native functions, e.g. `float` for `kll_float_sketch_update`.
+macro_rules! kll_sketch_type {
+    ($Sketch:ident, $item:ident, $t:ident) => {
+        paste::paste! {
+            use crate::ascii;
+            use crate::buckets::{dedup_bounds, log_spaced, BucketStrategy};
+            use crate::defaults::default_k;
+            use crate::error::{DataSketchesError, Result};
+            use crate::format::{
+                check_compatible, convert_byte_order, largest_k_within, map_items, serialized_size_bytes,
+                weighted_items_bytes,
+            };
+            use crate::fraction::Fraction;
+            use crate::frozen::FrozenSketch;
+            use crate::handle::{check_capacity, NativeHandle, NativeItem};
+            use crate::kparam::KParam;
+            use crate::line_protocol::Point;
+            use crate::nonempty::NonEmptySketch;
+            use crate::observer::{compaction_trampoline, CompactionObserver};
+            use crate::piecewise::PiecewiseMap;
+            use crate::range::RangeInclusivity;
+            use crate::search_criteria::SearchCriteria;
+            use crate::seed::reseed;
+            use crate::serde_form::{deserialize_sketch, serialize_sketch, Description, RawSerde};
+            use crate::summary::SummaryFormat;
+            use crate::status::{checked, checked_as, infallible};
+            use crate::sys::{
+                [<kll_ $t _sketch_copy_checked>],
+                [<kll_ $t _sketch_delete_checked>],
+                [<kll_ $t _sketch_deserialize_checked>],
+                [<kll_ $t _sketch_get_cdf_checked>],
+                [<kll_ $t _sketch_get_items_discarded_checked>],
+                [<kll_ $t _sketch_get_k_checked>],
+                [<kll_ $t _sketch_get_level_capacities_checked>],
+                [<kll_ $t _sketch_get_max_value_checked>],
+                [<kll_ $t _sketch_get_min_value_checked>],
+                [<kll_ $t _sketch_get_n_checked>],
+                [<kll_ $t _sketch_get_normalized_rank_error_checked>],
+                [<kll_ $t _sketch_get_num_retained_checked>],
+                [<kll_ $t _sketch_get_pmf_checked>],
+                [<kll_ $t _sketch_get_quantile_checked>],
+                [<kll_ $t _sketch_get_quantile_criteria_checked>],
+                [<kll_ $t _sketch_get_quantiles_checked>],
+                [<kll_ $t _sketch_get_quantiles_criteria_checked>],
+                [<kll_ $t _sketch_get_quantiles_evenly_spaced_checked>],
+                [<kll_ $t _sketch_get_rank_checked>],
+                [<kll_ $t _sketch_get_rank_criteria_checked>],
+                [<kll_ $t _sketch_get_retained_items_checked>],
+                [<kll_ $t _sketch_is_empty_checked>],
+                [<kll_ $t _sketch_is_estimation_mode_checked>],
+                [<kll_ $t _sketch_merge_tracked_checked>],
+                [<kll_ $t _sketch_new_with_k_checked>],
+                [<kll_ $t _sketch_serialize_checked>],
+                [<kll_ $t _sketch_set_compaction_callback_checked>],
+                [<kll_ $t _sketch_update_checked>],
+                [<kll_ $t _sketch_update_tracked_checked>],
+            };
+            use crate::timing::elapsed_millis;
+            use crate::traits::{Mergeable, QuantileSketch};
+            use crate::sketch_type::{peak_prominence, ScratchBytes, SketchItem};
+            use serde::{Deserialize, Serialize};
+            use std::future::Future;
+            use std::io::Write;
+            use std::num::NonZeroU64;
+            use std::os::raw::c_void;
+            use std::sync::OnceLock;
+            use std::time::Instant;
+
+            /// Two-sided z-score of the 99% confidence level used by the normalized rank error.
+            const RANK_ERROR_Z_99: f64 = 2.576;
+
+            /// Lower bound applied to bin proportions when computing PSI.
+            const PSI_EPSILON: f64 = 1e-4;
+
+            /// Number of values buffered in Rust before the native sketch is created.
+            ///
+            /// Kept at the minimum k so that flushing the buffer never compacts.
+            const INLINE_CAPACITY: usize = 8;
+
+            #[doc = concat!("A KLL sketch for ", stringify!($t), " values.")]
+            ///
+            /// KLL (Karp, Luby, Lamport) sketches are a type of quantile sketch that provide
+            /// approximate quantile estimates with strong accuracy guarantees.
+            ///
+            /// The first few updates are held in a small Rust buffer, and the native
+            /// sketch is only allocated once that buffer fills or the sketch is queried.
+            /// Short series therefore never pay for the native allocation.
+            #[derive(Debug)]
+            pub struct $Sketch {
+                k: u16,
+                /// Values received before the native sketch was created.
+                buffer: Vec<$item>,
+                native: OnceLock<NativeHandle<$item>>,
+                observer: Option<Box<CompactionObserver>>,
+                compactions: u64,
+                seed: Option<u64>,
+                memory_budget: Option<usize>,
+            }
+
+            impl $Sketch {
+                #[doc = concat!("Creates a new KLL ", stringify!($t), " sketch with the default k.")]
+                ///
+                /// The default is 200 unless changed process-wide with `set_default_k`.
+                /// This never fails and does not allocate; the native sketch is created
+                /// on first use.
+                pub fn new() -> Self {
+                    Self::unallocated(default_k())
+                }
+
+                const fn unallocated(k: u16) -> Self {
+                    $Sketch {
+                        k,
+                        buffer: Vec::new(),
+                        native: OnceLock::new(),
+                        observer: None,
+                        compactions: 0,
+                        seed: None,
+                        memory_budget: None,
+                    }
+                }
+
+                #[doc = concat!("Creates a new KLL ", stringify!($t), " sketch with a specific k parameter.")]
+                ///
+                /// The k parameter controls the accuracy/space trade-off.
+                /// Larger values of k provide better accuracy but use more memory.
+                ///
+                /// Fails if k is outside the limits checked by [`KParam::new`].
+                pub fn new_with_k(k: u16) -> Result<Self> {
+                    KParam::new(k).map(Self::with_k)
+                }
+
+                #[doc = concat!("Creates a new KLL ", stringify!($t), " sketch with an already validated k.")]
+                ///
+                /// This is a `const fn`, so sketches can be built in statics.
+                pub const fn with_k(k: KParam) -> Self {
+                    Self::unallocated(k.get())
+                }
+
+                /// Creates a sketch with the default k and allocates its native sketch now,
+                /// so an allocation failure is reported here rather than panicking on first
+                /// use.
+                pub fn try_default() -> Result<Self> {
+                    let sketch = Self::new();
+                    sketch.try_native()?;
+                    Ok(sketch)
+                }
+
+                /// Returns the native sketch, creating it from the buffered values on first use.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the native sketch cannot be allocated.
+                fn native(&self) -> *mut c_void {
+                    self.try_native()
+                        .unwrap_or_else(|err| panic!("native sketch call failed: {}", err))
+                }
+
+                /// Returns the native sketch, creating it from the buffered values on first
+                /// use and reporting an allocation failure.
+                fn try_native(&self) -> Result<*mut c_void> {
+                    if let Some(handle) = self.native.get() {
+                        return Ok(handle.as_ptr());
+                    }
+                    #[cfg(feature = "failpoints")]
+                    crate::failpoints::check(crate::failpoints::FailPoint::Allocate)?;
+                    let handle = unsafe {
+                        NativeHandle::from_created(checked_as(DataSketchesError::CreationError, |status| {
+                            [<kll_ $t _sketch_new_with_k_checked>](self.k, status)
+                        })?)?
+                    };
+                    for &value in &self.buffer {
+                        checked(|status| unsafe {
+                            [<kll_ $t _sketch_update_checked>](handle.as_ptr(), value, status)
+                        })?;
+                    }
+                    // If another thread created the native sketch first, ours is dropped.
+                    let _ = self.native.set(handle);
+                    Ok(self.native.get().expect("the native sketch was just set").as_ptr())
+                }
+
+                /// Returns the native sketch and releases the buffer it was built from.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the native sketch cannot be allocated.
+                fn native_mut(&mut self) -> *mut c_void {
+                    self.try_native_mut()
+                        .unwrap_or_else(|err| panic!("native sketch call failed: {}", err))
+                }
+
+                /// Returns the native sketch and releases the buffer it was built from,
+                /// reporting an allocation failure.
+                fn try_native_mut(&mut self) -> Result<*mut c_void> {
+                    let ptr = self.try_native()?;
+                    if !self.buffer.is_empty() {
+                        #[cfg(feature = "zeroize")]
+                        zeroize::Zeroize::zeroize(&mut self.buffer);
+                        self.buffer = Vec::new();
+                    }
+                    Ok(ptr)
+                }
+
+                /// Creates a sketch with the largest k whose serialized size stays within `bytes`.
+                ///
+                /// The bound is the maximum serialized size for a stream of 2^48 values,
+                /// so it holds however long the sketch is fed. Fails if even the smallest
+                /// k does not fit.
+                pub fn with_memory_budget(bytes: usize) -> Result<Self> {
+                    let k = largest_k_within(bytes, std::mem::size_of::<$item>()).ok_or_else(|| {
+                        DataSketchesError::InvalidParameter(format!(
+                            "memory budget of {} bytes is too small for any k",
+                            bytes
+                        ))
+                    })?;
+                    let mut sketch = Self::new_with_k(k)?;
+                    sketch.memory_budget = Some(bytes);
+                    Ok(sketch)
+                }
+
+                /// Returns the budget passed to `with_memory_budget`, if the sketch was built that way.
+                pub fn memory_budget(&self) -> Option<usize> {
+                    self.memory_budget
+                }
+
+                /// Updates the sketch with a new value.
+                pub fn update(&mut self, value: $item) {
+                    if self.native.get().is_none() && self.buffer.len() < INLINE_CAPACITY {
+                        // The native sketch ignores NaN, so the buffer does too.
+                        if !value.is_nan() {
+                            self.buffer.push(value);
+                        }
+                        return;
+                    }
+                    let ptr = self.native_mut();
+                    self.reseed();
+                    if infallible(|status| unsafe {
+                        [<kll_ $t _sketch_update_tracked_checked>](ptr, value, status)
+                    }) {
+                        self.compactions += 1;
+                    }
+                    if let Some(observer) = &mut self.observer {
+                        observer.resume_panic();
+                    }
+                }
+
+                /// Merges another sketch into this one.
+                ///
+                /// Fails with [`DataSketchesError::IncompatibleMerge`] if `other`
+                /// has a different k and is in estimation mode: the merged sketch would
+                /// keep this sketch's k while its error bound followed the smaller
+                /// one. Use [`merge_mixed_k`](Self::merge_mixed_k) to accept that.
+                pub fn merge(&mut self, other: &$Sketch) -> Result<()> {
+                    if other.k != self.k && other.is_estimation_mode() {
+                        let min_k = self.k.min(other.k);
+                        return Err(DataSketchesError::IncompatibleMerge(format!(
+                            "cannot merge a sketch with k={} into one with k={}: the result would \
+                             keep k={} with the rank error of k={}; use merge_mixed_k to accept this",
+                            other.k, self.k, self.k, min_k
+                        )));
+                    }
+                    self.merge_mixed_k(other)
+                }
+
+                /// Merges another sketch into this one whatever its k.
+                ///
+                /// The result keeps this sketch's k, but its error bound is that of
+                /// the smallest k among everything merged in, as in DataSketches.
+                pub fn merge_mixed_k(&mut self, other: &$Sketch) -> Result<()> {
+                    if other.native.get().is_none() {
+                        for &value in &other.buffer {
+                            self.update(value);
+                        }
+                        return Ok(());
+                    }
+
+                    #[cfg(feature = "failpoints")]
+                    crate::failpoints::check(crate::failpoints::FailPoint::Merge)?;
+                    self.reseed();
+                    let (ptr, other_ptr) = (self.try_native_mut()?, other.try_native()?);
+                    let compactions = checked(|status| unsafe {
+                        [<kll_ $t _sketch_merge_tracked_checked>](ptr, other_ptr, status)
+                    })?;
+                    self.compactions += other.compactions + compactions as u64;
+                    Ok(())
+                }
+
+                /// Returns true if the sketch is empty.
+                pub fn is_empty(&self) -> bool {
+                    match self.native.get().map(NativeHandle::as_ptr) {
+                        Some(ptr) => infallible(|status| unsafe {
+                            [<kll_ $t _sketch_is_empty_checked>](ptr, status)
+                        }),
+                        None => self.buffer.is_empty(),
+                    }
+                }
+
+                /// Returns the k parameter of the sketch.
+                pub fn get_k(&self) -> u16 {
+                    self.k
+                }
+
+                /// Returns the number of values processed by the sketch.
+                pub fn get_n(&self) -> u64 {
+                    match self.native.get().map(NativeHandle::as_ptr) {
+                        Some(ptr) => infallible(|status| unsafe {
+                            [<kll_ $t _sketch_get_n_checked>](ptr, status)
+                        }),
+                        None => self.buffer.len() as u64,
+                    }
+                }
+
+                /// Returns the number of values retained by the sketch.
+                pub fn get_num_retained(&self) -> u32 {
+                    match self.native.get().map(NativeHandle::as_ptr) {
+                        Some(ptr) => infallible(|status| unsafe {
+                            [<kll_ $t _sketch_get_num_retained_checked>](ptr, status)
+                        }),
+                        None => self.buffer.len() as u32,
+                    }
+                }
+
+                /// Returns true if the sketch is in estimation mode.
+                pub fn is_estimation_mode(&self) -> bool {
+                    match self.native.get().map(NativeHandle::as_ptr) {
+                        Some(ptr) => infallible(|status| unsafe {
+                            [<kll_ $t _sketch_is_estimation_mode_checked>](ptr, status)
+                        }),
+                        // Buffered values have never been compacted.
+                        None => false,
+                    }
+                }
+
+                /// Returns the minimum value seen by the sketch.
+                pub fn get_min_value(&self) -> $item {
+                    if self.is_empty() {
+                        return $item::NAN;
+                    }
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_min_value_checked>](self.native(), status)
+                    })
+                }
+
+                /// Returns the maximum value seen by the sketch.
+                pub fn get_max_value(&self) -> $item {
+                    if self.is_empty() {
+                        return $item::NAN;
+                    }
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_max_value_checked>](self.native(), status)
+                    })
+                }
+
+                /// Returns the approximate quantile for a given fraction.
+                ///
+                /// # Arguments
+                /// * `fraction` - A value between 0.0 and 1.0 representing the desired quantile.
+                pub fn get_quantile(&self, fraction: f64) -> $item {
+                    if self.is_empty() {
+                        return $item::NAN;
+                    }
+
+                    // Validate fraction parameter to prevent C++ exceptions
+                    if !fraction.is_finite() || !(0.0..=1.0).contains(&fraction) {
+                        return $item::NAN;
+                    }
+
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantile_checked>](self.native(), fraction, status)
+                    })
+                }
+
+                /// Returns the approximate quantile for a given fraction under the given
+                /// search criteria; [`get_quantile`](Self::get_quantile) is inclusive.
+                ///
+                /// Returns NaN if the sketch is empty or the fraction is outside [0, 1].
+                pub fn get_quantile_with(&self, fraction: f64, criteria: SearchCriteria) -> $item {
+                    if self.is_empty() || !fraction.is_finite() || !(0.0..=1.0).contains(&fraction) {
+                        return $item::NAN;
+                    }
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantile_criteria_checked>](
+                            self.native(),
+                            fraction,
+                            criteria.is_inclusive(),
+                            status,
+                        )
+                    })
+                }
+
+                /// Returns the approximate quantile at an already validated fraction.
+                ///
+                /// Returns NaN if the sketch is empty.
+                pub fn quantile(&self, fraction: Fraction) -> $item {
+                    if self.is_empty() {
+                        return $item::NAN;
+                    }
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantile_checked>](
+                            self.native(),
+                            fraction.get(),
+                            status,
+                        )
+                    })
+                }
+
+                /// Returns the quantiles at already validated fractions.
+                ///
+                /// Returns no quantiles if the sketch is empty.
+                pub fn quantiles(&self, fractions: &[Fraction]) -> Vec<$item> {
+                    if self.is_empty() || fractions.is_empty() {
+                        return vec![];
+                    }
+                    let mut results = vec![0.0; fractions.len()];
+                    check_capacity("get_quantiles", fractions.len(), results.len());
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantiles_checked>](
+                            self.native(),
+                            Fraction::as_f64_slice(fractions).as_ptr(),
+                            fractions.len(),
+                            results.as_mut_ptr(),
+                            status,
+                        );
+                    });
+                    results
+                }
+
+                /// Returns a view with NaN-free queries, or `None` if the sketch is empty.
+                ///
+                /// Creates the native sketch if it only holds buffered values.
+                pub fn as_non_empty(&self) -> Option<NonEmptySketch<'_, $Sketch>> {
+                    if self.is_empty() {
+                        return None;
+                    }
+                    self.native();
+                    Some(NonEmptySketch::new_unchecked(self))
+                }
+
+                /// Returns the approximate rank of a value.
+                ///
+                /// The rank is the fraction of values in the sketch that are less than or equal to the given value.
+                pub fn get_rank(&self, value: $item) -> f64 {
+                    if self.is_empty() {
+                        return f64::NAN;
+                    }
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_rank_checked>](self.native(), value, status)
+                    })
+                }
+
+                /// Returns the approximate rank of a value under the given search criteria:
+                /// the fraction of values at or below it if inclusive, or strictly below it
+                /// if exclusive. [`get_rank`](Self::get_rank) is inclusive.
+                pub fn get_rank_with(&self, value: $item, criteria: SearchCriteria) -> f64 {
+                    if self.is_empty() {
+                        return f64::NAN;
+                    }
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_rank_criteria_checked>](
+                            self.native(),
+                            value,
+                            criteria.is_inclusive(),
+                            status,
+                        )
+                    })
+                }
+
+                /// Estimates how many values lie between `low` and `high`, with the ends
+                /// included as `inclusive` says.
+                ///
+                /// The bounds are converted to the item type first. An empty sketch, a NaN
+                /// bound or a range with `low > high` counts nothing.
+                pub fn estimate_count_in_range(&self, low: f64, high: f64, inclusive: RangeInclusivity) -> u64 {
+                    if self.is_empty() || low.is_nan() || high.is_nan() || low > high {
+                        return 0;
+                    }
+                    let (low, high) = ($item::from_f64(low), $item::from_f64(high));
+                    // Items are discrete, so the values below `v` are those at or below
+                    // the next representable value under it.
+                    let at_or_below = |value: $item| (self.get_rank(value) * self.get_n() as f64).round() as u64;
+                    let below = |value: $item| at_or_below(SketchItem::next_below(value));
+                    let upper = if inclusive.includes_high() { at_or_below(high) } else { below(high) };
+                    let lower = if inclusive.includes_low() { below(low) } else { at_or_below(low) };
+                    upper.saturating_sub(lower)
+                }
+
+                /// Returns quantiles for multiple fractions.
+                pub fn get_quantiles(&self, fractions: &[f64]) -> Vec<$item> {
+                    if self.is_empty() || fractions.is_empty() {
+                        return vec![];
+                    }
+
+                    // Validate all fractions to prevent C++ exceptions
+                    for &fraction in fractions {
+                        if !fraction.is_finite() || !(0.0..=1.0).contains(&fraction) {
+                            // If any fraction is invalid, return NaN for all results
+                            return vec![$item::NAN; fractions.len()];
+                        }
+                    }
+
+                    let mut results = vec![0.0; fractions.len()];
+                    check_capacity("get_quantiles", fractions.len(), results.len());
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantiles_checked>](
+                            self.native(),
+                            fractions.as_ptr(),
+                            fractions.len(),
+                            results.as_mut_ptr(),
+                            status,
+                        );
+                    });
+                    results
+                }
+
+                /// Returns quantiles for multiple fractions under the given search criteria;
+                /// [`get_quantiles`](Self::get_quantiles) is inclusive.
+                pub fn get_quantiles_with(&self, fractions: &[f64], criteria: SearchCriteria) -> Vec<$item> {
+                    if self.is_empty() || fractions.is_empty() {
+                        return vec![];
+                    }
+                    if fractions.iter().any(|f| !f.is_finite() || !(0.0..=1.0).contains(f)) {
+                        return vec![$item::NAN; fractions.len()];
+                    }
+                    let mut results = vec![0.0; fractions.len()];
+                    check_capacity("get_quantiles", fractions.len(), results.len());
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantiles_criteria_checked>](
+                            self.native(),
+                            fractions.as_ptr(),
+                            fractions.len(),
+                            criteria.is_inclusive(),
+                            results.as_mut_ptr(),
+                            status,
+                        );
+                    });
+                    results
+                }
+
+                /// Returns the approximate fraction of items in each interval between
+                /// `split_points`, which must be strictly increasing.
+                ///
+                /// With `m` split points there are `m + 1` intervals: up to the first point,
+                /// between each pair, and above the last. Each interval includes its upper
+                /// split point, and the fractions sum to 1.
+                pub fn get_pmf(&self, split_points: &[$item]) -> Vec<f64> {
+                    if let Some(results) = self.invalid_split_points(split_points) {
+                        return results;
+                    }
+                    let mut results = vec![0.0; split_points.len() + 1];
+                    check_capacity("get_pmf", split_points.len() + 1, results.len());
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_pmf_checked>](
+                            self.native(),
+                            split_points.as_ptr(),
+                            split_points.len() as u32,
+                            results.as_mut_ptr(),
+                            status,
+                        )
+                    });
+                    results
+                }
+
+                /// Returns the approximate fraction of items at or below each of
+                /// `split_points`, which must be strictly increasing, followed by 1.
+                ///
+                /// This is the running sum of [`get_pmf`](Self::get_pmf), and matches
+                /// [`get_rank`](Self::get_rank) at each split point in one native call.
+                pub fn get_cdf(&self, split_points: &[$item]) -> Vec<f64> {
+                    if let Some(results) = self.invalid_split_points(split_points) {
+                        return results;
+                    }
+                    let mut results = vec![0.0; split_points.len() + 1];
+                    check_capacity("get_cdf", split_points.len() + 1, results.len());
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_cdf_checked>](
+                            self.native(),
+                            split_points.as_ptr(),
+                            split_points.len() as u32,
+                            results.as_mut_ptr(),
+                            status,
+                        )
+                    });
+                    results
+                }
+
+                /// The answer to a PMF or CDF query that must not reach the native sketch:
+                /// empty for an empty sketch, and all NaN for split points that are not
+                /// strictly increasing, since the C++ side would throw, or too many to
+                /// count in the `u32` it takes.
+                fn invalid_split_points(&self, split_points: &[$item]) -> Option<Vec<f64>> {
+                    if self.is_empty() {
+                        return Some(vec![]);
+                    }
+                    let increasing = split_points.windows(2).all(|pair| pair[0] < pair[1]);
+                    if !increasing
+                        || split_points.iter().any(|point| point.is_nan())
+                        || u32::try_from(split_points.len()).is_err()
+                    {
+                        return Some(vec![f64::NAN; split_points.len() + 1]);
+                    }
+                    None
+                }
+
+                /// Returns evenly spaced quantiles.
+                ///
+                /// # Arguments
+                /// * `num` - The number of quantiles to return.
+                pub fn get_quantiles_evenly_spaced(&self, num: u32) -> Vec<$item> {
+                    if self.is_empty() || num == 0 {
+                        return vec![];
+                    }
+
+                    let mut results = vec![0.0; num as usize];
+                    check_capacity("get_quantiles_evenly_spaced", num as usize, results.len());
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantiles_evenly_spaced_checked>](
+                            self.native(),
+                            num,
+                            results.as_mut_ptr(),
+                            status,
+                        );
+                    });
+                    results
+                }
+
+                /// Writes the quantiles for `fractions` into `out`, without allocating.
+                ///
+                /// `out` must be as long as `fractions`. Every fraction must be in
+                /// `[0, 1]`. An empty sketch fills `out` with NaN, like `get_quantile`.
+                pub fn get_quantiles_into(&self, fractions: &[f64], out: &mut [$item]) -> Result<()> {
+                    if out.len() != fractions.len() {
+                        return Err(DataSketchesError::InvalidParameter(format!(
+                            "output holds {} quantiles but {} fractions were given",
+                            out.len(),
+                            fractions.len()
+                        )));
+                    }
+                    if let Some(&fraction) = fractions
+                        .iter()
+                        .find(|f| !f.is_finite() || !(0.0..=1.0).contains(*f))
+                    {
+                        return Err(DataSketchesError::InvalidParameter(format!(
+                            "fraction {} is outside [0, 1]",
+                            fraction
+                        )));
+                    }
+                    if self.is_empty() {
+                        out.fill($item::NAN);
+                        return Ok(());
+                    }
+                    if !out.is_empty() {
+                        check_capacity("get_quantiles", fractions.len(), out.len());
+                        let ptr = self.try_native()?;
+                        infallible(|status| unsafe {
+                            [<kll_ $t _sketch_get_quantiles_checked>](
+                                ptr,
+                                fractions.as_ptr(),
+                                fractions.len(),
+                                out.as_mut_ptr(),
+                                status,
+                            );
+                        });
+                    }
+                    Ok(())
+                }
+
+                /// Writes `out.len()` evenly spaced quantiles into `out`, without
+                /// allocating.
+                ///
+                /// An empty sketch fills `out` with NaN.
+                pub fn get_quantiles_evenly_spaced_into(&self, out: &mut [$item]) -> Result<()> {
+                    let num = u32::try_from(out.len()).map_err(|_| {
+                        DataSketchesError::InvalidParameter(format!(
+                            "cannot compute {} quantiles at once",
+                            out.len()
+                        ))
+                    })?;
+                    if self.is_empty() {
+                        out.fill($item::NAN);
+                        return Ok(());
+                    }
+                    if num > 0 {
+                        check_capacity("get_quantiles_evenly_spaced", num as usize, out.len());
+                        let ptr = self.try_native()?;
+                        infallible(|status| unsafe {
+                            [<kll_ $t _sketch_get_quantiles_evenly_spaced_checked>](
+                                ptr,
+                                num,
+                                out.as_mut_ptr(),
+                                status,
+                            );
+                        });
+                    }
+                    Ok(())
+                }
+
+                /// Serializes the sketch to bytes.
+                pub fn serialize(&self) -> Result<Vec<u8>> {
+                    let mut result = self.with_native_bytes(<[u8]>::to_vec)?;
+                    convert_byte_order(&mut result, std::mem::size_of::<$item>())?;
+                    Ok(result)
+                }
+
+                /// Serializes the sketch into a buffer that is overwritten with zeros
+                /// when dropped.
+                #[cfg(feature = "zeroize")]
+                pub fn serialize_zeroizing(&self) -> Result<zeroize::Zeroizing<Vec<u8>>> {
+                    let mut result = zeroize::Zeroizing::new(self.with_native_bytes(<[u8]>::to_vec)?);
+                    convert_byte_order(&mut result, std::mem::size_of::<$item>())?;
+                    Ok(result)
+                }
+
+                /// Serializes the sketch into `out`, returning the number of bytes written.
+                ///
+                /// Fails with [`DataSketchesError::BufferTooSmall`] if `out` is shorter
+                /// than [`get_serialized_size_bytes`](Self::get_serialized_size_bytes).
+                pub fn serialize_to_slice(&self, out: &mut [u8]) -> Result<usize> {
+                    let available = out.len();
+                    let written = self.with_native_bytes(|bytes| {
+                        let target = out.get_mut(..bytes.len())?;
+                        target.copy_from_slice(bytes);
+                        Some(bytes.len())
+                    })?;
+                    let written = written.ok_or_else(|| DataSketchesError::BufferTooSmall {
+                        needed: self.get_serialized_size_bytes(),
+                        available,
+                    })?;
+                    convert_byte_order(&mut out[..written], std::mem::size_of::<$item>())?;
+                    Ok(written)
+                }
+
+                /// Returns the number of bytes `serialize` would produce right now.
+                pub fn get_serialized_size_bytes(&self) -> usize {
+                    let mut capacities = [0u32; u8::MAX as usize];
+                    let num_levels = infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_level_capacities_checked>](
+                            self.native(),
+                            capacities.as_mut_ptr(),
+                            u8::MAX,
+                            status,
+                        )
+                    });
+                    serialized_size_bytes(
+                        self.get_n(),
+                        num_levels,
+                        self.get_num_retained(),
+                        std::mem::size_of::<$item>(),
+                    )
+                }
+
+                /// Serializes natively and passes the bytes, still in the native
+                /// library's buffer, to `f`.
+                fn with_native_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Result<R> {
+                    #[cfg(feature = "failpoints")]
+                    crate::failpoints::check(crate::failpoints::FailPoint::Serialize)?;
+                    let ptr = self.try_native()?;
+                    let mut size = 0;
+                    let data_ptr = checked_as(DataSketchesError::SerializationError, |status| unsafe {
+                        [<kll_ $t _sketch_serialize_checked>](ptr, &mut size, status)
+                    })?;
+                    unsafe {
+                        // A slice may not span more than isize::MAX bytes, which is only
+                        // 2 GiB on 32-bit targets.
+                        if size > isize::MAX as usize {
+                            libc::free(data_ptr as *mut libc::c_void);
+                            return Err(DataSketchesError::SerializationError(format!(
+                                "serialized size of {} bytes exceeds the address space",
+                                size
+                            )));
+                        }
+
+                        let result = f(std::slice::from_raw_parts(data_ptr, size));
+                        #[cfg(feature = "zeroize")]
+                        zeroize::Zeroize::zeroize(std::slice::from_raw_parts_mut(data_ptr, size));
+
+                        // Use libc::free to match the C++ new[] allocation
+                        // The C++ side uses new uint8_t[], so we need to use the corresponding free
+                        libc::free(data_ptr as *mut libc::c_void);
+                        Ok(result)
+                    }
+                }
+
+                /// Deserializes a sketch from bytes.
+                pub fn deserialize(data: &[u8]) -> Result<Self> {
+                    #[cfg(feature = "failpoints")]
+                    crate::failpoints::check(crate::failpoints::FailPoint::Deserialize)?;
+                    check_compatible(data, std::mem::size_of::<$item>())?;
+                    let mut host_order: ScratchBytes;
+                    let data = if cfg!(target_endian = "big") {
+                        host_order = data.to_vec().into();
+                        convert_byte_order(&mut host_order, std::mem::size_of::<$item>())?;
+                        host_order.as_slice()
+                    } else {
+                        data
+                    };
+                    let handle: NativeHandle<$item> = unsafe {
+                        NativeHandle::from_created(checked_as(DataSketchesError::DeserializationError, |status| {
+                            [<kll_ $t _sketch_deserialize_checked>](data.as_ptr(), data.len(), status)
+                        })?)?
+                    };
+                    Ok($Sketch {
+                        k: infallible(|status| unsafe {
+                            [<kll_ $t _sketch_get_k_checked>](handle.as_ptr(), status)
+                        }),
+                        buffer: Vec::new(),
+                        native: OnceLock::from(handle),
+                        observer: None,
+                        compactions: 0,
+                        seed: None,
+                        memory_budget: None,
+                    })
+                }
+
+                /// Rewrites bytes produced by an older serializer into the current format.
+                ///
+                /// The bytes are decoded by the vendored datasketches-cpp, which still reads
+                /// the older serial versions, and re-encoded with the current writer.
+                /// Use `needs_migration` to find sketches that would change.
+                pub fn migrate_bytes(old: &[u8]) -> Result<Vec<u8>> {
+                    crate::format::detect_serial_version(old)?;
+                    Self::deserialize(old)?.serialize()
+                }
+
+                /// Returns a copy of the sketch with every value mapped to `scale * value + offset`,
+                /// for changing the unit of stored sketches.
+                ///
+                /// Retained items are mapped in place, so every rank and the error bound carry
+                /// over exactly; only rounding to the item type can merge neighbouring values.
+                /// A negative scale reverses the order. Fails unless `scale` is finite and
+                /// nonzero and `offset` finite, or if a mapped value overflows the item type.
+                pub fn affine_transform(&self, scale: f64, offset: f64) -> Result<Self> {
+                    if !scale.is_finite() || scale == 0.0 || !offset.is_finite() {
+                        return Err(DataSketchesError::InvalidParameter(format!(
+                            "affine transform needs a finite nonzero scale and a finite offset, \
+                             got scale {} and offset {}",
+                            scale, offset
+                        )));
+                    }
+                    let mut bytes = self.serialize()?;
+                    map_items(&mut bytes, std::mem::size_of::<$item>(), scale < 0.0, |value| {
+                        scale * value + offset
+                    })?;
+                    let mut sketch = Self::deserialize(&bytes)?;
+                    sketch.seed = self.seed;
+                    sketch.memory_budget = self.memory_budget;
+                    Ok(sketch)
+                }
+
+                /// Creates a copy of the sketch, as [`try_clone`](Self::try_clone).
+                pub fn copy(&self) -> Result<Self> {
+                    self.try_clone()
+                }
+
+                /// Creates a deep copy of the sketch with the native copy constructor,
+                /// reporting an allocation failure instead of panicking as `clone` does.
+                ///
+                /// A sketch still holding its values inline is copied without touching the
+                /// native library. The observer is not copied.
+                pub fn try_clone(&self) -> Result<Self> {
+                    let Some(original) = self.native.get() else {
+                        return Ok($Sketch {
+                            k: self.k,
+                            buffer: self.buffer.clone(),
+                            native: OnceLock::new(),
+                            observer: None,
+                            compactions: self.compactions,
+                            seed: self.seed,
+                            memory_budget: self.memory_budget,
+                        });
+                    };
+                    #[cfg(feature = "failpoints")]
+                    crate::failpoints::check(crate::failpoints::FailPoint::Allocate)?;
+                    let handle = unsafe {
+                        NativeHandle::from_created(checked_as(DataSketchesError::CreationError, |status| {
+                            [<kll_ $t _sketch_copy_checked>](original.as_ptr(), status)
+                        })?)?
+                    };
+                    Ok($Sketch {
+                        k: self.k,
+                        buffer: Vec::new(),
+                        native: OnceLock::from(handle),
+                        observer: None,
+                        compactions: self.compactions,
+                        seed: self.seed,
+                        memory_budget: self.memory_budget,
+                    })
+                }
+
+                /// Releases the native sketch to the caller, for passing ownership across
+                /// another FFI boundary.
+                ///
+                /// Buffered values are flushed into the native sketch first. Any compaction
+                /// callback is removed, and the seed and compaction count, which live on the
+                /// Rust side, are lost. The pointer must eventually go back through
+                /// [`from_raw`](Self::from_raw), or the native sketch leaks.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the native sketch cannot be allocated.
+                pub fn into_raw(mut self) -> *mut c_void {
+                    if self.observer.is_some() {
+                        self.clear_compaction_callback();
+                    }
+                    self.native_mut();
+                    self.native.take().expect("the native sketch was just created").into_raw()
+                }
+
+                /// Takes back ownership of a native sketch released by
+                /// [`into_raw`](Self::into_raw).
+                ///
+                /// # Safety
+                ///
+                #[doc = concat!("`ptr` must come from `into_raw` on a `", stringify!($Sketch), "`, and must not")]
+                /// be used again by the caller or passed to `from_raw` twice.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `ptr` is null.
+                pub unsafe fn from_raw(ptr: *mut c_void) -> Self {
+                    let handle = NativeHandle::from_raw(ptr);
+                    let mut sketch = Self::unallocated(infallible(|status| {
+                        [<kll_ $t _sketch_get_k_checked>](handle.as_ptr(), status)
+                    }));
+                    sketch.native = OnceLock::from(handle);
+                    sketch
+                }
+
+                /// Returns true while the sketch still holds every value it has seen.
+                ///
+                /// This is the opposite of `is_estimation_mode`: no compaction has happened,
+                /// so quantiles and ranks are exact.
+                pub fn is_exact(&self) -> bool {
+                    !self.is_estimation_mode()
+                }
+
+                /// Returns the raw values in ascending order while the sketch is exact.
+                ///
+                /// Returns `None` once the sketch is in estimation mode.
+                pub fn exact_values(&self) -> Option<Vec<$item>> {
+                    if !self.is_exact() {
+                        return None;
+                    }
+                    let (mut values, _) = self.retained_items();
+                    values.sort_by(|a, b| a.total_cmp(b));
+                    Some(values)
+                }
+
+                /// Copies the retained items and their weights out of the native sketch.
+                fn retained_items(&self) -> (Vec<$item>, Vec<u64>) {
+                    let capacity = self.get_num_retained();
+                    let mut items = vec![0.0; capacity as usize];
+                    let mut weights = vec![0u64; capacity as usize];
+                    let count = infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_retained_items_checked>](
+                            self.native(),
+                            items.as_mut_ptr(),
+                            weights.as_mut_ptr(),
+                            capacity,
+                            status,
+                        )
+                    });
+                    check_capacity("get_retained_items", count as usize, capacity as usize);
+                    items.truncate(count as usize);
+                    weights.truncate(count as usize);
+                    (items, weights)
+                }
+
+                /// Converts the sketch into a read-only snapshot answered without FFI calls.
+                ///
+                /// The sorted view is materialized once; see [`FrozenSketch`].
+                pub fn freeze(self) -> FrozenSketch<$item> {
+                    let (items, weights) = self.retained_items();
+                    FrozenSketch::from_weighted(
+                        items,
+                        weights,
+                        self.get_k(),
+                        self.get_n(),
+                        self.get_min_value(),
+                        self.get_max_value(),
+                    )
+                }
+
+                /// Returns the retained items with their weights, in ascending order.
+                ///
+                /// Each weight is the number of input values the item stands for, and the
+                /// weights sum to `get_n()`. Equal items are combined into one entry.
+                pub fn to_weighted_values(&self) -> Vec<($item, u64)> {
+                    if self.is_empty() {
+                        return Vec::new();
+                    }
+                    let (items, weights) = self.retained_items();
+                    let mut pairs: Vec<($item, u64)> = items.into_iter().zip(weights).collect();
+                    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    pairs.dedup_by(|next, kept| {
+                        if next.0 == kept.0 {
+                            kept.1 += next.1;
+                            true
+                        } else {
+                            false
+                        }
+                    });
+                    pairs
+                }
+
+                /// Builds a sketch with parameter `k` from retained items and their weights,
+                /// the inverse of [`to_weighted_values`](Self::to_weighted_values).
+                ///
+                /// The items need not be sorted or distinct; each stands for `weight` input
+                /// values. As long as the items fit in a sketch with parameter `k`, as those
+                /// of a sketch with the same k always do, the result answers exactly as the
+                /// weighted items do. Otherwise they are compacted to fit, as the sketch would
+                /// have compacted the values they stand for. The minimum and maximum are the
+                /// smallest and largest items given.
+                ///
+                /// Fails if k is outside the limits checked by [`KParam::new`], an item is
+                /// NaN, a weight is zero, or the weights sum to more than `u64::MAX`.
+                pub fn from_weighted_items(items: &[($item, u64)], k: u16) -> Result<Self> {
+                    let k = KParam::new(k)?.get();
+                    let widened: Vec<(f64, u64)> =
+                        items.iter().map(|&(item, weight)| (item as f64, weight)).collect();
+                    let bytes = weighted_items_bytes(&widened, k, std::mem::size_of::<$item>())?;
+                    Self::deserialize(&bytes)
+                }
+
+                /// Suggests upper bounds for a fixed-bucket histogram, such as a Prometheus
+                /// histogram's `le` labels, fitted to the values seen so far.
+                ///
+                /// Returns at most `num_buckets` strictly increasing bounds, the last being
+                /// the largest value, so everything seen lands in a finite bucket; the
+                /// `+Inf` bucket is left to the histogram. Fewer come back when values
+                /// repeat so much that bounds coincide, and none for an empty sketch.
+                pub fn suggest_buckets(&self, num_buckets: usize, strategy: BucketStrategy) -> Vec<f64> {
+                    if self.is_empty() || num_buckets == 0 {
+                        return Vec::new();
+                    }
+                    let max = self.get_max_value().to_f64();
+                    let bounds = match strategy {
+                        BucketStrategy::EquiDepth => {
+                            let fractions: Vec<f64> =
+                                (1..=num_buckets).map(|i| i as f64 / num_buckets as f64).collect();
+                            self.get_quantiles(&fractions).into_iter().map(SketchItem::to_f64).collect()
+                        }
+                        BucketStrategy::LogSpaced => {
+                            let smallest_positive = self
+                                .to_weighted_values()
+                                .into_iter()
+                                .map(|(item, _)| item.to_f64())
+                                .find(|&item| item > 0.0);
+                            match smallest_positive {
+                                Some(low) => log_spaced(low, max, num_buckets),
+                                None => vec![max],
+                            }
+                        }
+                    };
+                    dedup_bounds(bounds)
+                }
+
+                /// Returns a monotone map sending values of this distribution to the values
+                /// of `target` at the same quantile, for quantile normalization.
+                ///
+                /// The map passes through the quantiles of both sketches at `resolution + 1`
+                /// evenly spaced fractions, from the minimum to the maximum, and is empty if
+                /// either sketch is. A resolution of 0 is taken as 1.
+                pub fn quantile_map(&self, target: &$Sketch, resolution: u32) -> PiecewiseMap {
+                    if self.is_empty() || target.is_empty() {
+                        return PiecewiseMap::default();
+                    }
+                    let resolution = resolution.max(1);
+                    let fractions: Vec<f64> =
+                        (0..=resolution).map(|i| i as f64 / resolution as f64).collect();
+                    let quantiles = |sketch: &$Sketch| -> Vec<f64> {
+                        sketch.get_quantiles(&fractions).into_iter().map(SketchItem::to_f64).collect()
+                    };
+                    PiecewiseMap::through(&quantiles(self), &quantiles(target))
+                }
+
+                /// Reconstructs a sample of at most `max_points` values, in ascending order.
+                ///
+                /// When `get_n()` fits, every item is repeated by its weight, which
+                /// reproduces the sketch's distribution exactly. Otherwise the points are
+                /// the items at evenly spaced ranks `(i + 0.5) / max_points`, so each one
+                /// stands for an equal share of the input.
+                pub fn expand_approximate(&self, max_points: usize) -> Vec<$item> {
+                    let pairs = self.to_weighted_values();
+                    let n = self.get_n();
+                    if max_points == 0 || n == 0 {
+                        return Vec::new();
+                    }
+                    if n <= max_points as u64 {
+                        return pairs
+                            .into_iter()
+                            .flat_map(|(item, weight)| std::iter::repeat(item).take(weight as usize))
+                            .collect();
+                    }
+                    let mut points = Vec::with_capacity(max_points);
+                    let mut pairs = pairs.into_iter();
+                    let (mut item, mut cumulative) = pairs.next().expect("non-empty sketch has items");
+                    for i in 0..max_points {
+                        let target = (i as f64 + 0.5) / max_points as f64 * n as f64;
+                        while (cumulative as f64) < target {
+                            let (next, weight) = pairs.next().expect("weights sum to n");
+                            item = next;
+                            cumulative += weight;
+                        }
+                        points.push(item);
+                    }
+                    points
+                }
+
+                /// Returns the normalized rank error of the sketch.
+                ///
+                /// This is the rank error bound at 99% confidence. With `pmf` set, the
+                /// bound applies to PMF/histogram queries instead of single rank queries.
+                pub fn get_normalized_rank_error(&self, pmf: bool) -> f64 {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_normalized_rank_error_checked>](
+                            self.native(),
+                            pmf,
+                            status,
+                        )
+                    })
+                }
+
+                /// Returns `(estimate, lower, upper)` quantiles for each fraction.
+                ///
+                /// The bounds are the quantiles at the fraction shifted by `num_std_devs`
+                /// standard deviations of rank error, derived from the 99% confidence
+                /// normalized rank error. All quantiles are fetched in a single query.
+                /// Returns an empty vector for an empty sketch.
+                pub fn error_band(&self, fractions: &[f64], num_std_devs: u8) -> Vec<($item, $item, $item)> {
+                    if self.is_empty() || fractions.is_empty() {
+                        return vec![];
+                    }
+
+                    let std_dev = self.get_normalized_rank_error(false) / RANK_ERROR_Z_99;
+                    let delta = std_dev * num_std_devs as f64;
+                    let mut query = Vec::with_capacity(fractions.len() * 3);
+                    for &fraction in fractions {
+                        query.push(fraction);
+                        query.push((fraction - delta).max(0.0));
+                        query.push((fraction + delta).min(1.0));
+                    }
+
+                    self.get_quantiles(&query)
+                        .chunks_exact(3)
+                        .map(|q| (q[0], q[1], q[2]))
+                        .collect()
+                }
+
+                /// Approximates the Wasserstein-1 (earth mover's) distance to another sketch.
+                ///
+                /// The distance is the integral of `|Q_self(p) - Q_other(p)|` over `p` in
+                /// `[0, 1]`, evaluated with the midpoint rule at `resolution` points.
+                /// Returns NaN if either sketch is empty or `resolution` is zero.
+                pub fn wasserstein_distance(&self, other: &Self, resolution: u32) -> f64 {
+                    if self.is_empty() || other.is_empty() || resolution == 0 {
+                        return f64::NAN;
+                    }
+
+                    let fractions: Vec<f64> = (0..resolution)
+                        .map(|i| (i as f64 + 0.5) / resolution as f64)
+                        .collect();
+                    let ours = self.get_quantiles(&fractions);
+                    let theirs = other.get_quantiles(&fractions);
+
+                    ours.iter()
+                        .zip(&theirs)
+                        .map(|(&a, &b)| (a.to_f64() - b.to_f64()).abs())
+                        .sum::<f64>()
+                        / resolution as f64
+                }
+
+                /// Computes the Population Stability Index of this sketch against a baseline.
+                ///
+                /// Bins are equi-depth in the baseline: the bin edges are the baseline
+                /// quantiles at `i / num_bins`, with duplicate edges collapsed. Both
+                /// distributions are measured on the same `(edge[i - 1], edge[i]]` bins and
+                /// proportions are floored at a small epsilon so empty bins do not produce
+                /// infinities. Returns NaN if either sketch is empty or `num_bins` is zero.
+                pub fn psi(&self, baseline: &Self, num_bins: usize) -> f64 {
+                    if self.is_empty() || baseline.is_empty() || num_bins == 0 {
+                        return f64::NAN;
+                    }
+
+                    let fractions: Vec<f64> = (1..num_bins).map(|i| i as f64 / num_bins as f64).collect();
+                    let mut edges = baseline.get_quantiles(&fractions);
+                    edges.dedup();
+
+                    let proportions = |sketch: &Self| {
+                        let mut previous = 0.0;
+                        let mut bins: Vec<f64> = edges
+                            .iter()
+                            .map(|&edge| {
+                                let rank = sketch.get_rank(edge);
+                                let mass = rank - previous;
+                                previous = rank;
+                                mass
+                            })
+                            .collect();
+                        bins.push(1.0 - previous);
+                        bins
+                    };
+
+                    proportions(self)
+                        .into_iter()
+                        .zip(proportions(baseline))
+                        .map(|(actual, expected)| {
+                            let actual = actual.max(PSI_EPSILON);
+                            let expected = expected.max(PSI_EPSILON);
+                            (actual - expected) * (actual / expected).ln()
+                        })
+                        .sum()
+                }
+
+                /// Registers a callback invoked as `(level, items_compacted)` whenever an
+                /// update compacts a level.
+                ///
+                /// Replaces any previous callback. Compactions caused by `merge` are not
+                /// reported, and copies of the sketch start without a callback. Updates that
+                /// compact, and merges, scan the retained items of an observed sketch; other
+                /// updates cost no more than without a callback. The callback must not panic.
+                pub fn on_compaction<F>(&mut self, callback: F)
+                where
+                    F: FnMut(u8, u32) + Send + 'static,
+                {
+                    let mut observer = CompactionObserver::new(callback);
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_set_compaction_callback_checked>](
+                            self.native_mut(),
+                            Some(compaction_trampoline),
+                            observer.user_data(),
+                            status,
+                        );
+                    });
+                    self.observer = Some(observer);
+                }
+
+                /// Removes the compaction callback, if any.
+                pub fn clear_compaction_callback(&mut self) {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_set_compaction_callback_checked>](
+                            self.native_mut(),
+                            None,
+                            std::ptr::null_mut(),
+                            status,
+                        );
+                    });
+                    self.observer = None;
+                }
+
+                /// Returns the number of compactions this sketch has performed.
+                ///
+                /// Counts include those recorded by sketches merged into this one. A merge
+                /// that has to compact counts as a single compaction, and a deserialized
+                /// or copied sketch starts from the count it was created with (zero for
+                /// deserialized sketches, since the wire format does not record it).
+                pub fn compactions_performed(&self) -> u64 {
+                    self.compactions
+                }
+
+                /// Returns the number of input values dropped by compaction.
+                ///
+                /// Each compaction keeps half of the items it processes, so this is the
+                /// difference between `get_n` and `get_num_retained`.
+                pub fn items_discarded(&self) -> u64 {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_items_discarded_checked>](self.native(), status)
+                    })
+                }
+
+                /// Returns the capacity of each level, lowest level first.
+                pub fn level_capacities(&self) -> Vec<u32> {
+                    let mut capacities = vec![0u32; u8::MAX as usize];
+                    let num_levels = infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_level_capacities_checked>](
+                            self.native(),
+                            capacities.as_mut_ptr(),
+                            u8::MAX,
+                            status,
+                        )
+                    });
+                    check_capacity("get_level_capacities", num_levels as usize, capacities.len());
+                    capacities.truncate(num_levels as usize);
+                    capacities
+                }
+
+                /// Gives this sketch its own compaction seed.
+                ///
+                /// A seeded sketch reseeds the thread's generator from its seed and
+                /// compaction count before every update and merge, so the same input
+                /// always produces byte-identical sketches regardless of what other
+                /// sketches or threads do. The reseeding makes updates noticeably slower;
+                /// this is intended for regression tests and replay. Copies keep the seed.
+                ///
+                /// The thread's generator is left as the last reseeding put it, not
+                /// restored: unseeded sketches updated afterwards on the same thread no
+                /// longer follow the sequence set with
+                /// [`set_compaction_seed`](crate::set_compaction_seed).
+                pub fn set_seed(&mut self, seed: u64) {
+                    self.seed = Some(seed);
+                }
+
+                /// Returns the seed set with `set_seed`, if any.
+                pub fn seed(&self) -> Option<u64> {
+                    self.seed
+                }
+
+                fn reseed(&self) {
+                    if let Some(seed) = self.seed {
+                        reseed(seed, self.compactions);
+                    }
+                }
+
+                /// Returns the fraction of values at or below `threshold`.
+                ///
+                /// This is the inclusive rank, so a value equal to the threshold counts as
+                /// under it, and `fraction_under(t) + fraction_over(t)` is always 1.
+                /// Returns NaN if the sketch is empty.
+                pub fn fraction_under(&self, threshold: $item) -> f64 {
+                    self.get_rank(threshold)
+                }
+
+                /// Returns the fraction of values strictly above `threshold`.
+                ///
+                /// Returns NaN if the sketch is empty.
+                pub fn fraction_over(&self, threshold: $item) -> f64 {
+                    1.0 - self.get_rank(threshold)
+                }
+
+                /// Renders count and percentiles as text, laid out by `format`.
+                ///
+                /// See [`SummaryFormat`] for the available options.
+                pub fn format_summary(&self, format: &SummaryFormat) -> String {
+                    let quantiles: Vec<f64> = self
+                        .get_quantiles(format.fractions())
+                        .into_iter()
+                        .map(SketchItem::to_f64)
+                        .collect();
+                    format.render(
+                        self.get_n(),
+                        self.get_min_value().to_f64(),
+                        self.get_max_value().to_f64(),
+                        &quantiles,
+                    )
+                }
+
+                /// Formats the sketch as one InfluxDB line protocol point.
+                ///
+                /// `timestamp` is in nanoseconds since the Unix epoch; without one the
+                /// server assigns its own time. See [`write_line_protocol`](Self::write_line_protocol).
+                pub fn to_line_protocol(
+                    &self,
+                    measurement: &str,
+                    tags: &[(&str, &str)],
+                    fractions: &[f64],
+                    timestamp: Option<i64>,
+                ) -> String {
+                    let mut out = Vec::new();
+                    self.write_line_protocol(&mut out, measurement, tags, fractions, timestamp)
+                        .expect("writing to a Vec cannot fail");
+                    String::from_utf8(out).expect("line protocol is built from UTF-8 strings")
+                }
+
+                /// Writes the sketch as one InfluxDB line protocol point, with fields for
+                /// the count, minimum, maximum and the quantile at each fraction, named
+                /// like `p99` or `p99_9`.
+                pub fn write_line_protocol<W: Write + ?Sized>(
+                    &self,
+                    out: &mut W,
+                    measurement: &str,
+                    tags: &[(&str, &str)],
+                    fractions: &[f64],
+                    timestamp: Option<i64>,
+                ) -> std::io::Result<()> {
+                    let quantiles = self.get_quantiles(fractions).into_iter().map(SketchItem::to_f64);
+                    Point {
+                        measurement,
+                        tags: tags.to_vec(),
+                        n: self.get_n(),
+                        min: self.get_min_value().to_f64(),
+                        max: self.get_max_value().to_f64(),
+                        quantiles: fractions.iter().copied().zip(quantiles).collect(),
+                        timestamp,
+                    }
+                    .write(out)
+                }
+
+                /// Finds the peaks of the distribution, most probable first.
+                ///
+                /// The range from min to max is cut into `resolution` equal bins whose
+                /// masses are read from the CDF. A bin is reported as a mode if it rises
+                /// above the valleys separating it from any higher bin by more than the PMF
+                /// error bound of the sketch, so ripples from the approximation are not
+                /// mistaken for peaks. Each mode is the center of its bin and the fraction
+                /// of values in that bin.
+                ///
+                /// Finer grids separate close modes but spread each one over more, lighter
+                /// bins; 50 to 200 bins suit most latency distributions. Returns nothing for
+                /// an empty sketch or a zero `resolution`.
+                pub fn estimate_modes(&self, resolution: u32, max_modes: usize) -> Vec<(f64, f64)> {
+                    if self.is_empty() || resolution == 0 || max_modes == 0 {
+                        return vec![];
+                    }
+                    let min = self.get_min_value().to_f64();
+                    let max = self.get_max_value().to_f64();
+                    if max <= min {
+                        return vec![(min, 1.0)];
+                    }
+
+                    let width = (max - min) / resolution as f64;
+                    let mut splits: Vec<$item> = (1..resolution)
+                        .map(|i| $item::from_f64(min + width * i as f64))
+                        .collect();
+                    // Rounding to the item type can merge neighbouring split points.
+                    splits.dedup();
+                    let cdf = self.get_cdf(&splits);
+
+                    let mut bounds = vec![min];
+                    bounds.extend(splits.iter().map(|split| split.to_f64()));
+                    bounds.push(max);
+                    let mut previous = 0.0;
+                    let mass: Vec<f64> = cdf
+                        .iter()
+                        .map(|&rank| {
+                            let bin = rank - previous;
+                            previous = rank;
+                            bin
+                        })
+                        .collect();
+
+                    let threshold = if self.is_estimation_mode() {
+                        self.get_normalized_rank_error(true)
+                    } else {
+                        0.0
+                    };
+                    let mut modes: Vec<(f64, f64)> = (0..mass.len())
+                        .filter(|&i| peak_prominence(&mass, i).is_some_and(|p| p > threshold))
+                        .map(|i| ((bounds[i] + bounds[i + 1]) / 2.0, mass[i]))
+                        .collect();
+                    modes.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    modes.truncate(max_modes);
+                    modes
+                }
+
+                /// Renders the distribution as a text histogram `width` bins wide and `height` rows tall.
+                ///
+                /// Bins split the range from min to max evenly; a final line labels the
+                /// range. Returns an empty string for an empty sketch.
+                pub fn render_ascii_histogram(&self, width: usize, height: usize) -> String {
+                    if self.is_empty() {
+                        return String::new();
+                    }
+                    ascii::histogram(
+                        |value| self.get_rank(SketchItem::from_f64(value)),
+                        self.get_min_value().to_f64(),
+                        self.get_max_value().to_f64(),
+                        width,
+                        height,
+                    )
+                }
+
+                /// Renders the CDF from min to max as a one-line sparkline of `width` characters.
+                ///
+                /// Returns an empty string for an empty sketch.
+                pub fn cdf_sparkline(&self, width: usize) -> String {
+                    if self.is_empty() {
+                        return String::new();
+                    }
+                    ascii::sparkline(
+                        |value| self.get_rank(SketchItem::from_f64(value)),
+                        self.get_min_value().to_f64(),
+                        self.get_max_value().to_f64(),
+                        width,
+                    )
+                }
+
+                /// Runs `f`, records how long it took in milliseconds, and returns its result.
+                pub fn time<R, F: FnOnce() -> R>(&mut self, f: F) -> R {
+                    let start = Instant::now();
+                    let result = f();
+                    self.update(SketchItem::from_f64(elapsed_millis(start)));
+                    result
+                }
+
+                /// Awaits `future`, records how long it took in milliseconds, and returns its output.
+                ///
+                /// The clock starts at the first poll, so building the future is not timed.
+                pub async fn time_async<F: Future>(&mut self, future: F) -> F::Output {
+                    let start = Instant::now();
+                    let output = future.await;
+                    self.update(SketchItem::from_f64(elapsed_millis(start)));
+                    output
+                }
+
+                /// Returns the accumulated sketch and leaves an empty one with the same settings in its place.
+                ///
+                /// The replacement keeps k, the seed, the memory budget and any compaction
+                /// callback. With `&mut self` no update can land between reading and
+                /// resetting, so interval deltas taken this way never drop values.
+                pub fn swap_and_reset(&mut self) -> Self {
+                    let mut fresh = Self::unallocated(self.k);
+                    fresh.seed = self.seed;
+                    fresh.memory_budget = self.memory_budget;
+                    let observer = self.observer.take();
+                    if observer.is_some() {
+                        infallible(|status| unsafe {
+                            [<kll_ $t _sketch_set_compaction_callback_checked>](
+                                self.native_mut(),
+                                None,
+                                std::ptr::null_mut(),
+                                status,
+                            );
+                        });
+                    }
+                    let taken = std::mem::replace(self, fresh);
+                    if let Some(mut observer) = observer {
+                        infallible(|status| unsafe {
+                            [<kll_ $t _sketch_set_compaction_callback_checked>](
+                                self.native_mut(),
+                                Some(compaction_trampoline),
+                                observer.user_data(),
+                                status,
+                            );
+                        });
+                        self.observer = Some(observer);
+                    }
+                    taken
+                }
+            }
+
+            impl Default for $Sketch {
+                /// Creates an empty sketch with the default k, as `new` does.
+                ///
+                /// This never allocates, so it cannot fail; use `try_default` to allocate
+                /// up front.
+                fn default() -> Self {
+                    Self::new()
+                }
+            }
+
+            impl NativeItem for $item {
+                unsafe fn delete(ptr: std::ptr::NonNull<c_void>) {
+                    // Deleting cannot fail for a live handle, and Drop has nowhere to report it.
+                    let _ = checked(|status| [<kll_ $t _sketch_delete_checked>](ptr.as_ptr(), status));
+                }
+            }
+
+            #[cfg(feature = "zeroize")]
+            impl Drop for $Sketch {
+                fn drop(&mut self) {
+                    zeroize::Zeroize::zeroize(&mut self.buffer);
+                }
+            }
+
+            // The native handle is Send and Sync on its own; the observer is only
+            // called back from methods taking `&mut self`, so sharing the sketch never runs it.
+            unsafe impl Sync for $Sketch {}
+
+            impl Clone for $Sketch {
+                /// Creates a deep copy of the sketch, as `try_clone` does.
+                ///
+                /// # Panics
+                ///
+                /// Panics if the native copy cannot be allocated; use `try_clone` where
+                /// that has to be handled.
+                fn clone(&self) -> Self {
+                    self.try_clone()
+                        .unwrap_or_else(|err| panic!("failed to clone sketch: {}", err))
+                }
+            }
+
+            // Implement Serialize and Deserialize for serde support
+            impl Serialize for $Sketch {
+                fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+                where
+                    S: serde::Serializer,
+                {
+                    let bytes = self.serialize().map_err(serde::ser::Error::custom)?;
+                    let description = Description {
+                        type_name: concat!("kll_", stringify!($t)),
+                        k: self.get_k(),
+                        n: self.get_n(),
+                    };
+                    serialize_sketch(serializer, description, &bytes)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $Sketch {
+                fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+                where
+                    D: serde::Deserializer<'de>,
+                {
+                    let described = deserialize_sketch(deserializer, concat!("kll_", stringify!($t)))?;
+                    let sketch = Self::deserialize(&described.bytes).map_err(serde::de::Error::custom)?;
+                    match described.claimed {
+                        Some((k, n)) if (k, n) != (sketch.get_k(), sketch.get_n()) => {
+                            Err(serde::de::Error::custom(format!(
+                                "payload holds k={} and n={} but the object claims k={} and n={}",
+                                sketch.get_k(),
+                                sketch.get_n(),
+                                k,
+                                n
+                            )))
+                        }
+                        _ => Ok(sketch),
+                    }
+                }
+            }
+
+            impl RawSerde for $Sketch {
+                fn raw_bytes(&self) -> Result<Vec<u8>> {
+                    self.serialize()
+                }
+            }
+
+            impl TryFrom<&[u8]> for $Sketch {
+                type Error = DataSketchesError;
+
+                #[doc = concat!("Deserializes a sketch, as [`", stringify!($Sketch), "::deserialize`].")]
+                fn try_from(bytes: &[u8]) -> Result<Self> {
+                    Self::deserialize(bytes)
+                }
+            }
+
+            impl TryFrom<&$Sketch> for Vec<u8> {
+                type Error = DataSketchesError;
+
+                #[doc = concat!("Serializes a sketch, as [`", stringify!($Sketch), "::serialize`].")]
+                fn try_from(sketch: &$Sketch) -> Result<Self> {
+                    sketch.serialize()
+                }
+            }
+
+            impl QuantileSketch for $Sketch {
+                type Item = $item;
+
+                fn update(&mut self, value: $item) {
+                    $Sketch::update(self, value)
+                }
+
+                fn merge(&mut self, other: &Self) -> Result<()> {
+                    $Sketch::merge(self, other)
+                }
+
+                fn is_empty(&self) -> bool {
+                    $Sketch::is_empty(self)
+                }
+
+                fn get_n(&self) -> u64 {
+                    $Sketch::get_n(self)
+                }
+
+                fn get_quantile(&self, fraction: f64) -> $item {
+                    $Sketch::get_quantile(self, fraction)
+                }
+
+                fn get_quantiles(&self, fractions: &[f64]) -> Vec<$item> {
+                    $Sketch::get_quantiles(self, fractions)
+                }
+
+                fn get_rank(&self, value: $item) -> f64 {
+                    $Sketch::get_rank(self, value)
+                }
+            }
+
+            impl Mergeable for $Sketch {
+                fn empty_like(&self) -> Self {
+                    $Sketch::new_with_k(self.get_k()).expect("k of an existing sketch is valid")
+                }
+
+                fn merge_from(&mut self, other: &Self) -> Result<()> {
+                    $Sketch::merge(self, other)
+                }
+            }
+
+            impl NonEmptySketch<'_, $Sketch> {
+                /// Returns the number of values processed, which is at least one.
+                pub fn n(&self) -> NonZeroU64 {
+                    NonZeroU64::new(self.sketch().get_n()).expect("a non-empty sketch has n > 0")
+                }
+
+                /// Returns the minimum value seen.
+                pub fn min(&self) -> $item {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_min_value_checked>](self.sketch().native(), status)
+                    })
+                }
+
+                /// Returns the maximum value seen.
+                pub fn max(&self) -> $item {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_max_value_checked>](self.sketch().native(), status)
+                    })
+                }
+
+                /// Returns the approximate quantile at `fraction`.
+                pub fn quantile(&self, fraction: Fraction) -> $item {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_quantile_checked>](
+                            self.sketch().native(),
+                            fraction.get(),
+                            status,
+                        )
+                    })
+                }
+
+                /// Returns the approximate quantiles at `fractions`.
+                pub fn quantiles(&self, fractions: &[Fraction]) -> Vec<$item> {
+                    self.sketch().quantiles(fractions)
+                }
+
+                /// Returns the fraction of values less than or equal to `value`.
+                pub fn rank(&self, value: $item) -> f64 {
+                    infallible(|status| unsafe {
+                        [<kll_ $t _sketch_get_rank_checked>](self.sketch().native(), value, status)
+                    })
+                }
+            }
+
+            #[cfg(test)]
+            mod tests {
+                use super::*;
+
+                #[test]
+                fn test_create_sketch() {
+                    let sketch = $Sketch::new();
+                    assert!(sketch.is_empty());
+                    assert_eq!(sketch.get_n(), 0);
+                }
+
+                #[test]
+                fn test_update_and_query() {
+                    let mut sketch = $Sketch::new();
+
+                    for i in 1..=1000 {
+                        sketch.update(i as $item);
+                    }
+
+                    assert!(!sketch.is_empty());
+                    assert_eq!(sketch.get_n(), 1000);
+
+                    let median = sketch.get_quantile(0.5);
+                    assert!((median - 500.0).abs() < 50.0); // Allow some error
+                }
+
+                #[test]
+                fn test_serialization() {
+                    let mut sketch = $Sketch::new();
+
+                    for i in 1..=100 {
+                        sketch.update(i as $item);
+                    }
+
+                    let serialized = sketch.serialize().unwrap();
+                    let deserialized = $Sketch::deserialize(&serialized).unwrap();
+
+                    assert_eq!(sketch.get_n(), deserialized.get_n());
+                    assert_eq!(sketch.get_k(), deserialized.get_k());
+                }
+
+                #[test]
+                fn test_clone() {
+                    let mut original = $Sketch::new();
+
+                    // Add some data to the original sketch
+                    for i in 1..=1000 {
+                        original.update(i as $item);
+                    }
+
+                    // Clone the sketch
+                    let cloned = original.clone();
+
+                    // Verify the clone has the same properties
+                    assert_eq!(original.get_n(), cloned.get_n());
+                    assert_eq!(original.get_k(), cloned.get_k());
+                    assert_eq!(original.get_num_retained(), cloned.get_num_retained());
+                    assert_eq!(original.is_empty(), cloned.is_empty());
+                    assert_eq!(original.is_estimation_mode(), cloned.is_estimation_mode());
+
+                    // Compare some quantiles to ensure data integrity
+                    for fraction in [0.25, 0.5, 0.75, 0.9] {
+                        let original_quantile = original.get_quantile(fraction);
+                        let cloned_quantile = cloned.get_quantile(fraction);
+                        assert!(
+                            (original_quantile - cloned_quantile).abs() < <$item>::EPSILON,
+                            "Quantiles differ: original={}, cloned={}",
+                            original_quantile,
+                            cloned_quantile
+                        );
+                    }
+
+                    // Verify they are independent - modifying one doesn't affect the other
+                    let original_n_before = original.get_n();
+                    let cloned_n_before = cloned.get_n();
+
+                    // Modify the original
+                    original.update(999999.0);
+
+                    // Cloned should remain unchanged
+                    assert_eq!(cloned.get_n(), cloned_n_before);
+                    assert_eq!(original.get_n(), original_n_before + 1);
+                }
+
+                #[test]
+                fn test_exact_values() {
+                    let mut sketch = $Sketch::new_with_k(8).unwrap();
+                    assert_eq!(sketch.exact_values(), Some(vec![]));
+
+                    for i in (1..=5).rev() {
+                        sketch.update(i as $item);
+                    }
+                    assert!(sketch.is_exact());
+                    assert_eq!(sketch.exact_values(), Some(vec![1.0, 2.0, 3.0, 4.0, 5.0]));
+
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+                    assert!(!sketch.is_exact());
+                    assert_eq!(sketch.exact_values(), None);
+                }
+
+                #[test]
+                fn test_error_band() {
+                    let mut sketch = $Sketch::new();
+                    for i in 0..100_000 {
+                        sketch.update(i as $item);
+                    }
+                    let band = sketch.error_band(&[0.1, 0.5, 0.9], 2);
+                    assert_eq!(band.len(), 3);
+                    for (estimate, lower, upper) in band {
+                        assert!(lower <= estimate && estimate <= upper);
+                        assert!(lower < upper);
+                    }
+                    assert!($Sketch::new().error_band(&[0.5], 2).is_empty());
+                }
+
+                #[test]
+                fn test_wasserstein_distance() {
+                    let mut a = $Sketch::new();
+                    let mut b = $Sketch::new();
+                    for i in 0..100 {
+                        a.update(i as $item);
+                        b.update(i as $item + 10.0);
+                    }
+                    assert!(a.wasserstein_distance(&a, 100).abs() < 1e-9);
+                    assert!((a.wasserstein_distance(&b, 100) - 10.0).abs() < 1e-6);
+                    assert!(a.wasserstein_distance(&b, 0).is_nan());
+                }
+
+                #[test]
+                fn test_psi() {
+                    let mut baseline = $Sketch::new();
+                    let mut same = $Sketch::new();
+                    let mut shifted = $Sketch::new();
+                    for i in 0..1000 {
+                        baseline.update(i as $item);
+                        same.update(i as $item);
+                        shifted.update(i as $item + 500.0);
+                    }
+                    assert!(baseline.psi(&baseline, 10).abs() < 1e-12);
+                    assert!(same.psi(&baseline, 10) < 0.1);
+                    assert!(shifted.psi(&baseline, 10) > 0.25);
+                    assert!(shifted.psi(&baseline, 0).is_nan());
+                }
+
+                #[test]
+                fn test_compaction_callback() {
+                    use std::sync::{Arc, Mutex};
+
+                    let events = Arc::new(Mutex::new(Vec::new()));
+                    let sink = events.clone();
+                    let mut sketch = $Sketch::new_with_k(8).unwrap();
+                    sketch.on_compaction(move |level, items| sink.lock().unwrap().push((level, items)));
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+
+                    let seen = events.lock().unwrap().clone();
+                    assert!(!seen.is_empty());
+                    assert_eq!(seen[0].0, 0);
+                    assert!(seen.iter().all(|&(_, items)| items > 0 && items % 2 == 0));
+                    assert_eq!(seen.len() as u64, sketch.compactions_performed());
+
+                    // A merge changes the levels without reporting; later updates
+                    // still report one event per compaction.
+                    let mut other = $Sketch::new_with_k(8).unwrap();
+                    for i in 0..100_000 {
+                        other.update(i as $item);
+                    }
+                    sketch.merge(&other).unwrap();
+                    events.lock().unwrap().clear();
+                    let before = sketch.compactions_performed();
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+                    let seen = events.lock().unwrap().clone();
+                    assert_eq!(seen.len() as u64, sketch.compactions_performed() - before);
+                    assert!(seen.iter().all(|&(_, items)| items > 0 && items % 2 == 0));
+
+                    sketch.clear_compaction_callback();
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+                    assert_eq!(events.lock().unwrap().len(), seen.len());
+                }
+
+                #[test]
+                fn test_compaction_statistics() {
+                    let mut sketch = $Sketch::new_with_k(8).unwrap();
+                    assert_eq!(sketch.compactions_performed(), 0);
+                    assert_eq!(sketch.level_capacities(), vec![8]);
+
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+                    assert!(sketch.compactions_performed() > 0);
+                    // NaN is ignored by the native sketch and must not count as a compaction.
+                    let compactions = sketch.compactions_performed();
+                    for _ in 0..10 {
+                        sketch.update($item::NAN);
+                    }
+                    assert_eq!(sketch.compactions_performed(), compactions);
+                    assert_eq!(sketch.get_n(), 1000);
+                    assert_eq!(
+                        sketch.items_discarded(),
+                        sketch.get_n() - sketch.get_num_retained() as u64
+                    );
+
+                    let capacities = sketch.level_capacities();
+                    assert!(capacities.len() > 1);
+                    assert_eq!(*capacities.last().unwrap(), 8);
+                    assert!(capacities.windows(2).all(|w| w[0] <= w[1]));
+
+                    let mut merged = $Sketch::new_with_k(8).unwrap();
+                    merged.merge(&sketch).unwrap();
+                    assert!(merged.compactions_performed() >= sketch.compactions_performed());
+                }
+
+                #[test]
+                fn test_merge_counts_compactions() {
+                    // Unweighted items are merged in as updates, with the same compactions.
+                    let mut exact = $Sketch::new_with_k(200).unwrap();
+                    let mut updated = $Sketch::new_with_k(8).unwrap();
+                    for i in 0..150 {
+                        exact.update(i as $item);
+                        updated.update(i as $item);
+                    }
+                    let mut merged = $Sketch::new_with_k(8).unwrap();
+                    merged.merge(&exact).unwrap();
+                    assert_eq!(merged.compactions_performed(), updated.compactions_performed());
+
+                    // Fitting a large sketch into k = 8 takes several compactions, though
+                    // the merged sketch retains more items than it did before.
+                    let mut large = $Sketch::new_with_k(200).unwrap();
+                    for i in 0..100_000 {
+                        large.update(i as $item);
+                    }
+                    let mut small = $Sketch::new_with_k(8).unwrap();
+                    for i in 0..100 {
+                        small.update(i as $item);
+                    }
+                    let before = small.compactions_performed() + large.compactions_performed();
+                    let retained = small.get_num_retained();
+                    small.merge_mixed_k(&large).unwrap();
+                    assert!(small.get_num_retained() > retained);
+                    assert!(small.compactions_performed() >= before + 2);
+                }
+
+                #[test]
+                fn test_with_memory_budget() {
+                    let sketch = $Sketch::with_memory_budget(16 * 1024).unwrap();
+                    assert_eq!(sketch.memory_budget(), Some(16 * 1024));
+                    let k = sketch.get_k();
+                    assert!(k > 8);
+
+                    let larger = $Sketch::with_memory_budget(64 * 1024).unwrap();
+                    assert!(larger.get_k() > k);
+                    assert_eq!(larger.clone().memory_budget(), Some(64 * 1024));
+
+                    assert!($Sketch::with_memory_budget(64).is_err());
+                    assert_eq!($Sketch::new().memory_budget(), None);
+                }
+
+                #[test]
+                fn test_new_is_lazy() {
+                    let sketch = $Sketch::default();
+                    assert!(sketch.is_empty());
+                    assert_eq!(sketch.get_k(), 200);
+                    assert!(sketch.native.get().is_none());
+                    assert!(sketch.get_quantile(0.5).is_nan());
+                    assert!(sketch.native.get().is_none());
+                }
+
+                #[test]
+                fn test_inline_buffer() {
+                    let mut sketch = $Sketch::new();
+                    for i in 1..=5 {
+                        sketch.update(i as $item);
+                    }
+                    sketch.update($item::NAN);
+                    assert!(sketch.native.get().is_none());
+                    assert_eq!(sketch.get_n(), 5);
+                    assert_eq!(sketch.get_num_retained(), 5);
+                    assert!(sketch.is_exact());
+                    assert!(!sketch.is_estimation_mode());
+                    assert!(sketch.native.get().is_none());
+
+                    let copy = sketch.clone();
+                    let mut merged = $Sketch::new();
+                    merged.merge(&copy).unwrap();
+                    assert!(merged.native.get().is_none());
+                    assert_eq!(merged.get_n(), 5);
+
+                    // A query flushes the buffer into the native sketch.
+                    assert_eq!(sketch.get_quantile(1.0), 5.0);
+                    assert!(sketch.native.get().is_some());
+                    sketch.update(6.0);
+                    assert!(sketch.buffer.is_empty());
+                    assert_eq!(sketch.get_n(), 6);
+
+                    // So does filling it.
+                    for i in 0..=INLINE_CAPACITY {
+                        merged.update(i as $item);
+                    }
+                    assert!(merged.native.get().is_some());
+                    assert_eq!(merged.get_n(), 5 + INLINE_CAPACITY as u64 + 1);
+                }
+
+                #[test]
+                fn test_seeded_sketches_are_identical() {
+                    let build = |seed: u64| {
+                        let mut sketch = $Sketch::new_with_k(16).unwrap();
+                        sketch.set_seed(seed);
+                        for i in 0..5_000 {
+                            sketch.update(((i * 7919) % 5_000) as $item);
+                        }
+                        sketch.serialize().unwrap()
+                    };
+                    assert_eq!(build(42), build(42));
+                    assert_ne!(build(42), build(43));
+
+                    let unseeded = || {
+                        let mut sketch = $Sketch::new_with_k(16).unwrap();
+                        for i in 0..5_000 {
+                            sketch.update(i as $item);
+                        }
+                        sketch.serialize().unwrap()
+                    };
+                    crate::set_compaction_seed(7);
+                    let first = unseeded();
+                    crate::set_compaction_seed(7);
+                    assert_eq!(unseeded(), first);
+                }
+
+                #[test]
+                fn test_fraction_over_under() {
+                    let mut sketch = $Sketch::new();
+                    assert!(sketch.fraction_over(1.0).is_nan());
+                    for i in 1..=100 {
+                        sketch.update(i as $item);
+                    }
+                    assert_eq!(sketch.fraction_under(90.0), 0.9);
+                    assert!((sketch.fraction_over(90.0) - 0.1).abs() < 1e-12);
+                    assert_eq!(sketch.fraction_over(100.0), 0.0);
+                    assert_eq!(sketch.fraction_under(0.0), 0.0);
+                }
+
+                #[test]
+                fn test_weighted_values_and_expansion() {
+                    let mut sketch = $Sketch::new_with_k(8).unwrap();
+                    assert!(sketch.to_weighted_values().is_empty());
+                    assert!(sketch.expand_approximate(10).is_empty());
+
+                    for value in [3.0, 1.0, 3.0, 2.0] {
+                        sketch.update(value);
+                    }
+                    assert_eq!(
+                        sketch.to_weighted_values(),
+                        vec![(1.0, 1), (2.0, 1), (3.0, 2)]
+                    );
+                    assert_eq!(sketch.expand_approximate(10), vec![1.0, 2.0, 3.0, 3.0]);
+                    assert_eq!(sketch.expand_approximate(2), vec![1.0, 3.0]);
+
+                    for i in 0..10_000 {
+                        sketch.update(i as $item);
+                    }
+                    let pairs = sketch.to_weighted_values();
+                    assert_eq!(pairs.iter().map(|p| p.1).sum::<u64>(), sketch.get_n());
+                    assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0));
+
+                    let points = sketch.expand_approximate(100);
+                    assert_eq!(points.len(), 100);
+                    assert!(points.windows(2).all(|w| w[0] <= w[1]));
+                    assert!((points[50] - 5_000.0).abs() < 500.0);
+                }
+
+                #[test]
+                fn test_from_weighted_items() {
+                    let mut sketch = $Sketch::new_with_k(64).unwrap();
+                    for i in 0..50_000 {
+                        sketch.update((i % 997) as $item);
+                    }
+                    let pairs = sketch.to_weighted_values();
+                    let restored = $Sketch::from_weighted_items(&pairs, 64).unwrap();
+                    assert_eq!(restored.get_n(), sketch.get_n());
+                    assert_eq!(restored.get_k(), 64);
+                    // The true extremes may have been compacted away; the items' stand in.
+                    assert_eq!(restored.get_min_value(), pairs[0].0);
+                    assert_eq!(restored.get_max_value(), pairs[pairs.len() - 1].0);
+                    assert_eq!(restored.to_weighted_values(), pairs);
+                    for fraction in [0.01, 0.25, 0.5, 0.99] {
+                        assert_eq!(restored.get_quantile(fraction), sketch.get_quantile(fraction));
+                    }
+
+                    // More distinct items than k holds are compacted to fit.
+                    let wide: Vec<($item, u64)> = (0..10_000).map(|i| (i as $item, 3)).collect();
+                    let mut compacted = $Sketch::from_weighted_items(&wide, 8).unwrap();
+                    assert_eq!(compacted.get_n(), 30_000);
+                    assert_eq!(compacted.get_min_value(), 0.0);
+                    assert_eq!(compacted.get_max_value(), 9_999.0);
+                    assert!((compacted.get_quantile(0.5) - 5_000.0).abs() < 1_000.0);
+                    compacted.update(1.0);
+                    assert_eq!(compacted.get_n(), 30_001);
+
+                    assert!($Sketch::from_weighted_items(&[], 8).unwrap().is_empty());
+                    assert_eq!($Sketch::from_weighted_items(&[(2.0, 1)], 8).unwrap().get_n(), 1);
+                    assert!($Sketch::from_weighted_items(&[(1.0, 0)], 8).is_err());
+                    assert!($Sketch::from_weighted_items(&[(<$item>::NAN, 1)], 8).is_err());
+                    assert!($Sketch::from_weighted_items(&[(1.0, u64::MAX), (2.0, 1)], 8).is_err());
+                    assert!($Sketch::from_weighted_items(&[(1.0, 1)], 1).is_err());
+                }
+
+                #[test]
+                fn test_byte_conversions() {
+                    let mut sketch = $Sketch::new();
+                    for i in 1..=10 {
+                        sketch.update(i as $item);
+                    }
+                    let bytes = Vec::<u8>::try_from(&sketch).unwrap();
+                    assert_eq!(bytes, sketch.serialize().unwrap());
+
+                    let restored = $Sketch::try_from(bytes.as_slice()).unwrap();
+                    assert_eq!(restored.get_n(), 10);
+                    assert!($Sketch::try_from(&[1u8, 2, 3][..]).is_err());
+                }
+
+                #[test]
+                fn test_time_closures() {
+                    let mut sketch = $Sketch::new();
+                    let value = sketch.time(|| {
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        42
+                    });
+                    assert_eq!(value, 42);
+                    assert_eq!(sketch.get_n(), 1);
+                    assert!(sketch.get_min_value() >= 5.0);
+
+                    {
+                        use std::task::{RawWaker, RawWakerVTable, Waker};
+                        // `Waker::noop` needs a newer compiler than the MSRV.
+                        const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+                        const RAW: RawWaker = RawWaker::new(std::ptr::null(), &VTABLE);
+                        let waker = unsafe { Waker::from_raw(RAW) };
+                        let mut future = std::pin::pin!(sketch.time_async(async { "done" }));
+                        let mut cx = std::task::Context::from_waker(&waker);
+                        assert_eq!(
+                            future.as_mut().poll(&mut cx),
+                            std::task::Poll::Ready("done")
+                        );
+                    }
+                    assert_eq!(sketch.get_n(), 2);
+                }
+
+                #[test]
+                fn test_swap_and_reset() {
+                    use std::sync::atomic::{AtomicUsize, Ordering};
+                    use std::sync::Arc;
+
+                    let fired = Arc::new(AtomicUsize::new(0));
+                    let counter = fired.clone();
+                    let mut sketch = $Sketch::new_with_k(8).unwrap();
+                    sketch.set_seed(7);
+                    sketch.on_compaction(move |_, _| {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    });
+                    for i in 0..100 {
+                        sketch.update(i as $item);
+                    }
+
+                    let interval = sketch.swap_and_reset();
+                    assert_eq!(interval.get_n(), 100);
+                    assert!(sketch.is_empty());
+                    assert_eq!(sketch.get_k(), 8);
+                    assert_eq!(sketch.seed(), Some(7));
+
+                    let before = fired.load(Ordering::Relaxed);
+                    for i in 0..100 {
+                        sketch.update(i as $item);
+                    }
+                    assert!(fired.load(Ordering::Relaxed) > before);
+                }
+
+                #[test]
+                fn test_quantiles_into() {
+                    let mut sketch = $Sketch::new();
+                    let mut out = [0.0; 3];
+                    sketch
+                        .get_quantiles_into(&[0.0, 0.5, 1.0], &mut out)
+                        .unwrap();
+                    assert!(out.iter().all(|q| q.is_nan()));
+
+                    for i in 1..=5 {
+                        sketch.update(i as $item);
+                    }
+                    sketch
+                        .get_quantiles_into(&[0.0, 0.5, 1.0], &mut out)
+                        .unwrap();
+                    assert_eq!(out, [1.0, 3.0, 5.0]);
+                    sketch.get_quantiles_evenly_spaced_into(&mut out).unwrap();
+                    assert_eq!(out, [1.0, 3.0, 5.0]);
+                    sketch.get_quantiles_evenly_spaced_into(&mut []).unwrap();
+
+                    assert!(sketch.get_quantiles_into(&[0.5], &mut out).is_err());
+                    assert!(sketch
+                        .get_quantiles_into(&[0.5, 1.5, 1.0], &mut out)
+                        .is_err());
+                }
+
+                #[test]
+                fn test_search_criteria() {
+                    let mut sketch = $Sketch::new();
+                    assert!(sketch.get_rank_with(1.0, SearchCriteria::Exclusive).is_nan());
+                    for value in [1.0, 2.0, 2.0, 3.0] {
+                        sketch.update(value);
+                    }
+                    assert_eq!(
+                        sketch.get_rank_with(2.0, SearchCriteria::Inclusive),
+                        sketch.get_rank(2.0)
+                    );
+                    assert_eq!(sketch.get_rank_with(2.0, SearchCriteria::Exclusive), 0.25);
+                    assert_eq!(sketch.get_rank_with(1.0, SearchCriteria::Exclusive), 0.0);
+                    assert_eq!(sketch.get_quantile_with(0.5, SearchCriteria::Inclusive), 2.0);
+                    assert_eq!(sketch.get_quantile_with(0.5, SearchCriteria::Exclusive), 2.0);
+                    assert_eq!(sketch.get_quantile_with(0.75, SearchCriteria::Exclusive), 3.0);
+                    assert_eq!(sketch.get_quantile_with(1.0, SearchCriteria::Exclusive), 3.0);
+                    assert!(sketch.get_quantile_with(1.5, SearchCriteria::Exclusive).is_nan());
+
+                    let fractions = [0.0, 0.25, 0.75];
+                    assert_eq!(
+                        sketch.get_quantiles_with(&fractions, SearchCriteria::Inclusive),
+                        sketch.get_quantiles(&fractions)
+                    );
+                    assert_eq!(
+                        sketch.get_quantiles_with(&fractions, SearchCriteria::Exclusive),
+                        vec![1.0, 2.0, 3.0]
+                    );
+                }
+
+                #[test]
+                fn test_pmf_and_cdf() {
+                    let mut sketch = $Sketch::new();
+                    assert!(sketch.get_pmf(&[1.0]).is_empty());
+                    for i in 1..=10 {
+                        sketch.update(i as $item);
+                    }
+                    let splits = [2.0, 5.0, 9.0];
+                    let pmf = sketch.get_pmf(&splits);
+                    for (p, expected) in pmf.iter().zip([0.2, 0.3, 0.4, 0.1]) {
+                        assert!((p - expected).abs() < 1e-9, "{:?}", pmf);
+                    }
+                    let cdf = sketch.get_cdf(&splits);
+                    assert_eq!(cdf, vec![0.2, 0.5, 0.9, 1.0]);
+                    for (split, rank) in splits.iter().zip(&cdf) {
+                        assert_eq!(sketch.get_rank(*split), *rank);
+                    }
+                    assert_eq!(sketch.get_cdf(&[]), vec![1.0]);
+
+                    assert!(sketch.get_pmf(&[5.0, 2.0]).iter().all(|p| p.is_nan()));
+                    let cdf = sketch.get_cdf(&[2.0, 2.0, $item::NAN]);
+                    assert!(cdf.len() == 4 && cdf.iter().all(|p| p.is_nan()));
+                    assert!(sketch.get_cdf(&[$item::NAN]).iter().all(|p| p.is_nan()));
+                }
+
+                #[test]
+                fn test_serialize_to_slice() {
+                    let mut sketch = $Sketch::new_with_k(8).unwrap();
+                    let mut buffer = [0u8; 4096];
+                    for n in 0..200 {
+                        let bytes = sketch.serialize().unwrap();
+                        assert_eq!(sketch.get_serialized_size_bytes(), bytes.len());
+                        assert_eq!(sketch.serialize_to_slice(&mut buffer).unwrap(), bytes.len());
+                        assert_eq!(&buffer[..bytes.len()], &bytes[..]);
+                        sketch.update(n as $item);
+                    }
+
+                    let needed = sketch.get_serialized_size_bytes();
+                    match sketch.serialize_to_slice(&mut buffer[..needed - 1]) {
+                        Err(DataSketchesError::BufferTooSmall {
+                            needed: n,
+                            available,
+                        }) => {
+                            assert_eq!((n, available), (needed, needed - 1));
+                        }
+                        other => panic!("expected BufferTooSmall, got {:?}", other),
+                    }
+                }
+
+                #[test]
+                fn test_with_k_param() {
+                    let sketch = $Sketch::with_k(KParam::MIN);
+                    assert_eq!(sketch.get_k(), 8);
+                    assert!($Sketch::new_with_k(7).is_err());
+                    assert_eq!(
+                        $Sketch::new_with_k(u16::MAX).unwrap().get_k(),
+                        u16::MAX
+                    );
+                }
+
+                #[test]
+                fn test_validated_fractions() {
+                    let mut sketch = $Sketch::new();
+                    assert!(sketch.quantile(Fraction::MEDIAN).is_nan());
+                    assert!(sketch.quantiles(&[Fraction::MEDIAN]).is_empty());
+                    for i in 1..=5 {
+                        sketch.update(i as $item);
+                    }
+                    assert_eq!(sketch.quantile(Fraction::MEDIAN), 3.0);
+                    let fractions = [Fraction::ZERO, Fraction::new(0.8).unwrap(), Fraction::ONE];
+                    assert_eq!(sketch.quantiles(&fractions), vec![1.0, 4.0, 5.0]);
+                }
+
+                #[test]
+                fn test_as_non_empty() {
+                    let mut sketch = $Sketch::new();
+                    assert!(sketch.as_non_empty().is_none());
+                    for i in 1..=5 {
+                        sketch.update(i as $item);
+                    }
+                    let view = sketch.as_non_empty().unwrap();
+                    assert_eq!(view.n().get(), 5);
+                    assert_eq!((view.min(), view.max()), (1.0, 5.0));
+                    assert_eq!(view.quantile(Fraction::MEDIAN), 3.0);
+                    assert_eq!(view.quantiles(&[Fraction::ONE]), vec![5.0]);
+                    assert_eq!(view.rank(2.0), 0.4);
+                }
+
+                #[test]
+                fn test_estimate_count_in_range() {
+                    assert_eq!(SketchItem::next_below(0.0 as $item), -$item::from_bits(1));
+                    assert_eq!(SketchItem::next_below(1.0 as $item).to_bits(), (1.0 as $item).to_bits() - 1);
+                    assert_eq!(SketchItem::next_below(-1.0 as $item).to_bits(), (-1.0 as $item).to_bits() + 1);
+                    assert_eq!(SketchItem::next_below($item::INFINITY), $item::MAX);
+                    assert_eq!(SketchItem::next_below($item::NEG_INFINITY), $item::NEG_INFINITY);
+
+                    let mut sketch = $Sketch::new();
+                    assert_eq!(sketch.estimate_count_in_range(0.0, 1.0, RangeInclusivity::Closed), 0);
+                    for i in 1..=100 {
+                        sketch.update(i as $item);
+                        sketch.update(i as $item);
+                    }
+                    let count = |low, high, inclusive| sketch.estimate_count_in_range(low, high, inclusive);
+                    assert_eq!(count(10.0, 20.0, RangeInclusivity::Closed), 22);
+                    assert_eq!(count(10.0, 20.0, RangeInclusivity::ClosedOpen), 20);
+                    assert_eq!(count(10.0, 20.0, RangeInclusivity::OpenClosed), 20);
+                    assert_eq!(count(10.0, 20.0, RangeInclusivity::Open), 18);
+                    assert_eq!(count(10.5, 20.5, RangeInclusivity::Open), 20);
+                    assert_eq!(count(5.0, 5.0, RangeInclusivity::Closed), 2);
+                    assert_eq!(count(5.0, 5.0, RangeInclusivity::ClosedOpen), 0);
+                    assert_eq!(count(-10.0, 1000.0, RangeInclusivity::Open), 200);
+                    assert_eq!(count(20.0, 10.0, RangeInclusivity::Closed), 0);
+                    assert_eq!(count(f64::NAN, 10.0, RangeInclusivity::Closed), 0);
+                }
+
+                #[test]
+                fn test_suggest_buckets() {
+                    let mut sketch = $Sketch::new();
+                    assert!(sketch.suggest_buckets(4, BucketStrategy::EquiDepth).is_empty());
+                    for i in 1..=100 {
+                        sketch.update(i as $item);
+                    }
+                    assert_eq!(
+                        sketch.suggest_buckets(4, BucketStrategy::EquiDepth),
+                        vec![25.0, 50.0, 75.0, 100.0]
+                    );
+                    assert!(sketch.suggest_buckets(0, BucketStrategy::EquiDepth).is_empty());
+
+                    sketch.update(0.0);
+                    let log = sketch.suggest_buckets(2, BucketStrategy::LogSpaced);
+                    assert_eq!(log.len(), 2);
+                    assert!((log[0] - 10.0).abs() < 1e-3 && log[1] == 100.0, "{:?}", log);
+
+                    let mut constant = $Sketch::new();
+                    for _ in 0..50 {
+                        constant.update(3.0);
+                    }
+                    assert_eq!(constant.suggest_buckets(5, BucketStrategy::EquiDepth), vec![3.0]);
+                    assert_eq!(constant.suggest_buckets(5, BucketStrategy::LogSpaced), vec![3.0]);
+                }
+
+                #[cfg(feature = "zeroize")]
+                #[test]
+                fn test_serialize_zeroizing() {
+                    let mut sketch = $Sketch::new();
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+                    let bytes = sketch.serialize_zeroizing().unwrap();
+                    assert_eq!(*bytes, sketch.serialize().unwrap());
+                    assert_eq!($Sketch::deserialize(&bytes).unwrap().get_n(), 1000);
+                }
+
+                #[test]
+                fn test_merge_rejects_k_mismatch() {
+                    let mut sketch = $Sketch::new_with_k(200).unwrap();
+                    let mut other = $Sketch::new_with_k(64).unwrap();
+                    sketch.merge(&other).unwrap();
+                    for i in 0..1000 {
+                        other.update(i as $item);
+                    }
+                    match sketch.merge(&other) {
+                        Err(DataSketchesError::IncompatibleMerge(message)) => {
+                            assert!(message.contains("k=64") && message.contains("merge_mixed_k"), "{}", message)
+                        }
+                        other => panic!("unexpected {:?}", other),
+                    }
+                    assert!(sketch.is_empty());
+                    sketch.merge_mixed_k(&other).unwrap();
+                    assert_eq!((sketch.get_k(), sketch.get_n()), (200, 1000));
+
+                    // Exact sketches merge whatever their k, even once a query has
+                    // allocated their native sketch.
+                    let mut exact = $Sketch::new_with_k(64).unwrap();
+                    for i in 0..50 {
+                        exact.update(i as $item);
+                    }
+                    let queried = exact.clone();
+                    queried.get_quantile(0.5);
+                    assert!(!queried.is_estimation_mode());
+                    let mut target = $Sketch::new_with_k(200).unwrap();
+                    target.merge(&exact).unwrap();
+                    target.merge(&queried).unwrap();
+                    assert_eq!(target.get_n(), 100);
+
+                    let mut bytes = $Sketch::new().serialize().unwrap();
+                    bytes[2] = 7;
+                    match $Sketch::deserialize(&bytes) {
+                        Err(DataSketchesError::DeserializationError(message)) => {
+                            assert!(message.contains("HLL"), "{}", message)
+                        }
+                        other => panic!("unexpected {:?}", other.map(|s| s.get_n())),
+                    }
+                }
+
+                #[test]
+                fn test_default_is_lazy() {
+                    #[derive(Default)]
+                    struct Holder {
+                        sketch: $Sketch,
+                    }
+                    static EMPTY: $Sketch = $Sketch::with_k(KParam::MIN);
+                    assert_eq!(EMPTY.get_k(), 8);
+
+                    let mut holder = Holder::default();
+                    assert!(holder.sketch.native.get().is_none());
+                    holder.sketch.update(1.0 as $item);
+                    assert_eq!(holder.sketch.get_n(), 1);
+
+                    let eager = $Sketch::try_default().unwrap();
+                    assert!(eager.native.get().is_some());
+                    assert!(eager.is_empty());
+                }
+
+                #[test]
+                fn test_try_clone() {
+                    let mut sketch = $Sketch::new();
+                    sketch.update(1.0 as $item);
+                    let inline = sketch.try_clone().unwrap();
+                    assert!(inline.native.get().is_none());
+                    assert_eq!(inline.get_n(), 1);
+
+                    for i in 0..1000 {
+                        sketch.update(i as $item);
+                    }
+                    let copy = sketch.try_clone().unwrap();
+                    assert_ne!(copy.native.get(), sketch.native.get());
+                    assert_eq!(copy.serialize().unwrap(), sketch.serialize().unwrap());
+                    sketch.update(5000.0 as $item);
+                    assert_eq!(copy.get_n() + 1, sketch.get_n());
+                }
+
+                #[test]
+                fn test_quantile_map() {
+                    let mut source = $Sketch::new();
+                    let mut target = $Sketch::new();
+                    for i in 0..1000 {
+                        source.update(i as $item);
+                        target.update((i * 10 + 5) as $item);
+                    }
+                    let map = source.quantile_map(&target, 50);
+                    assert_eq!(map.knots().len(), 51);
+                    for value in [0.0, 250.0, 999.0] {
+                        assert!((map.apply(value) - (value * 10.0 + 5.0)).abs() < 200.0);
+                    }
+                    assert!(map.apply(-1.0) <= map.apply(500.0));
+                    assert!(source.quantile_map(&$Sketch::new(), 10).is_empty());
+                }
+
+                #[test]
+                fn test_affine_transform() {
+                    let mut sketch = $Sketch::new_with_k(32).unwrap();
+                    let ms: Vec<f64> = (1..=5000).map(|i| i as f64).collect();
+                    for &value in &ms {
+                        sketch.update(value as $item);
+                    }
+                    let seconds = sketch.affine_transform(0.001, 0.0).unwrap();
+                    assert_eq!(seconds.get_n(), sketch.get_n());
+                    assert_eq!(seconds.get_num_retained(), sketch.get_num_retained());
+                    assert!((seconds.get_max_value() as f64 - 5.0).abs() < 1e-6);
+                    for fraction in [0.1, 0.5, 0.9] {
+                        let expected = sketch.get_quantile(fraction) as f64 * 0.001;
+                        assert!((seconds.get_quantile(fraction) as f64 - expected).abs() < 1e-6);
+                    }
+
+                    let negated = sketch.affine_transform(-1.0, 10.0).unwrap();
+                    assert_eq!(negated.get_min_value() as f64, -4990.0);
+                    assert_eq!(negated.get_max_value() as f64, 9.0);
+                    let rank = sketch.get_rank(2000.0 as $item);
+                    let mirrored = 1.0 - negated.get_rank(-1990.0 as $item) + 1.0 / 5000.0;
+                    assert!((rank - mirrored).abs() < 0.01, "{} vs {}", rank, mirrored);
+
+                    let mut single = $Sketch::new();
+                    single.update(2.0 as $item);
+                    assert_eq!(single.affine_transform(3.0, 1.0).unwrap().get_quantile(0.5), 7.0 as $item);
+                    assert!($Sketch::new().affine_transform(2.0, 0.0).unwrap().is_empty());
+                    assert!(sketch.affine_transform(0.0, 1.0).is_err());
+                    assert!(sketch.affine_transform(f64::NAN, 1.0).is_err());
+                    assert!(sketch.affine_transform(1e308, 0.0).is_err());
+                }
+
+                #[test]
+                fn test_raw_round_trip() {
+                    let mut sketch = $Sketch::new_with_k(64).unwrap();
+                    sketch.on_compaction(|_, _| {});
+                    for i in 1..=5 {
+                        sketch.update(i as $item);
+                    }
+                    let ptr = sketch.into_raw();
+                    assert!(!ptr.is_null());
+                    let restored = unsafe { $Sketch::from_raw(ptr) };
+                    assert_eq!((restored.get_k(), restored.get_n()), (64, 5));
+                    assert_eq!(restored.get_quantile(0.5), 3.0 as $item);
+                }
+
+                #[test]
+                fn test_native_errors_carry_messages() {
+                    let mut sketch = $Sketch::new();
+                    for i in 0..100 {
+                        sketch.update(i as $item);
+                    }
+                    let bytes = sketch.serialize().unwrap();
+                    match $Sketch::deserialize(&bytes[..bytes.len() / 2]) {
+                        Err(DataSketchesError::DeserializationError(message)) => assert!(!message.is_empty()),
+                        other => panic!("unexpected {:?}", other.map(|s| s.get_n())),
+                    }
+                }
+
+                #[test]
+                fn test_estimate_modes() {
+                    // Two bells of sums of pseudo-random uniforms: a fast mode at 10 holding
+                    // two thirds of the values and a slow one at 40.
+                    let uniform = |i: u64| ((i.wrapping_mul(2_654_435_761) >> 7) % 10_000) as f64 / 10_000.0;
+                    let mut sketch = $Sketch::new();
+                    for i in 0..30_000u64 {
+                        let bell = (0..4).map(|j| uniform(i * 4 + j)).sum::<f64>() - 2.0;
+                        let value = if i % 3 == 2 { 40.0 + 4.0 * bell } else { 10.0 + 2.0 * bell };
+                        sketch.update(value as $item);
+                    }
+                    let modes = sketch.estimate_modes(60, 5);
+                    assert_eq!(modes.len(), 2, "{:?}", modes);
+                    assert!((modes[0].0 - 10.0).abs() < 1.5, "{:?}", modes);
+                    assert!((modes[1].0 - 40.0).abs() < 2.5, "{:?}", modes);
+                    assert!(modes[0].1 > modes[1].1);
+                    assert_eq!(sketch.estimate_modes(60, 1).len(), 1);
+
+                    assert!($Sketch::new().estimate_modes(10, 3).is_empty());
+                    let mut constant = $Sketch::new();
+                    constant.update(5.0);
+                    assert_eq!(constant.estimate_modes(10, 3), vec![(5.0, 1.0)]);
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use kll_sketch_type;
@@ -0,0 +1,149 @@
+//! Latency SLO compliance and error budgets.
+
+use crate::error::{DataSketchesError, Result};
+use crate::traits::SketchLike;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    good: f64,
+    total: u64,
+}
+
+/// Tracks an objective such as "99% of requests complete within 250ms".
+///
+/// Each recorded interval contributes the number of values at or below the
+/// threshold, estimated from the threshold's rank. Compliance and error
+/// budget are computed over the most recent `window` intervals.
+#[derive(Debug, Clone)]
+pub struct SloTracker {
+    threshold: f64,
+    target: f64,
+    window: usize,
+    intervals: VecDeque<Interval>,
+}
+
+impl SloTracker {
+    /// Creates a tracker requiring `target` of values to be at or below `threshold`.
+    ///
+    /// `target` must lie in `(0, 1)` and `window` must be at least one interval.
+    pub fn new(threshold: f64, target: f64, window: usize) -> Result<Self> {
+        if !(target > 0.0 && target < 1.0) {
+            return Err(DataSketchesError::InvalidParameter(
+                "SLO target must be between 0 and 1".to_string(),
+            ));
+        }
+        if window == 0 {
+            return Err(DataSketchesError::InvalidParameter(
+                "SLO window must hold at least one interval".to_string(),
+            ));
+        }
+        Ok(SloTracker {
+            threshold,
+            target,
+            window,
+            intervals: VecDeque::with_capacity(window),
+        })
+    }
+
+    /// Returns the latency threshold.
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Returns the required fraction of values at or below the threshold.
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// Records one interval, dropping the oldest once the window is full.
+    pub fn record_interval<S: SketchLike + ?Sized>(&mut self, sketch: &S) {
+        let total = sketch.get_n();
+        let good = if total == 0 {
+            0.0
+        } else {
+            sketch.get_rank(self.threshold) * total as f64
+        };
+        if self.intervals.len() == self.window {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back(Interval { good, total });
+    }
+
+    /// Returns the number of values in the window.
+    pub fn total_count(&self) -> u64 {
+        self.intervals.iter().map(|i| i.total).sum()
+    }
+
+    /// Returns the fraction of values in the window at or below the threshold.
+    ///
+    /// Returns NaN if the window holds no values.
+    pub fn compliance(&self) -> f64 {
+        let total = self.total_count();
+        if total == 0 {
+            return f64::NAN;
+        }
+        self.intervals.iter().map(|i| i.good).sum::<f64>() / total as f64
+    }
+
+    /// Returns true if the window meets the target. An empty window meets it.
+    pub fn is_met(&self) -> bool {
+        self.total_count() == 0 || self.compliance() >= self.target
+    }
+
+    /// Returns the fraction of the window's error budget still available.
+    ///
+    /// The budget is the `1 - target` share of values allowed above the
+    /// threshold. The result is 1.0 when nothing has breached and drops
+    /// below zero once the budget is overspent.
+    pub fn error_budget_remaining(&self) -> f64 {
+        let total = self.total_count();
+        if total == 0 {
+            return 1.0;
+        }
+        let allowed = (1.0 - self.target) * total as f64;
+        let bad = (1.0 - self.compliance()) * total as f64;
+        1.0 - bad / allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KllDoubleSketch;
+
+    fn interval(slow: u32) -> KllDoubleSketch {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 0..100 {
+            sketch.update(if i < slow { 500.0 } else { 10.0 });
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_error_budget() {
+        let mut slo = SloTracker::new(250.0, 0.99, 2).unwrap();
+        assert!(slo.is_met());
+        assert_eq!(slo.error_budget_remaining(), 1.0);
+
+        slo.record_interval(&interval(0));
+        assert_eq!(slo.compliance(), 1.0);
+
+        // One slow value out of 200 uses half of a 1% budget.
+        slo.record_interval(&interval(1));
+        assert!((slo.error_budget_remaining() - 0.5).abs() < 1e-9);
+        assert!(slo.is_met());
+
+        // The clean interval rolls out of the window.
+        slo.record_interval(&interval(3));
+        assert_eq!(slo.total_count(), 200);
+        assert!(!slo.is_met());
+        assert!(slo.error_budget_remaining() < 0.0);
+    }
+
+    #[test]
+    fn test_invalid_slo() {
+        assert!(SloTracker::new(1.0, 1.0, 1).is_err());
+        assert!(SloTracker::new(1.0, 0.9, 0).is_err());
+    }
+}
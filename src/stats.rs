@@ -0,0 +1,161 @@
+//! Counters of calls into the native library, for the `instrumented` feature.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Kinds of native calls counted by [`stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FfiCall {
+    /// Creating a sketch.
+    Create,
+    /// Copying a sketch.
+    Copy,
+    /// Freeing a sketch.
+    Delete,
+    /// Adding a value.
+    Update,
+    /// Merging one sketch into another.
+    Merge,
+    /// Reading anything off a sketch.
+    Query,
+    /// Serializing a sketch.
+    Serialize,
+    /// Deserializing a sketch.
+    Deserialize,
+    /// Setting the random seed or a compaction callback.
+    Configure,
+}
+
+impl FfiCall {
+    /// Every kind, in declaration order.
+    pub const ALL: [FfiCall; 9] = [
+        FfiCall::Create,
+        FfiCall::Copy,
+        FfiCall::Delete,
+        FfiCall::Update,
+        FfiCall::Merge,
+        FfiCall::Query,
+        FfiCall::Serialize,
+        FfiCall::Deserialize,
+        FfiCall::Configure,
+    ];
+}
+
+/// A snapshot of the native call counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FfiStats {
+    calls: [u64; FfiCall::ALL.len()],
+    /// Bytes returned by serialize calls.
+    pub bytes_serialized: u64,
+    /// Bytes passed to deserialize calls.
+    pub bytes_deserialized: u64,
+    /// Sketches allocated by the native library.
+    pub sketches_allocated: u64,
+    /// Sketches freed by the native library.
+    pub sketches_freed: u64,
+    /// Serialization buffers allocated by the native library.
+    pub buffers_allocated: u64,
+}
+
+impl FfiStats {
+    /// Returns the number of calls of one kind.
+    pub fn calls(&self, kind: FfiCall) -> u64 {
+        self.calls[kind as usize]
+    }
+
+    /// Returns the number of calls of every kind.
+    pub fn total_calls(&self) -> u64 {
+        self.calls.iter().sum()
+    }
+}
+
+static CALLS: [AtomicU64; FfiCall::ALL.len()] = [const { AtomicU64::new(0) }; FfiCall::ALL.len()];
+static BYTES_SERIALIZED: AtomicU64 = AtomicU64::new(0);
+static BYTES_DESERIALIZED: AtomicU64 = AtomicU64::new(0);
+static SKETCHES_ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static SKETCHES_FREED: AtomicU64 = AtomicU64::new(0);
+static BUFFERS_ALLOCATED: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn record(kind: FfiCall) {
+    CALLS[kind as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_sketch_allocated(sketch: *mut std::os::raw::c_void) {
+    if !sketch.is_null() {
+        SKETCHES_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn record_sketch_freed(sketch: *mut std::os::raw::c_void) {
+    if !sketch.is_null() {
+        SKETCHES_FREED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn record_serialized(buffer: *mut u8, size: usize) {
+    if !buffer.is_null() {
+        BUFFERS_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+        BYTES_SERIALIZED.fetch_add(size as u64, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn record_deserialized(size: usize) {
+    BYTES_DESERIALIZED.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// Returns the native call counters accumulated by all threads.
+///
+/// Counters are process-wide and updated with relaxed atomics, so a snapshot
+/// taken while other threads make calls is not a consistent cut.
+pub fn stats() -> FfiStats {
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    FfiStats {
+        calls: std::array::from_fn(|i| load(&CALLS[i])),
+        bytes_serialized: load(&BYTES_SERIALIZED),
+        bytes_deserialized: load(&BYTES_DESERIALIZED),
+        sketches_allocated: load(&SKETCHES_ALLOCATED),
+        sketches_freed: load(&SKETCHES_FREED),
+        buffers_allocated: load(&BUFFERS_ALLOCATED),
+    }
+}
+
+/// Sets every counter back to zero.
+pub fn reset_stats() {
+    for counter in CALLS.iter().chain([
+        &BYTES_SERIALIZED,
+        &BYTES_DESERIALIZED,
+        &SKETCHES_ALLOCATED,
+        &SKETCHES_FREED,
+        &BUFFERS_ALLOCATED,
+    ]) {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KllDoubleSketch;
+
+    #[test]
+    fn test_counts_native_calls() {
+        // Counters are global and other tests run concurrently, so only
+        // check that this test's calls are at least reflected.
+        let before = stats();
+        let mut sketch = KllDoubleSketch::new();
+        for i in 0..10 {
+            sketch.update(i as f64);
+        }
+        let bytes = sketch.serialize().unwrap();
+        let copy = KllDoubleSketch::deserialize(&bytes).unwrap();
+        drop((sketch, copy));
+        let after = stats();
+
+        assert!(after.calls(FfiCall::Update) >= before.calls(FfiCall::Update) + 10);
+        assert!(after.calls(FfiCall::Serialize) > before.calls(FfiCall::Serialize));
+        assert!(after.bytes_serialized >= before.bytes_serialized + bytes.len() as u64);
+        assert!(after.bytes_deserialized >= before.bytes_deserialized + bytes.len() as u64);
+        assert!(after.sketches_allocated >= before.sketches_allocated + 2);
+        assert!(after.sketches_freed >= before.sketches_freed + 2);
+        assert!(after.total_calls() > before.total_calls());
+    }
+}
@@ -0,0 +1,385 @@
+//! Export of registry percentiles to statsd and DogStatsD.
+//!
+//! Each non-empty series in a [`SketchRegistry`] becomes one gauge per
+//! configured percentile, named `<prefix>.<series>.p<percentile>`:
+//!
+//! ```text
+//! api.http.latency.p99:48.5|g|#route:/users
+//! ```
+//!
+//! Gauges are packed into UDP datagrams no larger than the configured packet
+//! size. Tags are only sent in the DogStatsD flavor; plain statsd has no
+//! tags, so series that differ only in tags report the same gauge there.
+//!
+//! ```no_run
+//! use kll_rs::statsd::{StatsdExporter, StatsdFlavor};
+//! use kll_rs::SketchRegistry;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let registry = Arc::new(SketchRegistry::default());
+//! let exporter = StatsdExporter::new("127.0.0.1:8125", &[0.5, 0.99])?
+//!     .flavor(StatsdFlavor::DogStatsD)
+//!     .prefix("api")
+//!     .tag("env", "prod");
+//! let handle = exporter.spawn(Arc::clone(&registry), Duration::from_secs(10));
+//!
+//! registry.record("http.latency", &[("route", "/users")], 12.5);
+//! // ...
+//! handle.stop();
+//! # Ok::<(), kll_rs::DataSketchesError>(())
+//! ```
+
+use crate::error::{DataSketchesError, Result};
+use crate::events::event;
+use crate::registry::{SeriesKey, SketchRegistry};
+use crate::KllDoubleSketch;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Largest datagram that fits an Ethernet MTU without fragmenting.
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 1432;
+
+/// The line format spoken by the statsd server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsdFlavor {
+    /// Plain Etsy statsd, without tags.
+    #[default]
+    Statsd,
+    /// Datadog's DogStatsD, with `|#key:value` tags.
+    DogStatsD,
+}
+
+/// Sends percentile gauges from a [`SketchRegistry`] to a statsd server.
+#[derive(Debug)]
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    fractions: Vec<f64>,
+    flavor: StatsdFlavor,
+    prefix: Option<String>,
+    tags: Vec<(String, String)>,
+    max_packet_size: usize,
+    reset: bool,
+}
+
+impl StatsdExporter {
+    /// Creates an exporter sending the quantiles at `fractions` to `addr`.
+    pub fn new<A: ToSocketAddrs>(addr: A, fractions: &[f64]) -> Result<Self> {
+        if fractions.is_empty() || fractions.iter().any(|f| !(0.0..=1.0).contains(f)) {
+            return Err(DataSketchesError::InvalidParameter(
+                "statsd percentiles must be fractions between 0 and 1".to_string(),
+            ));
+        }
+        let socket = connect(addr)
+            .map_err(|e| DataSketchesError::Unknown(format!("statsd socket: {}", e)))?;
+        Ok(StatsdExporter {
+            socket,
+            fractions: fractions.to_vec(),
+            flavor: StatsdFlavor::default(),
+            prefix: None,
+            tags: vec![],
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            reset: false,
+        })
+    }
+
+    /// Selects the line format.
+    pub fn flavor(mut self, flavor: StatsdFlavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    /// Prepends `prefix.` to every gauge name.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Adds a tag sent with every gauge, in the DogStatsD flavor.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the largest datagram to send. A single gauge longer than this
+    /// still goes out in a datagram of its own.
+    pub fn max_packet_size(mut self, bytes: usize) -> Self {
+        self.max_packet_size = bytes;
+        self
+    }
+
+    /// Empties the registry on every export, so each one reports only the
+    /// values recorded since the last.
+    pub fn reset_each_export(mut self, reset: bool) -> Self {
+        self.reset = reset;
+        self
+    }
+
+    /// Formats the gauges for every non-empty series of `registry`.
+    pub fn lines(&self, registry: &SketchRegistry) -> Vec<String> {
+        let series = if self.reset {
+            registry.take()
+        } else {
+            registry.snapshot()
+        };
+        series
+            .iter()
+            .filter(|(_, sketch)| !sketch.is_empty())
+            .flat_map(|(key, sketch)| self.series_lines(key, sketch))
+            .collect()
+    }
+
+    /// Sends one round of gauges, returning the number of datagrams sent.
+    pub fn export(&self, registry: &SketchRegistry) -> Result<usize> {
+        let packets = pack(&self.lines(registry), self.max_packet_size);
+        for packet in &packets {
+            self.socket
+                .send(packet.as_bytes())
+                .map_err(|e| DataSketchesError::Unknown(format!("statsd send: {}", e)))?;
+        }
+        Ok(packets.len())
+    }
+
+    /// Exports from `registry` every `interval` on a background thread until
+    /// the returned handle is stopped or dropped. Failed sends are dropped,
+    /// as statsd over UDP offers no delivery guarantee anyway.
+    pub fn spawn(self, registry: Arc<SketchRegistry>, interval: Duration) -> StatsdHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            // Runs until told to stop or the handle is gone.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let start = Instant::now();
+                match self.export(&registry) {
+                    Ok(packets) => event!(
+                        debug,
+                        "exported registry to statsd",
+                        packets = packets,
+                        micros = start.elapsed().as_micros() as u64,
+                    ),
+                    Err(err) => event!(warn, "statsd export failed", error = err.to_string()),
+                }
+            }
+        });
+        StatsdHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    fn series_lines(&self, key: &SeriesKey, sketch: &KllDoubleSketch) -> Vec<String> {
+        let mut name = String::new();
+        if let Some(prefix) = &self.prefix {
+            name.push_str(&sanitize(prefix));
+            name.push('.');
+        }
+        name.push_str(&sanitize(key.name()));
+
+        let mut suffix = String::new();
+        if self.flavor == StatsdFlavor::DogStatsD {
+            let tags: Vec<String> = self
+                .tags
+                .iter()
+                .chain(key.tags())
+                .map(|(k, v)| format!("{}:{}", sanitize(k), sanitize(v)))
+                .collect();
+            if !tags.is_empty() {
+                suffix = format!("|#{}", tags.join(","));
+            }
+        }
+
+        let quantiles = sketch.get_quantiles(&self.fractions);
+        self.fractions
+            .iter()
+            .zip(quantiles)
+            .map(|(&fraction, value)| {
+                format!(
+                    "{}.{}:{}|g{}",
+                    name,
+                    percentile_label(fraction),
+                    value,
+                    suffix
+                )
+            })
+            .collect()
+    }
+}
+
+/// Stops a [`StatsdExporter`] started with [`StatsdExporter::spawn`].
+#[derive(Debug)]
+pub struct StatsdHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatsdHandle {
+    /// Stops the exporter and waits for its thread to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for StatsdHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Names a fraction as a percentile: `0.99` is `p99` and `0.999` is `p99_9`.
+fn percentile_label(fraction: f64) -> String {
+    let percent = format!("{:.6}", fraction * 100.0);
+    let percent = percent.trim_end_matches('0').trim_end_matches('.');
+    format!("p{}", percent.replace('.', "_"))
+}
+
+/// Replaces the characters that delimit statsd fields.
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Joins lines into newline-separated packets of at most `max` bytes.
+fn pack(lines: &[String], max: usize) -> Vec<String> {
+    let mut packets = vec![];
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > max {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Connects a UDP socket to the first address `addr` resolves to that
+/// accepts it, binding each attempt in that address's family.
+fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+    let mut last_error = None;
+    for target in addr.to_socket_addrs()? {
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        match UdpSocket::bind(local).and_then(|socket| socket.connect(target).map(|()| socket)) {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> SketchRegistry {
+        let registry = SketchRegistry::default();
+        for i in 1..=100 {
+            registry.record("http latency", &[("route", "/a|b")], i as f64);
+        }
+        registry
+    }
+
+    #[test]
+    fn test_statsd_lines() {
+        assert_eq!(percentile_label(0.5), "p50");
+        assert_eq!(percentile_label(0.999), "p99_9");
+        assert_eq!(percentile_label(1.0), "p100");
+
+        let plain = StatsdExporter::new("127.0.0.1:8125", &[0.5, 0.99]).unwrap();
+        assert_eq!(
+            plain.lines(&registry()),
+            vec!["http_latency.p50:50|g", "http_latency.p99:99|g"]
+        );
+
+        let dog = StatsdExporter::new("127.0.0.1:8125", &[0.5])
+            .unwrap()
+            .flavor(StatsdFlavor::DogStatsD)
+            .prefix("api")
+            .tag("env", "prod");
+        assert_eq!(
+            dog.lines(&registry()),
+            vec!["api.http_latency.p50:50|g|#env:prod,route:/a_b"]
+        );
+        assert!(StatsdExporter::new("127.0.0.1:8125", &[1.5]).is_err());
+    }
+
+    #[test]
+    fn test_statsd_ipv6() {
+        // Hosts without IPv6 cannot run this.
+        let Ok(server) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let exporter = StatsdExporter::new(server.local_addr().unwrap(), &[0.5]).unwrap();
+        assert_eq!(exporter.export(&registry()).unwrap(), 1);
+        let mut buf = [0; 2048];
+        let len = server.recv(&mut buf).unwrap();
+        assert!(std::str::from_utf8(&buf[..len])
+            .unwrap()
+            .starts_with("http_latency.p50:"));
+    }
+
+    #[test]
+    fn test_statsd_export() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let fractions: Vec<f64> = (1..=20).map(|i| i as f64 / 20.0).collect();
+        let exporter = StatsdExporter::new(server.local_addr().unwrap(), &fractions)
+            .unwrap()
+            .max_packet_size(200)
+            .reset_each_export(true);
+
+        let registry = registry();
+        let packets = exporter.export(&registry).unwrap();
+        assert!(packets > 1);
+        assert!(registry.is_empty());
+
+        let mut lines = 0;
+        let mut buf = [0; 2048];
+        for _ in 0..packets {
+            let len = server.recv(&mut buf).unwrap();
+            assert!(len <= 200);
+            lines += std::str::from_utf8(&buf[..len]).unwrap().lines().count();
+        }
+        assert_eq!(lines, 20);
+
+        let registry = Arc::new(self::registry());
+        let handle = exporter.spawn(registry, Duration::from_millis(10));
+        let len = server.recv(&mut buf).unwrap();
+        assert!(std::str::from_utf8(&buf[..len])
+            .unwrap()
+            .starts_with("http_latency.p5:"));
+        handle.stop();
+    }
+}
@@ -0,0 +1,101 @@
+//! Conversion of the status reported by `*_checked` native calls into errors.
+
+use crate::error::{DataSketchesError, Result};
+use crate::sys::{
+    SketchStatus, SKETCH_STATUS_INVALID_ARGUMENT, SKETCH_STATUS_MESSAGE_LEN,
+    SKETCH_STATUS_NULL_POINTER, SKETCH_STATUS_OK,
+};
+
+/// Runs a checked native call, turning a failure status into an error.
+///
+/// A null handle becomes [`DataSketchesError::NullPointer`], a rejected
+/// argument [`DataSketchesError::InvalidParameter`] and anything else
+/// [`DataSketchesError::Unknown`], each with the native message.
+pub(crate) fn checked<R>(call: impl FnOnce(*mut SketchStatus) -> R) -> Result<R> {
+    let (result, status) = run(call);
+    match status.code {
+        SKETCH_STATUS_OK => Ok(result),
+        SKETCH_STATUS_NULL_POINTER => Err(DataSketchesError::NullPointer),
+        SKETCH_STATUS_INVALID_ARGUMENT => {
+            Err(DataSketchesError::InvalidParameter(message(&status)))
+        }
+        _ => Err(DataSketchesError::Unknown(message(&status))),
+    }
+}
+
+/// Runs a checked native call, reporting every failure but a null handle as
+/// `wrap` applied to the native message.
+pub(crate) fn checked_as<R>(
+    wrap: impl FnOnce(String) -> DataSketchesError,
+    call: impl FnOnce(*mut SketchStatus) -> R,
+) -> Result<R> {
+    let (result, status) = run(call);
+    match status.code {
+        SKETCH_STATUS_OK => Ok(result),
+        SKETCH_STATUS_NULL_POINTER => Err(DataSketchesError::NullPointer),
+        _ => Err(wrap(message(&status))),
+    }
+}
+
+/// Runs a checked native call that can only fail if an invariant of the safe
+/// types is broken.
+///
+/// # Panics
+///
+/// Panics with the native message if the call fails.
+pub(crate) fn infallible<R>(call: impl FnOnce(*mut SketchStatus) -> R) -> R {
+    checked(call).unwrap_or_else(|err| panic!("native sketch call failed: {}", err))
+}
+
+fn run<R>(call: impl FnOnce(*mut SketchStatus) -> R) -> (R, SketchStatus) {
+    let mut status = SketchStatus {
+        code: SKETCH_STATUS_OK,
+        message: [0; SKETCH_STATUS_MESSAGE_LEN],
+    };
+    let result = call(&mut status);
+    (result, status)
+}
+
+fn message(status: &SketchStatus) -> String {
+    let bytes: Vec<u8> = status
+        .message
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail(status: *mut SketchStatus, code: i32, text: &str) {
+        let status = unsafe { &mut *status };
+        status.code = code;
+        for (out, &byte) in status.message.iter_mut().zip(text.as_bytes()) {
+            *out = byte as _;
+        }
+    }
+
+    #[test]
+    fn test_status_conversion() {
+        assert_eq!(checked(|_| 7).unwrap(), 7);
+        assert!(matches!(
+            checked(|s| fail(s, SKETCH_STATUS_NULL_POINTER, "null")),
+            Err(DataSketchesError::NullPointer)
+        ));
+        match checked(|s| fail(s, SKETCH_STATUS_INVALID_ARGUMENT, "bad k")) {
+            Err(DataSketchesError::InvalidParameter(message)) => assert_eq!(message, "bad k"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match checked_as(DataSketchesError::DeserializationError, |s| {
+            fail(s, SKETCH_STATUS_INVALID_ARGUMENT, "truncated")
+        }) {
+            Err(DataSketchesError::DeserializationError(message)) => {
+                assert_eq!(message, "truncated")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
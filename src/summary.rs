@@ -0,0 +1,147 @@
+//! Configurable one-line and multi-line sketch summaries.
+
+use std::fmt::Write;
+
+/// Layout of a rendered summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SummaryStyle {
+    /// A single line such as `n=1000 p50=12.00ms p99=48.00ms`.
+    #[default]
+    Compact,
+    /// One `label: value` line each for count, min, the percentiles, and max.
+    Verbose,
+}
+
+/// Controls how `format_summary` renders a sketch.
+///
+/// The default shows p50, p90 and p99 with two decimals, no unit, in the
+/// compact style.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryFormat {
+    fractions: Vec<f64>,
+    precision: usize,
+    unit: String,
+    style: SummaryStyle,
+}
+
+impl SummaryFormat {
+    /// Creates the default format.
+    pub fn new() -> Self {
+        SummaryFormat {
+            fractions: vec![0.5, 0.9, 0.99],
+            precision: 2,
+            unit: String::new(),
+            style: SummaryStyle::default(),
+        }
+    }
+
+    /// Sets the percentiles to show, as fractions in `[0, 1]`.
+    pub fn percentiles(mut self, fractions: &[f64]) -> Self {
+        self.fractions = fractions.to_vec();
+        self
+    }
+
+    /// Sets the number of decimals printed for each value.
+    pub fn precision(mut self, precision: usize) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Sets a unit suffix such as `"ms"`, appended to every value.
+    pub fn unit(mut self, unit: impl Into<String>) -> Self {
+        self.unit = unit.into();
+        self
+    }
+
+    /// Sets the layout.
+    pub fn style(mut self, style: SummaryStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub(crate) fn fractions(&self) -> &[f64] {
+        &self.fractions
+    }
+
+    /// Renders a summary from values already read off a sketch.
+    pub(crate) fn render(&self, n: u64, min: f64, max: f64, quantiles: &[f64]) -> String {
+        let mut out = String::new();
+        match self.style {
+            SummaryStyle::Compact => {
+                let _ = write!(out, "n={}", n);
+                if n > 0 {
+                    for (&fraction, &value) in self.fractions.iter().zip(quantiles) {
+                        let _ =
+                            write!(out, " {}={}", percentile_label(fraction), self.value(value));
+                    }
+                }
+            }
+            SummaryStyle::Verbose => {
+                let _ = write!(out, "count: {}", n);
+                if n > 0 {
+                    let _ = write!(out, "\nmin: {}", self.value(min));
+                    for (&fraction, &value) in self.fractions.iter().zip(quantiles) {
+                        let _ = write!(
+                            out,
+                            "\n{}: {}",
+                            percentile_label(fraction),
+                            self.value(value)
+                        );
+                    }
+                    let _ = write!(out, "\nmax: {}", self.value(max));
+                }
+            }
+        }
+        out
+    }
+
+    fn value(&self, value: f64) -> String {
+        format!("{:.*}{}", self.precision, value, self.unit)
+    }
+}
+
+impl Default for SummaryFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Labels a fraction as a percentile, e.g. `0.999` as `p99.9`.
+pub(crate) fn percentile_label(fraction: f64) -> String {
+    let percent = format!("{:.4}", fraction * 100.0);
+    let percent = percent.trim_end_matches('0').trim_end_matches('.');
+    format!("p{}", percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KllDoubleSketch;
+
+    #[test]
+    fn test_format_summary() {
+        let mut sketch = KllDoubleSketch::new();
+        assert_eq!(sketch.format_summary(&SummaryFormat::new()), "n=0");
+
+        for i in 1..=100 {
+            sketch.update(i as f64);
+        }
+        let compact = SummaryFormat::new()
+            .percentiles(&[0.5, 0.999])
+            .precision(1)
+            .unit("ms");
+        assert_eq!(
+            sketch.format_summary(&compact),
+            "n=100 p50=50.0ms p99.9=100.0ms"
+        );
+
+        let verbose = SummaryFormat::new()
+            .percentiles(&[0.5])
+            .precision(0)
+            .style(SummaryStyle::Verbose);
+        assert_eq!(
+            sketch.format_summary(&verbose),
+            "count: 100\nmin: 1\np50: 50\nmax: 100"
+        );
+    }
+}
@@ -0,0 +1,563 @@
+//! The C API the sketches are built on: the native library, or the pure-Rust
+//! mock when the `mock` feature is enabled.
+//!
+//! With the `instrumented` feature every function is wrapped to update the
+//! counters in `stats` before forwarding to the backend. The wrappers shadow
+//! the glob re-export, so call sites are the same either way. Only the
+//! `*_checked` functions are wrapped, since the sketches call nothing else.
+
+#[cfg(not(any(feature = "native", feature = "mock")))]
+compile_error!("kll-rs needs either the `native` or the `mock` feature");
+
+#[cfg(not(feature = "mock"))]
+use libdatasketches_sys as backend;
+
+#[cfg(all(feature = "mock", not(feature = "native")))]
+use crate::mock as backend;
+
+#[cfg(all(feature = "mock", feature = "native"))]
+use dispatch as backend;
+
+pub(crate) use backend::*;
+
+/// Opens the native library at `path` for every sketch in the process.
+///
+/// With the `dynamic` feature the native code lives in a shared library,
+/// which many cdylibs can share. Without a call to this, the first sketch
+/// loads it from the `KLL_RS_NATIVE_LIBRARY` environment variable or from
+/// where the build put it. Only the first library loaded is used; later calls
+/// return `Ok(())` without opening anything.
+#[cfg(feature = "dynamic")]
+pub fn load_native_library<P: AsRef<std::ffi::OsStr>>(path: P) -> crate::error::Result<()> {
+    let path = path.as_ref();
+    libdatasketches_sys::load_library(path).map_err(|err| {
+        crate::error::DataSketchesError::CreationError(format!(
+            "cannot load the native library from {:?}: {}",
+            path, err
+        ))
+    })
+}
+
+/// Forwards every function the sketches call to the implementation selected
+/// at runtime, when both are compiled in. Types and constants are the native
+/// ones, which the pure-Rust implementation shares.
+#[cfg(all(feature = "mock", feature = "native"))]
+mod dispatch {
+    use crate::implementation::{implementation, Implementation};
+    use libc::size_t;
+    use std::os::raw::c_void;
+
+    pub(crate) use libdatasketches_sys::*;
+
+    macro_rules! dispatched {
+        ($(fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {$(
+            pub(crate) unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                match implementation() {
+                    Implementation::Native => libdatasketches_sys::$name($($arg),*),
+                    Implementation::Rust => crate::mock::$name($($arg),*),
+                }
+            }
+        )*};
+    }
+
+    macro_rules! dispatched_sketch {
+        ($t:ident, $item:ty) => {
+            paste::paste! {
+                dispatched! {
+                    fn [<kll_ $t _sketch_new_with_k_checked>](k: u16, status: *mut SketchStatus) -> *mut c_void;
+                    fn [<kll_ $t _sketch_copy_checked>](
+                        sketch: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> *mut c_void;
+                    fn [<kll_ $t _sketch_delete_checked>](sketch: *mut c_void, status: *mut SketchStatus);
+                    fn [<kll_ $t _sketch_serialize_checked>](
+                        sketch: *mut c_void,
+                        size: *mut size_t,
+                        status: *mut SketchStatus,
+                    ) -> *mut u8;
+                    fn [<kll_ $t _sketch_deserialize_checked>](
+                        data: *const u8,
+                        size: size_t,
+                        status: *mut SketchStatus,
+                    ) -> *mut c_void;
+                    fn [<kll_ $t _sketch_update_checked>](
+                        sketch: *mut c_void,
+                        value: $item,
+                        status: *mut SketchStatus,
+                    );
+                    fn [<kll_ $t _sketch_update_tracked_checked>](
+                        sketch: *mut c_void,
+                        value: $item,
+                        status: *mut SketchStatus,
+                    ) -> bool;
+                    fn [<kll_ $t _sketch_merge_tracked_checked>](
+                        sketch: *mut c_void,
+                        other: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> u32;
+                    fn [<kll_ $t _sketch_is_empty_checked>](sketch: *mut c_void, status: *mut SketchStatus) -> bool;
+                    fn [<kll_ $t _sketch_get_k_checked>](sketch: *mut c_void, status: *mut SketchStatus) -> u16;
+                    fn [<kll_ $t _sketch_get_n_checked>](sketch: *mut c_void, status: *mut SketchStatus) -> u64;
+                    fn [<kll_ $t _sketch_get_num_retained_checked>](
+                        sketch: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> u32;
+                    fn [<kll_ $t _sketch_is_estimation_mode_checked>](
+                        sketch: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> bool;
+                    fn [<kll_ $t _sketch_get_min_value_checked>](
+                        sketch: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> $item;
+                    fn [<kll_ $t _sketch_get_max_value_checked>](
+                        sketch: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> $item;
+                    fn [<kll_ $t _sketch_get_quantile_checked>](
+                        sketch: *mut c_void,
+                        fraction: f64,
+                        status: *mut SketchStatus,
+                    ) -> $item;
+                    fn [<kll_ $t _sketch_get_rank_checked>](
+                        sketch: *mut c_void,
+                        value: $item,
+                        status: *mut SketchStatus,
+                    ) -> f64;
+                    fn [<kll_ $t _sketch_get_quantiles_checked>](
+                        sketch: *mut c_void,
+                        fractions: *const f64,
+                        num_fractions: size_t,
+                        results: *mut $item,
+                        status: *mut SketchStatus,
+                    );
+                    fn [<kll_ $t _sketch_get_quantile_criteria_checked>](
+                        sketch: *mut c_void,
+                        fraction: f64,
+                        inclusive: bool,
+                        status: *mut SketchStatus,
+                    ) -> $item;
+                    fn [<kll_ $t _sketch_get_rank_criteria_checked>](
+                        sketch: *mut c_void,
+                        value: $item,
+                        inclusive: bool,
+                        status: *mut SketchStatus,
+                    ) -> f64;
+                    fn [<kll_ $t _sketch_get_quantiles_criteria_checked>](
+                        sketch: *mut c_void,
+                        fractions: *const f64,
+                        num_fractions: size_t,
+                        inclusive: bool,
+                        results: *mut $item,
+                        status: *mut SketchStatus,
+                    );
+                    fn [<kll_ $t _sketch_get_quantiles_evenly_spaced_checked>](
+                        sketch: *mut c_void,
+                        num: u32,
+                        results: *mut $item,
+                        status: *mut SketchStatus,
+                    );
+                    fn [<kll_ $t _sketch_get_retained_items_checked>](
+                        sketch: *mut c_void,
+                        items: *mut $item,
+                        weights: *mut u64,
+                        capacity: u32,
+                        status: *mut SketchStatus,
+                    ) -> u32;
+                    fn [<kll_ $t _sketch_get_normalized_rank_error_checked>](
+                        sketch: *mut c_void,
+                        pmf: bool,
+                        status: *mut SketchStatus,
+                    ) -> f64;
+                    fn [<kll_ $t _sketch_get_items_discarded_checked>](
+                        sketch: *mut c_void,
+                        status: *mut SketchStatus,
+                    ) -> u64;
+                    fn [<kll_ $t _sketch_get_level_capacities_checked>](
+                        sketch: *mut c_void,
+                        capacities: *mut u32,
+                        capacity: u8,
+                        status: *mut SketchStatus,
+                    ) -> u8;
+                    fn [<kll_ $t _sketch_get_cdf_checked>](
+                        sketch: *mut c_void,
+                        split_points: *const $item,
+                        num_split_points: u32,
+                        results: *mut f64,
+                        status: *mut SketchStatus,
+                    );
+                    fn [<kll_ $t _sketch_get_pmf_checked>](
+                        sketch: *mut c_void,
+                        split_points: *const $item,
+                        num_split_points: u32,
+                        results: *mut f64,
+                        status: *mut SketchStatus,
+                    );
+                    fn [<kll_ $t _sketch_set_compaction_callback_checked>](
+                        sketch: *mut c_void,
+                        callback: kll_compaction_callback_t,
+                        user_data: *mut c_void,
+                        status: *mut SketchStatus,
+                    );
+                }
+            }
+        };
+    }
+
+    dispatched! {
+        fn kll_set_random_seed_checked(seed: u64, status: *mut SketchStatus);
+    }
+    dispatched_sketch!(float, f32);
+    dispatched_sketch!(double, f64);
+}
+
+#[cfg(feature = "instrumented")]
+use crate::stats::{self, FfiCall};
+#[cfg(feature = "instrumented")]
+use libc::size_t;
+#[cfg(feature = "instrumented")]
+use std::os::raw::c_void;
+
+/// Defines counting wrappers that forward their arguments unchanged.
+#[cfg(feature = "instrumented")]
+macro_rules! counted {
+    ($($kind:ident => fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?;)*) => {$(
+        pub(crate) unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+            stats::record(FfiCall::$kind);
+            backend::$name($($arg),*)
+        }
+    )*};
+}
+
+/// Defines the wrappers that also track allocations and byte counts.
+#[cfg(feature = "instrumented")]
+macro_rules! lifecycle {
+    ($new_with_k:ident, $copy:ident, $delete:ident,
+     $serialize:ident, $deserialize:ident) => {
+        pub(crate) unsafe fn $new_with_k(k: u16, status: *mut SketchStatus) -> *mut c_void {
+            stats::record(FfiCall::Create);
+            let sketch = backend::$new_with_k(k, status);
+            stats::record_sketch_allocated(sketch);
+            sketch
+        }
+
+        pub(crate) unsafe fn $copy(sketch: *mut c_void, status: *mut SketchStatus) -> *mut c_void {
+            stats::record(FfiCall::Copy);
+            let copy = backend::$copy(sketch, status);
+            stats::record_sketch_allocated(copy);
+            copy
+        }
+
+        pub(crate) unsafe fn $delete(sketch: *mut c_void, status: *mut SketchStatus) {
+            stats::record(FfiCall::Delete);
+            backend::$delete(sketch, status);
+            stats::record_sketch_freed(sketch);
+        }
+
+        pub(crate) unsafe fn $serialize(
+            sketch: *mut c_void,
+            size: *mut size_t,
+            status: *mut SketchStatus,
+        ) -> *mut u8 {
+            stats::record(FfiCall::Serialize);
+            let buffer = backend::$serialize(sketch, size, status);
+            if !buffer.is_null() {
+                stats::record_serialized(buffer, *size);
+            }
+            buffer
+        }
+
+        pub(crate) unsafe fn $deserialize(
+            data: *const u8,
+            size: size_t,
+            status: *mut SketchStatus,
+        ) -> *mut c_void {
+            stats::record(FfiCall::Deserialize);
+            stats::record_deserialized(size);
+            let sketch = backend::$deserialize(data, size, status);
+            stats::record_sketch_allocated(sketch);
+            sketch
+        }
+    };
+}
+
+#[cfg(feature = "instrumented")]
+lifecycle!(
+    kll_float_sketch_new_with_k_checked,
+    kll_float_sketch_copy_checked,
+    kll_float_sketch_delete_checked,
+    kll_float_sketch_serialize_checked,
+    kll_float_sketch_deserialize_checked
+);
+
+#[cfg(feature = "instrumented")]
+lifecycle!(
+    kll_double_sketch_new_with_k_checked,
+    kll_double_sketch_copy_checked,
+    kll_double_sketch_delete_checked,
+    kll_double_sketch_serialize_checked,
+    kll_double_sketch_deserialize_checked
+);
+
+#[cfg(feature = "instrumented")]
+counted! {
+    Configure => fn kll_set_random_seed_checked(seed: u64, status: *mut SketchStatus);
+
+    Update => fn kll_float_sketch_update_checked(
+        sketch: *mut c_void,
+        value: f32,
+        status: *mut SketchStatus
+    );
+    Update => fn kll_float_sketch_update_tracked_checked(
+        sketch: *mut c_void,
+        value: f32,
+        status: *mut SketchStatus
+    ) -> bool;
+    Merge => fn kll_float_sketch_merge_tracked_checked(
+        sketch: *mut c_void,
+        other: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u32;
+    Query => fn kll_float_sketch_is_empty_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> bool;
+    Query => fn kll_float_sketch_get_k_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u16;
+    Query => fn kll_float_sketch_get_n_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u64;
+    Query => fn kll_float_sketch_get_num_retained_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u32;
+    Query => fn kll_float_sketch_is_estimation_mode_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> bool;
+    Query => fn kll_float_sketch_get_min_value_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> f32;
+    Query => fn kll_float_sketch_get_max_value_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> f32;
+    Query => fn kll_float_sketch_get_quantile_checked(
+        sketch: *mut c_void,
+        fraction: f64,
+        status: *mut SketchStatus
+    ) -> f32;
+    Query => fn kll_float_sketch_get_rank_checked(
+        sketch: *mut c_void,
+        value: f32,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_float_sketch_get_quantiles_checked(
+        sketch: *mut c_void,
+        fractions: *const f64,
+        num_fractions: size_t,
+        results: *mut f32,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_float_sketch_get_quantile_criteria_checked(
+        sketch: *mut c_void,
+        fraction: f64,
+        inclusive: bool,
+        status: *mut SketchStatus
+    ) -> f32;
+    Query => fn kll_float_sketch_get_rank_criteria_checked(
+        sketch: *mut c_void,
+        value: f32,
+        inclusive: bool,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_float_sketch_get_quantiles_criteria_checked(
+        sketch: *mut c_void,
+        fractions: *const f64,
+        num_fractions: size_t,
+        inclusive: bool,
+        results: *mut f32,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_float_sketch_get_quantiles_evenly_spaced_checked(
+        sketch: *mut c_void,
+        num: u32,
+        results: *mut f32,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_float_sketch_get_retained_items_checked(
+        sketch: *mut c_void,
+        items: *mut f32,
+        weights: *mut u64,
+        capacity: u32,
+        status: *mut SketchStatus
+    ) -> u32;
+    Query => fn kll_float_sketch_get_normalized_rank_error_checked(
+        sketch: *mut c_void,
+        pmf: bool,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_float_sketch_get_items_discarded_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u64;
+    Query => fn kll_float_sketch_get_level_capacities_checked(
+        sketch: *mut c_void,
+        capacities: *mut u32,
+        capacity: u8,
+        status: *mut SketchStatus
+    ) -> u8;
+    Query => fn kll_float_sketch_get_cdf_checked(
+        sketch: *mut c_void,
+        split_points: *const f32,
+        num_split_points: u32,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_float_sketch_get_pmf_checked(
+        sketch: *mut c_void,
+        split_points: *const f32,
+        num_split_points: u32,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Configure => fn kll_float_sketch_set_compaction_callback_checked(
+        sketch: *mut c_void,
+        callback: kll_compaction_callback_t,
+        user_data: *mut c_void,
+        status: *mut SketchStatus
+    );
+
+    Update => fn kll_double_sketch_update_checked(
+        sketch: *mut c_void,
+        value: f64,
+        status: *mut SketchStatus
+    );
+    Update => fn kll_double_sketch_update_tracked_checked(
+        sketch: *mut c_void,
+        value: f64,
+        status: *mut SketchStatus
+    ) -> bool;
+    Merge => fn kll_double_sketch_merge_tracked_checked(
+        sketch: *mut c_void,
+        other: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u32;
+    Query => fn kll_double_sketch_is_empty_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> bool;
+    Query => fn kll_double_sketch_get_k_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u16;
+    Query => fn kll_double_sketch_get_n_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u64;
+    Query => fn kll_double_sketch_get_num_retained_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u32;
+    Query => fn kll_double_sketch_is_estimation_mode_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> bool;
+    Query => fn kll_double_sketch_get_min_value_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_max_value_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_quantile_checked(
+        sketch: *mut c_void,
+        fraction: f64,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_rank_checked(
+        sketch: *mut c_void,
+        value: f64,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_quantiles_checked(
+        sketch: *mut c_void,
+        fractions: *const f64,
+        num_fractions: size_t,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_double_sketch_get_quantile_criteria_checked(
+        sketch: *mut c_void,
+        fraction: f64,
+        inclusive: bool,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_rank_criteria_checked(
+        sketch: *mut c_void,
+        value: f64,
+        inclusive: bool,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_quantiles_criteria_checked(
+        sketch: *mut c_void,
+        fractions: *const f64,
+        num_fractions: size_t,
+        inclusive: bool,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_double_sketch_get_quantiles_evenly_spaced_checked(
+        sketch: *mut c_void,
+        num: u32,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_double_sketch_get_retained_items_checked(
+        sketch: *mut c_void,
+        items: *mut f64,
+        weights: *mut u64,
+        capacity: u32,
+        status: *mut SketchStatus
+    ) -> u32;
+    Query => fn kll_double_sketch_get_normalized_rank_error_checked(
+        sketch: *mut c_void,
+        pmf: bool,
+        status: *mut SketchStatus
+    ) -> f64;
+    Query => fn kll_double_sketch_get_items_discarded_checked(
+        sketch: *mut c_void,
+        status: *mut SketchStatus
+    ) -> u64;
+    Query => fn kll_double_sketch_get_level_capacities_checked(
+        sketch: *mut c_void,
+        capacities: *mut u32,
+        capacity: u8,
+        status: *mut SketchStatus
+    ) -> u8;
+    Query => fn kll_double_sketch_get_cdf_checked(
+        sketch: *mut c_void,
+        split_points: *const f64,
+        num_split_points: u32,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Query => fn kll_double_sketch_get_pmf_checked(
+        sketch: *mut c_void,
+        split_points: *const f64,
+        num_split_points: u32,
+        results: *mut f64,
+        status: *mut SketchStatus
+    );
+    Configure => fn kll_double_sketch_set_compaction_callback_checked(
+        sketch: *mut c_void,
+        callback: kll_compaction_callback_t,
+        user_data: *mut c_void,
+        status: *mut SketchStatus
+    );
+}
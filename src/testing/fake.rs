@@ -0,0 +1,135 @@
+//! A deterministic [`SketchLike`] test double.
+
+use crate::traits::SketchLike;
+
+/// Records updates and answers queries exactly or from a script.
+///
+/// Quantiles and ranks scripted with [`with_quantile`](Self::with_quantile)
+/// and [`with_rank`](Self::with_rank) are returned as given; anything else is
+/// computed exactly from the recorded values, with the same inclusive rank
+/// semantics as the real sketches.
+///
+/// ```
+/// use kll_rs::testing::FakeSketch;
+/// use kll_rs::SketchLike;
+///
+/// let mut sketch = FakeSketch::new().with_quantile(0.99, 250.0).with_n(1000);
+/// sketch.update(3.0);
+/// assert_eq!(sketch.get_quantile(0.99), 250.0);
+/// assert_eq!(sketch.get_quantile(0.5), 3.0);
+/// assert_eq!(sketch.updates(), &[3.0]);
+/// assert_eq!(sketch.get_n(), 1000);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FakeSketch {
+    updates: Vec<f64>,
+    quantiles: Vec<(f64, f64)>,
+    ranks: Vec<(f64, f64)>,
+    n: Option<u64>,
+}
+
+impl FakeSketch {
+    /// Creates a fake with no recorded values and nothing scripted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scripts the value returned for `fraction`.
+    pub fn with_quantile(mut self, fraction: f64, value: f64) -> Self {
+        self.quantiles.retain(|&(f, _)| f != fraction);
+        self.quantiles.push((fraction, value));
+        self
+    }
+
+    /// Scripts the rank returned for `value`.
+    pub fn with_rank(mut self, value: f64, rank: f64) -> Self {
+        self.ranks.retain(|&(v, _)| v != value);
+        self.ranks.push((value, rank));
+        self
+    }
+
+    /// Scripts the count, instead of the number of recorded values.
+    pub fn with_n(mut self, n: u64) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    /// Returns the values passed to `update`, in order.
+    pub fn updates(&self) -> &[f64] {
+        &self.updates
+    }
+
+    /// Forgets the recorded values, keeping the script.
+    pub fn clear_updates(&mut self) {
+        self.updates.clear();
+    }
+
+    fn sorted(&self) -> Vec<f64> {
+        let mut values: Vec<f64> = self
+            .updates
+            .iter()
+            .copied()
+            .filter(|v| !v.is_nan())
+            .collect();
+        values.sort_by(f64::total_cmp);
+        values
+    }
+}
+
+impl SketchLike for FakeSketch {
+    fn update(&mut self, value: f64) {
+        self.updates.push(value);
+    }
+
+    fn get_n(&self) -> u64 {
+        self.n.unwrap_or(self.updates.len() as u64)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        if let Some(&(_, value)) = self.quantiles.iter().find(|&&(f, _)| f == fraction) {
+            return value;
+        }
+        let values = self.sorted();
+        if values.is_empty() || !(0.0..=1.0).contains(&fraction) {
+            return f64::NAN;
+        }
+        let index = (fraction * values.len() as f64).ceil() as usize;
+        values[index.saturating_sub(1)]
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        if let Some(&(_, rank)) = self.ranks.iter().find(|&&(v, _)| v == value) {
+            return rank;
+        }
+        let values = self.sorted();
+        if values.is_empty() {
+            return f64::NAN;
+        }
+        values.iter().filter(|&&v| v <= value).count() as f64 / values.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_sketch() {
+        let mut fake = FakeSketch::new();
+        assert!(fake.is_empty());
+        assert!(fake.get_quantile(0.5).is_nan());
+        assert!(fake.get_quantiles(&[0.5]).is_empty());
+
+        for value in [4.0, 1.0, 3.0, 2.0] {
+            fake.update(value);
+        }
+        assert_eq!(fake.updates(), &[4.0, 1.0, 3.0, 2.0]);
+        assert_eq!(fake.get_quantiles(&[0.0, 0.5, 1.0]), vec![1.0, 2.0, 4.0]);
+        assert_eq!(fake.get_rank(2.0), 0.5);
+
+        let fake = fake.with_quantile(0.5, 10.0).with_rank(2.0, 0.9);
+        assert_eq!(fake.get_quantile(0.5), 10.0);
+        assert_eq!(fake.get_rank(2.0), 0.9);
+        assert_eq!(fake.get_quantile(1.0), 4.0);
+    }
+}
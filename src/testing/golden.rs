@@ -0,0 +1,240 @@
+//! Golden fixtures for sketch wire stability.
+//!
+//! A fixture is a file holding one sketch in the DataSketches format. The
+//! first run of [`assert_golden`] writes it, later runs compare against it,
+//! and setting `KLL_UPDATE_GOLDEN=1` rewrites it:
+//!
+//! ```no_run
+//! use kll_rs::testing::golden::assert_golden;
+//! use kll_rs::KllDoubleSketch;
+//!
+//! kll_rs::set_compaction_seed(7);
+//! let mut sketch = KllDoubleSketch::new();
+//! for i in 0..10_000 {
+//!     sketch.update(i as f64);
+//! }
+//! assert_golden("tests/fixtures/latency.kll", &sketch.serialize().unwrap());
+//! ```
+//!
+//! Sketches past their first compaction depend on the compaction randomness,
+//! so seed it before building a sketch meant to match a fixture.
+
+use crate::any::{deserialize_any, AnySketch, SketchKind};
+use crate::error::{DataSketchesError, Result};
+use std::fmt;
+use std::path::Path;
+
+/// Environment variable that makes [`assert_golden`] rewrite its fixture.
+pub const UPDATE_GOLDEN_ENV: &str = "KLL_UPDATE_GOLDEN";
+
+/// Fractions whose quantiles a [`GoldenDiff`] reports.
+pub const GOLDEN_FRACTIONS: [f64; 9] = [0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0];
+
+/// How a sketch differs from its fixture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenDiff {
+    /// Item type of the fixture and of the actual sketch.
+    pub kind: (SketchKind, SketchKind),
+    /// `k` of the fixture and of the actual sketch.
+    pub k: (u16, u16),
+    /// Count of the fixture and of the actual sketch.
+    pub n: (u64, u64),
+    /// Serialized length of the fixture and of the actual sketch.
+    pub len: (usize, usize),
+    /// Offset of the first differing byte.
+    pub first_difference: usize,
+    /// Fraction, fixture quantile and actual quantile for each of
+    /// [`GOLDEN_FRACTIONS`], when both sketches are non-empty.
+    pub quantiles: Vec<(f64, f64, f64)>,
+}
+
+impl GoldenDiff {
+    /// Returns the largest absolute quantile difference.
+    pub fn max_quantile_delta(&self) -> f64 {
+        self.quantiles
+            .iter()
+            .map(|&(_, expected, actual)| (actual - expected).abs())
+            .fold(0.0, f64::max)
+    }
+}
+
+impl fmt::Display for GoldenDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sketch differs from its fixture:")?;
+        let mut field = |name: &str, expected: String, actual: String| {
+            let marker = if expected == actual { " " } else { "*" };
+            writeln!(f, "{} {:<6} {} -> {}", marker, name, expected, actual)
+        };
+        field(
+            "kind",
+            format!("{:?}", self.kind.0),
+            format!("{:?}", self.kind.1),
+        )?;
+        field("k", self.k.0.to_string(), self.k.1.to_string())?;
+        field("n", self.n.0.to_string(), self.n.1.to_string())?;
+        field("bytes", self.len.0.to_string(), self.len.1.to_string())?;
+        writeln!(
+            f,
+            "  first differing byte at offset {}",
+            self.first_difference
+        )?;
+        for &(fraction, expected, actual) in &self.quantiles {
+            writeln!(
+                f,
+                "  q{:<5} {} -> {} (delta {})",
+                fraction,
+                expected,
+                actual,
+                actual - expected
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares serialized sketches, returning `None` if they are identical.
+///
+/// Both must deserialize as KLL sketches; the diff describes them decoded.
+pub fn compare(expected: &[u8], actual: &[u8]) -> Result<Option<GoldenDiff>> {
+    if expected == actual {
+        return Ok(None);
+    }
+    let (old, new) = (deserialize_any(expected)?, deserialize_any(actual)?);
+    let quantiles = if old.is_empty() || new.is_empty() {
+        Vec::new()
+    } else {
+        let (old_q, new_q) = (
+            old.get_quantiles(&GOLDEN_FRACTIONS),
+            new.get_quantiles(&GOLDEN_FRACTIONS),
+        );
+        GOLDEN_FRACTIONS
+            .iter()
+            .zip(old_q.into_iter().zip(new_q))
+            .map(|(&fraction, (e, a))| (fraction, e, a))
+            .collect()
+    };
+    let first_difference = expected
+        .iter()
+        .zip(actual)
+        .position(|(e, a)| e != a)
+        .unwrap_or(expected.len().min(actual.len()));
+    Ok(Some(GoldenDiff {
+        kind: (old.kind(), new.kind()),
+        k: (old.get_k(), new.get_k()),
+        n: (old.get_n(), new.get_n()),
+        len: (expected.len(), actual.len()),
+        first_difference,
+        quantiles,
+    }))
+}
+
+/// Writes serialized sketch bytes to a fixture, creating parent directories.
+pub fn write_fixture<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| io_error(path, e))?;
+    }
+    std::fs::write(path, bytes).map_err(|e| io_error(path, e))
+}
+
+/// Loads a fixture as a sketch of whichever item type it holds.
+pub fn load_fixture<P: AsRef<Path>>(path: P) -> Result<AnySketch> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|e| io_error(path, e))?;
+    deserialize_any(&bytes)
+}
+
+/// Compares bytes with a fixture, writing the fixture first if it is missing
+/// or `KLL_UPDATE_GOLDEN` is set.
+pub fn check_golden<P: AsRef<Path>>(path: P, actual: &[u8]) -> Result<Option<GoldenDiff>> {
+    let path = path.as_ref();
+    if !path.exists() || std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        deserialize_any(actual)?;
+        write_fixture(path, actual)?;
+        return Ok(None);
+    }
+    let expected = std::fs::read(path).map_err(|e| io_error(path, e))?;
+    compare(&expected, actual)
+}
+
+/// Asserts that bytes match a fixture, as [`check_golden`].
+///
+/// # Panics
+///
+/// Panics with a [`GoldenDiff`] if they differ, or if either side cannot be
+/// read.
+pub fn assert_golden<P: AsRef<Path>>(path: P, actual: &[u8]) {
+    let path = path.as_ref();
+    match check_golden(path, actual) {
+        Ok(None) => {}
+        Ok(Some(diff)) => panic!(
+            "{}: {}(rerun with {}=1 to accept the change)",
+            path.display(),
+            diff,
+            UPDATE_GOLDEN_ENV
+        ),
+        Err(err) => panic!("{}: {}", path.display(), err),
+    }
+}
+
+/// Asserts that a fixture still deserializes and serializes back to the same
+/// bytes, i.e. that this build reads and writes the stored format unchanged.
+///
+/// # Panics
+///
+/// Panics with a [`GoldenDiff`] if the round trip changes the bytes, or if
+/// the fixture cannot be read.
+pub fn assert_compatible<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let result = std::fs::read(path)
+        .map_err(|e| io_error(path, e))
+        .and_then(|bytes| {
+            let round_trip = deserialize_any(&bytes)?.serialize()?;
+            compare(&bytes, &round_trip)
+        });
+    match result {
+        Ok(None) => {}
+        Ok(Some(diff)) => panic!("{}: round trip changed the bytes, {}", path.display(), diff),
+        Err(err) => panic!("{}: {}", path.display(), err),
+    }
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> DataSketchesError {
+    DataSketchesError::Unknown(format!("fixture {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KllDoubleSketch;
+
+    fn sketch(n: u32) -> Vec<u8> {
+        let mut sketch = KllDoubleSketch::new();
+        for i in 1..=n {
+            sketch.update(i as f64);
+        }
+        sketch.serialize().unwrap()
+    }
+
+    #[test]
+    fn test_golden_fixtures() {
+        let dir = std::env::temp_dir().join(format!("kll_rs_golden_{}", std::process::id()));
+        let path = dir.join("sketch.kll");
+
+        assert_golden(&path, &sketch(100));
+        assert_golden(&path, &sketch(100));
+        assert_compatible(&path);
+        assert_eq!(load_fixture(&path).unwrap().get_n(), 100);
+
+        let diff = check_golden(&path, &sketch(150)).unwrap().unwrap();
+        assert_eq!(diff.n, (100, 150));
+        assert_eq!(diff.k, (200, 200));
+        assert_eq!(diff.max_quantile_delta(), 50.0);
+        let text = diff.to_string();
+        assert!(text.contains("* n      100 -> 150"), "{}", text);
+        assert!(text.contains("  k      200 -> 200"), "{}", text);
+
+        assert!(compare(&sketch(3), &[1, 2, 3]).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+//! Helpers for testing code that uses sketches.
+
+mod fake;
+pub mod golden;
+
+#[cfg(feature = "failpoints")]
+pub use crate::failpoints::{FailGuard, FailPoint};
+#[cfg(feature = "leak-tracking")]
+pub use crate::leak::LeakCheck;
+pub use fake::FakeSketch;
@@ -0,0 +1,8 @@
+//! Shared helpers for timing closures and futures into sketches.
+
+use std::time::Instant;
+
+/// Returns the time since `start` in fractional milliseconds.
+pub(crate) fn elapsed_millis(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
@@ -0,0 +1,244 @@
+//! Traits shared by the quantile sketch types.
+
+use crate::error::Result;
+use crate::{KllDoubleSketch, KllFloatSketch};
+
+/// Common interface of the KLL sketch types.
+///
+/// Lets code be written once for both item types. The methods have the same
+/// meaning as the inherent methods of the same name.
+pub trait QuantileSketch {
+    /// Type of the values the sketch summarizes.
+    type Item: Copy + PartialOrd;
+
+    /// Updates the sketch with a new value.
+    fn update(&mut self, value: Self::Item);
+
+    /// Merges another sketch of the same type into this one.
+    fn merge(&mut self, other: &Self) -> Result<()>;
+
+    /// Returns true if the sketch is empty.
+    fn is_empty(&self) -> bool;
+
+    /// Returns the number of values processed.
+    fn get_n(&self) -> u64;
+
+    /// Returns the approximate quantile for a given fraction.
+    fn get_quantile(&self, fraction: f64) -> Self::Item;
+
+    /// Returns quantiles for multiple fractions.
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<Self::Item>;
+
+    /// Returns the fraction of values less than or equal to `value`.
+    fn get_rank(&self, value: Self::Item) -> f64;
+}
+
+/// Object-safe view of a sketch as a source of `f64` quantiles.
+///
+/// Code that only feeds values in and reads quantiles out, such as alerting
+/// rules, can take a `SketchLike` so tests can pass a
+/// [`FakeSketch`](crate::testing::FakeSketch) instead of a native sketch.
+/// Empty sketches return NaN for quantiles and ranks.
+pub trait SketchLike {
+    /// Updates the sketch with a new value.
+    fn update(&mut self, value: f64);
+
+    /// Returns the number of values processed.
+    fn get_n(&self) -> u64;
+
+    /// Returns true if the sketch is empty.
+    fn is_empty(&self) -> bool {
+        self.get_n() == 0
+    }
+
+    /// Returns the approximate quantile for a given fraction.
+    fn get_quantile(&self, fraction: f64) -> f64;
+
+    /// Returns quantiles for multiple fractions, or none if the sketch is empty.
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        if self.is_empty() {
+            return vec![];
+        }
+        fractions.iter().map(|&f| self.get_quantile(f)).collect()
+    }
+
+    /// Returns the fraction of values less than or equal to `value`.
+    fn get_rank(&self, value: f64) -> f64;
+}
+
+/// State that replicas can exchange and combine, for use with
+/// eventually-consistent replication.
+///
+/// [`merge_from`](Self::merge_from) is associative and commutative and
+/// [`empty_like`](Self::empty_like) is its identity, so replicas may combine
+/// each other's states in any grouping and order. For the sketches these laws
+/// hold in distribution: exact counts, minimum and maximum are always equal,
+/// while compaction randomness can move estimated quantiles within the rank
+/// error bound.
+///
+/// Merging is not idempotent: merging the same state twice counts its values
+/// twice. That suits delivery schemes that hand each state or delta over
+/// exactly once, such as operation-based or delta CRDTs with deduplication,
+/// but not state-based CRDTs that re-merge full states on every exchange.
+/// There, keep one sketch per replica and merge them when reading.
+///
+/// ```
+/// use kll_rs::{KllDoubleSketch, Mergeable};
+///
+/// let mut replicas = vec![KllDoubleSketch::new(), KllDoubleSketch::new()];
+/// replicas[0].update(1.0);
+/// replicas[1].update(2.0);
+///
+/// let mut total = replicas[0].empty_like();
+/// total.merge_all(&replicas)?;
+/// assert_eq!(total.get_n(), 2);
+/// # Ok::<(), kll_rs::DataSketchesError>(())
+/// ```
+pub trait Mergeable: Sized {
+    /// Returns an empty state configured like this one, which leaves any
+    /// state unchanged when merged into it.
+    fn empty_like(&self) -> Self;
+
+    /// Combines another replica's state into this one.
+    fn merge_from(&mut self, other: &Self) -> Result<()>;
+
+    /// Combines every state in `others` into this one.
+    fn merge_all<'a, I>(&mut self, others: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Self>,
+        Self: 'a,
+    {
+        for other in others {
+            self.merge_from(other)?;
+        }
+        Ok(())
+    }
+}
+
+impl SketchLike for KllDoubleSketch {
+    fn update(&mut self, value: f64) {
+        KllDoubleSketch::update(self, value)
+    }
+
+    fn get_n(&self) -> u64 {
+        KllDoubleSketch::get_n(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        KllDoubleSketch::is_empty(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        KllDoubleSketch::get_quantile(self, fraction)
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        KllDoubleSketch::get_quantiles(self, fractions)
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        KllDoubleSketch::get_rank(self, value)
+    }
+}
+
+impl SketchLike for KllFloatSketch {
+    fn update(&mut self, value: f64) {
+        KllFloatSketch::update(self, value as f32)
+    }
+
+    fn get_n(&self) -> u64 {
+        KllFloatSketch::get_n(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        KllFloatSketch::is_empty(self)
+    }
+
+    fn get_quantile(&self, fraction: f64) -> f64 {
+        KllFloatSketch::get_quantile(self, fraction).into()
+    }
+
+    fn get_quantiles(&self, fractions: &[f64]) -> Vec<f64> {
+        KllFloatSketch::get_quantiles(self, fractions)
+            .into_iter()
+            .map(f64::from)
+            .collect()
+    }
+
+    fn get_rank(&self, value: f64) -> f64 {
+        KllFloatSketch::get_rank(self, value as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn median<S: QuantileSketch>(sketch: &mut S, values: &[S::Item]) -> S::Item {
+        for &value in values {
+            sketch.update(value);
+        }
+        sketch.get_quantile(0.5)
+    }
+
+    #[test]
+    fn test_generic_over_item_type() {
+        assert_eq!(median(&mut KllDoubleSketch::new(), &[1.0, 2.0, 3.0]), 2.0);
+        assert_eq!(median(&mut KllFloatSketch::new(), &[1.0, 2.0, 3.0]), 2.0);
+    }
+
+    #[test]
+    fn test_sketch_like_objects() {
+        let mut sketches: Vec<Box<dyn SketchLike>> = vec![
+            Box::new(KllDoubleSketch::new()),
+            Box::new(KllFloatSketch::new()),
+        ];
+        for sketch in &mut sketches {
+            assert!(sketch.get_quantiles(&[0.5]).is_empty());
+            for i in 1..=3 {
+                sketch.update(i as f64);
+            }
+            assert_eq!(sketch.get_quantiles(&[0.5, 1.0]), vec![2.0, 3.0]);
+            assert_eq!(sketch.get_rank(1.0), 1.0 / 3.0);
+        }
+    }
+
+    fn merge_grouped<S: Mergeable>(states: &[S], split: usize) -> S {
+        let mut left = states[0].empty_like();
+        left.merge_all(&states[..split]).unwrap();
+        let mut right = states[0].empty_like();
+        right.merge_all(states[split..].iter().rev()).unwrap();
+        right.merge_from(&left).unwrap();
+        right
+    }
+
+    #[test]
+    fn test_mergeable_laws() {
+        let replicas: Vec<KllDoubleSketch> = (0..4)
+            .map(|r| {
+                let mut sketch = KllDoubleSketch::new_with_k(64).unwrap();
+                for i in 0..1000 {
+                    sketch.update((r * 1000 + i) as f64);
+                }
+                sketch
+            })
+            .collect();
+        for split in 1..replicas.len() {
+            let merged = merge_grouped(&replicas, split);
+            assert_eq!(merged.get_k(), 64);
+            assert_eq!(merged.get_n(), 4000);
+            assert_eq!(merged.get_min_value(), 0.0);
+            assert_eq!(merged.get_max_value(), 3999.0);
+            let error = merged.get_normalized_rank_error(false);
+            assert!((merged.get_rank(2000.0) - 0.5).abs() < error);
+        }
+
+        let mut once = replicas[0].empty_like();
+        once.merge_from(&replicas[0]).unwrap();
+        once.merge_from(&replicas[0]).unwrap();
+        assert_eq!(once.get_n(), 2000, "merging is not idempotent");
+
+        let floats = vec![KllFloatSketch::new(), KllFloatSketch::new()];
+        assert!(merge_grouped(&floats, 1).is_empty());
+    }
+}
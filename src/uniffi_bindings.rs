@@ -0,0 +1,288 @@
+//! UniFFI interface for Kotlin and Swift consumers.
+//!
+//! The exported objects wrap the native sketches behind a mutex because UniFFI
+//! hands out shared references to foreign code. Bytes produced by `serialize`
+//! are the regular DataSketches wire format, so sketches recorded on device can
+//! be merged with sketches produced by the Rust API.
+
+use crate::error::Result;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A KLL sketch for double values, exported through UniFFI.
+#[derive(Debug, uniffi::Object)]
+pub struct KllDoubleSketch {
+    inner: Mutex<crate::KllDoubleSketch>,
+}
+
+impl KllDoubleSketch {
+    fn wrap(sketch: crate::KllDoubleSketch) -> Arc<Self> {
+        Arc::new(KllDoubleSketch {
+            inner: Mutex::new(sketch),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, crate::KllDoubleSketch> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[uniffi::export]
+impl KllDoubleSketch {
+    /// Creates a new sketch with default parameters.
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Self::wrap(crate::KllDoubleSketch::new())
+    }
+
+    /// Creates a new sketch with a specific k parameter.
+    #[uniffi::constructor]
+    pub fn new_with_k(k: u16) -> Result<Arc<Self>> {
+        crate::KllDoubleSketch::new_with_k(k).map(Self::wrap)
+    }
+
+    /// Deserializes a sketch from bytes.
+    #[uniffi::constructor]
+    pub fn deserialize(data: Vec<u8>) -> Result<Arc<Self>> {
+        crate::KllDoubleSketch::deserialize(&data).map(Self::wrap)
+    }
+
+    /// Updates the sketch with a new value.
+    pub fn update(&self, value: f64) {
+        self.lock().update(value);
+    }
+
+    /// Merges another sketch into this one.
+    pub fn merge(&self, other: Arc<KllDoubleSketch>) -> Result<()> {
+        // Copy `other` and release its lock before taking ours, so that
+        // `a.merge(b)` racing `b.merge(a)`, or merging a sketch into itself,
+        // never waits on a lock this thread or the other one holds.
+        let copy = other.lock().copy()?;
+        self.lock().merge(&copy)
+    }
+
+    /// Returns true if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Returns the k parameter of the sketch.
+    pub fn get_k(&self) -> u16 {
+        self.lock().get_k()
+    }
+
+    /// Returns the number of values processed by the sketch.
+    pub fn get_n(&self) -> u64 {
+        self.lock().get_n()
+    }
+
+    /// Returns the number of values retained by the sketch.
+    pub fn get_num_retained(&self) -> u32 {
+        self.lock().get_num_retained()
+    }
+
+    /// Returns true if the sketch is in estimation mode.
+    pub fn is_estimation_mode(&self) -> bool {
+        self.lock().is_estimation_mode()
+    }
+
+    /// Returns the minimum value seen by the sketch.
+    pub fn get_min_value(&self) -> f64 {
+        self.lock().get_min_value()
+    }
+
+    /// Returns the maximum value seen by the sketch.
+    pub fn get_max_value(&self) -> f64 {
+        self.lock().get_max_value()
+    }
+
+    /// Returns the approximate quantile for a given fraction.
+    pub fn get_quantile(&self, fraction: f64) -> f64 {
+        self.lock().get_quantile(fraction)
+    }
+
+    /// Returns the approximate rank of a value.
+    pub fn get_rank(&self, value: f64) -> f64 {
+        self.lock().get_rank(value)
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: Vec<f64>) -> Vec<f64> {
+        self.lock().get_quantiles(&fractions)
+    }
+
+    /// Serializes the sketch to bytes.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        self.lock().serialize()
+    }
+
+    /// Returns the accumulated sketch and resets this one to empty.
+    ///
+    /// Both steps happen under one lock, so updates from other threads land
+    /// either in the returned sketch or in the reset one.
+    pub fn swap_and_reset(&self) -> Arc<Self> {
+        Self::wrap(self.lock().swap_and_reset())
+    }
+}
+
+/// A KLL sketch for float values, exported through UniFFI.
+#[derive(Debug, uniffi::Object)]
+pub struct KllFloatSketch {
+    inner: Mutex<crate::KllFloatSketch>,
+}
+
+impl KllFloatSketch {
+    fn wrap(sketch: crate::KllFloatSketch) -> Arc<Self> {
+        Arc::new(KllFloatSketch {
+            inner: Mutex::new(sketch),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, crate::KllFloatSketch> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[uniffi::export]
+impl KllFloatSketch {
+    /// Creates a new sketch with default parameters.
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Self::wrap(crate::KllFloatSketch::new())
+    }
+
+    /// Creates a new sketch with a specific k parameter.
+    #[uniffi::constructor]
+    pub fn new_with_k(k: u16) -> Result<Arc<Self>> {
+        crate::KllFloatSketch::new_with_k(k).map(Self::wrap)
+    }
+
+    /// Deserializes a sketch from bytes.
+    #[uniffi::constructor]
+    pub fn deserialize(data: Vec<u8>) -> Result<Arc<Self>> {
+        crate::KllFloatSketch::deserialize(&data).map(Self::wrap)
+    }
+
+    /// Updates the sketch with a new value.
+    pub fn update(&self, value: f32) {
+        self.lock().update(value);
+    }
+
+    /// Merges another sketch into this one.
+    pub fn merge(&self, other: Arc<KllFloatSketch>) -> Result<()> {
+        // Copy `other` and release its lock before taking ours, so that
+        // `a.merge(b)` racing `b.merge(a)`, or merging a sketch into itself,
+        // never waits on a lock this thread or the other one holds.
+        let copy = other.lock().copy()?;
+        self.lock().merge(&copy)
+    }
+
+    /// Returns true if the sketch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Returns the k parameter of the sketch.
+    pub fn get_k(&self) -> u16 {
+        self.lock().get_k()
+    }
+
+    /// Returns the number of values processed by the sketch.
+    pub fn get_n(&self) -> u64 {
+        self.lock().get_n()
+    }
+
+    /// Returns the number of values retained by the sketch.
+    pub fn get_num_retained(&self) -> u32 {
+        self.lock().get_num_retained()
+    }
+
+    /// Returns true if the sketch is in estimation mode.
+    pub fn is_estimation_mode(&self) -> bool {
+        self.lock().is_estimation_mode()
+    }
+
+    /// Returns the minimum value seen by the sketch.
+    pub fn get_min_value(&self) -> f32 {
+        self.lock().get_min_value()
+    }
+
+    /// Returns the maximum value seen by the sketch.
+    pub fn get_max_value(&self) -> f32 {
+        self.lock().get_max_value()
+    }
+
+    /// Returns the approximate quantile for a given fraction.
+    pub fn get_quantile(&self, fraction: f64) -> f32 {
+        self.lock().get_quantile(fraction)
+    }
+
+    /// Returns the approximate rank of a value.
+    pub fn get_rank(&self, value: f32) -> f64 {
+        self.lock().get_rank(value)
+    }
+
+    /// Returns quantiles for multiple fractions.
+    pub fn get_quantiles(&self, fractions: Vec<f64>) -> Vec<f32> {
+        self.lock().get_quantiles(&fractions)
+    }
+
+    /// Serializes the sketch to bytes.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        self.lock().serialize()
+    }
+
+    /// Returns the accumulated sketch and resets this one to empty.
+    ///
+    /// Both steps happen under one lock, so updates from other threads land
+    /// either in the returned sketch or in the reset one.
+    pub fn swap_and_reset(&self) -> Arc<Self> {
+        Self::wrap(self.lock().swap_and_reset())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DataSketchesError;
+
+    #[test]
+    fn test_shared_sketch_roundtrip() {
+        let sketch = KllDoubleSketch::new();
+        for i in 1..=100 {
+            sketch.update(i as f64);
+        }
+        sketch.merge(sketch.clone()).unwrap();
+        assert_eq!(sketch.get_n(), 200);
+
+        let restored = KllDoubleSketch::deserialize(sketch.serialize().unwrap()).unwrap();
+        assert_eq!(restored.get_n(), 200);
+        assert_eq!(restored.get_max_value(), 100.0);
+    }
+
+    #[test]
+    fn test_crossed_merges_do_not_deadlock() {
+        let a = KllDoubleSketch::new();
+        let b = KllDoubleSketch::new();
+        a.update(1.0);
+        b.update(2.0);
+        let (a2, b2) = (a.clone(), b.clone());
+        let forward = std::thread::spawn(move || {
+            for _ in 0..30 {
+                a2.merge(b2.clone()).unwrap();
+            }
+        });
+        for _ in 0..30 {
+            b.merge(a.clone()).unwrap();
+        }
+        forward.join().unwrap();
+        assert!(a.get_n() > 30 && b.get_n() > 30);
+    }
+
+    #[test]
+    fn test_error_crosses_interface() {
+        match KllFloatSketch::new_with_k(2) {
+            Err(DataSketchesError::InvalidParameter(_)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Union accumulator for KLL sketches.
+
+use crate::error::Result;
+use crate::traits::Mergeable;
+use crate::KllDoubleSketch;
+
+/// Accumulates sketches and raw values into a result with a fixed k.
+///
+/// This mirrors the union idiom from the wider DataSketches ecosystem: the
+/// union owns its own internal sketch built with the target k, so the result
+/// always reports that k no matter which inputs arrive first. Merging into an
+/// arbitrary input sketch instead would keep whatever k that sketch happened
+/// to have.
+#[derive(Debug, Clone)]
+pub struct KllUnion {
+    gadget: KllDoubleSketch,
+}
+
+impl KllUnion {
+    /// Creates an empty union whose result will have the given k.
+    pub fn new(k: u16) -> Result<Self> {
+        Ok(KllUnion {
+            gadget: KllDoubleSketch::new_with_k(k)?,
+        })
+    }
+
+    /// Returns the k of the result sketch.
+    pub fn get_k(&self) -> u16 {
+        self.gadget.get_k()
+    }
+
+    /// Returns true if nothing has been added to the union.
+    pub fn is_empty(&self) -> bool {
+        self.gadget.is_empty()
+    }
+
+    /// Returns the total number of values across everything added.
+    pub fn get_n(&self) -> u64 {
+        self.gadget.get_n()
+    }
+
+    /// Adds a sketch of any k to the union.
+    pub fn update(&mut self, sketch: &KllDoubleSketch) -> Result<()> {
+        self.gadget.merge_mixed_k(sketch)
+    }
+
+    /// Adds a single raw value to the union.
+    pub fn update_value(&mut self, value: f64) {
+        self.gadget.update(value);
+    }
+
+    /// Returns a copy of the current result, leaving the union usable.
+    pub fn get_result(&self) -> Result<KllDoubleSketch> {
+        self.gadget.copy()
+    }
+
+    /// Consumes the union and returns the result sketch.
+    pub fn into_result(self) -> KllDoubleSketch {
+        self.gadget
+    }
+
+    /// Clears the union, keeping its k.
+    pub fn reset(&mut self) -> Result<()> {
+        self.gadget = KllDoubleSketch::new_with_k(self.get_k())?;
+        Ok(())
+    }
+}
+
+impl Mergeable for KllUnion {
+    fn empty_like(&self) -> Self {
+        KllUnion::new(self.get_k()).expect("k of an existing union is valid")
+    }
+
+    /// Adds everything the other union has accumulated.
+    fn merge_from(&mut self, other: &Self) -> Result<()> {
+        self.update(&other.gadget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_union_keeps_target_k() {
+        let mut small = KllDoubleSketch::new_with_k(64).unwrap();
+        let mut large = KllDoubleSketch::new_with_k(400).unwrap();
+        for i in 0..1000 {
+            small.update(i as f64);
+            large.update((i + 1000) as f64);
+        }
+
+        let mut union = KllUnion::new(200).unwrap();
+        union.update(&small).unwrap();
+        union.update(&large).unwrap();
+        union.update_value(-1.0);
+
+        let result = union.get_result().unwrap();
+        assert_eq!(result.get_k(), 200);
+        assert_eq!(result.get_n(), 2001);
+        assert_eq!(result.get_min_value(), -1.0);
+        assert_eq!(result.get_max_value(), 1999.0);
+
+        union.reset().unwrap();
+        assert!(union.is_empty());
+        assert_eq!(union.into_result().get_k(), 200);
+    }
+
+    #[test]
+    fn test_union_rejects_small_k() {
+        assert!(KllUnion::new(4).is_err());
+    }
+}
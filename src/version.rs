@@ -0,0 +1,57 @@
+//! Build and version introspection.
+
+use crate::format::SERIAL_VERSION_1;
+use std::fmt;
+
+/// Versions of the crate and the native library it was built against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Version of the `kll-rs` crate.
+    pub crate_version: &'static str,
+    /// Version of the vendored datasketches-cpp library.
+    pub datasketches_cpp_version: &'static str,
+    /// Commit of the vendored datasketches-cpp checkout.
+    pub datasketches_cpp_commit: &'static str,
+    /// Major version of the datasketches-cpp API the wrapper was compiled against.
+    pub datasketches_cpp_api_major: u8,
+    /// Serial version written for full sketches.
+    pub serial_version: u8,
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "kll-rs {} (datasketches-cpp {} @ {}, api {}.x, serial version {})",
+            self.crate_version,
+            self.datasketches_cpp_version,
+            self.datasketches_cpp_commit,
+            self.datasketches_cpp_api_major,
+            self.serial_version
+        )
+    }
+}
+
+/// Returns version information recorded when the crate was built.
+pub fn version() -> VersionInfo {
+    VersionInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        datasketches_cpp_version: crate::sys::DATASKETCHES_CPP_VERSION,
+        datasketches_cpp_commit: crate::sys::DATASKETCHES_CPP_COMMIT,
+        datasketches_cpp_api_major: crate::sys::DATASKETCHES_CPP_API_MAJOR,
+        serial_version: SERIAL_VERSION_1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_info() {
+        let info = version();
+        assert_eq!(info.crate_version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.datasketches_cpp_version.is_empty());
+        assert!(info.to_string().starts_with("kll-rs "));
+    }
+}
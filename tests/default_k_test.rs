@@ -0,0 +1,19 @@
+// The process-wide default k can only be set once, so this lives in its own
+// test binary to keep it away from tests that rely on k=200.
+use kll_rs::{default_k, set_default_k, HybridQuantiles, KllDoubleSketch, KllFloatSketch};
+
+#[test]
+fn test_set_default_k() {
+    assert!(set_default_k(4).is_err());
+    set_default_k(256).unwrap();
+    assert!(set_default_k(128).is_err());
+    assert_eq!(default_k(), 256);
+
+    assert_eq!(KllDoubleSketch::new().get_k(), 256);
+    assert_eq!(KllFloatSketch::default().get_k(), 256);
+    assert_eq!(KllDoubleSketch::new_with_k(64).unwrap().get_k(), 64);
+
+    let mut hybrid = HybridQuantiles::new(0);
+    hybrid.update(1.0).unwrap();
+    assert_eq!(hybrid.sketch().unwrap().get_k(), 256);
+}
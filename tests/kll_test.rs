@@ -1,8 +1,13 @@
-use kll_rs::{KllDoubleSketch, KllFloatSketch};
+#![allow(clippy::manual_range_contains)]
+
+use kll_rs::{
+    detect_serial_version, needs_migration, KllDoubleSketch, KllFloatSketch, SERIAL_VERSION_1,
+    SERIAL_VERSION_2,
+};
 
 #[test]
 fn test_float_sketch_basic_functionality() {
-    let mut sketch = KllFloatSketch::new().unwrap();
+    let mut sketch = KllFloatSketch::new();
 
     // Test that new sketch is empty
     assert!(sketch.is_empty());
@@ -32,7 +37,7 @@ fn test_float_sketch_basic_functionality() {
 
 #[test]
 fn test_double_sketch_basic_functionality() {
-    let mut sketch = KllDoubleSketch::new().unwrap();
+    let mut sketch = KllDoubleSketch::new();
 
     // Test that new sketch is empty
     assert!(sketch.is_empty());
@@ -62,7 +67,7 @@ fn test_double_sketch_basic_functionality() {
 
 #[test]
 fn test_serialization() {
-    let mut sketch = KllFloatSketch::new().unwrap();
+    let mut sketch = KllFloatSketch::new();
 
     for i in 1..=100 {
         sketch.update(i as f32);
@@ -83,8 +88,8 @@ fn test_serialization() {
 
 #[test]
 fn test_merge() {
-    let mut sketch1 = KllFloatSketch::new().unwrap();
-    let mut sketch2 = KllFloatSketch::new().unwrap();
+    let mut sketch1 = KllFloatSketch::new();
+    let mut sketch2 = KllFloatSketch::new();
 
     // Add different ranges to each sketch
     for i in 1..=50 {
@@ -112,3 +117,28 @@ fn test_custom_k() {
 
     println!("Custom k test passed!");
 }
+
+#[test]
+fn test_migrate_legacy_single_item() {
+    // Single-item sketch written in the full layout by an older serializer.
+    let mut legacy = vec![5u8, 1, 15, 0, 200, 0, 8, 0];
+    legacy.extend_from_slice(&1u64.to_le_bytes());
+    legacy.extend_from_slice(&200u16.to_le_bytes());
+    legacy.extend_from_slice(&[1, 0]);
+    legacy.extend_from_slice(&199u32.to_le_bytes());
+    for _ in 0..3 {
+        legacy.extend_from_slice(&42.0f64.to_le_bytes());
+    }
+
+    assert_eq!(detect_serial_version(&legacy).unwrap(), SERIAL_VERSION_1);
+    assert!(needs_migration(&legacy).unwrap());
+
+    let migrated = KllDoubleSketch::migrate_bytes(&legacy).unwrap();
+    assert_eq!(detect_serial_version(&migrated).unwrap(), SERIAL_VERSION_2);
+    assert!(!needs_migration(&migrated).unwrap());
+    assert!(migrated.len() < legacy.len());
+
+    let sketch = KllDoubleSketch::deserialize(&migrated).unwrap();
+    assert_eq!(sketch.get_n(), 1);
+    assert_eq!(sketch.get_quantile(0.5), 42.0);
+}
@@ -8,7 +8,7 @@ fn test_boundary_conditions_and_edge_cases() {
     println!("=== Testing boundary conditions and edge cases ===");
 
     // Test with empty sketch operations
-    let empty_sketch = KllFloatSketch::new().unwrap();
+    let empty_sketch = KllFloatSketch::new();
 
     // Test quantile queries on empty sketch
     let result = empty_sketch.get_quantile(0.5);
@@ -43,7 +43,7 @@ fn test_boundary_conditions_and_edge_cases() {
 fn test_invalid_quantile_fractions() {
     println!("=== Testing invalid quantile fractions ===");
 
-    let mut sketch = KllFloatSketch::new().unwrap();
+    let mut sketch = KllFloatSketch::new();
 
     // Add some data first
     for i in 1..=100 {
@@ -79,7 +79,7 @@ fn test_invalid_quantile_fractions() {
 fn test_extreme_values() {
     println!("=== Testing extreme values ===");
 
-    let mut sketch = KllFloatSketch::new().unwrap();
+    let mut sketch = KllFloatSketch::new();
 
     // Test with extreme values
     let extreme_values = vec![
@@ -151,7 +151,7 @@ fn test_serialization_edge_cases() {
     println!("=== Testing serialization edge cases ===");
 
     // Test serialization of empty sketch
-    let empty_sketch = KllFloatSketch::new().unwrap();
+    let empty_sketch = KllFloatSketch::new();
     match empty_sketch.serialize() {
         Ok(data) => {
             println!("Empty sketch serialization size: {} bytes", data.len());
@@ -170,6 +170,7 @@ fn test_serialization_edge_cases() {
     }
 
     // Test deserialization with invalid data
+    #[allow(clippy::useless_vec)]
     let invalid_data_cases = vec![
         vec![],           // Empty data
         vec![0x00],       // Single byte
@@ -194,7 +195,7 @@ fn test_serialization_edge_cases() {
 fn test_large_data_volumes() {
     println!("=== Testing large data volumes ===");
 
-    let mut sketch = KllFloatSketch::new().unwrap();
+    let mut sketch = KllFloatSketch::new();
 
     // Test with a large number of updates
     let large_n = 1_000_000;
@@ -234,7 +235,7 @@ fn test_large_data_volumes() {
 fn test_thread_safety() {
     println!("=== Testing thread safety ===");
 
-    let sketch = Arc::new(KllFloatSketch::new().unwrap());
+    let sketch = Arc::new(KllFloatSketch::new());
     let mut handles = vec![];
 
     // Create multiple threads that read from the sketch
@@ -268,8 +269,8 @@ fn test_merge_edge_cases() {
     println!("=== Testing merge edge cases ===");
 
     // Test merging empty sketches
-    let mut sketch1 = KllFloatSketch::new().unwrap();
-    let sketch2 = KllFloatSketch::new().unwrap();
+    let mut sketch1 = KllFloatSketch::new();
+    let sketch2 = KllFloatSketch::new();
 
     match sketch1.merge(&sketch2) {
         Ok(_) => println!("✓ Successfully merged empty sketches"),
@@ -277,7 +278,7 @@ fn test_merge_edge_cases() {
     }
 
     // Test merging sketches with different k values
-    let mut sketch_k200 = KllFloatSketch::new().unwrap(); // Default k=200
+    let mut sketch_k200 = KllFloatSketch::new(); // Default k=200
     let mut sketch_k128 = KllFloatSketch::new_with_k(128).unwrap();
 
     // Add some data to both
@@ -302,7 +303,7 @@ fn test_clone_and_copy_edge_cases() {
     println!("=== Testing clone and copy edge cases ===");
 
     // Test cloning empty sketch
-    let empty_sketch = KllFloatSketch::new().unwrap();
+    let empty_sketch = KllFloatSketch::new();
     let cloned_empty = empty_sketch.clone();
 
     assert_eq!(empty_sketch.get_n(), cloned_empty.get_n());
@@ -320,7 +321,7 @@ fn test_clone_and_copy_edge_cases() {
     }
 
     // Test cloning large sketch
-    let mut large_sketch = KllFloatSketch::new().unwrap();
+    let mut large_sketch = KllFloatSketch::new();
     for i in 0..10000 {
         large_sketch.update(i as f32);
     }
@@ -340,7 +341,7 @@ fn test_clone_and_copy_edge_cases() {
 fn test_double_sketch_edge_cases() {
     println!("=== Testing double sketch edge cases ===");
 
-    let mut sketch = KllDoubleSketch::new().unwrap();
+    let mut sketch = KllDoubleSketch::new();
 
     // Test with extreme double values
     let extreme_values = vec![
@@ -386,7 +387,7 @@ fn test_panic_safety() {
     println!("=== Testing panic safety and recovery ===");
 
     // Test that panics in one operation don't corrupt the sketch
-    let mut sketch = KllFloatSketch::new().unwrap();
+    let mut sketch = KllFloatSketch::new();
 
     // Add some normal data first
     for i in 1..=100 {
@@ -0,0 +1,101 @@
+//! Neither C++ exceptions nor Rust panics may unwind across the FFI boundary.
+//!
+//! The wrapper catches every C++ exception before returning, and the
+//! compaction trampoline catches callback panics and re-raises them once the
+//! native call has returned. Under `panic = "abort"` a callback panic aborts
+//! without unwinding through any C++ frame, which is equally well defined;
+//! a child process checks that.
+
+use kll_rs::KllDoubleSketch;
+#[cfg(feature = "native")]
+use libdatasketches_sys::{
+    kll_double_sketch_delete, kll_double_sketch_get_max_value, kll_double_sketch_get_min_value,
+    kll_double_sketch_get_quantile, kll_double_sketch_get_rank, kll_double_sketch_new_with_k,
+    kll_double_sketch_update,
+};
+use std::{env, panic, process};
+
+/// Set in the child process that `test_callback_panic_aborts` runs.
+const ABORT_CHILD_VAR: &str = "KLL_RS_UNWIND_TEST_ABORT_CHILD";
+
+#[cfg(feature = "native")]
+#[test]
+fn test_cpp_exceptions_do_not_cross() {
+    unsafe {
+        let sketch = kll_double_sketch_new_with_k(8);
+        assert!(!sketch.is_null());
+
+        // datasketches-cpp throws for queries on an empty sketch.
+        assert_eq!(kll_double_sketch_get_quantile(sketch, 0.5), 0.0);
+        assert_eq!(kll_double_sketch_get_rank(sketch, 1.0), 0.0);
+        assert_eq!(kll_double_sketch_get_min_value(sketch), 0.0);
+        assert_eq!(kll_double_sketch_get_max_value(sketch), 0.0);
+
+        // And for ranks outside [0, 1].
+        kll_double_sketch_update(sketch, 1.0);
+        assert_eq!(kll_double_sketch_get_quantile(sketch, 2.0), 0.0);
+        assert_eq!(kll_double_sketch_get_quantile(sketch, 0.5), 1.0);
+
+        kll_double_sketch_delete(sketch);
+    }
+}
+
+#[test]
+fn test_callback_panic_resumes_in_rust() {
+    let mut sketch = KllDoubleSketch::new_with_k(8).unwrap();
+    sketch.on_compaction(|_, _| panic!("callback failed"));
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        for i in 0..100 {
+            sketch.update(i as f64);
+        }
+    }));
+    let payload = result.expect_err("the callback panic should propagate");
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"callback failed"));
+
+    // The update that compacted completed before the panic was re-raised.
+    let n = sketch.get_n();
+    assert!(n > 0);
+    sketch.clear_compaction_callback();
+    for i in 0..100 {
+        sketch.update(i as f64);
+    }
+    assert_eq!(sketch.get_n(), n + 100);
+}
+
+#[test]
+fn test_callback_panic_aborts() {
+    if env::var_os(ABORT_CHILD_VAR).is_some() {
+        // `panic = "abort"` aborts right after the panic hook runs, before
+        // anything unwinds; aborting from the hook does the same here.
+        panic::set_hook(Box::new(|info| {
+            eprintln!("{}", info);
+            process::abort();
+        }));
+        let mut sketch = KllDoubleSketch::new_with_k(8).unwrap();
+        sketch.on_compaction(|_, _| panic!("callback failed"));
+        for i in 0..100 {
+            sketch.update(i as f64);
+        }
+        process::exit(0);
+    }
+
+    let output = process::Command::new(env::current_exe().unwrap())
+        .args([
+            "test_callback_panic_aborts",
+            "--exact",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(ABORT_CHILD_VAR, "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "the child should have aborted");
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        // SIGABRT
+        assert_eq!(output.status.signal(), Some(6));
+    }
+    assert!(String::from_utf8_lossy(&output.stderr).contains("callback failed"));
+}